@@ -0,0 +1,632 @@
+#[cfg(test)]
+mod tests;
+
+use cgmath::Vector3;
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use output::{CropOutput, ExrPixelType, OutputSettings, ToneMapOperator, ToneMapping, TransferFunction};
+use render::{Aov, CropWindow, DenoiseSettings, IntegratorType, RenderBudget, RenderSettings};
+use distributed::DEFAULT_BUCKET_SIZE;
+use scene::Scene;
+use stats::StatsFormat;
+
+pub use output::OutputFormat;
+
+/// Command line usage, shown by `--help`
+pub const USAGE: &str = "\
+Usage: ray_rs [OPTIONS] <SCENE> <OUTPUT> [WIDTH HEIGHT]
+       ray_rs --animate [--frames <FIRST-LAST>] [OPTIONS] <SCENE> <DIRECTORY>
+       ray_rs --worker [--threads <N>]
+       ray_rs --connect <ADDRESS> [--threads <N>]
+
+Renders SCENE (.ray, .obj or .gltf/.glb) into the image OUTPUT. Options
+override the scene's own render settings, and the defaults apply where
+neither gives one. Animated scenes are rendered a frame at a time into
+DIRECTORY, as frame_0001.png and on.
+
+Options:
+  -w, --width <PIXELS>         image width
+  -H, --height <PIXELS>        image height
+  -r, --resolution <WxH>       image width and height, e.g. 640x480
+                               (sizes not given follow the camera's aspect
+                               ratio, and default to 512 pixels wide)
+  -s, --samples <N>            samples per pixel, or the most to take with
+                               --time-limit or --noise-threshold
+                               [default: 1, or 4096 with a limit]
+      --time-limit <SECONDS>   stop taking samples before running out of time
+      --noise-threshold <NOISE>
+                               stop taking samples once 99% of pixels are this
+                               smooth, as the error of their brightness relative
+                               to it, such as 0.01
+  -d, --max-depth <N>          bounces of reflected and refracted light [default: 5]
+  -t, --threads <N>            render threads [default: one per core]
+  -i, --integrator <NAME>      whitted or path [default: whitted]
+  -b, --background <R,G,B>     color of rays that miss everything [default: 0,0,0]
+  -f, --format <FORMAT>        png, jpeg, ppm, bmp, exr or hdr
+                               [default: from OUTPUT's extension]
+      --exr-type <TYPE>        half or float values in EXR images [default: half]
+      --tonemap <OPERATOR>     how PNG, JPEG, PPM and BMP images fit bright light in:
+                               clamp, reinhard, reinhard-extended, aces or uncharted2
+                               [default: clamp]
+      --exposure <STOPS>       brightens the image before tone mapping [default: 0]
+      --white-point <VALUE>    light that tone maps to white [default: the operator's]
+      --gamma <GAMMA>          encodes 8-bit images with a power curve rather
+                               than sRGB, 1 keeps them linear [default: srgb]
+      --aov <PASSES>           also render passes, comma separated, from depth, normal,
+                               uv, barycentrics, albedo, object_id and material_id,
+                               or all. EXR images get them as layers, other
+                               formats as files such as OUTPUT.depth.png
+      --aov-files              write passes to files of their own for EXR images too
+      --denoise                smooth out the noise before tone mapping, guided
+                               by the albedo, normal and depth passes
+      --denoise-iterations <N> passes of the denoiser, each reaching twice as far,
+                               up to 16 [default: 5]
+      --denoise-strength <SIGMA>
+                               differences in brightness the denoiser smooths
+                               over, in multiples of the noise, higher is
+                               smoother [default: 4]
+      --checkpoint <FILE>      save the render to FILE as it goes, to carry on with
+                               --resume if it's stopped
+      --checkpoint-interval <SECONDS>
+                               time between checkpoints [default: 60]
+      --resume                 carry on the render saved in the checkpoint file
+      --seed <N>               seed of the random samples [default: 0]
+      --crop <X0,Y0,X1,Y1>     only render this part of the image, in fractions
+                               of its size from the top left
+      --crop-pixels <X0,Y0,X1,Y1>
+                               only render these pixels, counted from the top
+                               left, up to but not including X1 and Y1
+      --crop-output <MODE>     full writes the whole image, black outside the
+                               crop, cropped just the cropped part, and
+                               composite pastes it into the image already at
+                               OUTPUT [default: full]
+      --animate                render the frames of the scene's animation
+      --frames <FIRST-LAST>    render these frames of the animation, or just
+                               one given on its own
+      --workers <N>            share the render out in buckets between N worker
+                               processes started on this machine
+      --listen <ADDRESS>       also share it with workers connecting to ADDRESS,
+                               such as 0.0.0.0:7878, waiting for them until
+                               every bucket is rendered
+      --bucket-size <PIXELS>   size of the buckets workers render [default: 64]
+      --worker                 render buckets for the process that started this
+                               one, over standard input and output
+      --connect <ADDRESS>      render buckets for the process listening at ADDRESS
+      --stats <FORMAT>         print statistics of the render to standard output
+                               when it's done, as text or json
+  -q, --quiet                  only report errors
+  -v, --verbose                report the scene and render settings
+  -h, --help                   show this help
+";
+
+/// Default width of images whose size isn't given
+const DEFAULT_WIDTH: u32 = 512;
+
+/// Configuration struct for parsing command line inputs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// A scene file as the scene input
+    pub ray_filename: String,
+    /// An output filename for our rendered scene
+    pub output_filename: String,
+    /// Image size, either part of which may be left to the scene
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Settings left out fall back to the scene's render settings
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub threads: usize,
+    pub integrator: Option<IntegratorType>,
+    pub background: Option<Vector3<f64>>,
+    pub format: OutputFormat,
+    pub exr_pixel_type: ExrPixelType,
+    pub tone_mapping: ToneMapping,
+    /// Passes rendered along with the image
+    pub aovs: Vec<Aov>,
+    /// Whether passes go in files of their own even when they could be EXR layers
+    pub aov_files: bool,
+    /// Denoises the image when given
+    pub denoise: Option<DenoiseSettings>,
+    /// File the render is saved to as it goes
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Duration,
+    /// Limits that stop the render before it has all its samples
+    pub budget: RenderBudget,
+    /// Whether to carry on from the checkpoint rather than start afresh
+    pub resume: bool,
+    pub seed: u64,
+    pub crop: Option<Crop>,
+    /// What's written of a cropped render
+    pub crop_output: CropOutput,
+    /// Prints the render's statistics when given
+    pub stats: Option<StatsFormat>,
+    /// Frames of the scene's animation to render, into the directory named
+    /// by `output_filename`, when given
+    pub frames: Option<Frames>,
+    pub distribution: Distribution,
+    pub verbosity: Verbosity,
+}
+
+/// Part of the image to render
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crop {
+    /// In fractions of the image's size
+    Window(CropWindow),
+    /// From pixel `(x0, y0)` up to `(x1, y1)`, leaving those out
+    Pixels { x0: u32, y0: u32, x1: u32, y1: u32 },
+}
+
+/// Which frames of the scene's animation to render
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frames {
+    /// Those the animation gives
+    All,
+    /// From `first` to `last`, both included
+    Range { first: u32, last: u32 },
+}
+
+/// How the render is shared out between processes
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// Rendered by this process alone
+    Local,
+    /// Shared out in buckets of `bucket_size` pixels across and down between
+    /// `workers` processes started here, and any connecting to `listen`
+    Coordinator { workers: usize, listen: Option<String>, bucket_size: u32 },
+    /// Renders buckets for a coordinator, over standard input and output or
+    /// by connecting to its address. Workers have no files of their own, the
+    /// coordinator sends the scene to render.
+    Worker { connect: Option<String> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Why the command line didn't give a configuration
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// Help was asked for rather than a render
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Help => write!(f, "help requested"),
+            ConfigError::Invalid(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Construction for Config struct
+    ///
+    /// # arguements
+    ///
+    /// * `args` - a reference to an array of strings representing the command line arguements,
+    ///   starting with the program name
+    pub fn new(args: &[String]) -> Result<Config, ConfigError> {
+        let mut positional = Vec::new();
+        let mut width = None;
+        let mut height = None;
+        let mut samples = None;
+        let mut max_depth = None;
+        let mut threads = thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+        let mut integrator = None;
+        let mut background = None;
+        let mut format = None;
+        let mut exr_pixel_type = ExrPixelType::Half;
+        let mut tone_mapping = ToneMapping::default();
+        let mut aovs = Vec::new();
+        let mut aov_files = false;
+        let mut denoise = None;
+        let mut checkpoint = None;
+        let mut checkpoint_interval = Duration::from_secs(60);
+        let mut budget = RenderBudget::default();
+        let mut resume = false;
+        let mut seed = 0;
+        let mut crop = None;
+        let mut crop_output = CropOutput::Full;
+        let mut stats = None;
+        let mut frames = None;
+        let mut workers = None;
+        let mut listen = None;
+        let mut bucket_size = None;
+        let mut worker = false;
+        let mut connect = None;
+        let mut verbosity = Verbosity::Normal;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                positional.push(arg.clone());
+                continue;
+            }
+
+            // values either follow the flag or are joined to it with '='
+            let (flag, joined) = match arg.find('=') {
+                Some(index) if arg.starts_with("--") => (&arg[..index], Some(arg[index + 1..].to_string())),
+                _ => (arg.as_str(), None),
+            };
+            if joined.is_some() && !takes_value(flag) {
+                return Err(invalid(format!("'{}' doesn't take a value", flag)));
+            }
+            let mut value = || joined.clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| invalid(format!("'{}' needs a value", flag)));
+
+            match flag {
+                "-h" | "--help" => return Err(ConfigError::Help),
+                "-w" | "--width" => width = Some(parse_positive(flag, &value()?)?),
+                "-H" | "--height" => height = Some(parse_positive(flag, &value()?)?),
+                "-r" | "--resolution" => {
+                    let (w, h) = parse_resolution(&value()?)?;
+                    width = Some(w);
+                    height = Some(h);
+                },
+                "-s" | "--samples" => samples = Some(parse_positive(flag, &value()?)?),
+                "-d" | "--max-depth" => max_depth = Some(parse_number(flag, &value()?)?),
+                "-t" | "--threads" => threads = parse_positive(flag, &value()?)?,
+                "-i" | "--integrator" => {
+                    let name = value()?;
+                    integrator = Some(IntegratorType::from_name(&name)
+                        .ok_or_else(|| invalid(format!("unknown integrator '{}', expected whitted or path", name)))?);
+                },
+                "-b" | "--background" => background = Some(parse_color(&value()?)?),
+                "-f" | "--format" => {
+                    let name = value()?;
+                    format = Some(OutputFormat::from_name(&name)
+                        .ok_or_else(|| invalid(format!("unknown output format '{}', expected png, jpeg, ppm, bmp, exr or hdr", name)))?);
+                },
+                "--exr-type" => {
+                    let name = value()?;
+                    exr_pixel_type = ExrPixelType::from_name(&name)
+                        .ok_or_else(|| invalid(format!("unknown EXR pixel type '{}', expected half or float", name)))?;
+                },
+                "--tonemap" => {
+                    let name = value()?;
+                    tone_mapping.operator = ToneMapOperator::from_name(&name)
+                        .ok_or_else(|| invalid(format!(
+                            "unknown tone mapping operator '{}', expected clamp, reinhard, reinhard-extended, aces or uncharted2", name)))?;
+                },
+                "--exposure" => tone_mapping.exposure = parse_number(flag, &value()?)?,
+                "--white-point" => tone_mapping.white_point = Some(parse_positive(flag, &value()?)?),
+                "--gamma" => {
+                    let gamma = value()?;
+                    tone_mapping.transfer = match gamma.as_str() {
+                        "srgb" => TransferFunction::Srgb,
+                        _ => TransferFunction::Gamma(parse_positive(flag, &gamma)?),
+                    };
+                },
+                "--aov" => aovs = parse_aovs(&value()?)?,
+                "--aov-files" => aov_files = true,
+                "--denoise" => denoise = Some(denoise.unwrap_or_default()),
+                "--denoise-iterations" => {
+                    let iterations = parse_number(flag, &value()?)?;
+                    if iterations > DenoiseSettings::MAX_ITERATIONS {
+                        return Err(invalid(format!("{} can be at most {}, got {}", flag, DenoiseSettings::MAX_ITERATIONS, iterations)));
+                    }
+                    denoise = Some(DenoiseSettings { iterations, ..denoise.unwrap_or_default() });
+                },
+                "--denoise-strength" => {
+                    let color_sigma = parse_positive(flag, &value()?)?;
+                    denoise = Some(DenoiseSettings { color_sigma, ..denoise.unwrap_or_default() });
+                },
+                "--checkpoint" => checkpoint = Some(value()?),
+                "--checkpoint-interval" => checkpoint_interval = parse_duration(flag, &value()?)?,
+                "--time-limit" => budget.time = Some(parse_duration(flag, &value()?)?),
+                "--noise-threshold" => budget.noise = Some(parse_positive(flag, &value()?)?),
+                "--resume" => resume = true,
+                "--seed" => seed = parse_number(flag, &value()?)?,
+                "--crop" => crop = Some(Crop::Window(parse_crop(&value()?)?)),
+                "--crop-pixels" => crop = Some(parse_crop_pixels(&value()?)?),
+                "--crop-output" => {
+                    let name = value()?;
+                    crop_output = CropOutput::from_name(&name)
+                        .ok_or_else(|| invalid(format!("unknown crop output '{}', expected full, cropped or composite", name)))?;
+                },
+                "--stats" => {
+                    let name = value()?;
+                    stats = Some(StatsFormat::from_name(&name)
+                        .ok_or_else(|| invalid(format!("unknown statistics format '{}', expected text or json", name)))?);
+                },
+                "--animate" => frames = Some(frames.unwrap_or(Frames::All)),
+                "--frames" => frames = Some(parse_frames(&value()?)?),
+                "--workers" => workers = Some(parse_positive(flag, &value()?)?),
+                "--listen" => listen = Some(value()?),
+                "--bucket-size" => bucket_size = Some(parse_positive(flag, &value()?)?),
+                "--worker" => worker = true,
+                "--connect" => connect = Some(value()?),
+                "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+                "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+                _ => return Err(invalid(format!("unknown option '{}'", arg))),
+            }
+        }
+
+        let distribution = if worker || connect.is_some() {
+            if frames.is_some() {
+                return Err(invalid("workers render the frame their coordinator gives them, so don't take --animate or --frames"));
+            }
+            if workers.is_some() || listen.is_some() {
+                return Err(invalid("a worker can't share its render out to workers of its own"));
+            }
+            if let Some(file) = positional.first() {
+                return Err(invalid(format!("workers are sent the scene by their coordinator, so don't take '{}'", file)));
+            }
+            // nothing else a worker is given matters, the coordinator sends the
+            // scene and how to render it
+            positional = vec![String::new(), String::new()];
+            format = Some(OutputFormat::Png);
+            Distribution::Worker { connect }
+        } else if workers.is_some() || listen.is_some() {
+            if checkpoint.is_some() || !budget.is_unlimited() {
+                return Err(invalid("renders shared out to workers take every sample, so can't use --checkpoint, --time-limit or --noise-threshold"));
+            }
+            if frames.is_some() {
+                return Err(invalid("animations can't be shared out to workers, workers render a frame of the scene as it's read"));
+            }
+            Distribution::Coordinator { workers: workers.unwrap_or(0), listen, bucket_size: bucket_size.unwrap_or(DEFAULT_BUCKET_SIZE) }
+        } else if bucket_size.is_some() {
+            return Err(invalid("--bucket-size needs --workers or --listen"));
+        } else {
+            Distribution::Local
+        };
+
+        // the size may also follow the file names, as it used to have to
+        match positional.len() {
+            2 => {},
+            4 if width.is_none() && height.is_none() => {
+                width = Some(parse_positive("WIDTH", &positional[2])?);
+                height = Some(parse_positive("HEIGHT", &positional[3])?);
+            },
+            4 => return Err(invalid("image size given both as arguments and as options")),
+            0 | 1 => return Err(invalid("expected a scene file and an output file")),
+            3 => return Err(invalid("expected both a width and a height after the file names")),
+            _ => return Err(invalid(format!("unexpected argument '{}'", positional[4]))),
+        }
+
+        if crop_output != CropOutput::Full && crop.is_none() {
+            return Err(invalid("--crop-output needs --crop or --crop-pixels"));
+        }
+        if resume && checkpoint.is_none() {
+            return Err(invalid("--resume needs the --checkpoint file to carry on from"));
+        }
+        if frames.is_some() && checkpoint.is_some() {
+            return Err(invalid("animations render each frame afresh, so can't use --checkpoint"));
+        }
+
+        let output_filename = positional[1].clone();
+        let format = match format {
+            Some(format) => format,
+            // frames go in a directory, so they're PNG images unless asked otherwise
+            None if frames.is_some() => OutputFormat::Png,
+            None => Path::new(&output_filename).extension()
+                .and_then(|extension| extension.to_str())
+                .and_then(OutputFormat::from_name)
+                .ok_or_else(|| invalid(format!("can't tell the image format of '{}', use --format", output_filename)))?,
+        };
+
+        Ok(Config {
+            ray_filename: positional[0].clone(),
+            output_filename,
+            width,
+            height,
+            samples,
+            max_depth,
+            threads,
+            integrator,
+            background,
+            format,
+            exr_pixel_type,
+            tone_mapping,
+            aovs,
+            aov_files,
+            denoise,
+            checkpoint,
+            checkpoint_interval,
+            budget,
+            resume,
+            seed,
+            crop,
+            crop_output,
+            stats,
+            frames,
+            distribution,
+            verbosity,
+        })
+    }
+
+    /// The image size. A size given on the command line replaces the scene's,
+    /// and whatever's still missing follows the camera's aspect ratio.
+    pub fn dimensions(&self, scene: &Scene) -> (u32, u32) {
+        let settings = scene.settings();
+        let (width, height) = if self.width.is_some() || self.height.is_some() {
+            (self.width, self.height)
+        } else {
+            (settings.width, settings.height)
+        };
+
+        let aspect_ratio = scene.camera().aspect_ratio();
+        let scaled = |size: u32, scale: f64| ((f64::from(size) * scale).round() as u32).max(1);
+        match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, scaled(width, 1.0 / aspect_ratio)),
+            (None, Some(height)) => (scaled(height, aspect_ratio), height),
+            (None, None) => (DEFAULT_WIDTH, scaled(DEFAULT_WIDTH, 1.0 / aspect_ratio)),
+        }
+    }
+
+    /// Where frame `frame` of an animation is written
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        Path::new(&self.output_filename).join(format!("frame_{:04}.{}", frame, self.format.extension()))
+    }
+
+    pub fn output_settings(&self) -> OutputSettings {
+        OutputSettings { format: self.format, exr_pixel_type: self.exr_pixel_type, tone_mapping: self.tone_mapping }
+    }
+
+    /// Settings for rendering the scene, taking those the command line
+    /// doesn't give from the scene. Fails if the crop is outside the image.
+    pub fn render_settings(&self, scene: &Scene) -> Result<RenderSettings, ConfigError> {
+        let defaults = RenderSettings::default();
+        let settings = scene.settings();
+        let (width, height) = self.dimensions(scene);
+        let crop = match self.crop {
+            Some(Crop::Window(window)) => Some(window),
+            Some(Crop::Pixels { x0, y0, x1, y1 }) if x1 <= width && y1 <= height => Some(CropWindow::from_pixels(x0, y0, x1, y1, width, height)),
+            Some(Crop::Pixels { x0, y0, x1, y1 }) => {
+                return Err(invalid(format!("crop pixels {},{},{},{} go past the {}x{} image", x0, y0, x1, y1, width, height)));
+            },
+            None => None,
+        };
+        Ok(RenderSettings {
+            width,
+            height,
+            samples: self.samples.or(settings.samples)
+                .unwrap_or(if self.budget.is_unlimited() { defaults.samples } else { RenderBudget::MAX_SAMPLES }),
+            max_depth: self.max_depth.or(settings.max_depth).unwrap_or(defaults.max_depth),
+            threads: self.threads,
+            seed: self.seed,
+            integrator: self.integrator.or(settings.integrator).unwrap_or(defaults.integrator),
+            background: self.background.or(settings.background).unwrap_or(defaults.background),
+            crop,
+        })
+    }
+}
+
+fn invalid<S: Into<String>>(message: S) -> ConfigError {
+    ConfigError::Invalid(message.into())
+}
+
+fn takes_value(flag: &str) -> bool {
+    !matches!(flag, "-h" | "--help" | "-q" | "--quiet" | "-v" | "--verbose" | "--aov-files" | "--denoise" | "--resume" | "--worker" | "--animate")
+}
+
+/// The numbers options take, which have to be finite
+trait Number: FromStr + PartialOrd + Default {
+    fn is_finite(&self) -> bool {
+        true
+    }
+}
+
+impl Number for u32 {}
+impl Number for u64 {}
+impl Number for usize {}
+
+impl Number for f64 {
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
+}
+
+fn parse_number<T: Number>(name: &str, value: &str) -> Result<T, ConfigError> {
+    match value.parse::<T>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(invalid(format!("'{}' isn't a valid number for {}", value, name))),
+    }
+}
+
+fn parse_positive<T: Number>(name: &str, value: &str) -> Result<T, ConfigError> {
+    let number = parse_number(name, value)?;
+    if number <= T::default() {
+        return Err(invalid(format!("{} must be more than zero, got '{}'", name, value)));
+    }
+    Ok(number)
+}
+
+fn parse_duration(name: &str, value: &str) -> Result<Duration, ConfigError> {
+    let seconds: f64 = parse_number(name, value)?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid(format!("{} can't be {} seconds", name, seconds)))
+}
+
+/// `first-last`, or a single frame
+fn parse_frames(value: &str) -> Result<Frames, ConfigError> {
+    let mut bounds = value.splitn(2, '-').map(|bound| bound.trim().parse::<u32>());
+    let (first, last) = match (bounds.next(), bounds.next()) {
+        (Some(Ok(first)), Some(Ok(last))) => (first, last),
+        (Some(Ok(frame)), None) => (frame, frame),
+        _ => return Err(invalid(format!("frames '{}' should be a frame or the first and last, like 1-48", value))),
+    };
+    if first > last {
+        return Err(invalid(format!("frames '{}' should run from low to high", value)));
+    }
+    Ok(Frames::Range { first, last })
+}
+
+fn parse_crop_pixels(value: &str) -> Result<Crop, ConfigError> {
+    let bounds = value.split(',')
+        .map(|bound| bound.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|bounds| bounds.len() == 4)
+        .ok_or_else(|| invalid(format!("crop pixels '{}' should be four whole numbers, x0,y0,x1,y1", value)))?;
+
+    let (x0, y0, x1, y1) = (bounds[0], bounds[1], bounds[2], bounds[3]);
+    if x0 >= x1 || y0 >= y1 {
+        return Err(invalid(format!("crop pixels '{}' should run from low to high", value)));
+    }
+    Ok(Crop::Pixels { x0, y0, x1, y1 })
+}
+
+fn parse_resolution(value: &str) -> Result<(u32, u32), ConfigError> {
+    let mut sizes = value.splitn(2, ['x', 'X']);
+    match (sizes.next(), sizes.next()) {
+        (Some(width), Some(height)) => Ok((parse_positive("the width", width)?, parse_positive("the height", height)?)),
+        _ => Err(invalid(format!("resolution '{}' should look like 640x480", value))),
+    }
+}
+
+fn parse_color(value: &str) -> Result<Vector3<f64>, ConfigError> {
+    let channels = value.split(',')
+        .map(|channel| channel.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|channels| channels.len() == 3 && channels.iter().all(|channel| channel.is_finite()))
+        .ok_or_else(|| invalid(format!("color '{}' should be three numbers, r,g,b", value)))?;
+    Ok(Vector3::new(channels[0], channels[1], channels[2]))
+}
+
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, ConfigError> {
+    if value == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+
+    let mut aovs = Vec::new();
+    for name in value.split(',').map(|name| name.trim()) {
+        let aov = Aov::from_name(name).ok_or_else(|| invalid(format!(
+            "unknown pass '{}', expected depth, normal, uv, barycentrics, albedo, object_id, material_id or all", name)))?;
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+    Ok(aovs)
+}
+
+fn parse_crop(value: &str) -> Result<CropWindow, ConfigError> {
+    let bounds = value.split(',')
+        .map(|bound| bound.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|bounds| bounds.len() == 4)
+        .ok_or_else(|| invalid(format!("crop window '{}' should be four numbers, x0,y0,x1,y1", value)))?;
+
+    let crop = CropWindow { x_min: bounds[0], y_min: bounds[1], x_max: bounds[2], y_max: bounds[3] };
+    let in_range = |min: f64, max: f64| 0.0 <= min && min < max && max <= 1.0;
+    if !in_range(crop.x_min, crop.x_max) || !in_range(crop.y_min, crop.y_max) {
+        return Err(invalid(format!("crop window '{}' should run from low to high between 0 and 1", value)));
+    }
+    Ok(crop)
+}
@@ -0,0 +1,255 @@
+use cgmath::Vector3;
+
+use scene::{Camera, SceneSettings};
+use scene::builder::SceneBuilder;
+
+use distributed::DEFAULT_BUCKET_SIZE;
+
+use super::*;
+
+fn config(args: &[&str]) -> Result<Config, ConfigError> {
+    let args: Vec<String> = ["ray_rs"].iter().chain(args).map(|arg| arg.to_string()).collect();
+    Config::new(&args)
+}
+
+fn error(args: &[&str]) -> String {
+    match config(args) {
+        Err(ConfigError::Invalid(message)) => message,
+        other => panic!("expected an error, got {:?}", other),
+    }
+}
+
+/// An empty scene seen through a camera with the aspect ratio
+fn scene(aspect_ratio: f64, settings: SceneSettings) -> Scene {
+    let mut camera = Camera::new();
+    camera.set_aspect_ratio(aspect_ratio);
+    SceneBuilder::new().camera(camera).settings(settings).build()
+}
+
+#[test]
+fn defaults_test() {
+    let config = config(&["scene.ray", "out.png"]).unwrap();
+    assert_eq!(config.ray_filename, "scene.ray");
+    assert_eq!(config.output_filename, "out.png");
+    assert_eq!((config.width, config.height), (None, None));
+    assert_eq!((config.samples, config.max_depth, config.seed), (None, None, 0));
+    assert!(config.threads > 0);
+    assert_eq!((config.integrator, config.background), (None, None));
+    assert_eq!(config.format, OutputFormat::Png);
+    assert_eq!(config.exr_pixel_type, ExrPixelType::Half);
+    assert_eq!(config.tone_mapping, ToneMapping::default());
+    assert_eq!((config.aovs.len(), config.aov_files), (0, false));
+    assert_eq!(config.denoise, None);
+    assert_eq!((config.checkpoint, config.checkpoint_interval, config.resume), (None, Duration::from_secs(60), false));
+    assert!(config.budget.is_unlimited());
+    assert_eq!((config.crop, config.crop_output), (None, CropOutput::Full));
+    assert_eq!(config.stats, None);
+    assert_eq!(config.distribution, Distribution::Local);
+    assert_eq!(config.frames, None);
+    assert_eq!(config.verbosity, Verbosity::Normal);
+}
+
+#[test]
+fn flags_test() {
+    let config = config(&[
+        "-s", "16", "--max-depth=2", "scene.ray", "--threads", "3", "-i", "path", "out.img",
+        "--format", "JPEG", "--seed", "42", "--crop", "0.25,0,1,0.5", "--crop-output", "composite", "-r", "640x480", "-q",
+        "--background", "0.1, 0.2,0.3", "--exr-type", "float",
+        "--aov", "depth, normal,depth", "--aov-files", "--denoise-iterations", "3", "--denoise-strength", "0.25", "--tonemap", "aces", "--exposure", "-1.5", "--white-point=8", "--gamma", "2.2",
+        "--checkpoint", "out.checkpoint", "--checkpoint-interval", "0.5", "--resume",
+        "--time-limit", "90", "--noise-threshold", "0.02", "--stats", "json",
+    ]).unwrap();
+    assert_eq!(config.ray_filename, "scene.ray");
+    assert_eq!(config.output_filename, "out.img");
+    assert_eq!((config.width, config.height), (Some(640), Some(480)));
+    assert_eq!((config.samples, config.max_depth, config.threads, config.seed), (Some(16), Some(2), 3, 42));
+    assert_eq!(config.integrator, Some(IntegratorType::Path));
+    assert_eq!(config.background, Some(Vector3::new(0.1, 0.2, 0.3)));
+    assert_eq!(config.format, OutputFormat::Jpeg);
+    assert_eq!(config.exr_pixel_type, ExrPixelType::Float);
+    assert_eq!((config.aovs.clone(), config.aov_files), (vec![Aov::Depth, Aov::Normal], true));
+    assert_eq!(config.denoise, Some(DenoiseSettings { iterations: 3, color_sigma: 0.25, ..DenoiseSettings::default() }));
+    assert_eq!(config.checkpoint, Some("out.checkpoint".to_string()));
+    assert_eq!((config.checkpoint_interval, config.resume), (Duration::from_millis(500), true));
+    assert_eq!(config.budget, RenderBudget { time: Some(Duration::from_secs(90)), noise: Some(0.02) });
+    assert_eq!(config.tone_mapping, ToneMapping {
+        operator: ToneMapOperator::Aces,
+        exposure: -1.5,
+        white_point: Some(8.0),
+        transfer: TransferFunction::Gamma(2.2),
+    });
+    assert_eq!(config.crop, Some(Crop::Window(CropWindow { x_min: 0.25, y_min: 0.0, x_max: 1.0, y_max: 0.5 })));
+    assert_eq!(config.crop_output, CropOutput::Composite);
+    assert_eq!(config.stats, Some(StatsFormat::Json));
+    assert_eq!(config.verbosity, Verbosity::Quiet);
+}
+
+#[test]
+fn dimensions_test() {
+    // the size used to be given after the file names
+    let given = config(&["scene.ray", "out.ppm", "320", "200"]).unwrap();
+    assert_eq!(given.format, OutputFormat::Ppm);
+    assert_eq!(config(&["scene.ray", "out.EXR"]).unwrap().format, OutputFormat::Exr);
+    assert_eq!(config(&["scene.ray", "out.hdr"]).unwrap().format, OutputFormat::Hdr);
+    assert_eq!(config(&["scene.ray", "out.exr", "--aov", "all"]).unwrap().aovs, Aov::ALL.to_vec());
+    assert_eq!(config(&["scene.ray", "out.exr", "--denoise"]).unwrap().denoise, Some(DenoiseSettings::default()));
+    assert_eq!(given.dimensions(&scene(1.0, SceneSettings::default())), (320, 200));
+
+    // sizes that aren't given follow the camera
+    let dimensions = |args: &[&str], aspect_ratio: f64, settings: SceneSettings| {
+        config(args).unwrap().dimensions(&scene(aspect_ratio, settings))
+    };
+    assert_eq!(dimensions(&["scene.ray", "out.bmp", "-w", "300"], 1.5, SceneSettings::default()), (300, 200));
+    assert_eq!(dimensions(&["scene.ray", "out.bmp", "-H", "300"], 1.5, SceneSettings::default()), (450, 300));
+    assert_eq!(dimensions(&["scene.ray", "out.bmp"], 2.0, SceneSettings::default()), (512, 256));
+
+    // the scene's size counts unless the command line gives one
+    let sized = SceneSettings { width: Some(200), height: Some(100), ..SceneSettings::default() };
+    assert_eq!(dimensions(&["scene.ray", "out.bmp"], 1.0, sized.clone()), (200, 100));
+    assert_eq!(dimensions(&["scene.ray", "out.bmp", "-w", "300"], 1.5, sized.clone()), (300, 200));
+    let wide = SceneSettings { width: Some(400), ..SceneSettings::default() };
+    assert_eq!(dimensions(&["scene.ray", "out.bmp"], 2.0, wide), (400, 200));
+
+    let settings = config(&["scene.ray", "out.png", "-w", "100", "-s", "4"]).unwrap()
+        .render_settings(&scene(0.5, SceneSettings::default())).unwrap();
+    assert_eq!((settings.width, settings.height, settings.samples), (100, 200, 4));
+}
+
+#[test]
+fn scene_settings_test() {
+    let from_scene = SceneSettings {
+        width: Some(64),
+        height: Some(48),
+        samples: Some(8),
+        max_depth: Some(2),
+        integrator: Some(IntegratorType::Path),
+        background: Some(Vector3::new(0.5, 0.5, 1.0)),
+    };
+
+    let settings = config(&["scene.ray", "out.png"]).unwrap().render_settings(&scene(1.0, from_scene.clone())).unwrap();
+    assert_eq!((settings.width, settings.height, settings.samples, settings.max_depth), (64, 48, 8, 2));
+    assert_eq!(settings.integrator, IntegratorType::Path);
+    assert_eq!(settings.background, Vector3::new(0.5, 0.5, 1.0));
+
+    // the command line wins over the scene
+    let settings = config(&["scene.ray", "out.png", "-s", "2", "-d", "0", "-i", "whitted", "-b", "0,0,0"]).unwrap()
+        .render_settings(&scene(1.0, from_scene)).unwrap();
+    assert_eq!((settings.samples, settings.max_depth), (2, 0));
+    assert_eq!(settings.integrator, IntegratorType::Whitted);
+    assert_eq!(settings.background, Vector3::new(0.0, 0.0, 0.0));
+
+    // and the defaults fill in for both
+    let settings = config(&["scene.ray", "out.png"]).unwrap().render_settings(&scene(1.0, SceneSettings::default())).unwrap();
+    assert_eq!((settings.samples, settings.max_depth), (1, 5));
+    assert_eq!(settings.integrator, IntegratorType::Whitted);
+    assert_eq!(settings.background, Vector3::new(0.0, 0.0, 0.0));
+
+    // renders with a budget take as many samples as they can by default
+    let settings = config(&["scene.ray", "out.png", "--time-limit", "10"]).unwrap()
+        .render_settings(&scene(1.0, SceneSettings::default())).unwrap();
+    assert_eq!(settings.samples, RenderBudget::MAX_SAMPLES);
+}
+
+#[test]
+fn crop_pixels_test() {
+    let cropped = config(&["scene.ray", "out.png", "-r", "7x5", "--crop-pixels", "3, 1,5,5", "--crop-output", "cropped"]).unwrap();
+    assert_eq!(cropped.crop, Some(Crop::Pixels { x0: 3, y0: 1, x1: 5, y1: 5 }));
+    assert_eq!(cropped.crop_output, CropOutput::Cropped);
+
+    // the pixels become a window over just them
+    let settings = cropped.render_settings(&scene(1.0, SceneSettings::default())).unwrap();
+    assert_eq!(settings.pixels(), (3..5, 1..5));
+
+    let outside = config(&["scene.ray", "out.png", "-r", "7x5", "--crop-pixels", "0,0,8,5"]).unwrap();
+    assert_eq!(outside.render_settings(&scene(1.0, SceneSettings::default())).unwrap_err().to_string(),
+        "crop pixels 0,0,8,5 go past the 7x5 image");
+}
+
+#[test]
+fn distribution_test() {
+    let coordinator = config(&["scene.ray", "out.png", "--workers", "4", "--listen", "0.0.0.0:7878", "--bucket-size=32"]).unwrap();
+    assert_eq!(coordinator.distribution,
+        Distribution::Coordinator { workers: 4, listen: Some("0.0.0.0:7878".to_string()), bucket_size: 32 });
+    assert_eq!(config(&["scene.ray", "out.png", "--listen", "localhost:7878"]).unwrap().distribution,
+        Distribution::Coordinator { workers: 0, listen: Some("localhost:7878".to_string()), bucket_size: DEFAULT_BUCKET_SIZE });
+
+    // workers need nothing but the threads to render with
+    let worker = config(&["--worker", "-t", "2"]).unwrap();
+    assert_eq!((worker.distribution, worker.threads), (Distribution::Worker { connect: None }, 2));
+    assert_eq!(config(&["--connect", "10.0.0.2:7878"]).unwrap().distribution,
+        Distribution::Worker { connect: Some("10.0.0.2:7878".to_string()) });
+}
+
+#[test]
+fn frames_test() {
+    let all = config(&["--animate", "scene.ray", "frames"]).unwrap();
+    assert_eq!((all.frames, all.format), (Some(Frames::All), OutputFormat::Png));
+    assert_eq!(all.frame_path(7), Path::new("frames").join("frame_0007.png"));
+
+    let range = config(&["scene.ray", "frames", "--frames", "12-24", "-f", "exr"]).unwrap();
+    assert_eq!(range.frames, Some(Frames::Range { first: 12, last: 24 }));
+    assert_eq!(range.frame_path(120), Path::new("frames").join("frame_0120.exr"));
+    assert_eq!(config(&["scene.ray", "frames", "--frames", "3", "--animate"]).unwrap().frames, Some(Frames::Range { first: 3, last: 3 }));
+}
+
+#[test]
+fn help_test() {
+    assert_eq!(config(&["--help"]), Err(ConfigError::Help));
+    assert_eq!(config(&["scene.ray", "-h", "out.png"]), Err(ConfigError::Help));
+}
+
+#[test]
+fn error_test() {
+    assert_eq!(error(&[]), "expected a scene file and an output file");
+    assert_eq!(error(&["scene.ray"]), "expected a scene file and an output file");
+    assert_eq!(error(&["scene.ray", "out.png", "100"]), "expected both a width and a height after the file names");
+    assert_eq!(error(&["scene.ray", "out.png", "100", "x"]), "'x' isn't a valid number for HEIGHT");
+    assert_eq!(error(&["scene.ray", "out.png", "1", "2", "3"]), "unexpected argument '3'");
+    assert_eq!(error(&["scene.ray", "out.png", "1", "2", "-w", "3"]), "image size given both as arguments and as options");
+    assert_eq!(error(&["scene.ray", "out.png", "--width"]), "'--width' needs a value");
+    assert_eq!(error(&["scene.ray", "out.png", "-w", "0"]), "-w must be more than zero, got '0'");
+    assert_eq!(error(&["scene.ray", "out.png", "-s", "-2"]), "'-2' isn't a valid number for -s");
+    assert_eq!(error(&["scene.ray", "out.png", "-r", "640"]), "resolution '640' should look like 640x480");
+    assert_eq!(error(&["scene.ray", "out.png", "-i", "raster"]), "unknown integrator 'raster', expected whitted or path");
+    assert_eq!(error(&["scene.ray", "out.png", "-b", "1,1"]), "color '1,1' should be three numbers, r,g,b");
+    assert_eq!(error(&["scene.ray", "out.png", "-f", "gif"]), "unknown output format 'gif', expected png, jpeg, ppm, bmp, exr or hdr");
+    assert_eq!(error(&["scene.ray", "out.png", "--tonemap", "filmic"]),
+        "unknown tone mapping operator 'filmic', expected clamp, reinhard, reinhard-extended, aces or uncharted2");
+    assert_eq!(error(&["scene.ray", "out.png", "--gamma", "0"]), "--gamma must be more than zero, got '0'");
+    assert_eq!(error(&["scene.ray", "out.png", "--aov", "depth,speed"]),
+        "unknown pass 'speed', expected depth, normal, uv, barycentrics, albedo, object_id, material_id or all");
+    assert_eq!(error(&["scene.ray", "out.exr", "--exr-type", "double"]), "unknown EXR pixel type 'double', expected half or float");
+    assert_eq!(error(&["scene.ray", "out"]), "can't tell the image format of 'out', use --format");
+    assert_eq!(error(&["scene.ray", "out.png", "--crop", "0,0,1"]), "crop window '0,0,1' should be four numbers, x0,y0,x1,y1");
+    assert_eq!(error(&["scene.ray", "out.png", "--crop", "0.5,0,0.5,1"]), "crop window '0.5,0,0.5,1' should run from low to high between 0 and 1");
+    assert_eq!(error(&["scene.ray", "out.png", "--resume"]), "--resume needs the --checkpoint file to carry on from");
+    assert_eq!(error(&["scene.ray", "out.png", "--checkpoint-interval", "-1"]), "--checkpoint-interval can't be -1 seconds");
+    assert_eq!(error(&["scene.ray", "out.png", "--noise-threshold", "0"]), "--noise-threshold must be more than zero, got '0'");
+    assert_eq!(error(&["scene.ray", "out.png", "--noise-threshold", "nan"]), "'nan' isn't a valid number for --noise-threshold");
+    assert_eq!(error(&["scene.ray", "out.png", "--white-point", "inf"]), "'inf' isn't a valid number for --white-point");
+    assert_eq!(error(&["scene.ray", "out.png", "--exposure", "NaN"]), "'NaN' isn't a valid number for --exposure");
+    assert_eq!(error(&["scene.ray", "out.png", "--denoise-strength", "nan"]), "'nan' isn't a valid number for --denoise-strength");
+    assert_eq!(error(&["scene.ray", "out.png", "-b", "0,nan,0"]), "color '0,nan,0' should be three numbers, r,g,b");
+    assert_eq!(error(&["scene.ray", "out.png", "--denoise-iterations", "63"]), "--denoise-iterations can be at most 16, got 63");
+    assert_eq!(error(&["scene.ray", "out.png", "--stats", "xml"]), "unknown statistics format 'xml', expected text or json");
+    assert_eq!(error(&["scene.ray", "out.png", "--crop-pixels", "0,0,1.5,2"]), "crop pixels '0,0,1.5,2' should be four whole numbers, x0,y0,x1,y1");
+    assert_eq!(error(&["scene.ray", "out.png", "--crop-pixels", "4,0,4,2"]), "crop pixels '4,0,4,2' should run from low to high");
+    assert_eq!(error(&["scene.ray", "out.png", "--crop", "0,0,1,1", "--crop-output", "tiles"]),
+        "unknown crop output 'tiles', expected full, cropped or composite");
+    assert_eq!(error(&["scene.ray", "out.png", "--crop-output", "cropped"]), "--crop-output needs --crop or --crop-pixels");
+    assert_eq!(error(&["scene.ray", "out.png", "--workers", "0"]), "--workers must be more than zero, got '0'");
+    assert_eq!(error(&["scene.ray", "out.png", "--bucket-size", "16"]), "--bucket-size needs --workers or --listen");
+    assert_eq!(error(&["scene.ray", "out.png", "--workers", "2", "--time-limit", "60"]),
+        "renders shared out to workers take every sample, so can't use --checkpoint, --time-limit or --noise-threshold");
+    assert_eq!(error(&["--worker", "--workers", "2"]), "a worker can't share its render out to workers of its own");
+    assert_eq!(error(&["--connect", "localhost:7878", "scene.ray"]), "workers are sent the scene by their coordinator, so don't take 'scene.ray'");
+    assert_eq!(error(&["scene.ray", "frames", "--frames", "1-x"]), "frames '1-x' should be a frame or the first and last, like 1-48");
+    assert_eq!(error(&["scene.ray", "frames", "--frames", "9-2"]), "frames '9-2' should run from low to high");
+    assert_eq!(error(&["scene.ray", "frames", "--animate", "--checkpoint", "out.checkpoint"]),
+        "animations render each frame afresh, so can't use --checkpoint");
+    assert_eq!(error(&["scene.ray", "frames", "--animate", "--workers", "2"]),
+        "animations can't be shared out to workers, workers render a frame of the scene as it's read");
+    assert_eq!(error(&["--worker", "--frames", "1-4"]), "workers render the frame their coordinator gives them, so don't take --animate or --frames");
+    assert_eq!(error(&["scene.ray", "out.png", "--fast"]), "unknown option '--fast'");
+    assert_eq!(error(&["scene.ray", "out.png", "--quiet=yes"]), "'--quiet' doesn't take a value");
+}
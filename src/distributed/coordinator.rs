@@ -0,0 +1,214 @@
+use image::Rgb;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::super::config::Verbosity;
+use super::super::render::Framebuffer;
+use super::super::stats::Counters;
+use super::{buckets, Bucket, Connection, Job, Message};
+
+/// How often a coordinator listening for workers checks whether the frame
+/// is done between them connecting
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shares the buckets of a job out between workers
+#[derive(Clone, Debug)]
+pub struct Coordinator {
+    pub job: Job,
+    /// Most pixels across and down of every bucket
+    pub bucket_size: u32,
+    /// Workers connecting and stopping are reported when this is at least
+    /// `Normal`
+    pub verbosity: Verbosity,
+}
+
+/// How a distributed render went
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoordinatorReport {
+    pub buckets: usize,
+    /// Workers that rendered at least one bucket
+    pub workers: usize,
+    /// Buckets handed to another worker after the one rendering them stopped
+    pub requeued: usize,
+    /// What the workers counted
+    pub counters: Counters,
+}
+
+impl fmt::Display for CoordinatorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} buckets rendered by {} workers", self.buckets, self.workers)?;
+        if self.requeued > 0 {
+            write!(f, ", {} of them again after a worker stopped", self.requeued)?;
+        }
+        Ok(())
+    }
+}
+
+/// The frame as the workers render it
+struct Progress {
+    /// Buckets no worker has yet
+    waiting: VecDeque<Bucket>,
+    /// Buckets still to be rendered, waiting or with a worker
+    remaining: usize,
+    image: Framebuffer,
+    report: CoordinatorReport,
+    /// Why the last worker to stop stopped
+    last_error: Option<String>,
+}
+
+impl Coordinator {
+    /// Renders the frame with the workers at the other end of `connections`,
+    /// and any that connect to `listener`. Without a listener the render
+    /// fails when every worker has stopped with buckets still to render,
+    /// while with one it waits for more to connect.
+    pub fn render(&self, connections: Vec<Connection>, listener: Option<TcpListener>) -> Result<(Framebuffer, CoordinatorReport), String> {
+        let settings = &self.job.settings;
+        let buckets = buckets(settings, self.bucket_size);
+        let progress = Mutex::new(Progress {
+            remaining: buckets.len(),
+            report: CoordinatorReport { buckets: buckets.len(), workers: 0, requeued: 0, counters: Counters::default() },
+            waiting: buckets.into_iter().collect(),
+            image: Framebuffer::new(settings.width, settings.height),
+            last_error: None,
+        });
+        let changed = Condvar::new();
+
+        thread::scope(|scope| {
+            for connection in connections {
+                let (progress, changed) = (&progress, &changed);
+                scope.spawn(move || self.work(connection, progress, changed));
+            }
+
+            let listener = match listener {
+                Some(listener) => listener,
+                None => return Ok(()),
+            };
+            listener.set_nonblocking(true).map_err(|err| format!("couldn't listen for workers: {}", err))?;
+            while progress.lock().unwrap().remaining > 0 {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_INTERVAL);
+                        continue;
+                    },
+                    Err(err) => return Err(format!("couldn't accept a worker: {}", err)),
+                };
+
+                let connection = match stream.set_nonblocking(false).and_then(|_| Connection::tcp(stream)) {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        self.log(Verbosity::Normal, format!("Couldn't connect to a worker: {}", err));
+                        continue;
+                    },
+                };
+                self.log(Verbosity::Verbose, format!("Worker {} connected", connection.name()));
+                let (progress, changed) = (&progress, &changed);
+                scope.spawn(move || self.work(connection, progress, changed));
+            }
+            Ok(())
+        })?;
+
+        let progress = progress.into_inner().unwrap();
+        if progress.remaining > 0 {
+            let why = progress.last_error.map(|err| format!(", the last because {}", err)).unwrap_or_default();
+            return Err(format!("every worker stopped with {} of {} buckets still to render{}",
+                progress.remaining, progress.report.buckets, why));
+        }
+        Ok((progress.image, progress.report))
+    }
+
+    /// Hands buckets to the worker at the other end of `connection` until
+    /// there are none left or it stops, putting the bucket it had back in
+    /// the queue if it does
+    fn work(&self, mut connection: Connection, progress: &Mutex<Progress>, changed: &Condvar) {
+        let mut bucket = None;
+        let mut rendered = 0;
+        let result = self.drive(&mut connection, progress, changed, &mut bucket, &mut rendered);
+
+        let mut progress = progress.lock().unwrap();
+        if rendered > 0 {
+            progress.report.workers += 1;
+        }
+        if let Err(err) = result {
+            match bucket {
+                Some(bucket) => {
+                    self.log(Verbosity::Normal, format!("Worker {} stopped ({}), handing bucket {} to another", connection.name(), err, bucket.id));
+                    progress.waiting.push_back(bucket);
+                    progress.report.requeued += 1;
+                },
+                None => self.log(Verbosity::Normal, format!("Worker {} stopped: {}", connection.name(), err)),
+            }
+            progress.last_error = Some(err);
+        }
+        changed.notify_all();
+    }
+
+    fn drive(&self, connection: &mut Connection, progress: &Mutex<Progress>, changed: &Condvar,
+        bucket: &mut Option<Bucket>, rendered: &mut usize) -> Result<(), String>
+    {
+        let lost = |err: io::Error| err.to_string();
+        connection.send(&Message::Job(self.job.clone())).map_err(lost)?;
+        match connection.receive().map_err(lost)? {
+            Message::Ready => {},
+            Message::Error(err) => return Err(err),
+            message => return Err(format!("expected the worker to be ready, got a {} message", message.name())),
+        }
+
+        loop {
+            let next = match next_bucket(progress, changed) {
+                Some(next) => next,
+                None => return connection.send(&Message::Done).map_err(lost),
+            };
+            *bucket = Some(next.clone());
+            connection.send(&Message::Bucket(next.clone())).map_err(lost)?;
+
+            let Bucket { id, columns, rows } = next;
+            let (pixels, counters) = match connection.receive().map_err(lost)? {
+                Message::Rendered { id: rendered_id, pixels, counters } if rendered_id == id => (pixels, counters),
+                Message::Error(err) => return Err(err),
+                message => return Err(format!("expected bucket {}, got a {} message", id, message.name())),
+            };
+            if pixels.len() != columns.len() * rows.len() * 3 {
+                return Err(format!("bucket {} came back with {} values rather than {}", id, pixels.len(), columns.len() * rows.len() * 3));
+            }
+
+            let mut progress = progress.lock().unwrap();
+            for (index, pixel) in pixels.chunks_exact(3).enumerate() {
+                let (x, y) = (index % columns.len(), index / columns.len());
+                progress.image.put_pixel(columns.start + x as u32, rows.start + y as u32, Rgb([pixel[0], pixel[1], pixel[2]]));
+            }
+            progress.report.counters.add(&counters);
+            progress.remaining -= 1;
+            *bucket = None;
+            *rendered += 1;
+            changed.notify_all();
+        }
+    }
+
+    fn log(&self, verbosity: Verbosity, message: String) {
+        if self.verbosity >= verbosity {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// The next bucket waiting, waiting for one to come back from a worker that
+/// stopped while others are still rendering. None once every bucket is done.
+fn next_bucket(progress: &Mutex<Progress>, changed: &Condvar) -> Option<Bucket> {
+    let mut progress = progress.lock().unwrap();
+    loop {
+        if let Some(bucket) = progress.waiting.pop_front() {
+            return Some(bucket);
+        }
+        if progress.remaining == 0 {
+            return None;
+        }
+        progress = changed.wait(progress).unwrap();
+    }
+}
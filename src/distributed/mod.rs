@@ -0,0 +1,314 @@
+//! Shares a frame out between worker processes, on this machine or others. A
+//! coordinator splits the image into buckets, hands them one at a time to the
+//! workers connected to it, over their standard input and output or TCP, and
+//! puts the rendered buckets together into the image. When a worker stops
+//! partway, its bucket goes back in the queue for another to render.
+//!
+//! Messages are lines of JSON. The coordinator starts with the job, the scene
+//! to load and how to render it, and the worker answers that it's ready or
+//! why it isn't. Then the coordinator sends a bucket at a time and the worker
+//! sends back its pixels, until the coordinator says it's done. Pixels are
+//! rendered exactly as they would be in a render of the whole image, so the
+//! image doesn't depend on which worker rendered what.
+
+mod coordinator;
+mod worker;
+#[cfg(test)]
+mod tests;
+
+pub use self::coordinator::{Coordinator, CoordinatorReport};
+pub use self::worker::serve;
+
+use base64;
+use cgmath::Vector3;
+use serde_json::{self, Map, Value};
+
+use std::convert::TryFrom;
+use std::env;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+use std::process::{Child, Command, Stdio};
+
+use super::render::{IntegratorType, RenderSettings};
+use super::stats::Counters;
+
+/// Version of the messages, which coordinators and workers have to agree on
+const PROTOCOL_VERSION: u64 = 1;
+
+/// Size of the buckets frames are split into, in pixels across and down
+pub const DEFAULT_BUCKET_SIZE: u32 = 64;
+
+/// What every worker renders its buckets of
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    /// Path of the scene file, which the workers load for themselves
+    pub scene: String,
+    /// How to render it. Workers render with threads of their own, and only
+    /// the pixels of the buckets they're given.
+    pub settings: RenderSettings,
+}
+
+/// Part of the frame rendered by one worker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    pub id: usize,
+    pub columns: Range<u32>,
+    pub rows: Range<u32>,
+}
+
+/// The pixels a render covers split into buckets up to `size` pixels across
+/// and down, a row of buckets at a time from the top left
+pub fn buckets(settings: &RenderSettings, size: u32) -> Vec<Bucket> {
+    let (columns, rows) = settings.pixels();
+    let size = size.max(1);
+    let starts = |range: &Range<u32>| (range.start..range.end).step_by(size as usize).collect::<Vec<_>>();
+
+    let mut buckets = Vec::new();
+    for &y in &starts(&rows) {
+        for &x in &starts(&columns) {
+            let id = buckets.len();
+            buckets.push(Bucket { id, columns: x..(x + size).min(columns.end), rows: y..(y + size).min(rows.end) });
+        }
+    }
+    buckets
+}
+
+/// What coordinators and workers say to each other
+#[derive(Clone, Debug, PartialEq)]
+enum Message {
+    Job(Job),
+    /// The worker has loaded the scene
+    Ready,
+    /// Why the worker can't go on
+    Error(String),
+    Bucket(Bucket),
+    /// A bucket's pixels, as red, green and blue row by row, and the work
+    /// they took
+    Rendered { id: usize, pixels: Vec<f32>, counters: Counters },
+    /// There are no more buckets
+    Done,
+}
+
+impl Message {
+    fn name(&self) -> &'static str {
+        match *self {
+            Message::Job(_) => "job",
+            Message::Ready => "ready",
+            Message::Error(_) => "error",
+            Message::Bucket(_) => "bucket",
+            Message::Rendered { .. } => "rendered",
+            Message::Done => "done",
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut json = Map::new();
+        let mut insert = |name: &str, value: Value| { json.insert(name.to_string(), value); };
+        insert("type", Value::from(self.name()));
+        match *self {
+            Message::Job(ref job) => {
+                let settings = &job.settings;
+                let background = settings.background;
+                insert("version", Value::from(PROTOCOL_VERSION));
+                insert("scene", Value::from(job.scene.as_str()));
+                insert("width", Value::from(settings.width));
+                insert("height", Value::from(settings.height));
+                insert("samples", Value::from(settings.samples));
+                insert("max_depth", Value::from(settings.max_depth));
+                insert("seed", Value::from(settings.seed));
+                insert("integrator", Value::from(settings.integrator.name()));
+                insert("background", Value::from(vec![background.x, background.y, background.z]));
+            },
+            Message::Ready | Message::Done => {},
+            Message::Error(ref message) => {
+                insert("message", Value::from(message.as_str()));
+            },
+            Message::Bucket(ref bucket) => {
+                insert("id", Value::from(bucket.id as u64));
+                insert("x0", Value::from(bucket.columns.start));
+                insert("y0", Value::from(bucket.rows.start));
+                insert("x1", Value::from(bucket.columns.end));
+                insert("y1", Value::from(bucket.rows.end));
+            },
+            Message::Rendered { id, ref pixels, ref counters } => {
+                // pixels go as their bytes, so they arrive exactly as rendered
+                let bytes: Vec<u8> = pixels.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect();
+                insert("id", Value::from(id as u64));
+                insert("pixels", Value::from(base64::encode(&bytes)));
+                insert("counters", counters_to_json(counters));
+            },
+        }
+        Value::Object(json)
+    }
+
+    fn from_json(json: &Value) -> io::Result<Message> {
+        let kind = json["type"].as_str().ok_or_else(|| invalid_data("the message has no type"))?;
+        match kind {
+            "job" => {
+                let version = number(json, "version")?;
+                if version != PROTOCOL_VERSION {
+                    return Err(invalid_data(format!("the coordinator speaks version {} of the protocol, not {}", version, PROTOCOL_VERSION)));
+                }
+                let name = text(json, "integrator")?;
+                let integrator = IntegratorType::from_name(name)
+                    .ok_or_else(|| invalid_data(format!("unknown integrator '{}'", name)))?;
+                let background = json["background"].as_array()
+                    .map(|channels| channels.iter().filter_map(Value::as_f64).collect::<Vec<_>>())
+                    .filter(|channels| channels.len() == 3)
+                    .ok_or_else(|| invalid_data("the job's background should be three numbers"))?;
+                let settings = RenderSettings {
+                    width: small(json, "width")?,
+                    height: small(json, "height")?,
+                    samples: small(json, "samples")?,
+                    max_depth: small(json, "max_depth")?,
+                    seed: number(json, "seed")?,
+                    integrator,
+                    background: Vector3::new(background[0], background[1], background[2]),
+                    crop: None,
+                    ..RenderSettings::default()
+                };
+                Ok(Message::Job(Job { scene: text(json, "scene")?.to_string(), settings }))
+            },
+            "ready" => Ok(Message::Ready),
+            "error" => Ok(Message::Error(text(json, "message")?.to_string())),
+            "bucket" => Ok(Message::Bucket(Bucket {
+                id: number(json, "id")? as usize,
+                columns: small(json, "x0")?..small(json, "x1")?,
+                rows: small(json, "y0")?..small(json, "y1")?,
+            })),
+            "rendered" => {
+                let bytes = base64::decode(text(json, "pixels")?)
+                    .map_err(|err| invalid_data(format!("the bucket's pixels aren't base64: {}", err)))?;
+                let pixels = bytes.chunks_exact(4)
+                    .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                    .collect();
+                Ok(Message::Rendered { id: number(json, "id")? as usize, pixels, counters: counters_from_json(&json["counters"])? })
+            },
+            "done" => Ok(Message::Done),
+            _ => Err(invalid_data(format!("unknown message '{}'", kind))),
+        }
+    }
+}
+
+/// One end of the link between a coordinator and a worker
+pub struct Connection {
+    /// Who's at the other end, for reporting
+    name: String,
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+    /// The worker process, when this process started it
+    child: Option<Child>,
+}
+
+impl Connection {
+    pub fn new<R, W>(name: &str, reader: R, writer: W) -> Connection
+        where R: Read + Send + 'static, W: Write + Send + 'static
+    {
+        Connection { name: name.to_string(), reader: Box::new(BufReader::new(reader)), writer: Box::new(BufWriter::new(writer)), child: None }
+    }
+
+    /// Talks over standard input and output, as the worker of the process
+    /// that started this one
+    pub fn stdio() -> Connection {
+        Connection::new("the coordinator", io::stdin(), io::stdout())
+    }
+
+    pub fn tcp(stream: TcpStream) -> io::Result<Connection> {
+        // messages are sent a line at a time, and answered before the next
+        stream.set_nodelay(true)?;
+        let name = stream.peer_addr()?.to_string();
+        Ok(Connection::new(&name, stream.try_clone()?, stream))
+    }
+
+    /// Starts `command` as a worker, talking over its standard input and
+    /// output. The worker is killed when the connection is dropped, if it
+    /// hasn't stopped already.
+    pub fn spawn(mut command: Command) -> io::Result<Connection> {
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let (stdin, stdout) = (child.stdin.take().unwrap(), child.stdout.take().unwrap());
+        let mut connection = Connection::new(&format!("process {}", child.id()), stdout, stdin);
+        connection.child = Some(child);
+        Ok(connection)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        writeln!(self.writer, "{}", message.to_json())?;
+        self.writer.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<Message> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection closed"));
+        }
+        let json = serde_json::from_str(&line).map_err(|err| invalid_data(format!("the message isn't JSON: {}", err)))?;
+        Message::from_json(&json)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(ref mut child) = self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Starts `count` workers running this program, each rendering with
+/// `threads` threads
+pub fn spawn_workers(count: usize, threads: usize) -> io::Result<Vec<Connection>> {
+    let program = env::current_exe()?;
+    (0..count).map(|_| {
+        let mut command = Command::new(&program);
+        command.args(["--worker", "--threads", &threads.to_string()]);
+        Connection::spawn(command)
+    }).collect()
+}
+
+fn counters_to_json(counters: &Counters) -> Value {
+    let mut json = Map::new();
+    json.insert("rays".to_string(), Value::from(counters.rays.to_vec()));
+    json.insert("intersection_tests".to_string(), Value::from(counters.intersection_tests));
+    json.insert("intersection_hits".to_string(), Value::from(counters.intersection_hits));
+    json.insert("bvh_nodes".to_string(), Value::from(counters.bvh_nodes));
+    json.insert("samples".to_string(), Value::from(counters.samples));
+    json.insert("depth_sum".to_string(), Value::from(counters.depth_sum));
+    Value::Object(json)
+}
+
+fn counters_from_json(json: &Value) -> io::Result<Counters> {
+    let rays = json["rays"].as_array()
+        .map(|rays| rays.iter().filter_map(Value::as_u64).collect::<Vec<_>>())
+        .filter(|rays| rays.len() == 4)
+        .ok_or_else(|| invalid_data("the bucket's rays should be four numbers"))?;
+    let mut counters = Counters::default();
+    counters.rays.copy_from_slice(&rays);
+    counters.intersection_tests = number(json, "intersection_tests")?;
+    counters.intersection_hits = number(json, "intersection_hits")?;
+    counters.bvh_nodes = number(json, "bvh_nodes")?;
+    counters.samples = number(json, "samples")?;
+    counters.depth_sum = number(json, "depth_sum")?;
+    Ok(counters)
+}
+
+fn number(json: &Value, name: &str) -> io::Result<u64> {
+    json[name].as_u64().ok_or_else(|| invalid_data(format!("the message's '{}' should be a whole number", name)))
+}
+
+fn small(json: &Value, name: &str) -> io::Result<u32> {
+    u32::try_from(number(json, name)?).map_err(|_| invalid_data(format!("the message's '{}' is too big", name)))
+}
+
+fn text<'a>(json: &'a Value, name: &str) -> io::Result<&'a str> {
+    json[name].as_str().ok_or_else(|| invalid_data(format!("the message's '{}' should be a string", name)))
+}
+
+fn invalid_data<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
@@ -0,0 +1,167 @@
+use cgmath::Vector3;
+
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use config::Verbosity;
+use render::{CropWindow, IntegratorType, RenderSettings, Renderer};
+use scene::{Material, MaterialParameter, Scene};
+use scene::builder::{Element, SceneBuilder};
+use scene::objects::{Light, LightType};
+
+use super::*;
+
+/// A reflective sphere on a floor, lit from above
+fn scene() -> Scene {
+    let mut shiny = Material::new();
+    shiny.diffuse = MaterialParameter::new(Vector3::new(0.6, 0.3, 0.3));
+    shiny.reflective = MaterialParameter::new(Vector3::new(0.3, 0.3, 0.3));
+    SceneBuilder::new()
+        .add_light(Light::new(LightType::PointLight { pos: Vector3::new(0.0, 4.0, 0.0), a: 1.0, b: 0.0, c: 0.0 }, Vector3::new(1.0, 1.0, 1.0)))
+        .add(Element::sphere().material(shiny).translate(Vector3::new(0.0, 0.0, -4.0)))
+        .add(Element::square().rotate(Vector3::unit_x(), -90.0).scale(Vector3::new(10.0, 1.0, 10.0)).translate(Vector3::new(0.0, -1.0, -4.0)))
+        .build()
+}
+
+fn settings() -> RenderSettings {
+    RenderSettings {
+        width: 24,
+        height: 18,
+        samples: 3,
+        max_depth: 3,
+        threads: 2,
+        seed: 7,
+        integrator: IntegratorType::Path,
+        background: Vector3::new(0.1, 0.2, 0.3),
+        crop: None,
+    }
+}
+
+fn coordinator(settings: RenderSettings) -> Coordinator {
+    Coordinator { job: Job { scene: "scene.ray".to_string(), settings }, bucket_size: 8, verbosity: Verbosity::Quiet }
+}
+
+/// The coordinator's end of a connection, and the worker's
+fn connect() -> (Connection, Connection) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let worker = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (coordinator, _) = listener.accept().unwrap();
+    (Connection::tcp(coordinator).unwrap(), Connection::tcp(worker).unwrap())
+}
+
+/// A worker that takes its first bucket and then stops, as if it crashed
+fn crash(mut connection: Connection) {
+    assert!(matches!(connection.receive().unwrap(), Message::Job(_)));
+    connection.send(&Message::Ready).unwrap();
+    assert!(matches!(connection.receive().unwrap(), Message::Bucket(_)));
+}
+
+#[test]
+fn buckets_test() {
+    let mut settings = settings();
+    let buckets = buckets(&settings, 10);
+    assert_eq!(buckets.len(), 3 * 2);
+    assert_eq!(buckets[1], Bucket { id: 1, columns: 10..20, rows: 0..10 });
+    assert_eq!(buckets[5], Bucket { id: 5, columns: 20..24, rows: 10..18 });
+
+    // only the crop window is shared out, and every pixel of it once
+    settings.crop = Some(CropWindow::from_pixels(5, 3, 21, 12, 24, 18));
+    let mut covered = vec![0; 24 * 18];
+    for bucket in super::buckets(&settings, 7) {
+        for y in bucket.rows.clone() {
+            for x in bucket.columns.clone() {
+                covered[(y * 24 + x) as usize] += 1;
+            }
+        }
+    }
+    let inside = |index: usize| (5..21).contains(&(index % 24)) && (3..12).contains(&(index / 24));
+    assert!(covered.iter().enumerate().all(|(index, &count)| count == if inside(index) { 1 } else { 0 }));
+}
+
+#[test]
+fn message_test() {
+    let mut counters = Counters::default();
+    counters.rays = [4, 3, 2, 1];
+    counters.intersection_hits = 9;
+    let messages = [
+        Message::Job(Job { scene: "/scenes/scene.ray".to_string(), settings: RenderSettings { threads: 1, ..settings() } }),
+        Message::Ready,
+        Message::Error("couldn't load it".to_string()),
+        Message::Bucket(Bucket { id: 3, columns: 8..16, rows: 0..5 }),
+        Message::Rendered { id: 3, pixels: vec![0.1, f32::INFINITY, -2.5e-8], counters },
+        Message::Done,
+    ];
+    for message in &messages {
+        let line = message.to_json().to_string();
+        let mut read = Message::from_json(&serde_json::from_str(&line).unwrap()).unwrap();
+        // workers render with threads of their own
+        if let Message::Job(ref mut job) = read {
+            job.settings.threads = 1;
+        }
+        assert_eq!(&read, message);
+    }
+
+    let mut job = messages[0].to_json();
+    job["version"] = Value::from(PROTOCOL_VERSION + 1);
+    assert_eq!(Message::from_json(&job).unwrap_err().to_string(), "the coordinator speaks version 2 of the protocol, not 1");
+    job["type"] = Value::from("render");
+    assert_eq!(Message::from_json(&job).unwrap_err().to_string(), "unknown message 'render'");
+}
+
+#[test]
+fn distributed_render_test() {
+    let mut settings = settings();
+    settings.crop = Some(CropWindow::from_pixels(2, 1, 23, 17, 24, 18));
+    let expected = Renderer::new(&scene(), settings.clone()).render();
+
+    // workers both started by the coordinator and connecting to it
+    let (coordinator_end, worker_end) = connect();
+    let started = thread::spawn(move || serve(worker_end, 1, |_| Ok(scene())).unwrap());
+    // connected before the render starts, so it isn't left waiting to be
+    // accepted once the other worker has rendered every bucket
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let connecting = thread::spawn(move || {
+        serve(Connection::tcp(stream).unwrap(), 2, |scene_path| {
+            assert_eq!(scene_path, "scene.ray");
+            Ok(scene())
+        }).unwrap()
+    });
+
+    let (image, report) = coordinator(settings).render(vec![coordinator_end], Some(listener)).unwrap();
+    let rendered = started.join().unwrap() + connecting.join().unwrap();
+    assert_eq!(image.into_raw(), expected.into_raw());
+    assert_eq!((report.buckets, rendered, report.requeued), (6, 6, 0));
+    assert_eq!(report.counters.samples, 21 * 16 * 3);
+}
+
+#[test]
+fn crashed_worker_test() {
+    let expected = Renderer::new(&scene(), settings()).render();
+
+    let (crashing, crashing_worker) = connect();
+    let (working, worker) = connect();
+    let crashed = thread::spawn(move || crash(crashing_worker));
+    let served = thread::spawn(move || serve(worker, 1, |_| Ok(scene())).unwrap());
+
+    // the crashed worker's bucket goes to the other one
+    let (image, report) = coordinator(settings()).render(vec![crashing, working], None).unwrap();
+    crashed.join().unwrap();
+    assert_eq!(served.join().unwrap(), 9);
+    assert_eq!(image.into_raw(), expected.into_raw());
+    assert_eq!((report.workers, report.requeued), (1, 1));
+    assert_eq!(report.to_string(), "9 buckets rendered by 1 workers, 1 of them again after a worker stopped");
+}
+
+#[test]
+fn workers_stopped_test() {
+    let (crashing, crashing_worker) = connect();
+    let (failing, failing_worker) = connect();
+    let crashed = thread::spawn(move || crash(crashing_worker));
+    let failed = thread::spawn(move || serve(failing_worker, 1, |_| Err("couldn't load 'scene.ray'".to_string())));
+
+    let error = coordinator(settings()).render(vec![crashing, failing], None).unwrap_err();
+    crashed.join().unwrap();
+    assert_eq!(failed.join().unwrap().unwrap_err(), "couldn't load 'scene.ray'");
+    assert!(error.starts_with("every worker stopped with 9 of 9 buckets still to render, the last because "), "{}", error);
+}
@@ -0,0 +1,50 @@
+use std::io;
+
+use super::super::render::{RenderSettings, Renderer};
+use super::super::scene::Scene;
+use super::{Connection, Message};
+
+/// Renders buckets for the coordinator at the other end of `connection`
+/// with `threads` threads, until it says it's done. `load` reads the scene
+/// the job names, and why it couldn't is passed on to the coordinator.
+/// Gives the number of buckets rendered.
+pub fn serve<F>(mut connection: Connection, threads: usize, load: F) -> Result<usize, String>
+    where F: FnOnce(&str) -> Result<Scene, String>
+{
+    let failed = |err: io::Error| format!("lost the coordinator: {}", err);
+    let job = match connection.receive() {
+        Ok(Message::Job(job)) => job,
+        Ok(message) => return Err(format!("expected a job from the coordinator, got a {} message", message.name())),
+        Err(err) => {
+            // the coordinator may not understand the job either
+            let _ = connection.send(&Message::Error(err.to_string()));
+            return Err(format!("couldn't take the job: {}", err));
+        },
+    };
+
+    let scene = match load(&job.scene) {
+        Ok(scene) => scene,
+        Err(err) => {
+            let _ = connection.send(&Message::Error(err.clone()));
+            return Err(err);
+        },
+    };
+    connection.send(&Message::Ready).map_err(failed)?;
+
+    let settings = RenderSettings { threads, ..job.settings };
+    let mut rendered = 0;
+    loop {
+        match connection.receive().map_err(failed)? {
+            Message::Bucket(bucket) => {
+                // a renderer per bucket counts just the bucket's work
+                let renderer = Renderer::new(&scene, settings.clone());
+                let image = renderer.render_bucket(bucket.columns, bucket.rows);
+                connection.send(&Message::Rendered { id: bucket.id, pixels: image.into_raw(), counters: renderer.counters() })
+                    .map_err(failed)?;
+                rendered += 1;
+            },
+            Message::Done => return Ok(rendered),
+            message => return Err(format!("expected a bucket from the coordinator, got a {} message", message.name())),
+        }
+    }
+}
@@ -6,44 +6,381 @@ extern crate cgmath;
 #[macro_use]
 extern crate lazy_static;
 extern crate regex;
+extern crate serde_json;
+extern crate base64;
 
 pub mod config;
+pub mod distributed;
+pub mod output;
 pub mod parser;
+pub mod render;
 pub mod scene;
+pub mod stats;
 
-// external crates
-use image::{ImageBuffer, Rgb};
+#[cfg(test)]
+mod tests;
 
 // standard lib
 use std::error::Error;
 use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::f64;
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 // internal
-use config::Config;
-//use parser::Parser;
+use config::{Config, Distribution, Frames, OutputFormat, Verbosity};
+use distributed::{Connection, Coordinator, Job};
+use output::{CropOutput, OutputSettings};
+use parser::ParserRegistry;
+use render::{Film, Framebuffer, RenderReport, RenderSettings, Renderer, StopReason};
+use scene::Scene;
+use stats::{Stats, StatsFormat};
 
 /// Given a Configuration, attempts to generate a ray traced image
 ///
 /// # arguements
 ///
 /// * `config` - a configuration for the ray tracer
-pub fn run(config: Config) ->Result<(), Box<Error>> {
+pub fn run(config: Config) ->Result<(), Box<dyn Error>> {
+    if let Distribution::Worker { ref connect } = config.distribution {
+        return work(&config, connect.as_ref());
+    }
+    if let Some(frames) = config.frames {
+        return animate(&config, frames);
+    }
+    let start = Instant::now();
 
-    // read the input filename and parse for the given scene
-    let mut f = File::open(config.ray_filename)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
+    // read the input file and parse it for the given scene, in whichever
+    // format it's written in
+    let scene = ParserRegistry::default().parse_file(Path::new(&config.ray_filename))?;
+    // the scene's hierarchies are built as it's read
+    let mut counters = stats::take();
+    let parse_time = start.elapsed().checked_sub(counters.bvh_build).unwrap_or_default();
+    if config.verbosity >= Verbosity::Verbose {
+        eprintln!("Parsed '{}' in {:.2}s: {} objects, {} lights",
+            config.ray_filename, start.elapsed().as_secs_f64(), scene.object_count(), scene.lights().len());
+    }
 
-    // TODO: parsing and scene setup
+    let (report, render_time) = render_image(&config, &scene, Path::new(&config.output_filename), &mut counters)?;
 
-    let (width, height) = config.output_dimensions;
-    let image_buf: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
+    // the finished render doesn't need carrying on, one its budget stopped
+    // keeps its checkpoint
+    if let (Some(checkpoint), StopReason::Samples) = (config.checkpoint.as_ref(), report.stopped) {
+        if Path::new(checkpoint).exists() {
+            fs::remove_file(checkpoint).map_err(|err| format!("couldn't remove '{}': {}", checkpoint, err))?;
+        }
+    }
 
-    // TODO: divy up parts of the image for ray tracing
+    if let Some(format) = config.stats {
+        let stats = Stats {
+            counters,
+            parse_time,
+            bvh_time: counters.bvh_build,
+            render_time,
+            geometry_bytes: scene.geometry_memory(),
+            report,
+        };
+        print_stats(format, &stats);
+    }
 
-    // TODO: more safe error handling
-    image_buf.save(config.output_filename).unwrap();
+    Ok(())
+}
+
+/// Renders the frames of the scene's animation into the output directory.
+/// The scene is only built again for a frame when the animation moves its
+/// objects, otherwise the objects and their hierarchy are kept and just the
+/// camera and lights change.
+fn animate(config: &Config, frames: Frames) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let animated = ParserRegistry::default().parse_animated_file(Path::new(&config.ray_filename))?
+        .ok_or_else(|| format!("'{}' isn't animated, there are no keys to render frames of", config.ray_filename))?;
+    let frames = match frames {
+        Frames::All => animated.animation().frame_range(),
+        Frames::Range { first, last } => first..=last,
+    };
+    if config.verbosity >= Verbosity::Verbose {
+        eprintln!("Parsed '{}' in {:.2}s: frames {} to {}{}", config.ray_filename, start.elapsed().as_secs_f64(), frames.start(), frames.end(),
+            if animated.animation().moves_objects() { ", which move objects" } else { "" });
+    }
+    fs::create_dir_all(&config.output_filename).map_err(|err| format!("couldn't create '{}': {}", config.output_filename, err))?;
+
+    let mut counters = stats::take();
+    let mut build_time = start.elapsed();
+    let mut render_time = Duration::default();
+    let mut last = None;
+    for frame in frames.clone() {
+        let build_start = Instant::now();
+        let scene = match last.take() {
+            Some((scene, _)) => animated.advance(scene, f64::from(frame)),
+            None => animated.scene_at(f64::from(frame)),
+        };
+        counters.add(&stats::take());
+        build_time += build_start.elapsed();
+
+        let (report, time) = render_image(config, &scene, &config.frame_path(frame), &mut counters)?;
+        render_time += time;
+        last = Some((scene, report));
+    }
+    if config.verbosity >= Verbosity::Normal {
+        eprintln!("Rendered frames {} to {} into '{}' in {:.2}s", frames.start(), frames.end(), config.output_filename, start.elapsed().as_secs_f64());
+    }
+
+    if let (Some(format), Some((scene, report))) = (config.stats, last) {
+        // the work of every frame, and how the last went
+        let stats = Stats {
+            counters,
+            parse_time: build_time.checked_sub(counters.bvh_build).unwrap_or_default(),
+            bvh_time: counters.bvh_build,
+            render_time,
+            geometry_bytes: scene.geometry_memory(),
+            report,
+        };
+        print_stats(format, &stats);
+    }
+    Ok(())
+}
+
+/// Renders the scene and writes it, and the passes asked for, to
+/// `output_path`. Gives how the render went and the time it took, and adds
+/// the work it took to `counters`.
+fn render_image(config: &Config, scene: &Scene, output_path: &Path, counters: &mut stats::Counters)
+    -> Result<(RenderReport, Duration), Box<dyn Error>>
+{
+    let settings = config.render_settings(scene)?;
+    if config.verbosity >= Verbosity::Verbose {
+        eprintln!("Rendering {}x{} with the {} integrator, {} samples per pixel, max depth {}, {} threads",
+            settings.width, settings.height, settings.integrator.name(), settings.samples, settings.max_depth, settings.threads);
+    }
+
+    let render_start = Instant::now();
+    let renderer = Renderer::new(scene, settings);
+    let (mut image_buf, report) = match config.distribution {
+        Distribution::Coordinator { workers, ref listen, bucket_size } => {
+            let (image, report, worker_counters) = coordinate(config, renderer.settings(), workers, listen.as_ref(), bucket_size)?;
+            counters.add(&worker_counters);
+            (image, report)
+        },
+        _ => render_film(config, &renderer)?,
+    };
+
+    // the denoiser's guides are rendered with the passes asked for
+    let mut passes = config.aovs.clone();
+    if config.denoise.is_some() {
+        passes.extend(render::DenoiseSettings::GUIDES.iter().filter(|guide| !config.aovs.contains(guide)));
+    }
+    let mut aovs = renderer.render_aovs(&passes);
+    let render_time = render_start.elapsed();
+    if config.verbosity >= Verbosity::Normal {
+        eprintln!("Rendered '{}' in {:.2}s: {}", output_path.display(), render_time.as_secs_f64(), report);
+    }
+
+    if let Some(ref denoise) = config.denoise {
+        let denoise_start = Instant::now();
+        image_buf = render::denoise(&image_buf, &aovs, denoise);
+        aovs.retain(|buffer| config.aovs.contains(&buffer.aov));
+        if config.verbosity >= Verbosity::Verbose {
+            eprintln!("Denoised in {:.2}s", denoise_start.elapsed().as_secs_f64());
+        }
+    }
+
+    let output_settings = config.output_settings();
+    let (columns, rows) = renderer.settings().pixels();
+    if config.crop_output == CropOutput::Cropped {
+        image_buf = output::crop(&image_buf, columns.clone(), rows.clone());
+        aovs = aovs.iter().map(|buffer| buffer.crop(columns.clone(), rows.clone())).collect();
+    }
+    let composite = Composite { columns, rows, settings: &output_settings };
+    let composite = if config.crop_output == CropOutput::Composite { Some(&composite) } else { None };
+
+    if config.format == OutputFormat::Exr && !config.aov_files {
+        write_file(output_path, composite, |file| output::write_exr(file, &image_buf, &aovs, config.exr_pixel_type).map_err(From::from))?;
+    } else {
+        write_file(output_path, composite, |file| output::write_image(file, &image_buf, &output_settings))?;
+        for aov in &aovs {
+            let path = output::aov_path(output_path, aov.aov);
+            write_file(&path, composite, |file| output::write_aov(file, aov, &output_settings))?;
+            if config.verbosity >= Verbosity::Verbose {
+                eprintln!("Wrote the {} pass to '{}'", aov.aov.name(), path.display());
+            }
+        }
+    }
+
+    counters.add(&renderer.counters());
+    Ok((report, render_time))
+}
 
+fn print_stats(format: StatsFormat, stats: &Stats) {
+    match format {
+        StatsFormat::Text => println!("{}", stats),
+        StatsFormat::Json => println!("{}", stats.to_json()),
+    }
+}
+
+/// Renders the film, saving checkpoints to carry on from as it goes and
+/// when the budget runs out
+fn render_film(config: &Config, renderer: &Renderer) -> Result<(Framebuffer, RenderReport), Box<dyn Error>> {
+    // checkpoints are only resumed on the scene they were saved from
+    let scene = match config.checkpoint {
+        Some(_) => scene_fingerprint(Path::new(&config.ray_filename))?,
+        None => 0,
+    };
+    let mut film = match config.checkpoint {
+        Some(ref checkpoint) if config.resume => {
+            let film = read_checkpoint(Path::new(checkpoint), renderer.settings(), scene)?;
+            if config.verbosity >= Verbosity::Normal {
+                eprintln!("Resuming '{}' at {} of {} samples per pixel", checkpoint, film.samples(), renderer.settings().samples);
+            }
+            film
+        },
+        _ => Film::new(renderer.settings()),
+    };
+
+    let mut last_checkpoint = Instant::now();
+    let mut checkpoint_error = None;
+    let report = renderer.render_with_budget(&mut film, &config.budget, |film| {
+        let checkpoint = match config.checkpoint {
+            Some(ref checkpoint) => Path::new(checkpoint),
+            None => return true,
+        };
+        if film.samples() >= renderer.settings().samples || last_checkpoint.elapsed() < config.checkpoint_interval {
+            return true;
+        }
+
+        if let Err(err) = write_checkpoint(checkpoint, film, scene) {
+            checkpoint_error = Some(err);
+            return false;
+        }
+        last_checkpoint = Instant::now();
+        if config.verbosity >= Verbosity::Verbose {
+            eprintln!("Saved {} samples per pixel to '{}'", film.samples(), checkpoint.display());
+        }
+        true
+    });
+    if let Some(err) = checkpoint_error {
+        return Err(err.into());
+    }
+
+    // what a budget stopped short is saved to carry on from later
+    if let (Some(checkpoint), StopReason::Time | StopReason::Noise) = (config.checkpoint.as_ref(), report.stopped) {
+        write_checkpoint(Path::new(checkpoint), &film, scene)?;
+        if config.verbosity >= Verbosity::Verbose {
+            eprintln!("Saved {} samples per pixel to '{}'", film.samples(), checkpoint);
+        }
+    }
+    Ok((film.image(), report))
+}
+
+/// Shares the render out between the workers it starts and those
+/// connecting to `listen`, giving the image, how it went and what the
+/// workers counted
+fn coordinate(config: &Config, settings: &RenderSettings, workers: usize, listen: Option<&String>, bucket_size: u32)
+    -> Result<(Framebuffer, RenderReport, stats::Counters), Box<dyn Error>>
+{
+    let start = Instant::now();
+    // workers may be running somewhere else
+    let scene = fs::canonicalize(&config.ray_filename)
+        .map_err(|err| format!("couldn't find '{}': {}", config.ray_filename, err))?;
+    let listener = match listen {
+        Some(address) => {
+            let listener = TcpListener::bind(address).map_err(|err| format!("couldn't listen on '{}': {}", address, err))?;
+            if config.verbosity >= Verbosity::Normal {
+                eprintln!("Listening for workers on {}", listener.local_addr()?);
+            }
+            Some(listener)
+        },
+        None => None,
+    };
+    // the workers started here share the threads
+    let connections = distributed::spawn_workers(workers, (config.threads / workers.max(1)).max(1))
+        .map_err(|err| format!("couldn't start the workers: {}", err))?;
+
+    let coordinator = Coordinator {
+        job: Job { scene: scene.to_string_lossy().into_owned(), settings: settings.clone() },
+        bucket_size,
+        verbosity: config.verbosity,
+    };
+    let (image, report) = coordinator.render(connections, listener)?;
+    if config.verbosity >= Verbosity::Verbose {
+        eprintln!("Shared out '{}': {}", config.output_filename, report);
+    }
+
+    let (columns, rows) = settings.pixels();
+    let render_report = RenderReport {
+        samples: settings.samples,
+        pixels: columns.len() as u64 * rows.len() as u64,
+        passes: 1,
+        time: start.elapsed(),
+        noise: f64::INFINITY,
+        stopped: StopReason::Samples,
+    };
+    Ok((image, render_report, report.counters))
+}
+
+/// Renders buckets for the coordinator at `connect`, or the one that
+/// started this process
+fn work(config: &Config, connect: Option<&String>) -> Result<(), Box<dyn Error>> {
+    let connection = match connect {
+        Some(address) => {
+            let stream = TcpStream::connect(address).map_err(|err| format!("couldn't connect to '{}': {}", address, err))?;
+            Connection::tcp(stream)?
+        },
+        None => Connection::stdio(),
+    };
+    let rendered = distributed::serve(connection, config.threads, |scene| {
+        ParserRegistry::default().parse_file(Path::new(scene)).map_err(|err| format!("couldn't load '{}': {}", scene, err))
+    })?;
+    if config.verbosity >= Verbosity::Verbose {
+        eprintln!("Rendered {} buckets", rendered);
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// FNV-1a hash of the scene file, which checkpoints are saved with
+fn scene_fingerprint(path: &Path) -> Result<u64, String> {
+    let bytes = fs::read(path).map_err(|err| format!("couldn't read '{}': {}", path.display(), err))?;
+    Ok(bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)))
+}
+
+fn read_checkpoint(path: &Path, settings: &RenderSettings, scene: u64) -> Result<Film, String> {
+    let file = File::open(path).map_err(|err| format!("couldn't open '{}': {}", path.display(), err))?;
+    Film::read_checkpoint(&mut BufReader::new(file), settings, scene)
+        .map_err(|err| format!("couldn't resume from '{}': {}", path.display(), err))
+}
+
+/// Saves the film next to `path` first, so a render stopped while saving
+/// still has the checkpoint before
+fn write_checkpoint(path: &Path, film: &Film, scene: u64) -> Result<(), String> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = Path::new(&partial);
+    let file = File::create(partial).map_err(|err| format!("couldn't create '{}': {}", partial.display(), err))?;
+    film.write_checkpoint(&mut BufWriter::new(file), scene)
+        .and_then(|_| fs::rename(partial, path))
+        .map_err(|err| format!("couldn't save the checkpoint '{}': {}", path.display(), err))
+}
+
+/// Pixels of a cropped render to paste into the images already written
+struct Composite<'a> {
+    columns: Range<u32>,
+    rows: Range<u32>,
+    settings: &'a OutputSettings,
+}
+
+/// Writes the file at `path` with `write`. When compositing into a file
+/// that's already there, only the crop window's pixels are replaced.
+fn write_file<F>(path: &Path, composite: Option<&Composite>, write: F) -> Result<(), String>
+    where F: FnOnce(&mut Vec<u8>) -> image::ImageResult<()>
+{
+    let mut image = Vec::new();
+    write(&mut image).map_err(|err| format!("couldn't write '{}': {}", path.display(), err))?;
+    if let Some(composite) = composite.filter(|_| path.exists()) {
+        let existing = fs::read(path).map_err(|err| format!("couldn't read '{}' to composite into: {}", path.display(), err))?;
+        image = output::composite(&existing, &image, composite.columns.clone(), composite.rows.clone(), composite.settings)
+            .map_err(|err| format!("couldn't composite into '{}': {}", path.display(), err))?;
+    }
+    fs::write(path, image).map_err(|err| format!("couldn't write '{}': {}", path.display(), err))
+}
@@ -3,18 +3,27 @@ extern crate ray_rs;
 use std::env;
 use std::process;
 
-use ray_rs::config;
+use ray_rs::config::{self, ConfigError};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let config = config::Config::new(&args).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        process::exit(1);
+        match err {
+            ConfigError::Help => {
+                print!("{}", config::USAGE);
+                process::exit(0);
+            },
+            err => {
+                eprintln!("Problem parsing arguments: {}", err);
+                eprintln!("Run with --help to see the options");
+                process::exit(2);
+            },
+        }
     });
 
     if let Err(e) = ray_rs::run(config) {
-        println!("Application error: {}", e);
+        eprintln!("Application error: {}", e);
         process::exit(1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,349 @@
+//! A writer for uncompressed scanline OpenEXR images, enough for renders
+//! and their extra channels to be read by compositing tools, and a reader
+//! for the same kind of image, to composite renders into.
+
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// File format version 2, as a single part scanline image
+const VERSION: [u8; 4] = [2, 0, 0, 0];
+
+/// How channel values are stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPixelType {
+    /// 16-bit floats, plenty for color and half the size
+    Half,
+    /// 32-bit floats, for data such as depth that needs the precision
+    Float,
+}
+
+impl ExrPixelType {
+    pub fn from_name(name: &str) -> Option<ExrPixelType> {
+        match name {
+            "half" => Some(ExrPixelType::Half),
+            "float" => Some(ExrPixelType::Float),
+            _ => None,
+        }
+    }
+
+    /// The type's number in the channel list
+    fn id(&self) -> i32 {
+        match *self {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float => 4,
+        }
+    }
+
+    fn from_id(id: i32) -> Option<ExrPixelType> {
+        match id {
+            1 => Some(ExrPixelType::Half),
+            2 => Some(ExrPixelType::Float),
+            _ => None,
+        }
+    }
+}
+
+/// One channel of an image, its values row by row from the top left. Names
+/// like `albedo.R` put the channel in a layer.
+pub struct ExrChannel<'a> {
+    pub name: &'a str,
+    pub values: &'a [f32],
+}
+
+pub struct ExrEncoder<W: Write> {
+    writer: W,
+    pixel_type: ExrPixelType,
+}
+
+impl<W: Write> ExrEncoder<W> {
+    pub fn new(writer: W, pixel_type: ExrPixelType) -> ExrEncoder<W> {
+        ExrEncoder { writer, pixel_type }
+    }
+
+    /// Writes the channels of a `width` by `height` image, each of which
+    /// must have a value for every pixel
+    pub fn encode(mut self, channels: &[ExrChannel], width: usize, height: usize) -> io::Result<()> {
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "an EXR image can't be empty"));
+        }
+        if let Some(channel) = channels.iter().find(|channel| channel.values.len() != width * height) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("channel '{}' has {} values for {} pixels", channel.name, channel.values.len(), width * height)));
+        }
+
+        // readers expect the channels in alphabetical order, in the list and in the pixels
+        let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+        channels.sort_by(|a, b| a.name.cmp(b.name));
+
+        let header = self.header(&channels, width, height);
+        let line_size = width * channels.len() * self.pixel_type.size();
+        let first_line = MAGIC.len() + VERSION.len() + header.len() + height * 8;
+
+        let mut output = Vec::with_capacity(first_line + height * (8 + line_size));
+        output.extend_from_slice(&MAGIC);
+        output.extend_from_slice(&VERSION);
+        output.extend_from_slice(&header);
+        // every line is a chunk of its own, starting with its row and size
+        for y in 0..height {
+            output.extend_from_slice(&((first_line + y * (8 + line_size)) as u64).to_le_bytes());
+        }
+        for y in 0..height {
+            output.extend_from_slice(&(y as i32).to_le_bytes());
+            output.extend_from_slice(&(line_size as i32).to_le_bytes());
+            for channel in &channels {
+                for &value in &channel.values[y * width..(y + 1) * width] {
+                    match self.pixel_type {
+                        ExrPixelType::Half => output.extend_from_slice(&to_half(value).to_le_bytes()),
+                        ExrPixelType::Float => output.extend_from_slice(&value.to_le_bytes()),
+                    }
+                }
+            }
+        }
+
+        self.writer.write_all(&output)?;
+        self.writer.flush()
+    }
+
+    fn header(&self, channels: &[&ExrChannel], width: usize, height: usize) -> Vec<u8> {
+        let mut list = Vec::new();
+        for channel in channels {
+            list.extend_from_slice(channel.name.as_bytes());
+            list.push(0);
+            list.extend_from_slice(&self.pixel_type.id().to_le_bytes());
+            // perceptually linear flag and reserved bytes, then x and y sampling
+            list.extend_from_slice(&[0, 0, 0, 0]);
+            list.extend_from_slice(&1i32.to_le_bytes());
+            list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        list.push(0);
+
+        let mut window = Vec::new();
+        for &bound in &[0, 0, width as i32 - 1, height as i32 - 1] {
+            window.extend_from_slice(&bound.to_le_bytes());
+        }
+
+        let mut header = Vec::new();
+        attribute(&mut header, "channels", "chlist", &list);
+        // no compression
+        attribute(&mut header, "compression", "compression", &[0]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        // increasing y
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        header.push(0);
+        header
+    }
+}
+
+/// An image read by `ExrDecoder`, with every channel's values row by row
+/// from the top left of its data window
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExrImage {
+    pub width: usize,
+    pub height: usize,
+    pub channels: Vec<(String, Vec<f32>)>,
+}
+
+impl ExrImage {
+    pub fn channel(&self, name: &str) -> Option<&[f32]> {
+        self.channels.iter().find(|&(channel, _)| channel == name).map(|(_, values)| &values[..])
+    }
+}
+
+/// Reads single part, uncompressed scanline images with half and float
+/// channels, such as those `ExrEncoder` writes
+pub struct ExrDecoder<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> ExrDecoder<R> {
+    pub fn new(reader: R) -> ExrDecoder<R> {
+        ExrDecoder { reader }
+    }
+
+    pub fn decode(mut self) -> io::Result<ExrImage> {
+        let mut bytes = Vec::new();
+        self.reader.read_to_end(&mut bytes)?;
+        let mut input = ExrReader { bytes: &bytes, at: 0 };
+
+        if input.take(4)? != MAGIC {
+            return Err(invalid_data("not an EXR image"));
+        }
+        let version = input.take(4)?;
+        if version[0] != 2 || version[1] & 0x1e != 0 {
+            return Err(invalid_data("only single part scanline EXR images can be read"));
+        }
+
+        let mut channels = Vec::new();
+        let mut window = None;
+        loop {
+            let name = input.name()?;
+            if name.is_empty() {
+                break;
+            }
+            let _type_name = input.name()?;
+            let size = input.i32()? as usize;
+            let mut value = ExrReader { bytes: input.take(size)?, at: 0 };
+            match name.as_str() {
+                "channels" => loop {
+                    let channel = value.name()?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    let pixel_type = ExrPixelType::from_id(value.i32()?)
+                        .ok_or_else(|| invalid_data(format!("channel '{}' isn't half or float", channel)))?;
+                    value.take(4)?;
+                    if (value.i32()?, value.i32()?) != (1, 1) {
+                        return Err(invalid_data(format!("channel '{}' is subsampled", channel)));
+                    }
+                    channels.push((channel, pixel_type));
+                },
+                "compression" if value.take(1)?[0] != 0 => return Err(invalid_data("only uncompressed EXR images can be read")),
+                "dataWindow" => window = Some([value.i32()?, value.i32()?, value.i32()?, value.i32()?]),
+                _ => {},
+            }
+        }
+
+        let window = window.ok_or_else(|| invalid_data("the EXR image has no data window"))?;
+        let (width, height) = (window[2] - window[0] + 1, window[3] - window[1] + 1);
+        if width <= 0 || height <= 0 {
+            return Err(invalid_data("the EXR image is empty"));
+        }
+        let (width, height) = (width as usize, height as usize);
+        let line_size: usize = channels.iter().map(|&(_, pixel_type)| pixel_type.size() * width).sum();
+
+        let offsets = (0..height).map(|_| input.u64()).collect::<io::Result<Vec<_>>>()?;
+        let mut values = vec![vec![0.0; width * height]; channels.len()];
+        for offset in offsets {
+            let mut line = ExrReader { bytes: &bytes, at: offset as usize };
+            let y = line.i32()? - window[1];
+            if y < 0 || y as usize >= height || line.i32()? as usize != line_size {
+                return Err(invalid_data("an EXR scanline is out of place"));
+            }
+            let start = y as usize * width;
+            for (&(_, pixel_type), values) in channels.iter().zip(&mut values) {
+                for value in &mut values[start..start + width] {
+                    *value = match pixel_type {
+                        ExrPixelType::Half => from_half(line.u16()?),
+                        ExrPixelType::Float => f32::from_bits(line.u32()?),
+                    };
+                }
+            }
+        }
+
+        let channels = channels.into_iter().map(|(name, _)| name).zip(values).collect();
+        Ok(ExrImage { width, height, channels })
+    }
+}
+
+struct ExrReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ExrReader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.at > self.bytes.len() || self.bytes.len() - self.at < count {
+            return Err(invalid_data("the EXR image ends too soon"));
+        }
+        self.at += count;
+        Ok(&self.bytes[self.at - count..self.at])
+    }
+
+    /// A string ending in a zero byte
+    fn name(&mut self) -> io::Result<String> {
+        let length = self.bytes.get(self.at..).and_then(|rest| rest.iter().position(|&byte| byte == 0))
+            .ok_or_else(|| invalid_data("the EXR image ends too soon"))?;
+        let name = String::from_utf8_lossy(self.take(length)?).into_owned();
+        self.take(1)?;
+        Ok(name)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+fn invalid_data<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(type_name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// The bits of the nearest 16-bit float, going to infinity past its range
+pub fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinite and NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // too small for the exponent, so it loses the implicit leading bit
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    // rounding may carry into the exponent, which is still the nearest value
+    let round = (mantissa >> 12) & 1;
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + round) as u16
+}
+
+/// The value of a 16-bit float's bits
+pub fn from_half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,290 @@
+//! Writes rendered images out. Renders stay in linear floating point until
+//! they're written, and only formats that can't hold more are tone mapped
+//! down to 8 bits. Extra passes go in EXR layers alongside the image, or in
+//! files of their own. Renders of part of the image can be written on their
+//! own, or pasted into an image written before.
+
+mod exr;
+mod tonemap;
+#[cfg(test)]
+mod tests;
+
+pub use self::exr::{ExrChannel, ExrDecoder, ExrEncoder, ExrImage, ExrPixelType};
+pub use self::tonemap::{ToneMapOperator, ToneMapping, TransferFunction};
+
+use cgmath::Vector3;
+
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult, Rgb, RgbImage};
+use image::hdr::{HDRDecoder, HDREncoder};
+
+use std::io::{self, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::render::{Aov, AovBuffer, Framebuffer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Ppm,
+    Bmp,
+    /// OpenEXR, with the full range of the render
+    Exr,
+    /// Radiance RGBE, with the full range of the render
+    Hdr,
+}
+
+impl OutputFormat {
+    /// The format with the given name or file extension
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+            "ppm" => Some(OutputFormat::Ppm),
+            "bmp" => Some(OutputFormat::Bmp),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    /// The usual extension of the format's files
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Ppm => "ppm",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Exr => "exr",
+            OutputFormat::Hdr => "hdr",
+        }
+    }
+
+    /// Whether the format keeps values outside of 0 to 1
+    pub fn is_hdr(&self) -> bool {
+        matches!(*self, OutputFormat::Exr | OutputFormat::Hdr)
+    }
+}
+
+/// What's written when only part of the image is rendered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropOutput {
+    /// The whole image, black outside the crop window
+    Full,
+    /// Just the crop window
+    Cropped,
+    /// The crop window pasted into the image already at the output, which
+    /// keeps the rest of it
+    Composite,
+}
+
+impl CropOutput {
+    pub fn from_name(name: &str) -> Option<CropOutput> {
+        match name {
+            "full" => Some(CropOutput::Full),
+            "cropped" => Some(CropOutput::Cropped),
+            "composite" => Some(CropOutput::Composite),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSettings {
+    pub format: OutputFormat,
+    /// How values are stored in EXR images
+    pub exr_pixel_type: ExrPixelType,
+    /// How 8-bit images are made, HDR images keep the linear light as rendered
+    pub tone_mapping: ToneMapping,
+}
+
+impl OutputSettings {
+    pub fn new(format: OutputFormat) -> OutputSettings {
+        OutputSettings { format, exr_pixel_type: ExrPixelType::Half, tone_mapping: ToneMapping::default() }
+    }
+}
+
+pub fn write_image<W: Write>(writer: &mut W, framebuffer: &Framebuffer, settings: &OutputSettings) -> ImageResult<()> {
+    let (width, height) = (framebuffer.width() as usize, framebuffer.height() as usize);
+    match settings.format {
+        OutputFormat::Exr => Ok(write_exr(writer, framebuffer, &[], settings.exr_pixel_type)?),
+        OutputFormat::Hdr => {
+            let pixels: Vec<Rgb<f32>> = framebuffer.pixels().cloned().collect();
+            Ok(HDREncoder::new(writer).encode(&pixels, width, height)?)
+        },
+        format => DynamicImage::ImageRgb8(quantize(framebuffer, &settings.tone_mapping)).save(writer, ldr_format(format)),
+    }
+}
+
+/// The image crate's name for one of the 8-bit formats
+fn ldr_format(format: OutputFormat) -> ImageFormat {
+    match format {
+        OutputFormat::Jpeg => ImageFormat::JPEG,
+        OutputFormat::Ppm => ImageFormat::PPM,
+        OutputFormat::Bmp => ImageFormat::BMP,
+        _ => ImageFormat::PNG,
+    }
+}
+
+/// Writes the render as an EXR image, with the passes in layers named
+/// after them, such as `depth.Z` and `normal.X`
+pub fn write_exr<W: Write>(writer: &mut W, framebuffer: &Framebuffer, aovs: &[AovBuffer], pixel_type: ExrPixelType) -> io::Result<()> {
+    let (red, green, blue) = split_channels(framebuffer);
+    let mut channels = vec![
+        ExrChannel { name: "R", values: &red },
+        ExrChannel { name: "G", values: &green },
+        ExrChannel { name: "B", values: &blue },
+    ];
+
+    let names: Vec<Vec<String>> = aovs.iter()
+        .map(|buffer| buffer.aov.channels().iter().map(|channel| format!("{}.{}", buffer.aov.name(), channel)).collect())
+        .collect();
+    for (buffer, names) in aovs.iter().zip(&names) {
+        for (name, values) in names.iter().zip(&buffer.channels) {
+            channels.push(ExrChannel { name, values });
+        }
+    }
+
+    ExrEncoder::new(writer, pixel_type).encode(&channels, framebuffer.width() as usize, framebuffer.height() as usize)
+}
+
+/// Writes a pass as an image of its own. EXR and Radiance images get the
+/// values as they are, and 8-bit images a picture of them: depth from near
+/// black to white at the farthest hit and beyond, normals from -1 to 1, and a
+/// color for each ID.
+pub fn write_aov<W: Write>(writer: &mut W, buffer: &AovBuffer, settings: &OutputSettings) -> ImageResult<()> {
+    // the values are data, not light, and get written as they are
+    let settings = OutputSettings {
+        tone_mapping: ToneMapping { transfer: TransferFunction::Gamma(1.0), ..ToneMapping::default() },
+        ..settings.clone()
+    };
+    write_image(writer, &aov_image(buffer, settings.format), &settings)
+}
+
+/// The file a pass is written to when it has one of its own, such as
+/// `render.depth.png` for `render.png`
+pub fn aov_path(output: &Path, aov: Aov) -> PathBuf {
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match output.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, aov.name(), extension.to_string_lossy()),
+        None => format!("{}.{}", stem, aov.name()),
+    };
+    output.with_file_name(name)
+}
+
+fn aov_image(buffer: &AovBuffer, format: OutputFormat) -> Framebuffer {
+    let farthest = match buffer.aov {
+        Aov::Depth => buffer.channels[0].iter().cloned().filter(|depth| depth.is_finite()).fold(0.0, f32::max),
+        _ => 0.0,
+    };
+
+    Framebuffer::from_fn(buffer.width, buffer.height, |x, y| {
+        let values = buffer.get(x, y);
+        let value = |channel: usize| values.get(channel).cloned().unwrap_or(0.0);
+        let pixel = match (buffer.aov, format.is_hdr()) {
+            // Radiance images can't hold infinity
+            (Aov::Depth, true) if format == OutputFormat::Hdr && value(0).is_infinite() => [0.0; 3],
+            (Aov::Depth, true) => [value(0); 3],
+            (Aov::Depth, false) if value(0).is_infinite() || farthest <= 0.0 => [1.0; 3],
+            (Aov::Depth, false) => [value(0) / farthest; 3],
+            (Aov::Normal, false) => [value(0) * 0.5 + 0.5, value(1) * 0.5 + 0.5, value(2) * 0.5 + 0.5],
+            (Aov::ObjectId, false) | (Aov::MaterialId, false) => id_color(value(0)),
+            (Aov::ObjectId, true) | (Aov::MaterialId, true) => [value(0); 3],
+            _ => [value(0), value(1), value(2)],
+        };
+        Rgb(pixel)
+    })
+}
+
+/// A color for telling IDs apart, black for 0
+fn id_color(id: f32) -> [f32; 3] {
+    if id <= 0.0 {
+        return [0.0; 3];
+    }
+    let hash = (id as u32).wrapping_mul(0x9e37_79b9);
+    let channel = |shift: u32| ((hash >> shift) & 0xff) as f32 / 255.0;
+    [channel(8), channel(16), channel(24)]
+}
+
+/// The render cut down to the given columns and rows
+pub fn crop(framebuffer: &Framebuffer, columns: Range<u32>, rows: Range<u32>) -> Framebuffer {
+    Framebuffer::from_fn(columns.len() as u32, rows.len() as u32, |x, y| *framebuffer.get_pixel(columns.start + x, rows.start + y))
+}
+
+/// Pastes the given columns and rows of `image` over `existing`, both written
+/// in the settings' format and the same size, giving the image to write in
+/// its place. Pixels outside them keep their values as written, and EXR
+/// channels only in `existing` are kept whole.
+pub fn composite(existing: &[u8], image: &[u8], columns: Range<u32>, rows: Range<u32>, settings: &OutputSettings) -> ImageResult<Vec<u8>> {
+    let check_size = |existing: (usize, usize), image: (usize, usize)| if existing == image {
+        Ok(())
+    } else {
+        Err(ImageError::FormatError(format!("the image is {}x{}, but the render is {}x{}", existing.0, existing.1, image.0, image.1)))
+    };
+    let pixels = rows.flat_map(|y| columns.clone().map(move |x| (x as usize, y as usize)));
+    let mut output = Vec::new();
+
+    match settings.format {
+        OutputFormat::Exr => {
+            let mut existing = ExrDecoder::new(existing).decode()?;
+            let image = ExrDecoder::new(image).decode()?;
+            check_size((existing.width, existing.height), (image.width, image.height))?;
+            for (name, values) in &image.channels {
+                if existing.channel(name).is_none() {
+                    existing.channels.push((name.clone(), vec![0.0; values.len()]));
+                }
+            }
+            for (name, values) in &mut existing.channels {
+                if let Some(pasted) = image.channel(name) {
+                    for (x, y) in pixels.clone() {
+                        values[y * image.width + x] = pasted[y * image.width + x];
+                    }
+                }
+            }
+            let channels: Vec<ExrChannel> = existing.channels.iter().map(|(name, values)| ExrChannel { name, values }).collect();
+            ExrEncoder::new(&mut output, settings.exr_pixel_type).encode(&channels, existing.width, existing.height)?;
+        },
+        OutputFormat::Hdr => {
+            let read = |bytes: &[u8]| -> ImageResult<(usize, usize, Vec<Rgb<f32>>)> {
+                let decoder = HDRDecoder::new(BufReader::new(bytes))?;
+                let (width, height) = (decoder.metadata().width as usize, decoder.metadata().height as usize);
+                Ok((width, height, decoder.read_image_hdr()?))
+            };
+            let (existing_width, existing_height, mut existing) = read(existing)?;
+            let (image_width, image_height, image) = read(image)?;
+            check_size((existing_width, existing_height), (image_width, image_height))?;
+            for (x, y) in pixels {
+                existing[y * image_width + x] = image[y * image_width + x];
+            }
+            HDREncoder::new(&mut output).encode(&existing, image_width, image_height)?;
+        },
+        format => {
+            let mut existing = image::load_from_memory(existing)?.to_rgb();
+            let image = image::load_from_memory(image)?.to_rgb();
+            check_size((existing.width() as usize, existing.height() as usize), (image.width() as usize, image.height() as usize))?;
+            for (x, y) in pixels {
+                existing.put_pixel(x as u32, y as u32, *image.get_pixel(x as u32, y as u32));
+            }
+            DynamicImage::ImageRgb8(existing).save(&mut output, ldr_format(format))?;
+        },
+    }
+
+    Ok(output)
+}
+
+/// The render tone mapped to 8 bits
+pub fn quantize(framebuffer: &Framebuffer, tone_mapping: &ToneMapping) -> RgbImage {
+    let channel = |value: f64| (value * 255.0).round() as u8;
+    RgbImage::from_fn(framebuffer.width(), framebuffer.height(), |x, y| {
+        let pixel = framebuffer.get_pixel(x, y).data;
+        let color = tone_mapping.map(Vector3::new(f64::from(pixel[0]), f64::from(pixel[1]), f64::from(pixel[2])));
+        Rgb([channel(color.x), channel(color.y), channel(color.z)])
+    })
+}
+
+/// The red, green and blue values of the render, each in a channel of its own
+fn split_channels(framebuffer: &Framebuffer) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let channel = |index: usize| framebuffer.pixels().map(|pixel| pixel.data[index]).collect();
+    (channel(0), channel(1), channel(2))
+}
@@ -0,0 +1,355 @@
+use cgmath::Vector3;
+use image::Rgb;
+use image::hdr::HDRDecoder;
+
+use std::f32;
+use std::io::BufReader;
+use std::path::Path;
+
+use render::{Aov, AovBuffer};
+
+use super::*;
+
+/// A gradient with values past what 8 bits can hold
+fn framebuffer() -> Framebuffer {
+    Framebuffer::from_fn(4, 3, |x, y| Rgb([x as f32 * 2.5, y as f32 * 0.25, -0.5]))
+}
+
+/// An EXR image written by the encoder, read back the way a reader would
+struct ExrImage {
+    /// Names and pixel type numbers
+    channels: Vec<(String, i32)>,
+    window: [i32; 4],
+    /// Every channel's values
+    values: Vec<Vec<f32>>,
+}
+
+fn read_exr(bytes: &[u8]) -> ExrImage {
+    assert_eq!(&bytes[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+    let read_i32 = |at: usize| i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let read_name = |at: usize| {
+        let end = at + bytes[at..].iter().position(|&byte| byte == 0).unwrap();
+        (String::from_utf8(bytes[at..end].to_vec()).unwrap(), end + 1)
+    };
+
+    let (mut channels, mut window) = (Vec::new(), [0; 4]);
+    let mut at = 8;
+    while bytes[at] != 0 {
+        let (name, type_at) = read_name(at);
+        let (_, size_at) = read_name(type_at);
+        let value_at = size_at + 4;
+        match name.as_str() {
+            "channels" => {
+                let mut channel_at = value_at;
+                while bytes[channel_at] != 0 {
+                    let (channel, next) = read_name(channel_at);
+                    channels.push((channel, read_i32(next)));
+                    channel_at = next + 16;
+                }
+            },
+            "dataWindow" => for (i, bound) in window.iter_mut().enumerate() {
+                *bound = read_i32(value_at + i * 4);
+            },
+            "compression" => assert_eq!(bytes[value_at], 0),
+            _ => {},
+        }
+        at = value_at + read_i32(size_at) as usize;
+    }
+
+    let (width, height) = ((window[2] + 1) as usize, (window[3] + 1) as usize);
+    let mut values = vec![Vec::new(); channels.len()];
+    for y in 0..height {
+        let offset_at = at + 1 + y * 8;
+        let mut offset = 0;
+        for i in (0..8).rev() {
+            offset = (offset << 8) | bytes[offset_at + i] as usize;
+        }
+        assert_eq!(read_i32(offset), y as i32);
+        let mut value_at = offset + 8;
+        for (index, &(_, pixel_type)) in channels.iter().enumerate() {
+            for _ in 0..width {
+                if pixel_type == 1 {
+                    values[index].push(exr::from_half(u16::from_le_bytes([bytes[value_at], bytes[value_at + 1]])));
+                    value_at += 2;
+                } else {
+                    values[index].push(f32::from_le_bytes([bytes[value_at], bytes[value_at + 1], bytes[value_at + 2], bytes[value_at + 3]]));
+                    value_at += 4;
+                }
+            }
+        }
+        assert_eq!(value_at - offset - 8, read_i32(offset + 4) as usize);
+    }
+    ExrImage { channels, window, values }
+}
+
+#[test]
+fn half_test() {
+    assert_eq!(exr::to_half(0.0), 0);
+    assert_eq!(exr::to_half(-0.0), 0x8000);
+    assert_eq!(exr::to_half(1.0), 0x3c00);
+    assert_eq!(exr::to_half(-2.0), 0xc000);
+    assert_eq!(exr::to_half(65504.0), 0x7bff);
+    assert_eq!(exr::to_half(1e6), 0x7c00);
+    assert_eq!(exr::to_half(f32::INFINITY), 0x7c00);
+    assert!(exr::from_half(exr::to_half(f32::NAN)).is_nan());
+    // the smallest values lose precision rather than going to zero
+    assert_eq!(exr::to_half(2f32.powi(-24)), 1);
+    assert_eq!(exr::to_half(2f32.powi(-20)), 16);
+
+    for &value in &[0.1, 0.333, 2.5, 1000.7, 6.1e-5, -42.42] {
+        let error = (exr::from_half(exr::to_half(value)) - value).abs();
+        assert!(error <= value.abs() / 2048.0, "{} came back as {}", value, exr::from_half(exr::to_half(value)));
+    }
+}
+
+#[test]
+fn exr_test() {
+    let image = framebuffer();
+    for &pixel_type in &[ExrPixelType::Half, ExrPixelType::Float] {
+        let mut bytes = Vec::new();
+        let settings = OutputSettings { exr_pixel_type: pixel_type, ..OutputSettings::new(OutputFormat::Exr) };
+        write_image(&mut bytes, &image, &settings).unwrap();
+
+        let ExrImage { channels, window, values } = read_exr(&bytes);
+        let type_id = if pixel_type == ExrPixelType::Half { 1 } else { 2 };
+        assert_eq!(channels, vec![("B".to_string(), type_id), ("G".to_string(), type_id), ("R".to_string(), type_id)]);
+        assert_eq!(window, [0, 0, 3, 2]);
+        for (index, pixel) in image.pixels().enumerate() {
+            assert_eq!([values[2][index], values[1][index], values[0][index]], pixel.data);
+        }
+    }
+}
+
+#[test]
+fn exr_channels_test() {
+    let depth = [1.0, 1e5, 0.125, 3.0];
+    let red = [0.0; 4];
+    let mut bytes = Vec::new();
+    let channels = [ExrChannel { name: "R", values: &red }, ExrChannel { name: "depth.Z", values: &depth }];
+    ExrEncoder::new(&mut bytes, ExrPixelType::Float).encode(&channels, 2, 2).unwrap();
+
+    let ExrImage { channels: names, window, values } = read_exr(&bytes);
+    assert_eq!(names, vec![("R".to_string(), 2), ("depth.Z".to_string(), 2)]);
+    assert_eq!(window, [0, 0, 1, 1]);
+    assert_eq!(values[1], depth.to_vec());
+
+    // every channel needs a value for every pixel
+    let short = [ExrChannel { name: "R", values: &red[..3] }];
+    assert!(ExrEncoder::new(Vec::new(), ExrPixelType::Half).encode(&short, 2, 2).is_err());
+}
+
+#[test]
+fn hdr_test() {
+    let image = framebuffer();
+    let mut bytes = Vec::new();
+    // tone mapping is only for 8-bit images
+    let mut settings = OutputSettings::new(OutputFormat::Hdr);
+    settings.tone_mapping.operator = ToneMapOperator::Reinhard;
+    write_image(&mut bytes, &image, &settings).unwrap();
+
+    let decoder = HDRDecoder::new(BufReader::new(&bytes[..])).unwrap();
+    let pixels = decoder.read_image_hdr().unwrap();
+    assert_eq!(pixels.len(), 12);
+    for (read, pixel) in pixels.iter().zip(image.pixels()) {
+        // RGBE shares an exponent between channels and can't hold negative values
+        let expected = [pixel.data[0], pixel.data[1], 0.0];
+        for (&value, &expected) in read.data.iter().zip(&expected) {
+            assert!((value - expected).abs() <= expected / 64.0 + 0.02, "expected {:?}, got {:?}", expected, read.data);
+        }
+    }
+}
+
+#[test]
+fn quantize_test() {
+    let mut bytes = Vec::new();
+    write_image(&mut bytes, &framebuffer(), &OutputSettings::new(OutputFormat::Ppm)).unwrap();
+    assert!(bytes.starts_with(b"P6"));
+
+    let linear = ToneMapping { transfer: TransferFunction::Gamma(1.0), ..ToneMapping::default() };
+    let image = quantize(&framebuffer(), &linear);
+    assert_eq!(image.get_pixel(0, 0).data, [0, 0, 0]);
+    assert_eq!(image.get_pixel(1, 1).data, [255, 64, 0]);
+
+    // sRGB brightens the darker values
+    let image = quantize(&framebuffer(), &ToneMapping::default());
+    assert_eq!(image.get_pixel(1, 1).data, [255, 137, 0]);
+}
+
+fn assert_close(actual: Vector3<f64>, expected: f64) {
+    for &value in &[actual.x, actual.y, actual.z] {
+        assert!((value - expected).abs() < 1e-6, "expected {}, got {:?}", expected, actual);
+    }
+}
+
+#[test]
+fn tone_mapping_test() {
+    let gray = |value: f64| Vector3::new(value, value, value);
+    let tone_mapping = |operator: ToneMapOperator| ToneMapping {
+        operator,
+        transfer: TransferFunction::Gamma(1.0),
+        ..ToneMapping::default()
+    };
+
+    let clamp = tone_mapping(ToneMapOperator::Clamp);
+    assert_close(clamp.map(gray(0.25)), 0.25);
+    assert_close(clamp.map(gray(3.0)), 1.0);
+    assert_close(clamp.map(gray(-1.0)), 0.0);
+
+    let reinhard = tone_mapping(ToneMapOperator::Reinhard);
+    assert_close(reinhard.map(gray(1.0)), 0.5);
+    assert_close(reinhard.map(gray(3.0)), 0.75);
+
+    // the extended operator reaches white at the white point
+    let mut extended = tone_mapping(ToneMapOperator::ReinhardExtended);
+    assert_close(extended.map(gray(4.0)), 1.0);
+    extended.white_point = Some(2.0);
+    assert_close(extended.map(gray(2.0)), 1.0);
+    assert_close(extended.map(gray(1.0)), 0.625);
+
+    let aces = tone_mapping(ToneMapOperator::Aces);
+    assert_close(aces.map(gray(0.0)), 0.0);
+    assert!((aces.map(gray(1.0)).x - 0.803_797).abs() < 1e-6);
+    assert_close(aces.map(gray(100.0)), 1.0);
+
+    let mut uncharted = tone_mapping(ToneMapOperator::Uncharted2);
+    assert_close(uncharted.map(gray(11.2)), 1.0);
+    assert!(uncharted.map(gray(1.0)).x < uncharted.map(gray(2.0)).x);
+    uncharted.white_point = Some(4.0);
+    assert_close(uncharted.map(gray(4.0)), 1.0);
+
+    // each stop of exposure doubles the light
+    let exposed = ToneMapping { exposure: 2.0, ..clamp };
+    assert_close(exposed.map(gray(0.125)), 0.5);
+    let darkened = ToneMapping { exposure: -1.0, ..reinhard };
+    assert_close(darkened.map(gray(2.0)), 0.5);
+}
+
+#[test]
+fn transfer_function_test() {
+    let srgb = ToneMapping::default();
+    assert_close(srgb.map(Vector3::new(0.0, 0.0, 0.0)), 0.0);
+    assert_close(srgb.map(Vector3::new(1.0, 1.0, 1.0)), 1.0);
+    // the linear segment near black, and mid gray
+    assert_close(srgb.map(Vector3::new(0.002, 0.002, 0.002)), 0.002 * 12.92);
+    assert!((srgb.map(Vector3::new(0.18, 0.18, 0.18)).x - 0.461_356).abs() < 1e-6);
+
+    let gamma = ToneMapping { transfer: TransferFunction::Gamma(2.0), ..srgb };
+    assert_close(gamma.map(Vector3::new(0.25, 0.25, 0.25)), 0.5);
+}
+
+fn depth_buffer() -> AovBuffer {
+    AovBuffer { aov: Aov::Depth, width: 2, height: 2, channels: vec![vec![1.0, 4.0, f32::INFINITY, 2.0]] }
+}
+
+#[test]
+fn exr_layers_test() {
+    let image = Framebuffer::from_fn(2, 2, |x, _| Rgb([x as f32, 0.0, 0.0]));
+    let normals = AovBuffer { aov: Aov::Normal, width: 2, height: 2, channels: vec![vec![0.0; 4], vec![-1.0; 4], vec![0.5; 4]] };
+    let mut bytes = Vec::new();
+    write_exr(&mut bytes, &image, &[depth_buffer(), normals], ExrPixelType::Float).unwrap();
+
+    let exr = read_exr(&bytes);
+    let names: Vec<&str> = exr.channels.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["B", "G", "R", "depth.Z", "normal.X", "normal.Y", "normal.Z"]);
+    assert_eq!(exr.values[2], vec![0.0, 1.0, 0.0, 1.0]);
+    assert_eq!(exr.values[3], depth_buffer().channels[0]);
+    assert_eq!(exr.values[5], vec![-1.0; 4]);
+}
+
+#[test]
+fn aov_file_test() {
+    assert_eq!(aov_path(Path::new("renders/out.png"), Aov::Depth), Path::new("renders/out.depth.png"));
+    assert_eq!(aov_path(Path::new("out"), Aov::ObjectId), Path::new("out.object_id"));
+
+    // 8-bit images get a picture of the values
+    let depth = aov_image(&depth_buffer(), OutputFormat::Png);
+    let gray = |x: u32, y: u32| depth.get_pixel(x, y).data[0];
+    assert_eq!((gray(0, 0), gray(1, 0), gray(0, 1), gray(1, 1)), (0.25, 1.0, 1.0, 0.5));
+    let normals = AovBuffer { aov: Aov::Normal, width: 1, height: 1, channels: vec![vec![-1.0], vec![0.0], vec![1.0]] };
+    assert_eq!(aov_image(&normals, OutputFormat::Png).get_pixel(0, 0).data, [0.0, 0.5, 1.0]);
+    let ids = AovBuffer { aov: Aov::ObjectId, width: 2, height: 1, channels: vec![vec![0.0, 3.0]] };
+    let ids = aov_image(&ids, OutputFormat::Png);
+    assert_eq!(ids.get_pixel(0, 0).data, [0.0; 3]);
+    assert!(ids.get_pixel(1, 0).data != [0.0; 3]);
+
+    // and HDR images the values as they are, as far as they can hold them
+    assert_eq!(aov_image(&depth_buffer(), OutputFormat::Exr).get_pixel(0, 1).data, [f32::INFINITY; 3]);
+    assert_eq!(aov_image(&depth_buffer(), OutputFormat::Hdr).get_pixel(0, 1).data, [0.0; 3]);
+    assert_eq!(aov_image(&depth_buffer(), OutputFormat::Hdr).get_pixel(1, 0).data, [4.0; 3]);
+
+    let mut bytes = Vec::new();
+    write_aov(&mut bytes, &depth_buffer(), &OutputSettings::new(OutputFormat::Ppm)).unwrap();
+    // written as they are, without sRGB
+    assert_eq!(&bytes[bytes.len() - 12..bytes.len() - 9], &[64, 64, 64]);
+}
+
+#[test]
+fn exr_decoder_test() {
+    let (red, depth) = ([0.5, 2.5, -1.0, 0.0, 1.0, 8.0], [1.0, 2.0, f32::INFINITY, 0.1, 0.2, 0.3]);
+    for &pixel_type in &[ExrPixelType::Half, ExrPixelType::Float] {
+        let mut bytes = Vec::new();
+        let channels = [ExrChannel { name: "R", values: &red }, ExrChannel { name: "depth.Z", values: &depth }];
+        ExrEncoder::new(&mut bytes, pixel_type).encode(&channels, 3, 2).unwrap();
+
+        let image = ExrDecoder::new(&bytes[..]).decode().unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.channel("R"), Some(&red[..]));
+        let read_depth = image.channel("depth.Z").unwrap();
+        for (&read, &expected) in read_depth.iter().zip(&depth) {
+            assert!(read == expected || (read - expected).abs() < expected / 1000.0, "expected {}, got {}", expected, read);
+        }
+        assert_eq!(image.channel("G"), None);
+
+        let error = ExrDecoder::new(&bytes[..bytes.len() - 1]).decode().unwrap_err();
+        assert_eq!(error.to_string(), "the EXR image ends too soon");
+    }
+    assert_eq!(ExrDecoder::new(&b"P6 1 1 255"[..]).decode().unwrap_err().to_string(), "not an EXR image");
+}
+
+#[test]
+fn crop_test() {
+    let cropped = crop(&framebuffer(), 1..3, 1..3);
+    assert_eq!(cropped.dimensions(), (2, 2));
+    assert_eq!(cropped.get_pixel(0, 0), framebuffer().get_pixel(1, 1));
+    assert_eq!(cropped.get_pixel(1, 1), framebuffer().get_pixel(2, 2));
+
+    let depth = depth_buffer().crop(0..2, 1..2);
+    assert_eq!((depth.width, depth.height), (2, 1));
+    assert_eq!(depth.channels, vec![vec![f32::INFINITY, 2.0]]);
+}
+
+#[test]
+fn composite_test() {
+    let before = Framebuffer::from_fn(4, 3, |_, _| Rgb([0.25; 3]));
+    let after = Framebuffer::from_fn(4, 3, |_, _| Rgb([0.75; 3]));
+    let inside = |x: u32, y: u32| (1..3).contains(&x) && y < 2;
+
+    for &format in &[OutputFormat::Png, OutputFormat::Bmp, OutputFormat::Hdr, OutputFormat::Exr] {
+        let settings = OutputSettings::new(format);
+        let write = |image: &Framebuffer| {
+            let mut bytes = Vec::new();
+            write_image(&mut bytes, image, &settings).unwrap();
+            bytes
+        };
+        let existing = write(&before);
+        let composited = composite(&existing, &write(&after), 1..3, 0..2, &settings).unwrap();
+
+        // read back the red channel, scaled to 0 to 1
+        let red: Vec<f32> = match format {
+            OutputFormat::Exr => ExrDecoder::new(&composited[..]).decode().unwrap().channel("R").unwrap().to_vec(),
+            OutputFormat::Hdr => HDRDecoder::new(BufReader::new(&composited[..])).unwrap()
+                .read_image_hdr().unwrap().iter().map(|pixel| pixel.data[0]).collect(),
+            _ => image::load_from_memory(&composited).unwrap().to_rgb().pixels().map(|pixel| f32::from(pixel.data[0]) / 255.0).collect(),
+        };
+        let (low, high) = if format.is_hdr() { (0.25, 0.75) } else { (0.537, 0.880) };
+        for (index, &value) in red.iter().enumerate() {
+            let (x, y) = (index as u32 % 4, index as u32 / 4);
+            let expected = if inside(x, y) { high } else { low };
+            assert!((value - expected).abs() < 0.01, "{:?} at ({}, {}): expected {}, got {}", format, x, y, expected, value);
+        }
+
+        // the images have to line up
+        let small = write(&Framebuffer::from_fn(2, 2, |_, _| Rgb([0.0; 3])));
+        assert!(composite(&small, &write(&after), 1..3, 0..2, &settings).is_err());
+    }
+}
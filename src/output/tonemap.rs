@@ -0,0 +1,126 @@
+//! Brings the linear light of a render into the range of an 8-bit image and
+//! encodes it for display.
+
+use cgmath::Vector3;
+
+/// How light brighter than the display can show is brought into range. Each
+/// is applied to the channels separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Cuts off everything past the white point
+    Clamp,
+    /// `c / (1 + c)`, which never quite reaches white
+    Reinhard,
+    /// Reinhard's operator with the white point reaching white
+    ReinhardExtended,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// Hable's filmic curve from Uncharted 2
+    Uncharted2,
+}
+
+impl ToneMapOperator {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::ReinhardExtended => "reinhard-extended",
+            ToneMapOperator::Aces => "aces",
+            ToneMapOperator::Uncharted2 => "uncharted2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ToneMapOperator> {
+        match name {
+            "clamp" => Some(ToneMapOperator::Clamp),
+            "reinhard" => Some(ToneMapOperator::Reinhard),
+            "reinhard-extended" => Some(ToneMapOperator::ReinhardExtended),
+            "aces" => Some(ToneMapOperator::Aces),
+            "uncharted2" => Some(ToneMapOperator::Uncharted2),
+            _ => None,
+        }
+    }
+
+    /// The light that becomes white when no white point is given, None for
+    /// operators that run on to white by themselves
+    pub fn default_white_point(&self) -> Option<f64> {
+        match *self {
+            ToneMapOperator::Clamp => Some(1.0),
+            ToneMapOperator::ReinhardExtended => Some(4.0),
+            ToneMapOperator::Uncharted2 => Some(11.2),
+            ToneMapOperator::Reinhard | ToneMapOperator::Aces => None,
+        }
+    }
+}
+
+/// The encoding from linear light to the values stored in the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+    /// The sRGB curve, what displays expect of images without a profile
+    Srgb,
+    /// A plain power curve, `c^(1 / gamma)`, where a gamma of 1 stores linear light
+    Gamma(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Stops the light is brightened by before it's mapped
+    pub exposure: f64,
+    /// Light that maps to white, after exposure. None leaves it to the
+    /// operator, and plain Reinhard doesn't use one.
+    pub white_point: Option<f64>,
+    pub transfer: TransferFunction,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+            white_point: None,
+            transfer: TransferFunction::Srgb,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// The display value from 0 to 1 of linear light
+    pub fn map(&self, color: Vector3<f64>) -> Vector3<f64> {
+        let scale = 2f64.powf(self.exposure);
+        let white = self.white_point.or_else(|| self.operator.default_white_point());
+        let channel = |value: f64| {
+            let value = (value * scale).max(0.0);
+            let mapped = match self.operator {
+                ToneMapOperator::Clamp => value / white.unwrap_or(1.0),
+                ToneMapOperator::Reinhard => value / (1.0 + value),
+                ToneMapOperator::ReinhardExtended => {
+                    let white = white.unwrap_or(1.0);
+                    value * (1.0 + value / (white * white)) / (1.0 + value)
+                },
+                ToneMapOperator::Aces => aces(value) / white.map_or(1.0, aces),
+                ToneMapOperator::Uncharted2 => uncharted2(value) / uncharted2(white.unwrap_or(1.0)),
+            };
+            self.encode(mapped.clamp(0.0, 1.0))
+        };
+        Vector3::new(channel(color.x), channel(color.y), channel(color.z))
+    }
+
+    fn encode(&self, value: f64) -> f64 {
+        match self.transfer {
+            TransferFunction::Srgb if value <= 0.003_130_8 => value * 12.92,
+            TransferFunction::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+            TransferFunction::Gamma(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+}
+
+fn aces(value: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (value * (a * value + b)) / (value * (c * value + d) + e)
+}
+
+fn uncharted2(value: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((value * (a * value + c * b) + d * e) / (value * (a * value + b) + d * f)) - e / f
+}
@@ -2,25 +2,35 @@ use std::error::Error;
 use std::fmt;
 use std::string::String;
 
+/// Error shared by every scene parser, from tokenizing through to loading the
+/// resources a scene refers to
 #[derive(Debug)]
-pub struct TokenizationError {
+pub struct ParseError {
     message: String
 }
 
-impl TokenizationError {
-    pub fn new<S: Into<String>>(message: S) -> TokenizationError {
-        TokenizationError{message: message.into()}
+impl ParseError {
+    pub fn new<S: Into<String>>(message: S) -> ParseError {
+        ParseError{message: message.into()}
+    }
+
+    /// Prefixes the message with the name of the source it happened in
+    pub fn in_source(self, source: &str) -> ParseError {
+        if source.is_empty() {
+            return self;
+        }
+        ParseError::new(format!("{}: {}", source, self.message))
     }
 }
 
-impl Error for TokenizationError {
+impl Error for ParseError {
     fn description(&self) -> &str {
-        return self.message.as_ref();
+        self.message.as_ref()
     }
 }
 
-impl fmt::Display for TokenizationError {
+impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Something went wrong")
+        write!(f, "{}", self.message)
     }
-}
\ No newline at end of file
+}
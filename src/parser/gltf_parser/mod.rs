@@ -0,0 +1,662 @@
+//! This module reads glTF 2.0 scenes, either as `.gltf` JSON with its buffers
+//! embedded as data URIs or next to it, or as binary `.glb` files, into a
+//! generalized Scene.
+//!
+//! glTF describes physically based materials, which are approximated by our
+//! Phong materials: metals tint their specular and reflective colors, and
+//! rougher surfaces get a lower shininess.
+
+#[cfg(test)]
+mod tests;
+
+use base64;
+use cgmath::{InnerSpace, Matrix4, Quaternion, Vector2, Vector3, Vector4, Zero};
+use image;
+use serde_json::{self, Value};
+
+use std::collections::HashMap;
+use std::f64::consts;
+use std::result;
+use std::str;
+use std::sync::Arc;
+
+use super::*;
+use super::error::ParseError;
+
+use super::super::scene::{Camera, Material, MaterialParameter, Projection, SceneObject, TextureMap, TransformNode};
+use super::super::scene::objects::{Light, LightType, Mesh, Trimesh, TrimeshFace};
+
+type Result<T> = result::Result<T, ParseError>;
+
+/// Magic number starting `.glb` files, "glTF"
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+const MODE_TRIANGLES: u64 = 4;
+const MODE_TRIANGLE_STRIP: u64 = 5;
+const MODE_TRIANGLE_FAN: u64 = 6;
+
+/// Parser for `.gltf` and `.glb` files
+pub struct GltfParser;
+
+impl Parser for GltfParser {
+    /// Parses either `.gltf` JSON or a binary `.glb` file
+    fn parse(&self, input: &[u8], source: &str, resolver: &dyn ResourceResolver) -> Result<Scene> {
+        let (json, binary) = if is_glb(input) {
+            split_glb(input).map_err(|err| err.in_source(source))?
+        } else {
+            (input, None)
+        };
+
+        let json: Value = serde_json::from_slice(json)
+            .map_err(|err| ParseError::new(format!("invalid glTF JSON: {}", err)).in_source(source))?;
+
+        GltfImporter::new(json, binary, source, resolver)
+            .and_then(GltfImporter::import)
+            .map_err(|err| err.in_source(source))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["gltf", "glb"]
+    }
+
+    /// Binary files start with their magic number, JSON ones with an object
+    /// declaring the glTF asset
+    fn detect(&self, input: &[u8]) -> bool {
+        if is_glb(input) {
+            return true;
+        }
+        let start = String::from_utf8_lossy(&input[..input.len().min(4096)]);
+        start.trim_start().starts_with('{') && start.contains("\"asset\"")
+    }
+}
+
+fn is_glb(data: &[u8]) -> bool {
+    data.len() >= 4 && read_u32(data, 0) == GLB_MAGIC
+}
+
+/// Splits a `.glb` file into its JSON and binary chunks
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    if data.len() < 12 || read_u32(data, 4) != 2 {
+        return Err(ParseError::new("only version 2 glb files are supported"));
+    }
+
+    let length = (read_u32(data, 8) as usize).min(data.len());
+    let mut position = 12;
+    let mut json = None;
+    let mut binary = None;
+
+    while position + 8 <= length {
+        let chunk_length = read_u32(data, position) as usize;
+        let chunk_type = read_u32(data, position + 4);
+        let chunk = data.get(position + 8..position + 8 + chunk_length)
+            .ok_or_else(|| ParseError::new("glb chunk runs past the end of the file"))?;
+
+        match chunk_type {
+            GLB_JSON_CHUNK if json.is_none() => json = Some(chunk),
+            GLB_BIN_CHUNK if binary.is_none() => binary = Some(chunk),
+            _ => {},
+        }
+        position += 8 + chunk_length;
+    }
+
+    let json = json.ok_or_else(|| ParseError::new("glb file has no JSON chunk"))?;
+    Ok((json, binary))
+}
+
+/// Flattened accessor data, `components` values for every element
+struct Accessor {
+    components: usize,
+    values: Vec<f64>,
+}
+
+impl Accessor {
+    fn count(&self) -> usize {
+        self.values.len() / self.components
+    }
+
+    fn vector2(&self, i: usize) -> Vector2<f64> {
+        Vector2::new(self.values[i * self.components], self.values[i * self.components + 1])
+    }
+
+    fn vector3(&self, i: usize) -> Vector3<f64> {
+        let values = &self.values[i * self.components..];
+        Vector3::new(values[0], values[1], values[2])
+    }
+}
+
+struct GltfImporter<'a> {
+    json: Value,
+    /// Name of the file being imported, which other files are resolved relative to
+    source: &'a str,
+    resolver: &'a dyn ResourceResolver,
+    buffers: Vec<Vec<u8>>,
+    meshes: HashMap<(usize, usize), Arc<Mesh>>,
+    materials: HashMap<usize, Arc<Material>>,
+    textures: HashMap<usize, TextureMap>,
+
+    objects: Vec<Box<dyn SceneObject>>,
+    lights: Vec<Light>,
+    camera: Option<Camera>,
+}
+
+impl<'a> GltfImporter<'a> {
+    fn new(json: Value, binary: Option<&[u8]>, source: &'a str, resolver: &'a dyn ResourceResolver) -> Result<GltfImporter<'a>> {
+        let version = json["asset"]["version"].as_str().unwrap_or("");
+        if !version.starts_with("2.") {
+            return Err(ParseError::new(format!("unsupported glTF version '{}'", version)));
+        }
+
+        let buffers = array(&json, "buffers").iter().enumerate()
+            .map(|(i, buffer)| match buffer["uri"].as_str() {
+                Some(uri) => load_uri(uri, source, resolver),
+                None if i == 0 => binary.map(|binary| binary.to_vec())
+                    .ok_or_else(|| ParseError::new("buffer 0 has no uri and there is no glb binary chunk")),
+                None => Err(ParseError::new(format!("buffer {} has no uri", i))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(GltfImporter {
+            json,
+            source,
+            resolver,
+            buffers,
+            meshes: HashMap::new(),
+            materials: HashMap::new(),
+            textures: HashMap::new(),
+            objects: Vec::new(),
+            lights: Vec::new(),
+            camera: None,
+        })
+    }
+
+    fn import(mut self) -> Result<Scene> {
+        let scene = index(&self.json, "scene")?.unwrap_or(0);
+        let roots: Vec<usize> = match self.json["scenes"].get(scene) {
+            Some(scene) => indices(scene, "nodes")?,
+            // without any scenes, every node that isn't a child is a root
+            None => {
+                let children: Vec<usize> = array(&self.json, "nodes").iter()
+                    .map(|node| indices(node, "children"))
+                    .collect::<Result<Vec<_>>>()?
+                    .concat();
+                (0..array(&self.json, "nodes").len()).filter(|node| !children.contains(node)).collect()
+            },
+        };
+
+        let mut path = Vec::new();
+        for root in roots {
+            self.import_node(root, &TransformNode::root(), &mut path)?;
+        }
+
+        Ok(Scene::new(self.camera.unwrap_or_default(), self.lights, Vector3::zero(), self.objects))
+    }
+
+    fn import_node(&mut self, node_index: usize, parent: &TransformNode, path: &mut Vec<usize>) -> Result<()> {
+        if path.contains(&node_index) {
+            return Err(ParseError::new(format!("node {} is its own ancestor", node_index)));
+        }
+        let node = self.json["nodes"].get(node_index).cloned()
+            .ok_or_else(|| ParseError::new(format!("node {} doesn't exist", node_index)))?;
+
+        let transform = parent.create_child(node_matrix(&node)?)
+            .ok_or_else(|| ParseError::new(format!("node {} has a singular transform", node_index)))?;
+
+        if let Some(mesh) = index(&node, "mesh")? {
+            self.import_mesh(mesh, &transform)?;
+        }
+        if let Some(camera) = index(&node, "camera")? {
+            self.import_camera(camera, &transform)?;
+        }
+        if let Some(light) = index(&node["extensions"]["KHR_lights_punctual"], "light")? {
+            self.import_light(light, &transform)?;
+        }
+
+        path.push(node_index);
+        for child in indices(&node, "children")? {
+            self.import_node(child, &transform, path)?;
+        }
+        path.pop();
+
+        Ok(())
+    }
+
+    fn import_mesh(&mut self, mesh_index: usize, transform: &TransformNode) -> Result<()> {
+        let primitives = self.json["meshes"].get(mesh_index)
+            .ok_or_else(|| ParseError::new(format!("mesh {} doesn't exist", mesh_index)))?["primitives"]
+            .as_array().cloned().unwrap_or_default();
+
+        for (primitive_index, primitive) in primitives.iter().enumerate() {
+            let mode = primitive["mode"].as_u64().unwrap_or(MODE_TRIANGLES);
+            if mode != MODE_TRIANGLES && mode != MODE_TRIANGLE_STRIP && mode != MODE_TRIANGLE_FAN {
+                // points and lines have no surface to render
+                continue;
+            }
+
+            // meshes used by several nodes share their triangles
+            let mesh = match self.meshes.get(&(mesh_index, primitive_index)) {
+                Some(mesh) => mesh.clone(),
+                None => {
+                    let mesh = Arc::new(self.read_primitive(primitive, mode)?);
+                    self.meshes.insert((mesh_index, primitive_index), mesh.clone());
+                    mesh
+                },
+            };
+
+            let mut material = match index(primitive, "material")? {
+                Some(material) => self.material(material)?,
+                None => Arc::new(Material::new()),
+            };
+            if !mesh.colors().is_empty() && material.diffuse.texture_map().is_none() {
+                let mut colored = (*material).clone();
+                colored.diffuse = MaterialParameter::from_vertex_colors(colored.diffuse.base_value());
+                material = Arc::new(colored);
+            }
+
+            self.objects.push(Box::new(Trimesh::new(transform.clone(), material, mesh)));
+        }
+
+        Ok(())
+    }
+
+    fn read_primitive(&self, primitive: &Value, mode: u64) -> Result<Mesh> {
+        let attributes = &primitive["attributes"];
+        let positions = match index(attributes, "POSITION")? {
+            Some(accessor) => self.read_accessor(accessor, "POSITION", &["VEC3"], None)?,
+            None => return Err(ParseError::new("mesh primitive has no POSITION attribute")),
+        };
+        let count = positions.count();
+
+        let vertices = (0..count).map(|i| positions.vector3(i)).collect();
+        let normals = match index(attributes, "NORMAL")? {
+            Some(accessor) => {
+                let normals = self.read_accessor(accessor, "NORMAL", &["VEC3"], Some(count))?;
+                (0..normals.count().min(count)).map(|i| normals.vector3(i)).collect()
+            },
+            None => Vec::new(),
+        };
+        // glTF puts the origin of texture coordinates at the top left
+        let uvs = match index(attributes, "TEXCOORD_0")? {
+            Some(accessor) => {
+                let uvs = self.read_accessor(accessor, "TEXCOORD_0", &["VEC2"], Some(count))?;
+                (0..uvs.count().min(count)).map(|i| {
+                    let uv = uvs.vector2(i);
+                    Vector2::new(uv.x, 1.0 - uv.y)
+                }).collect()
+            },
+            None => Vec::new(),
+        };
+        let colors = match index(attributes, "COLOR_0")? {
+            Some(accessor) => {
+                let colors = self.read_accessor(accessor, "COLOR_0", &["VEC3", "VEC4"], Some(count))?;
+                (0..colors.count().min(count)).map(|i| colors.vector3(i)).collect()
+            },
+            None => Vec::new(),
+        };
+
+        let order: Vec<usize> = match index(primitive, "indices")? {
+            Some(accessor) => self.read_accessor(accessor, "indices", &["SCALAR"], None)?.values.iter().map(|&index| index as usize).collect(),
+            None => (0..count).collect(),
+        };
+        let faces: Vec<TrimeshFace> = match mode {
+            MODE_TRIANGLE_STRIP => (2..order.len()).map(|i| if i % 2 == 0 {
+                TrimeshFace::new(order[i - 2], order[i - 1], order[i])
+            } else {
+                TrimeshFace::new(order[i - 1], order[i - 2], order[i])
+            }).collect(),
+            MODE_TRIANGLE_FAN => (2..order.len()).map(|i| TrimeshFace::new(order[0], order[i - 1], order[i])).collect(),
+            _ => order.chunks(3)
+                .filter(|triangle| triangle.len() == 3)
+                .map(|triangle| TrimeshFace::new(triangle[0], triangle[1], triangle[2]))
+                .collect(),
+        };
+
+        if faces.iter().any(|face| face.vertices().iter().any(|&index| index >= count)) {
+            return Err(ParseError::new("mesh index refers to a vertex that doesn't exist"));
+        }
+        // per-vertex data is only usable when every vertex has it
+        let keep = |length: usize| length == count;
+        let normals = if keep(normals.len()) { normals } else { Vec::new() };
+        let uvs = if keep(uvs.len()) { uvs } else { Vec::new() };
+        let colors = if keep(colors.len()) { colors } else { Vec::new() };
+
+        Ok(Mesh::new(vertices, faces, normals, uvs, Vec::new()).with_colors(colors))
+    }
+
+    /// Reads the accessor used for `usage`, which has to be one of `types`.
+    /// Accessors without a buffer view are all zeros, and may only have up to
+    /// `zeros` elements.
+    fn read_accessor(&self, accessor_index: usize, usage: &str, types: &[&str], zeros: Option<usize>) -> Result<Accessor> {
+        let accessor = self.json["accessors"].get(accessor_index)
+            .ok_or_else(|| ParseError::new(format!("accessor {} doesn't exist", accessor_index)))?;
+        if !accessor["sparse"].is_null() {
+            return Err(ParseError::new("sparse accessors aren't supported"));
+        }
+        if !accessor["type"].as_str().is_some_and(|kind| types.contains(&kind)) {
+            return Err(ParseError::new(format!("accessor {} can't be used for {}, which needs {}", accessor_index, usage, types.join(" or "))));
+        }
+
+        let count = accessor["count"].as_u64().unwrap_or(0) as usize;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(ParseError::new(format!("accessor {} has an unknown type", accessor_index))),
+        };
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(ParseError::new(format!("accessor {} has an unknown component type", accessor_index))),
+        };
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+
+        let view_index = match (index(accessor, "bufferView")?, zeros) {
+            (Some(view), _) => view,
+            (None, Some(zeros)) if count <= zeros => return Ok(Accessor { components, values: vec![0.0; count * components] }),
+            (None, _) => return Err(ParseError::new(format!("accessor {} has no buffer view", accessor_index))),
+        };
+        let view = self.json["bufferViews"].get(view_index)
+            .ok_or_else(|| ParseError::new(format!("buffer view {} doesn't exist", view_index)))?;
+        let buffer = index(view, "buffer")?
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or_else(|| ParseError::new(format!("buffer view {} has no buffer", view_index)))?;
+
+        let view_offset = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let view_length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+        let view_data = view_offset.checked_add(view_length)
+            .and_then(|end| buffer.get(view_offset..end))
+            .ok_or_else(|| ParseError::new(format!("buffer view {} runs past its buffer", view_index)))?;
+        let offset = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let stride = view["byteStride"].as_u64().map_or(component_size * components, |stride| stride as usize);
+
+        // elements may not overlap, which also keeps `count` within the data
+        if stride < components * component_size {
+            return Err(ParseError::new(format!("buffer view {} has a stride shorter than its elements", view_index)));
+        }
+        let end = match count {
+            0 => Some(0),
+            _ => (count - 1).checked_mul(stride)
+                .and_then(|start| start.checked_add(offset))
+                .and_then(|start| start.checked_add(components * component_size)),
+        };
+        if end.is_none_or(|end| end > view_data.len()) {
+            return Err(ParseError::new(format!("accessor {} runs past its buffer view", accessor_index)));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for i in 0..count {
+            for j in 0..components {
+                let at = offset + i * stride + j * component_size;
+                let bytes = &view_data[at..at + component_size];
+                let value = match component_type {
+                    5120 => if normalized { (f64::from(bytes[0] as i8) / 127.0).max(-1.0) } else { f64::from(bytes[0] as i8) },
+                    5121 => if normalized { f64::from(bytes[0]) / 255.0 } else { f64::from(bytes[0]) },
+                    5122 => {
+                        let value = f64::from(i16::from_le_bytes([bytes[0], bytes[1]]));
+                        if normalized { (value / 32767.0).max(-1.0) } else { value }
+                    },
+                    5123 => {
+                        let value = f64::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+                        if normalized { value / 65535.0 } else { value }
+                    },
+                    5125 => f64::from(read_u32(bytes, 0)),
+                    _ => f64::from(f32::from_bits(read_u32(bytes, 0))),
+                };
+                values.push(value);
+            }
+        }
+
+        Ok(Accessor { components, values })
+    }
+
+    /// Approximates a metallic-roughness material with a Phong material
+    fn material(&mut self, material_index: usize) -> Result<Arc<Material>> {
+        if let Some(material) = self.materials.get(&material_index) {
+            return Ok(material.clone());
+        }
+
+        let json = self.json["materials"].get(material_index).cloned()
+            .ok_or_else(|| ParseError::new(format!("material {} doesn't exist", material_index)))?;
+        let pbr = &json["pbrMetallicRoughness"];
+
+        let base_color = numbers(pbr, "baseColorFactor", &[1.0, 1.0, 1.0, 1.0])?;
+        let base_color = Vector4::new(base_color[0], base_color[1], base_color[2], base_color[3]);
+        let metallic = pbr["metallicFactor"].as_f64().unwrap_or(1.0);
+        let roughness = pbr["roughnessFactor"].as_f64().unwrap_or(1.0).max(0.01);
+        let emissive = numbers(&json, "emissiveFactor", &[0.0, 0.0, 0.0])?;
+
+        let color = base_color.truncate();
+        // dielectrics reflect about 4% of the light specularly, metals tint it
+        let specular = Vector3::new(0.04, 0.04, 0.04) * (1.0 - metallic) + color * metallic;
+        let alpha = roughness * roughness;
+
+        let mut material = Material::new();
+        material.diffuse = match index(&pbr["baseColorTexture"], "index")? {
+            Some(texture) => MaterialParameter::from_texture_map(self.texture(texture)?),
+            None => MaterialParameter::new(color * (1.0 - metallic)),
+        };
+        material.specular = MaterialParameter::new(specular);
+        material.reflective = MaterialParameter::new(specular * (1.0 - roughness));
+        material.shininess = (2.0 / (alpha * alpha) - 2.0).clamp(1.0, 1000.0);
+        material.emissive = match index(&json["emissiveTexture"], "index")? {
+            Some(texture) => MaterialParameter::from_texture_map(self.texture(texture)?),
+            None => MaterialParameter::new(Vector3::new(emissive[0], emissive[1], emissive[2])),
+        };
+
+        let transmission = json["extensions"]["KHR_materials_transmission"]["transmissionFactor"].as_f64().unwrap_or(0.0);
+        let opacity = if json["alphaMode"].as_str() == Some("BLEND") { base_color.w } else { 1.0 };
+        let transmissive = transmission.max(1.0 - opacity);
+        material.transmissive = MaterialParameter::new(Vector3::new(transmissive, transmissive, transmissive));
+        material.index = json["extensions"]["KHR_materials_ior"]["ior"].as_f64().unwrap_or(1.5);
+
+        let material = Arc::new(material);
+        self.materials.insert(material_index, material.clone());
+        Ok(material)
+    }
+
+    fn texture(&mut self, texture_index: usize) -> Result<TextureMap> {
+        if let Some(texture) = self.textures.get(&texture_index) {
+            return Ok(texture.clone());
+        }
+
+        let image_index = self.json["textures"].get(texture_index)
+            .map(|texture| index(texture, "source"))
+            .unwrap_or(Ok(None))?
+            .ok_or_else(|| ParseError::new(format!("texture {} has no image", texture_index)))?;
+        let image = self.json["images"].get(image_index)
+            .ok_or_else(|| ParseError::new(format!("image {} doesn't exist", image_index)))?;
+
+        let data = match (image["uri"].as_str(), index(image, "bufferView")?) {
+            (Some(uri), _) => load_uri(uri, self.source, self.resolver)?,
+            (None, Some(view_index)) => {
+                let view = &self.json["bufferViews"][view_index];
+                let offset = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+                let length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+                index(view, "buffer")?
+                    .and_then(|buffer| self.buffers.get(buffer))
+                    .and_then(|buffer| offset.checked_add(length).and_then(|end| buffer.get(offset..end)))
+                    .ok_or_else(|| ParseError::new(format!("image {} runs past its buffer", image_index)))?
+                    .to_vec()
+            },
+            (None, None) => return Err(ParseError::new(format!("image {} has no data", image_index))),
+        };
+
+        let decoded = image::load_from_memory(&data)
+            .map_err(|err| ParseError::new(format!("couldn't decode image {}: {}", image_index, err)))?;
+        let name = image["name"].as_str().map_or_else(|| format!("image {}", image_index), |name| name.to_string());
+
+        let texture = TextureMap::new(name, decoded.to_rgb());
+        self.textures.insert(texture_index, texture.clone());
+        Ok(texture)
+    }
+
+    /// Cameras look down their node's -z axis with +y up. Only the first
+    /// camera in the scene is used.
+    fn import_camera(&mut self, camera_index: usize, transform: &TransformNode) -> Result<()> {
+        let json = self.json["cameras"].get(camera_index)
+            .ok_or_else(|| ParseError::new(format!("camera {} doesn't exist", camera_index)))?;
+        if self.camera.is_some() {
+            return Ok(());
+        }
+
+        let matrix = transform.matrix();
+        let mut camera = Camera::new();
+        camera.set_eye(transform.local_to_world_point(Vector3::zero()));
+        camera.set_look((matrix * -Vector4::unit_z()).truncate(), (matrix * Vector4::unit_y()).truncate());
+        match json["type"].as_str() {
+            Some("perspective") => {
+                let perspective = &json["perspective"];
+                if let Some(yfov) = perspective["yfov"].as_f64() {
+                    if !(yfov > 0.0 && yfov < consts::PI) {
+                        return Err(ParseError::new(format!("camera {} has a yfov that isn't between 0 and pi", camera_index)));
+                    }
+                    camera.set_fov(yfov.to_degrees());
+                }
+                if let Some(aspect_ratio) = perspective["aspectRatio"].as_f64() {
+                    if !(aspect_ratio > 0.0 && aspect_ratio.is_finite()) {
+                        return Err(ParseError::new(format!("camera {} has an aspectRatio that isn't more than zero", camera_index)));
+                    }
+                    camera.set_aspect_ratio(aspect_ratio);
+                }
+            },
+            // magnifications are half the width and height of the view
+            Some("orthographic") => {
+                let orthographic = &json["orthographic"];
+                let (xmag, ymag) = match (orthographic["xmag"].as_f64(), orthographic["ymag"].as_f64()) {
+                    (Some(xmag), Some(ymag)) if xmag != 0.0 && ymag != 0.0 => (xmag.abs(), ymag.abs()),
+                    _ => return Err(ParseError::new(format!("orthographic camera {} needs a non-zero xmag and ymag", camera_index))),
+                };
+                camera.set_projection(Projection::Orthographic { height: ymag * 2.0 });
+                camera.set_aspect_ratio(xmag / ymag);
+            },
+            _ => return Ok(()),
+        }
+
+        self.camera = Some(camera);
+        Ok(())
+    }
+
+    /// Point lights fall off with the square of the distance, and spot lights
+    /// are treated as point lights
+    fn import_light(&mut self, light_index: usize, transform: &TransformNode) -> Result<()> {
+        let json = self.json["extensions"]["KHR_lights_punctual"]["lights"].get(light_index)
+            .ok_or_else(|| ParseError::new(format!("light {} doesn't exist", light_index)))?;
+
+        let color = numbers(json, "color", &[1.0, 1.0, 1.0])?;
+        let color = Vector3::new(color[0], color[1], color[2]) * json["intensity"].as_f64().unwrap_or(1.0);
+
+        let light_type = match json["type"].as_str() {
+            Some("directional") => LightType::DirectionalLight {
+                orientation: (transform.matrix() * -Vector4::unit_z()).truncate().normalize(),
+            },
+            Some("point") | Some("spot") => LightType::PointLight {
+                pos: transform.local_to_world_point(Vector3::zero()),
+                a: 0.0,
+                b: 0.0,
+                c: 1.0,
+            },
+            _ => return Err(ParseError::new(format!("light {} has an unknown type", light_index))),
+        };
+
+        self.lights.push(Light::new(light_type, color));
+        Ok(())
+    }
+}
+
+/// A node's local transform, from either its matrix or its translation,
+/// rotation and scale
+fn node_matrix(node: &Value) -> Result<Matrix4<f64>> {
+    if !node["matrix"].is_null() {
+        let m = numbers(node, "matrix", &[])?;
+        if m.len() != 16 {
+            return Err(ParseError::new("node matrix needs 16 numbers"));
+        }
+        // only affine transforms, which leave the bottom row as it is
+        if m[3] != 0.0 || m[7] != 0.0 || m[11] != 0.0 || m[15] != 1.0 {
+            return Err(ParseError::new("node matrix isn't an affine transform"));
+        }
+        // stored in column-major order, like cgmath's constructor takes them
+        return Ok(Matrix4::new(
+            m[0], m[1], m[2], m[3],
+            m[4], m[5], m[6], m[7],
+            m[8], m[9], m[10], m[11],
+            m[12], m[13], m[14], m[15],
+        ));
+    }
+
+    let translation = numbers(node, "translation", &[0.0, 0.0, 0.0])?;
+    let rotation = numbers(node, "rotation", &[0.0, 0.0, 0.0, 1.0])?;
+    let scale = numbers(node, "scale", &[1.0, 1.0, 1.0])?;
+    if translation.len() != 3 || rotation.len() != 4 || scale.len() != 3 {
+        return Err(ParseError::new("node has a malformed translation, rotation or scale"));
+    }
+
+    let rotation = Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]).normalize();
+    Ok(Matrix4::from_translation(Vector3::new(translation[0], translation[1], translation[2]))
+        * Matrix4::from(rotation)
+        * Matrix4::from_nonuniform_scale(scale[0], scale[1], scale[2]))
+}
+
+/// Decodes a base64 `data:` URI, or reads the file a relative URI refers to
+fn load_uri(uri: &str, source: &str, resolver: &dyn ResourceResolver) -> Result<Vec<u8>> {
+    if !uri.starts_with("data:") {
+        if uri.contains("://") {
+            return Err(ParseError::new(format!("only data URIs and relative paths are supported, found '{}'", uri)));
+        }
+        return resolver.read(&resolver.resolve(uri, source)?);
+    }
+
+    let data = uri.find(";base64,")
+        .map(|start| &uri[start + ";base64,".len()..])
+        .ok_or_else(|| ParseError::new("data URI isn't base64 encoded"))?;
+
+    base64::decode(data)
+        .map_err(|err| ParseError::new(format!("invalid base64 data: {}", err)))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(buffer)
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value[key].as_array().map_or(&[], |array| array.as_slice())
+}
+
+/// An optional index property
+fn index(value: &Value, key: &str) -> Result<Option<usize>> {
+    match value[key] {
+        Value::Null => Ok(None),
+        ref index => index.as_u64()
+            .map(|index| Some(index as usize))
+            .ok_or_else(|| ParseError::new(format!("'{}' isn't an index", key))),
+    }
+}
+
+/// An optional list of indices, empty when missing
+fn indices(value: &Value, key: &str) -> Result<Vec<usize>> {
+    array(value, key).iter()
+        .map(|index| index.as_u64()
+            .map(|index| index as usize)
+            .ok_or_else(|| ParseError::new(format!("'{}' should only hold indices", key))))
+        .collect()
+}
+
+/// An optional list of numbers, `default` when missing
+fn numbers(value: &Value, key: &str, default: &[f64]) -> Result<Vec<f64>> {
+    if value[key].is_null() {
+        return Ok(default.to_vec());
+    }
+
+    array(value, key).iter()
+        .map(|number| number.as_f64()
+            .ok_or_else(|| ParseError::new(format!("'{}' should only hold numbers", key))))
+        .collect()
+}
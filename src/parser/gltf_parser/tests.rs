@@ -0,0 +1,259 @@
+use super::*;
+
+use image::{ColorType, ImageBuffer, Rgb};
+use image::png::PNGEncoder;
+
+use super::super::super::scene::objects::{Intersect, Ray, RayType};
+
+/// A unit right triangle in the xy plane, as positions, texture coordinates,
+/// then indices
+fn triangle_buffer() -> Vec<u8> {
+    let mut data = Vec::new();
+    for &value in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    for &value in &[0.0f32, 1.0, 1.0, 1.0, 0.0, 0.0] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    for &index in &[0u16, 1, 2] {
+        data.extend_from_slice(&index.to_le_bytes());
+    }
+    data.extend_from_slice(&[0, 0]);
+    data
+}
+
+/// A 2x1 png, red on the left and blue on the right
+fn texture_png() -> Vec<u8> {
+    let image: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(2, 1, |x, _| {
+        if x == 0 { Rgb { data: [255, 0, 0] } } else { Rgb { data: [0, 0, 255] } }
+    });
+    let mut png = Vec::new();
+    PNGEncoder::new(&mut png).encode(&image.into_raw(), 2, 1, ColorType::RGB(8)).unwrap();
+    png
+}
+
+fn parse(input: &[u8]) -> Result<Scene> {
+    GltfParser.parse(input, "scene.gltf", &MemoryResolver::new())
+}
+
+/// `buffer` is either a URI or left out for the glb binary chunk
+fn triangle_json(buffer: Option<&str>) -> String {
+    let uri = buffer.map_or(String::new(), |uri| format!(r#""uri": "{}","#, uri));
+    format!(r#"{{
+        "asset": {{ "version": "2.0" }},
+        "scene": 0,
+        "scenes": [ {{ "nodes": [0, 3, 4] }} ],
+        "nodes": [
+            {{ "translation": [0, 0, -5], "children": [1, 2] }},
+            {{ "mesh": 0, "scale": [2, 2, 2] }},
+            {{ "mesh": 0, "matrix": [1,0,0,0, 0,1,0,0, 0,0,1,0, 10,0,0,1] }},
+            {{ "camera": 0, "translation": [0, 0, 1], "rotation": [0, 0.7071068, 0, 0.7071068] }},
+            {{ "extensions": {{ "KHR_lights_punctual": {{ "light": 0 }} }}, "rotation": [-0.7071068, 0, 0, 0.7071068] }}
+        ],
+        "meshes": [ {{ "primitives": [ {{
+            "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }},
+            "indices": 2,
+            "material": 0
+        }} ] }} ],
+        "materials": [ {{
+            "pbrMetallicRoughness": {{
+                "baseColorFactor": [0.5, 0.5, 0.5, 1],
+                "baseColorTexture": {{ "index": 0 }},
+                "metallicFactor": 0,
+                "roughnessFactor": 0.5
+            }},
+            "emissiveFactor": [0.1, 0, 0]
+        }} ],
+        "textures": [ {{ "source": 0 }} ],
+        "images": [ {{ "uri": "data:image/png;base64,{}" }} ],
+        "cameras": [ {{ "type": "perspective", "perspective": {{ "yfov": 0.5, "aspectRatio": 1.5, "znear": 0.1 }} }} ],
+        "extensions": {{ "KHR_lights_punctual": {{ "lights": [
+            {{ "type": "directional", "color": [1, 1, 0.5], "intensity": 2 }}
+        ] }} }},
+        "buffers": [ {{ {} "byteLength": 68 }} ],
+        "bufferViews": [
+            {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+            {{ "buffer": 0, "byteOffset": 36, "byteLength": 24 }},
+            {{ "buffer": 0, "byteOffset": 60, "byteLength": 6 }}
+        ],
+        "accessors": [
+            {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+            {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }},
+            {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+        ]
+    }}"#, base64::encode(texture_png()), uri)
+}
+
+fn glb(json: &str, binary: &[u8]) -> Vec<u8> {
+    let mut json = json.as_bytes().to_vec();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&GLB_MAGIC.to_le_bytes());
+    data.extend_from_slice(&2u32.to_le_bytes());
+    data.extend_from_slice(&((12 + 8 + json.len() + 8 + binary.len()) as u32).to_le_bytes());
+    data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    data.extend_from_slice(&GLB_JSON_CHUNK.to_le_bytes());
+    data.extend_from_slice(&json);
+    data.extend_from_slice(&(binary.len() as u32).to_le_bytes());
+    data.extend_from_slice(&GLB_BIN_CHUNK.to_le_bytes());
+    data.extend_from_slice(binary);
+    data
+}
+
+fn check_triangle_scene(scene: &Scene) {
+    // the mesh is shared by both nodes using it
+    assert_eq!(scene.object_count(), 2);
+
+    // the child's scale applies under its parent's translation
+    let ray = Ray::new(Vector3::new(0.5, 1.2, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 5.0).abs() < 1e-6);
+
+    let material = isect.material.clone().unwrap();
+    assert_eq!(material.emissive.base_value(), Vector3::new(0.1, 0.0, 0.0));
+    assert!((material.specular.base_value() - Vector3::new(0.04, 0.04, 0.04)).magnitude() < 1e-9);
+    assert!(material.shininess > 1.0);
+    // texture coordinates are flipped into our convention, so the left of the
+    // triangle is mostly red
+    assert!((isect.uv_coords - Vector2::new(0.25, 0.6)).magnitude() < 1e-6);
+    let texture = material.diffuse.texture_map().unwrap();
+    assert!((texture.mapped_value(isect.uv_coords) - Vector3::new(0.75, 0.0, 0.25)).magnitude() < 1e-6);
+
+    let ray = Ray::new(Vector3::new(10.2, 0.2, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    assert!(scene.intersect(&ray, &mut Intersect::new()));
+    let ray = Ray::new(Vector3::new(1.5, 1.5, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    assert!(!scene.intersect(&ray, &mut Intersect::new()));
+
+    // the camera is turned to look down -x
+    let camera = scene.camera();
+    assert!((camera.eye() - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+    assert_eq!(camera.aspect_ratio(), 1.5);
+    let view = camera.ray_through(0.5, 0.5).unwrap().direction();
+    assert!((view - Vector3::new(-1.0, 0.0, 0.0)).magnitude() < 1e-6);
+
+    // the light node points its -z axis straight down
+    assert_eq!(scene.lights().len(), 1);
+    let light = &scene.lights()[0];
+    assert_eq!(light.color(), Vector3::new(2.0, 2.0, 1.0));
+    assert!((light.direction(Vector3::zero()) - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-6);
+}
+
+#[test]
+fn gltf_embedded_test() {
+    let uri = format!("data:application/octet-stream;base64,{}", base64::encode(triangle_buffer()));
+    let scene = parse(triangle_json(Some(&uri)).as_bytes()).unwrap();
+    check_triangle_scene(&scene);
+}
+
+#[test]
+fn gltf_external_buffer_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("models/buffers/triangle.bin", triangle_buffer());
+    let json = triangle_json(Some("buffers/triangle.bin"));
+    let scene = GltfParser.parse(json.as_bytes(), "models/triangle.gltf", &resolver).unwrap();
+    check_triangle_scene(&scene);
+
+    // only relative paths are resolved
+    let json = triangle_json(Some("https://example.com/triangle.bin"));
+    assert!(GltfParser.parse(json.as_bytes(), "models/triangle.gltf", &resolver).is_err());
+}
+
+#[test]
+fn glb_test() {
+    let data = glb(&triangle_json(None), &triangle_buffer());
+    let scene = parse(&data).unwrap();
+    check_triangle_scene(&scene);
+}
+
+#[test]
+fn gltf_error_test() {
+    assert!(parse(b"not json").is_err());
+    assert!(parse(br#"{ "asset": { "version": "1.0" } }"#).is_err());
+    // the external file doesn't exist
+    assert!(parse(triangle_json(Some("triangle.bin")).as_bytes()).is_err());
+    // the binary chunk is missing
+    assert!(parse(triangle_json(None).as_bytes()).is_err());
+
+    let mut truncated = triangle_buffer();
+    truncated.truncate(40);
+    assert!(parse(&glb(&triangle_json(None), &truncated)).is_err());
+
+    // accessors of the wrong type for what uses them
+    let uri = format!("data:application/octet-stream;base64,{}", base64::encode(triangle_buffer()));
+    let json = triangle_json(Some(&uri));
+    for &(from, to) in &[
+        (r#""count": 3, "type": "VEC3""#, r#""count": 3, "type": "VEC2""#),
+        (r#""count": 3, "type": "VEC2""#, r#""count": 3, "type": "SCALAR""#),
+        (r#""count": 3, "type": "SCALAR""#, r#""count": 1, "type": "VEC3""#),
+    ] {
+        let error = parse(json.replacen(from, to, 1).as_bytes()).err().unwrap();
+        assert!(error.to_string().contains("can't be used for"), "{}", error);
+    }
+
+    // counts far beyond the data, with and without a buffer view
+    let uvs = r#"{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }"#;
+    for &replacement in &[
+        r#"{ "componentType": 5126, "count": 1099511627776, "type": "VEC2" }"#,
+        r#"{ "bufferView": 1, "componentType": 5126, "count": 1099511627776, "type": "VEC2" }"#,
+        r#"{ "bufferView": 1, "componentType": 5126, "count": 18446744073709551615, "type": "VEC2" }"#,
+    ] {
+        assert!(parse(json.replacen(uvs, replacement, 1).as_bytes()).is_err(), "{}", replacement);
+    }
+    let strideless = json.replacen(r#""byteOffset": 36, "byteLength": 24"#, r#""byteOffset": 36, "byteLength": 24, "byteStride": 0"#, 1);
+    assert!(parse(strideless.as_bytes()).is_err());
+    // a short accessor without a buffer view is all zeros
+    let zeros = json.replacen(uvs, r#"{ "componentType": 5126, "count": 3, "type": "VEC2" }"#, 1);
+    assert!(parse(zeros.as_bytes()).is_ok());
+
+    // the camera's view has to make sense
+    for &(from, to) in &[(r#""aspectRatio": 1.5"#, r#""aspectRatio": 0"#), (r#""yfov": 0.5"#, r#""yfov": 4"#)] {
+        assert!(parse(json.replacen(from, to, 1).as_bytes()).is_err(), "{}", to);
+    }
+
+    let cycle = r#"{ "asset": { "version": "2.0" }, "scenes": [ { "nodes": [0] } ],
+        "nodes": [ { "children": [1] }, { "children": [0] } ] }"#;
+    assert!(parse(cycle.as_bytes()).is_err());
+
+    let singular = r#"{ "asset": { "version": "2.0" }, "scenes": [ { "nodes": [0] } ],
+        "nodes": [ { "scale": [0, 1, 1] } ] }"#;
+    assert!(parse(singular.as_bytes()).is_err());
+
+    // a projective matrix whose upper 3x3 is singular, though it can be inverted
+    let projective = r#"{ "asset": { "version": "2.0" }, "scenes": [ { "nodes": [0] } ],
+        "nodes": [ { "matrix": [1,0,0,0, 0,1,0,0, 0,0,0,1, 0,0,1,0] } ] }"#;
+    assert!(parse(projective.as_bytes()).is_err());
+    let image = r#"{ "asset": { "version": "2.0" }, "buffers": [ { "uri": "data:application/octet-stream;base64,AAAA", "byteLength": 3 } ],
+        "bufferViews": [ { "buffer": 0, "byteOffset": 1, "byteLength": 18446744073709551615 }, { "buffer": 0, "byteLength": 3 } ],
+        "images": [ { "bufferView": 0, "mimeType": "image/png" } ], "textures": [ { "source": 0 } ],
+        "materials": [ { "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } } ],
+        "meshes": [ { "primitives": [ { "attributes": { "POSITION": 0 }, "material": 0 } ] } ],
+        "accessors": [ { "bufferView": 1, "componentType": 5126, "count": 0, "type": "VEC3" } ],
+        "scenes": [ { "nodes": [0] } ], "nodes": [ { "mesh": 0 } ] }"#;
+    assert!(parse(image.as_bytes()).is_err());
+
+    // an empty scene is fine
+    let empty = parse(br#"{ "asset": { "version": "2.0" } }"#).unwrap();
+    assert_eq!(empty.object_count(), 0);
+}
+
+#[test]
+fn gltf_orthographic_test() {
+    let json = r#"{ "asset": { "version": "2.0" }, "scenes": [ { "nodes": [0] } ],
+        "nodes": [ { "camera": 0, "translation": [0, 0, 4] } ],
+        "cameras": [ { "type": "orthographic", "orthographic": { "xmag": 3, "ymag": 1.5, "znear": 0.1, "zfar": 10 } } ] }"#;
+    let camera = parse(json.as_bytes()).unwrap().camera().clone();
+    assert_eq!(camera.projection(), Projection::Orthographic { height: 3.0 });
+    assert_eq!(camera.aspect_ratio(), 2.0);
+
+    // rays go straight ahead from across the view
+    let ray = camera.ray_through(1.0, 1.0).unwrap();
+    assert!((ray.position() - Vector3::new(3.0, 1.5, 4.0)).magnitude() < 1e-9);
+    assert!((ray.direction() - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-9);
+
+    let flat = json.replace("\"ymag\": 1.5", "\"ymag\": 0");
+    assert!(parse(flat.as_bytes()).is_err());
+}
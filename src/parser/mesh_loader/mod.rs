@@ -0,0 +1,38 @@
+//! Loaders for the mesh formats that hold a single triangle mesh, `.ply` and
+//! `.stl`, producing the same trimesh data the `.ray` `trimesh` block builds.
+
+#[cfg(test)]
+mod tests;
+mod ply;
+mod stl;
+
+use std::path::Path;
+use std::result;
+
+use super::error::ParseError;
+use super::obj_parser::ObjModel;
+use super::resolver::ResourceResolver;
+
+use super::super::scene::objects::Mesh;
+
+type Result<T> = result::Result<T, ParseError>;
+
+/// Loads the resolved mesh resource `resolved`, picking the format from its
+/// extension. The parts of an `.obj` file are merged together.
+pub fn load_mesh(resolved: &str, resolver: &dyn ResourceResolver) -> Result<Mesh> {
+    let extension = Path::new(resolved).extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    let parse: fn(&[u8]) -> Result<Mesh> = match extension.as_deref() {
+        Some("obj") => return ObjModel::load(resolved, resolver).map(|model| model.merged_mesh()),
+        Some("ply") => ply::parse_ply,
+        Some("stl") => stl::parse_stl,
+        _ => return Err(ParseError::new(format!("unknown mesh format for '{}'", resolved))),
+    };
+
+    let contents = resolver.read(resolved)?;
+
+    parse(&contents)
+        .map_err(|err| err.in_source(resolved))
+}
@@ -0,0 +1,354 @@
+use cgmath::{Vector2, Vector3};
+
+use std::str::{self, SplitAsciiWhitespace};
+
+use super::*;
+
+use super::super::super::scene::objects::TrimeshFace;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Result<ScalarType> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::Char,
+            "uchar" | "uint8" => ScalarType::UChar,
+            "short" | "int16" => ScalarType::Short,
+            "ushort" | "uint16" => ScalarType::UShort,
+            "int" | "int32" => ScalarType::Int,
+            "uint" | "uint32" => ScalarType::UInt,
+            "float" | "float32" => ScalarType::Float,
+            "double" | "float64" => ScalarType::Double,
+            _ => return Err(ParseError::new(format!("unknown property type '{}'", name))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Char | ScalarType::UChar => 1,
+            ScalarType::Short | ScalarType::UShort => 2,
+            ScalarType::Int | ScalarType::UInt | ScalarType::Float => 4,
+            ScalarType::Double => 8,
+        }
+    }
+
+    /// Scale that maps the type's range onto `[0, 1]`, for colors
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::Char | ScalarType::UChar => 1.0 / 255.0,
+            ScalarType::Short | ScalarType::UShort => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// Count type, then item type
+    List(ScalarType, ScalarType),
+}
+
+struct Property {
+    name: String,
+    property_type: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&property.name.as_str()))
+    }
+
+    /// How many of the element there could be in `length` bytes of data,
+    /// which caps the space reserved for them whatever the header says
+    fn capacity(&self, format: Format, length: usize) -> usize {
+        let size: usize = self.properties.iter().map(|property| match (format, property.property_type) {
+            // at least a digit and the space after it
+            (Format::Ascii, _) => 2,
+            (_, PropertyType::Scalar(scalar_type)) | (_, PropertyType::List(scalar_type, _)) => scalar_type.size(),
+        }).sum();
+        self.count.min(length / size.max(1))
+    }
+}
+
+/// Source of property values, in either encoding
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], position: usize, big_endian: bool },
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64> {
+        match *self {
+            Body::Ascii(ref mut words) => {
+                let word = words.next().ok_or_else(|| ParseError::new("unexpected end of data"))?;
+                word.parse().map_err(|_| ParseError::new(format!("invalid number '{}'", word)))
+            },
+            Body::Binary { data, ref mut position, big_endian } => {
+                let size = scalar_type.size();
+                let bytes = data.get(*position..*position + size)
+                    .ok_or_else(|| ParseError::new("unexpected end of data"))?;
+                *position += size;
+
+                macro_rules! read_as {
+                    ($type:ty) => {{
+                        let mut buffer = [0; ::std::mem::size_of::<$type>()];
+                        buffer.copy_from_slice(bytes);
+                        f64::from(if big_endian { <$type>::from_be_bytes(buffer) } else { <$type>::from_le_bytes(buffer) })
+                    }};
+                }
+
+                Ok(match scalar_type {
+                    ScalarType::Char => read_as!(i8),
+                    ScalarType::UChar => read_as!(u8),
+                    ScalarType::Short => read_as!(i16),
+                    ScalarType::UShort => read_as!(u16),
+                    ScalarType::Int => read_as!(i32),
+                    ScalarType::UInt => read_as!(u32),
+                    ScalarType::Float => read_as!(f32),
+                    ScalarType::Double => read_as!(f64),
+                })
+            },
+        }
+    }
+
+    /// Reads a list count or vertex index, which has to be a whole number
+    /// that isn't negative
+    fn read_index(&mut self, scalar_type: ScalarType) -> Result<usize> {
+        let value = self.read(scalar_type)?;
+        if !(value >= 0.0 && value.fract() == 0.0) {
+            return Err(ParseError::new(format!("invalid count or index {}", value)));
+        }
+        Ok(value as usize)
+    }
+}
+
+/// Parses an ASCII or binary `.ply` file. Vertices need `x`, `y` and `z`
+/// properties and may have normals, texture coordinates and colors, faces
+/// are polygons which are triangulated.
+pub fn parse_ply(contents: &[u8]) -> Result<Mesh> {
+    let (format, elements, body_start) = parse_header(contents)?;
+
+    let mut body = match format {
+        Format::Ascii => {
+            let text = str::from_utf8(&contents[body_start..])
+                .map_err(|_| ParseError::new("ascii data isn't valid text"))?;
+            Body::Ascii(text.split_ascii_whitespace())
+        },
+        Format::BinaryLittleEndian => Body::Binary { data: contents, position: body_start, big_endian: false },
+        Format::BinaryBigEndian => Body::Binary { data: contents, position: body_start, big_endian: true },
+    };
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut faces = Vec::new();
+
+    let mut values = Vec::new();
+    let mut polygon = Vec::new();
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let position = [
+                    element.property_index(&["x"]),
+                    element.property_index(&["y"]),
+                    element.property_index(&["z"]),
+                ];
+                let position = match position {
+                    [Some(x), Some(y), Some(z)] => [x, y, z],
+                    _ => return Err(ParseError::new("vertices need x, y and z properties")),
+                };
+                let normal = match [element.property_index(&["nx"]), element.property_index(&["ny"]), element.property_index(&["nz"])] {
+                    [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+                    _ => None,
+                };
+                let uv = match [element.property_index(&["u", "s", "texture_u"]), element.property_index(&["v", "t", "texture_v"])] {
+                    [Some(u), Some(v)] => Some([u, v]),
+                    _ => None,
+                };
+                let color = match [element.property_index(&["red", "r"]), element.property_index(&["green", "g"]), element.property_index(&["blue", "b"])] {
+                    [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+                    _ => None,
+                };
+                let color_scale = |index: usize| match element.properties[index].property_type {
+                    PropertyType::Scalar(scalar_type) => scalar_type.color_scale(),
+                    PropertyType::List(..) => 1.0,
+                };
+
+                vertices.reserve(element.capacity(format, contents.len() - body_start));
+                for _ in 0..element.count {
+                    read_scalars(&mut body, element, &mut values)?;
+
+                    vertices.push(Vector3::new(values[position[0]], values[position[1]], values[position[2]]));
+                    if let Some([x, y, z]) = normal {
+                        normals.push(Vector3::new(values[x], values[y], values[z]));
+                    }
+                    if let Some([u, v]) = uv {
+                        uvs.push(Vector2::new(values[u], values[v]));
+                    }
+                    if let Some([r, g, b]) = color {
+                        colors.push(Vector3::new(
+                            values[r] * color_scale(r),
+                            values[g] * color_scale(g),
+                            values[b] * color_scale(b),
+                        ));
+                    }
+                }
+            },
+            "face" => {
+                let indices = element.property_index(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| ParseError::new("faces need a vertex_indices property"))?;
+
+                faces.reserve(element.capacity(format, contents.len() - body_start));
+                for _ in 0..element.count {
+                    for (i, property) in element.properties.iter().enumerate() {
+                        match property.property_type {
+                            PropertyType::Scalar(scalar_type) => {
+                                body.read(scalar_type)?;
+                            },
+                            PropertyType::List(count_type, item_type) => {
+                                let count = body.read_index(count_type)?;
+                                polygon.clear();
+                                for _ in 0..count {
+                                    polygon.push(body.read_index(item_type)?);
+                                }
+                                if i != indices {
+                                    continue;
+                                }
+
+                                if polygon.len() < 3 {
+                                    return Err(ParseError::new("faces need at least three vertices"));
+                                }
+                                // triangulate polygons as a fan around their first vertex
+                                for j in 1..polygon.len() - 1 {
+                                    faces.push(TrimeshFace::new(polygon[0], polygon[j], polygon[j + 1]));
+                                }
+                            },
+                        }
+                    }
+                }
+            },
+            // skip the elements we don't use, like edges
+            _ => for _ in 0..element.count {
+                read_scalars(&mut body, element, &mut values)?;
+            },
+        }
+    }
+
+    if faces.iter().any(|face| face.vertices().iter().any(|&index| index >= vertices.len())) {
+        return Err(ParseError::new("face refers to a vertex that doesn't exist"));
+    }
+
+    Ok(Mesh::new(vertices, faces, normals, uvs, Vec::new()).with_colors(colors))
+}
+
+/// Reads every property of one element into `values`, lists are skipped and
+/// left as zero
+fn read_scalars(body: &mut Body, element: &Element, values: &mut Vec<f64>) -> Result<()> {
+    values.clear();
+    for property in &element.properties {
+        match property.property_type {
+            PropertyType::Scalar(scalar_type) => values.push(body.read(scalar_type)?),
+            PropertyType::List(count_type, item_type) => {
+                let count = body.read_index(count_type)?;
+                for _ in 0..count {
+                    body.read(item_type)?;
+                }
+                values.push(0.0);
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Returns the format, the declared elements and where the data starts
+fn parse_header(contents: &[u8]) -> Result<(Format, Vec<Element>, usize)> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut position = 0;
+    let mut first = true;
+
+    loop {
+        let end = contents[position..].iter().position(|&byte| byte == b'\n')
+            .map(|end| position + end)
+            .ok_or_else(|| ParseError::new("missing end_header"))?;
+        let line = str::from_utf8(&contents[position..end])
+            .map_err(|_| ParseError::new("header isn't valid text"))?;
+        position = end + 1;
+
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or("");
+
+        if first {
+            if keyword != "ply" {
+                return Err(ParseError::new("not a ply file"));
+            }
+            first = false;
+            continue;
+        }
+
+        match keyword {
+            "format" => format = Some(match words.next() {
+                Some("ascii") => Format::Ascii,
+                Some("binary_little_endian") => Format::BinaryLittleEndian,
+                Some("binary_big_endian") => Format::BinaryBigEndian,
+                _ => return Err(ParseError::new(format!("unknown format in '{}'", line))),
+            }),
+            "element" => {
+                let name = words.next();
+                let count = words.next().and_then(|count| count.parse().ok());
+                match (name, count) {
+                    (Some(name), Some(count)) => elements.push(Element { name: name.to_string(), count, properties: Vec::new() }),
+                    _ => return Err(ParseError::new(format!("invalid element '{}'", line))),
+                }
+            },
+            "property" => {
+                let element = elements.last_mut()
+                    .ok_or_else(|| ParseError::new("property before any element"))?;
+                let property = match words.next() {
+                    Some("list") => {
+                        let count_type = ScalarType::from_name(words.next().unwrap_or(""))?;
+                        let item_type = ScalarType::from_name(words.next().unwrap_or(""))?;
+                        PropertyType::List(count_type, item_type)
+                    },
+                    Some(scalar_type) => PropertyType::Scalar(ScalarType::from_name(scalar_type)?),
+                    None => return Err(ParseError::new("property needs a type")),
+                };
+                let name = words.next()
+                    .ok_or_else(|| ParseError::new("property needs a name"))?;
+                element.properties.push(Property { name: name.to_string(), property_type: property });
+            },
+            "end_header" => break,
+            // comments and obj_info
+            _ => {},
+        }
+    }
+
+    let format = format.ok_or_else(|| ParseError::new("missing format"))?;
+    Ok((format, elements, position))
+}
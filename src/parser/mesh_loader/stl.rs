@@ -0,0 +1,116 @@
+use cgmath::Vector3;
+
+use std::collections::HashMap;
+use std::str;
+
+use super::*;
+
+use super::super::super::scene::objects::TrimeshFace;
+
+/// Size of the header and triangle count of a binary file
+const BINARY_HEADER_SIZE: usize = 84;
+/// Normal, three vertices and an attribute byte count
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// Parses an ASCII or binary `.stl` file. STL stores every triangle's corners
+/// separately, so corners in the same place are welded back into shared
+/// vertices. Facet normals are dropped in favor of the triangles' own.
+pub fn parse_stl(contents: &[u8]) -> Result<Mesh> {
+    let mut welder = Welder::new();
+
+    // binary files may also start with "solid", so go by the size they declare
+    if is_binary(contents) {
+        let count = read_u32(contents, 80) as usize;
+        welder.reserve(count);
+        for i in 0..count {
+            let triangle = BINARY_HEADER_SIZE + i * BINARY_TRIANGLE_SIZE;
+            let corner = |j: usize| {
+                let offset = triangle + 12 + j * 12;
+                Vector3::new(read_f32(contents, offset), read_f32(contents, offset + 4), read_f32(contents, offset + 8))
+            };
+            welder.add_triangle([corner(0), corner(1), corner(2)]);
+        }
+    } else {
+        let text = str::from_utf8(contents)
+            .map_err(|_| ParseError::new("not a binary stl file, and not valid text"))?;
+        let mut words = text.split_ascii_whitespace();
+        if words.next() != Some("solid") {
+            return Err(ParseError::new("not an stl file"));
+        }
+
+        let mut corners = Vec::with_capacity(3);
+        while let Some(word) = words.next() {
+            if word != "vertex" {
+                continue;
+            }
+
+            let mut coordinate = || -> Result<f64> {
+                let word = words.next().ok_or_else(|| ParseError::new("unexpected end of file"))?;
+                word.parse().map_err(|_| ParseError::new(format!("invalid number '{}'", word)))
+            };
+            corners.push(Vector3::new(coordinate()?, coordinate()?, coordinate()?));
+
+            if corners.len() == 3 {
+                welder.add_triangle([corners[0], corners[1], corners[2]]);
+                corners.clear();
+            }
+        }
+
+        if !corners.is_empty() {
+            return Err(ParseError::new("facet with fewer than three vertices"));
+        }
+    }
+
+    Ok(Mesh::new(welder.vertices, welder.faces, Vec::new(), Vec::new(), Vec::new()))
+}
+
+fn is_binary(contents: &[u8]) -> bool {
+    contents.len() >= BINARY_HEADER_SIZE
+        && contents.len() == BINARY_HEADER_SIZE + read_u32(contents, 80) as usize * BINARY_TRIANGLE_SIZE
+}
+
+fn read_u32(contents: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&contents[offset..offset + 4]);
+    u32::from_le_bytes(buffer)
+}
+
+fn read_f32(contents: &[u8], offset: usize) -> f64 {
+    f64::from(f32::from_bits(read_u32(contents, offset)))
+}
+
+/// Merges corners with identical positions into one vertex
+struct Welder {
+    vertices: Vec<Vector3<f64>>,
+    faces: Vec<TrimeshFace>,
+    indices: HashMap<[u64; 3], usize>,
+}
+
+impl Welder {
+    fn new() -> Welder {
+        Welder { vertices: Vec::new(), faces: Vec::new(), indices: HashMap::new() }
+    }
+
+    fn reserve(&mut self, triangles: usize) {
+        // closed meshes have about half as many vertices as triangles
+        self.vertices.reserve(triangles / 2);
+        self.faces.reserve(triangles);
+        self.indices.reserve(triangles / 2);
+    }
+
+    fn add_triangle(&mut self, corners: [Vector3<f64>; 3]) {
+        let [a, b, c] = corners.map(|corner| self.vertex(corner));
+        self.faces.push(TrimeshFace::new(a, b, c));
+    }
+
+    fn vertex(&mut self, position: Vector3<f64>) -> usize {
+        // -0.0 and 0.0 are the same place
+        let key = [position.x + 0.0, position.y + 0.0, position.z + 0.0].map(f64::to_bits);
+        let next = self.vertices.len();
+        let index = *self.indices.entry(key).or_insert(next);
+        if index == next {
+            self.vertices.push(position);
+        }
+        index
+    }
+}
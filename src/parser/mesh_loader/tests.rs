@@ -0,0 +1,233 @@
+use super::*;
+use super::ply::parse_ply;
+use super::stl::parse_stl;
+
+use cgmath::{Vector2, Vector3};
+
+use super::super::{MemoryResolver, Parser, ParserRegistry, RayParser};
+use super::super::super::scene::objects::{Intersect, Ray, RayType};
+
+static ASCII_PLY: &str = "ply
+format ascii 1.0
+comment a colored quad
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+0 0 0 0 0 1 0 0 255 0 0
+1 0 0 0 0 1 1 0 0 255 0
+1 1 0 0 0 1 1 1 0 0 255
+0 1 0 0 0 1 0 1 255 255 255
+4 0 1 2 3
+0 1
+";
+
+/// The same quad as `ASCII_PLY`, without normals and texture coordinates
+fn binary_ply(big_endian: bool) -> Vec<u8> {
+    let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+    let mut contents = format!("ply
+format {} 1.0
+element vertex 4
+property double x
+property double y
+property double z
+property ushort red
+property ushort green
+property ushort blue
+element face 1
+property uchar flags
+property list uint8 uint32 vertex_indices
+end_header
+", format).into_bytes();
+
+    let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+    for (i, position) in positions.iter().enumerate() {
+        for &coordinate in position {
+            let f: f64 = coordinate;
+            contents.extend_from_slice(&if big_endian { f.to_be_bytes() } else { f.to_le_bytes() });
+        }
+        for channel in 0..3 {
+            let value: u16 = if channel == i % 3 { 65535 } else { 0 };
+            contents.extend_from_slice(&if big_endian { value.to_be_bytes() } else { value.to_le_bytes() });
+        }
+    }
+
+    contents.push(7);
+    contents.push(4);
+    for index in 0u32..4 {
+        contents.extend_from_slice(&if big_endian { index.to_be_bytes() } else { index.to_le_bytes() });
+    }
+    contents
+}
+
+/// A binary stl grid of `size` by `size` quads in the z = 0 plane
+fn binary_stl_grid(size: usize) -> Vec<u8> {
+    let mut contents = vec![0u8; 80];
+    contents.extend_from_slice(&(size as u32 * size as u32 * 2).to_le_bytes());
+
+    let mut triangle = |corners: [[usize; 2]; 3]| {
+        contents.extend_from_slice(&[0u8; 12]);
+        for corner in &corners {
+            for &coordinate in &[corner[0] as f32, corner[1] as f32, 0.0] {
+                contents.extend_from_slice(&coordinate.to_le_bytes());
+            }
+        }
+        contents.extend_from_slice(&[0u8; 2]);
+    };
+
+    for x in 0..size {
+        for y in 0..size {
+            triangle([[x, y], [x + 1, y], [x + 1, y + 1]]);
+            triangle([[x, y], [x + 1, y + 1], [x, y + 1]]);
+        }
+    }
+    contents
+}
+
+#[test]
+fn ascii_ply_test() {
+    let mesh = parse_ply(ASCII_PLY.as_bytes()).unwrap();
+    assert_eq!(mesh.vertices().len(), 4);
+    assert_eq!(mesh.faces().len(), 2);
+    assert_eq!(mesh.normals()[2], Vector3::new(0.0, 0.0, 1.0));
+    assert_eq!(mesh.uvs()[2], Vector2::new(1.0, 1.0));
+    assert_eq!(mesh.colors()[0], Vector3::new(1.0, 0.0, 0.0));
+    assert_eq!(mesh.colors()[3], Vector3::new(1.0, 1.0, 1.0));
+
+    // hits carry the interpolated vertex color
+    let ray = Ray::new(Vector3::new(0.5, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(mesh.intersect(&ray, &mut isect));
+    assert_eq!(isect.vertex_color, Some(Vector3::new(0.5, 0.5, 0.0)));
+}
+
+#[test]
+fn binary_ply_test() {
+    for &big_endian in &[false, true] {
+        let mesh = parse_ply(&binary_ply(big_endian)).unwrap();
+        assert_eq!(mesh.vertices()[2], Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.faces().len(), 2);
+        assert!(mesh.normals().is_empty());
+        assert_eq!(mesh.colors()[1], Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    let mut truncated = binary_ply(false);
+    truncated.pop();
+    assert!(parse_ply(&truncated).is_err());
+}
+
+#[test]
+fn ply_error_test() {
+    assert!(parse_ply(b"obj\n").is_err());
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n").is_err());
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n").is_err());
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty quad x\nend_header\n").is_err());
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n").is_err());
+    // vertex indices have to be whole numbers that aren't negative
+    for &face in &["3 0 -1 2", "3 0 1.5 2", "3 0 nan 2", "3 0 inf 2"] {
+        let ply = format!("ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar float vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n{}\n", face);
+        assert!(parse_ply(ply.as_bytes()).is_err(), "{}", face);
+    }
+    // counts far beyond the data fail when it runs out rather than up front
+    assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 4000000000000\nproperty float x\nproperty float y\n\
+        property float z\nend_header\n0 0 0\n").is_err());
+    assert!(parse_ply(b"ply\nformat binary_little_endian 1.0\nelement face 4000000000000\n\
+        property list uchar int vertex_indices\nend_header\n").is_err());
+}
+
+#[test]
+fn stl_test() {
+    let ascii = "solid triangle
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+  facet normal 0 0 1
+    outer loop
+      vertex 1 0 0
+      vertex 1 1 0
+      vertex -0 1 0
+    endloop
+  endfacet
+endsolid triangle
+";
+    let mesh = parse_stl(ascii.as_bytes()).unwrap();
+    assert_eq!(mesh.faces().len(), 2);
+    // the shared corners are welded together
+    assert_eq!(mesh.vertices().len(), 4);
+
+    let mesh = parse_stl(&binary_stl_grid(2)).unwrap();
+    assert_eq!(mesh.faces().len(), 8);
+    assert_eq!(mesh.vertices().len(), 9);
+
+    assert!(parse_stl(b"solid\nvertex 0 0 0\nvertex 1 0 0\n").is_err());
+    assert!(parse_stl(b"not an stl").is_err());
+}
+
+#[test]
+fn large_mesh_test() {
+    // big enough that anything quadratic in the triangle count would not finish
+    let size = 300;
+    let mesh = parse_stl(&binary_stl_grid(size)).unwrap();
+    assert_eq!(mesh.faces().len(), size * size * 2);
+    assert_eq!(mesh.vertices().len(), (size + 1) * (size + 1));
+
+    let ray = Ray::new(Vector3::new(123.25, 45.75, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(mesh.intersect(&ray, &mut isect));
+    assert!((isect.t - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn load_mesh_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("quad.ply", ASCII_PLY);
+    resolver.insert("grid.STL", binary_stl_grid(1));
+    resolver.insert("quad.xyz", "");
+    assert_eq!(load_mesh("quad.ply", &resolver).unwrap().faces().len(), 2);
+    assert_eq!(load_mesh("grid.STL", &resolver).unwrap().faces().len(), 2);
+    assert!(load_mesh("quad.xyz", &resolver).is_err());
+
+    let scene = ParserRegistry::new().parse(b"SBT-raytracer 1.0
+        material = { diffuse = (0.2, 0.2, 0.2); };
+        mesh('quad.ply');
+        translate(5, 0, 0, trimesh { file = 'grid.STL'; });
+    ", "scan.ray", &resolver).unwrap();
+    assert_eq!(scene.object_count(), 2);
+
+    // the colored mesh takes its diffuse color from its vertices
+    let ray = Ray::new(Vector3::new(0.5, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    let material = isect.material.clone().unwrap();
+    assert!(material.diffuse.uses_vertex_colors());
+    assert_eq!(material.diffuse.value(&isect), Vector3::new(0.5, 0.5, 0.0));
+
+    let ray = Ray::new(Vector3::new(5.5, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    let material = isect.material.clone().unwrap();
+    assert!(!material.diffuse.uses_vertex_colors());
+    assert_eq!(material.diffuse.value(&isect), Vector3::new(0.2, 0.2, 0.2));
+
+    assert!(RayParser.parse(b"SBT-raytracer 1.0 mesh('missing.ply');", "scan.ray", &resolver).is_err());
+}
@@ -1,36 +1,189 @@
 mod ray_tokenizer;
 mod ray_scene_builder;
+mod obj_parser;
+mod mesh_loader;
+mod gltf_parser;
+mod resolver;
+#[cfg(test)]
+mod tests;
 pub mod error;
 
-use std::error::Error;
+use image;
 
+use std::path::Path;
+use std::str;
+
+use self::error::ParseError;
 use self::ray_tokenizer::RayTokenizer;
-use self::ray_scene_builder::RaySceneBuilder;
 
-use super::scene::Scene;
+pub use self::gltf_parser::GltfParser;
+pub use self::obj_parser::ObjParser;
+pub use self::ray_scene_builder::RaySceneBuilder;
+pub use self::resolver::{FileResolver, MemoryResolver, ResourceResolver};
+
+use super::scene::{Scene, TextureMap};
+use super::scene::animation::AnimatedScene;
+
+/// A scene with its animation, which may borrow the resolver it was read with
+pub type Animated<'a> = Box<dyn AnimatedScene + 'a>;
 
 /// Trait a scene-file parser must implement to return a generalized
 /// Scene that our ray tracer understands how to render
-pub trait Parser<E> {
-   fn parse_scene(input: &str) -> Result<Scene, E>
-       where E: Error;
+pub trait Parser {
+    /// Parses the contents of a scene. `source` names where it came from,
+    /// usually its path, and relative resources it refers to are found
+    /// through `resolver` relative to that name.
+    fn parse(&self, input: &[u8], source: &str, resolver: &dyn ResourceResolver) -> Result<Scene, ParseError>;
+
+    /// Parses a scene along with its animation. None when the format can't
+    /// be animated, or the scene isn't.
+    fn parse_animated<'a>(&self, _input: &[u8], _source: &str, _resolver: &'a dyn ResourceResolver) -> Result<Option<Animated<'a>>, ParseError> {
+        Ok(None)
+    }
+
+    /// Lowercase file extensions of the format, without the dot
+    fn extensions(&self) -> &[&str];
+
+    /// Whether the start of `input` looks like this parser's format
+    fn detect(&self, input: &[u8]) -> bool;
 }
 
 /// Parser for `.ray` files
 pub struct RayParser;
 
-impl Parser<error::TokenizationError> for RayParser {
-	fn parse_scene(input: &str) -> Result<Scene, error::TokenizationError> {
+impl RayParser {
+    /// Parses a `.ray` scene into its element hierarchy rather than a Scene,
+    /// so it can be inspected or written back out with `write_ray`
+    pub fn parse_builder<'a>(&self, input: &[u8], source: &str, resolver: &'a dyn ResourceResolver) -> Result<RaySceneBuilder<'a>, ParseError> {
+        let input = str::from_utf8(input)
+            .map_err(|_| ParseError::new("scene isn't valid UTF-8").in_source(source))?;
+
         // construct a tokenizer with out input
-		let tokenizer = RayTokenizer::new(input);
+        let tokenizer = RayTokenizer::new(input);
 
         // tokenize input and check for token errors
-        let tokens = tokenizer.collect::<Result<Vec<_>, error::TokenizationError>>()?;
+        let tokens = tokenizer.collect::<Result<Vec<_>, ParseError>>()
+            .map_err(|err| err.in_source(source))?;
+
+        // build internal scene representation and check for syntax errors
+        RaySceneBuilder::from_source(tokens, source, resolver)
+            .map_err(|err| err.in_source(source))
+    }
+}
+
+impl Parser for RayParser {
+    fn parse(&self, input: &[u8], source: &str, resolver: &dyn ResourceResolver) -> Result<Scene, ParseError> {
+        let scene_builder = self.parse_builder(input, source, resolver)?;
+
+        // render internal representation into generalized Scene and return it
+        Ok(scene_builder.create_scene())
+    }
+
+    fn parse_animated<'a>(&self, input: &[u8], source: &str, resolver: &'a dyn ResourceResolver) -> Result<Option<Animated<'a>>, ParseError> {
+        let scene_builder = self.parse_builder(input, source, resolver)?;
+        if scene_builder.animation().is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(scene_builder)))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ray"]
+    }
+
+    fn detect(&self, input: &[u8]) -> bool {
+        // the header may follow whitespace and comments, and the cut off start
+        // may end partway through a character
+        let start = &input[..input.len().min(4096)];
+        let start = str::from_utf8(start)
+            .or_else(|err| str::from_utf8(&start[..err.valid_up_to()]))
+            .unwrap_or("");
+        let mut tokens = RayTokenizer::new(start);
+        matches!(tokens.next(), Some(Ok(ray_tokenizer::Token::SbtRaytracer)))
+    }
+}
+
+/// Picks the parser for a scene by its file extension, falling back to the
+/// magic header at the start of its contents
+pub struct ParserRegistry {
+    parsers: Vec<Box<dyn Parser>>,
+}
+
+impl ParserRegistry {
+    /// A registry without any parsers
+    pub fn empty() -> ParserRegistry {
+        ParserRegistry { parsers: Vec::new() }
+    }
+
+    /// A registry with every parser this crate provides
+    pub fn new() -> ParserRegistry {
+        let mut registry = ParserRegistry::empty();
+        registry.register(Box::new(RayParser));
+        registry.register(Box::new(GltfParser));
+        registry.register(Box::new(ObjParser));
+        registry
+    }
+
+    /// Adds a parser, which takes priority over those registered before it
+    pub fn register(&mut self, parser: Box<dyn Parser>) {
+        self.parsers.insert(0, parser);
+    }
+
+    /// The parser for the scene named `source` with the given contents
+    pub fn parser_for(&self, source: &str, input: &[u8]) -> Option<&dyn Parser> {
+        let extension = Path::new(source).extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
 
-		// build internal scene representation and check for syntax errors
-		let scene_builder = RaySceneBuilder::new(tokens)?;
+        extension
+            .and_then(|extension| self.parsers.iter().find(|parser| parser.extensions().contains(&extension.as_str())))
+            .or_else(|| self.parsers.iter().find(|parser| parser.detect(input)))
+            .map(|parser| parser.as_ref())
+    }
+
+    pub fn parse(&self, input: &[u8], source: &str, resolver: &dyn ResourceResolver) -> Result<Scene, ParseError> {
+        match self.parser_for(source, input) {
+            Some(parser) => parser.parse(input, source, resolver),
+            None => Err(ParseError::new("unrecognized scene format").in_source(source)),
+        }
+    }
+
+    pub fn parse_animated<'a>(&self, input: &[u8], source: &str, resolver: &'a dyn ResourceResolver) -> Result<Option<Animated<'a>>, ParseError> {
+        match self.parser_for(source, input) {
+            Some(parser) => parser.parse_animated(input, source, resolver),
+            None => Err(ParseError::new("unrecognized scene format").in_source(source)),
+        }
+    }
+
+    /// Reads and parses the scene file at `path`, resolving its resources
+    /// from the file system
+    pub fn parse_file(&self, path: &Path) -> Result<Scene, ParseError> {
+        let source = path.to_string_lossy();
+        let input = FileResolver.read(&source)?;
+        self.parse(&input, &source, &FileResolver)
+    }
+
+    /// Reads and parses the scene file at `path` along with its animation,
+    /// None when it isn't animated
+    pub fn parse_animated_file(&self, path: &Path) -> Result<Option<Animated<'static>>, ParseError> {
+        let source = path.to_string_lossy();
+        let input = FileResolver.read(&source)?;
+        self.parse_animated(&input, &source, &FileResolver)
+    }
+}
+
+impl Default for ParserRegistry {
+    fn default() -> ParserRegistry {
+        ParserRegistry::new()
+    }
+}
 
-		// render internal representation into generalized Scene and return it
-		Ok(scene_builder.create_scene())
-	}
+/// Loads the image `name` refers to from within `source` as a texture map
+/// called `filename`
+fn load_texture_map(name: &str, source: &str, filename: &str, resolver: &dyn ResourceResolver) -> Result<TextureMap, ParseError> {
+    let resolved = resolver.resolve(name, source)?;
+    let data = resolver.read(&resolved)?;
+    let image = image::load_from_memory(&data)
+        .map_err(|err| ParseError::new(format!("couldn't load texture map '{}': {}", resolved, err)))?;
+    Ok(TextureMap::new(filename, image.to_rgb()))
 }
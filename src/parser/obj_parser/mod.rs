@@ -0,0 +1,400 @@
+//! This module parses Wavefront `.obj` files, along with the `.mtl` material
+//! libraries they reference, into meshes that can either make up a scene on
+//! their own or be placed inside of a `.ray` scene.
+
+#[cfg(test)]
+mod tests;
+
+use cgmath::{Vector2, Vector3, Zero};
+
+use std::collections::HashMap;
+use std::result;
+use std::str::{self, SplitWhitespace};
+use std::sync::Arc;
+
+use super::*;
+use super::error::ParseError;
+
+use super::super::scene::{Camera, Material, MaterialParameter, SceneObject, TransformNode};
+use super::super::scene::objects::{Mesh, Trimesh, TrimeshFace};
+
+type Result<T> = result::Result<T, ParseError>;
+
+/// Parser for `.obj` files
+pub struct ObjParser;
+
+impl Parser for ObjParser {
+    fn parse(&self, input: &[u8], source: &str, resolver: &dyn ResourceResolver) -> Result<Scene> {
+        let input = str::from_utf8(input)
+            .map_err(|_| ParseError::new("not valid text").in_source(source))?;
+
+        ObjModel::parse(input, source, resolver)
+            .map(|model| model.create_scene())
+            .map_err(|err| err.in_source(source))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["obj"]
+    }
+
+    /// OBJ has no header, so look for a first statement that only OBJ files start with
+    fn detect(&self, input: &[u8]) -> bool {
+        let input = String::from_utf8_lossy(&input[..input.len().min(4096)]);
+        input.lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .find(|line| !line.is_empty())
+            .and_then(|line| line.split_whitespace().next())
+            .is_some_and(|keyword| ["v", "vt", "vn", "f", "g", "o", "mtllib", "usemtl", "s"].contains(&keyword))
+    }
+}
+
+/// The geometry of an `.obj` file, split into one mesh for every group and
+/// material used together
+pub struct ObjModel {
+    parts: Vec<ObjPart>,
+}
+
+pub struct ObjPart {
+    /// Name of the group or object the faces were declared in
+    pub name: String,
+    pub mesh: Arc<Mesh>,
+    /// Material from `usemtl`, if the faces had one
+    pub material: Option<Arc<Material>>,
+}
+
+impl ObjModel {
+    /// Reads and parses the resolved `.obj` resource `resolved`
+    pub fn load(resolved: &str, resolver: &dyn ResourceResolver) -> Result<ObjModel> {
+        let contents = read_text(resolved, resolver)?;
+
+        ObjModel::parse(&contents, resolved, resolver)
+            .map_err(|err| err.in_source(resolved))
+    }
+
+    /// Parses `.obj` source named `source`, which material libraries are
+    /// resolved relative to
+    pub fn parse(input: &str, source: &str, resolver: &dyn ResourceResolver) -> Result<ObjModel> {
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut normals = Vec::new();
+        let mut materials = HashMap::new();
+
+        let mut parts: Vec<PartBuilder> = Vec::new();
+        let mut part_indices = HashMap::new();
+        let mut group = String::from("default");
+        let mut material: Option<String> = None;
+
+        for (number, line) in input.lines().enumerate() {
+            let line_error = |err: ParseError| ParseError::new(format!("line {}: {}", number + 1, err));
+
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+
+            match keyword {
+                "v" => positions.push(parse_vector3(&mut words).map_err(line_error)?),
+                "vn" => normals.push(parse_vector3(&mut words).map_err(line_error)?),
+                "vt" => {
+                    let u = parse_number(&mut words).map_err(line_error)?;
+                    let v = words.next().map_or(Ok(0.0), parse_float).map_err(line_error)?;
+                    uvs.push(Vector2::new(u, v));
+                },
+                "f" => {
+                    let corners = words
+                        .map(|word| parse_face_vertex(word, positions.len(), uvs.len(), normals.len()))
+                        .collect::<Result<Vec<_>>>()
+                        .map_err(line_error)?;
+                    if corners.len() < 3 {
+                        return Err(line_error(ParseError::new("faces need at least three vertices")));
+                    }
+
+                    let key = (group.clone(), material.clone());
+                    let index = *part_indices.entry(key).or_insert_with(|| {
+                        parts.push(PartBuilder::new(&group, material.as_ref()));
+                        parts.len() - 1
+                    });
+                    parts[index].add_polygon(&corners, &positions, &uvs, &normals);
+                },
+                "g" | "o" => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    group = if name.is_empty() { String::from("default") } else { name };
+                },
+                "usemtl" => {
+                    let name = words.next()
+                        .ok_or_else(|| line_error(ParseError::new("usemtl needs a material name")))?;
+                    if !materials.contains_key(name) {
+                        return Err(line_error(ParseError::new(format!("unknown material '{}'", name))));
+                    }
+                    material = Some(name.to_string());
+                },
+                "mtllib" => {
+                    for filename in words {
+                        load_mtl(filename, source, resolver, &mut materials).map_err(line_error)?;
+                    }
+                },
+                // smoothing groups, lines, points and free-form geometry
+                // don't affect the triangle meshes we build
+                _ => {},
+            }
+        }
+
+        let parts = parts.into_iter()
+            .map(|part| part.build(&materials))
+            .collect();
+
+        Ok(ObjModel { parts })
+    }
+
+    pub fn parts(&self) -> &[ObjPart] {
+        &self.parts
+    }
+
+    /// Every part merged into a single mesh, dropping their materials
+    pub fn merged_mesh(&self) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut faces = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+
+        // per-vertex data is only kept when every part has it
+        let keep_normals = self.parts.iter().all(|part| !part.mesh.normals().is_empty());
+        let keep_uvs = self.parts.iter().all(|part| !part.mesh.uvs().is_empty());
+
+        for part in &self.parts {
+            let offset = vertices.len();
+            vertices.extend_from_slice(part.mesh.vertices());
+            faces.extend(part.mesh.faces().iter().map(|face| {
+                let [a, b, c] = face.vertices();
+                TrimeshFace::new(a + offset, b + offset, c + offset)
+            }));
+            if keep_normals {
+                normals.extend_from_slice(part.mesh.normals());
+            }
+            if keep_uvs {
+                uvs.extend_from_slice(part.mesh.uvs());
+            }
+        }
+
+        Mesh::new(vertices, faces, normals, uvs, Vec::new())
+    }
+
+    /// A scene holding only the model, seen through a default camera
+    fn create_scene(&self) -> Scene {
+        let default_material = Arc::new(Material::new());
+        let objects = self.parts.iter()
+            .map(|part| {
+                let material = part.material.clone().unwrap_or_else(|| default_material.clone());
+                Box::new(Trimesh::new(TransformNode::root(), material, part.mesh.clone())) as Box<dyn SceneObject>
+            })
+            .collect();
+
+        Scene::new(Camera::new(), Vec::new(), Vector3::zero(), objects)
+    }
+}
+
+/// Indices of a face vertex's position, texture coordinate and normal
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Faces of one group and material, with the vertices they use. OBJ indexes
+/// positions, texture coordinates and normals separately, so every distinct
+/// combination of them becomes a vertex of the mesh.
+struct PartBuilder {
+    name: String,
+    material: Option<String>,
+    vertices: Vec<Vector3<f64>>,
+    uvs: Vec<Option<Vector2<f64>>>,
+    normals: Vec<Option<Vector3<f64>>>,
+    faces: Vec<TrimeshFace>,
+    indices: HashMap<FaceVertex, usize>,
+}
+
+impl PartBuilder {
+    fn new(name: &str, material: Option<&String>) -> PartBuilder {
+        PartBuilder {
+            name: name.to_string(),
+            material: material.cloned(),
+            vertices: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            faces: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    fn add_polygon(&mut self, corners: &[FaceVertex], positions: &[Vector3<f64>], uvs: &[Vector2<f64>], normals: &[Vector3<f64>]) {
+        let indices: Vec<usize> = corners.iter().map(|&corner| {
+            let next = self.vertices.len();
+            let index = *self.indices.entry(corner).or_insert(next);
+            if index == next {
+                let (position, uv, normal) = corner;
+                self.vertices.push(positions[position]);
+                self.uvs.push(uv.map(|uv| uvs[uv]));
+                self.normals.push(normal.map(|normal| normals[normal]));
+            }
+            index
+        }).collect();
+
+        // triangulate polygons as a fan around their first vertex
+        for i in 1..indices.len() - 1 {
+            self.faces.push(TrimeshFace::new(indices[0], indices[i], indices[i + 1]));
+        }
+    }
+
+    fn build(self, materials: &HashMap<String, Arc<Material>>) -> ObjPart {
+        // texture coordinates and normals are only used when every vertex has them
+        let uvs = self.uvs.into_iter().collect::<Option<Vec<_>>>().unwrap_or_default();
+        let normals = self.normals.into_iter().collect::<Option<Vec<_>>>().unwrap_or_default();
+
+        ObjPart {
+            name: self.name,
+            mesh: Arc::new(Mesh::new(self.vertices, self.faces, normals, uvs, Vec::new())),
+            material: self.material.map(|name| materials[&name].clone()),
+        }
+    }
+}
+
+/// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face vertex, given the number
+/// of each element declared so far
+fn parse_face_vertex(word: &str, positions: usize, uvs: usize, normals: usize) -> Result<FaceVertex> {
+    let mut indices = word.split('/');
+
+    let position = parse_index(indices.next().unwrap_or(""), positions)?;
+    let uv = match indices.next() {
+        Some("") | None => None,
+        Some(index) => Some(parse_index(index, uvs)?),
+    };
+    let normal = match indices.next() {
+        Some("") | None => None,
+        Some(index) => Some(parse_index(index, normals)?),
+    };
+
+    Ok((position, uv, normal))
+}
+
+/// Converts a one-based or negative, relative to the end, index into a
+/// zero-based one
+fn parse_index(word: &str, count: usize) -> Result<usize> {
+    let index: i64 = word.parse()
+        .map_err(|_| ParseError::new(format!("invalid index '{}'", word)))?;
+
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(ParseError::new(format!("index {} doesn't refer to an existing element", index)));
+    }
+
+    Ok(resolved as usize)
+}
+
+/// Reads the `.mtl` file `name` refers to from within `source`, adding its
+/// materials to `materials`
+fn load_mtl(name: &str, source: &str, resolver: &dyn ResourceResolver, materials: &mut HashMap<String, Arc<Material>>) -> Result<()> {
+    let resolved = resolver.resolve(name, source)?;
+    let contents = read_text(&resolved, resolver)?;
+
+    parse_mtl(&contents, &resolved, resolver, materials)
+        .map_err(|err| err.in_source(&resolved))
+}
+
+/// Parses `.mtl` source named `source`, which texture maps are resolved relative to
+fn parse_mtl(input: &str, source: &str, resolver: &dyn ResourceResolver, materials: &mut HashMap<String, Arc<Material>>) -> Result<()> {
+    let mut current: Option<(String, Material)> = None;
+
+    for (number, line) in input.lines().enumerate() {
+        let line_error = |err: ParseError| ParseError::new(format!("line {}: {}", number + 1, err));
+
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = words.next()
+                .ok_or_else(|| line_error(ParseError::new("newmtl needs a material name")))?;
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, Arc::new(material));
+            }
+            current = Some((name.to_string(), Material::new()));
+            continue;
+        }
+
+        let material = match current {
+            Some((_, ref mut material)) => material,
+            None => return Err(line_error(ParseError::new(format!("'{}' before any newmtl", keyword)))),
+        };
+
+        match keyword {
+            "Ka" => material.ambient = MaterialParameter::new(parse_color(&mut words).map_err(line_error)?),
+            "Kd" => material.diffuse = MaterialParameter::new(parse_color(&mut words).map_err(line_error)?),
+            "Ks" => material.specular = MaterialParameter::new(parse_color(&mut words).map_err(line_error)?),
+            "Ke" => material.emissive = MaterialParameter::new(parse_color(&mut words).map_err(line_error)?),
+            "Ns" => material.shininess = parse_number(&mut words).map_err(line_error)?,
+            "Ni" => material.index = parse_number(&mut words).map_err(line_error)?,
+            "d" => {
+                let dissolve = parse_number(&mut words).map_err(line_error)?;
+                material.transmissive = MaterialParameter::new(Vector3::new(1.0, 1.0, 1.0) * (1.0 - dissolve));
+            },
+            "Tr" => {
+                let transparency = parse_number(&mut words).map_err(line_error)?;
+                material.transmissive = MaterialParameter::new(Vector3::new(1.0, 1.0, 1.0) * transparency);
+            },
+            "map_Ka" => material.ambient = parse_texture_map(words, source, resolver).map_err(line_error)?,
+            "map_Kd" => material.diffuse = parse_texture_map(words, source, resolver).map_err(line_error)?,
+            "map_Ks" => material.specular = parse_texture_map(words, source, resolver).map_err(line_error)?,
+            "map_Ke" => material.emissive = parse_texture_map(words, source, resolver).map_err(line_error)?,
+            // illumination models and the maps we can't use are ignored
+            _ => {},
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, Arc::new(material));
+    }
+
+    Ok(())
+}
+
+/// The file name is the last word of a `map_*` statement, any options before
+/// it are ignored
+fn parse_texture_map(words: SplitWhitespace, source: &str, resolver: &dyn ResourceResolver) -> Result<MaterialParameter> {
+    let filename = words.last()
+        .ok_or_else(|| ParseError::new("texture map needs a file name"))?;
+
+    Ok(MaterialParameter::from_texture_map(load_texture_map(filename, source, filename, resolver)?))
+}
+
+fn read_text(resolved: &str, resolver: &dyn ResourceResolver) -> Result<String> {
+    String::from_utf8(resolver.read(resolved)?)
+        .map_err(|_| ParseError::new(format!("'{}' isn't valid text", resolved)))
+}
+
+fn parse_float(word: &str) -> Result<f64> {
+    word.parse()
+        .map_err(|_| ParseError::new(format!("invalid number '{}'", word)))
+}
+
+fn parse_number(words: &mut SplitWhitespace) -> Result<f64> {
+    match words.next() {
+        Some(word) => parse_float(word),
+        None => Err(ParseError::new("missing number")),
+    }
+}
+
+/// Three numbers, anything after them (like a `w` coordinate or vertex
+/// colors) is ignored
+fn parse_vector3(words: &mut SplitWhitespace) -> Result<Vector3<f64>> {
+    Ok(Vector3::new(parse_number(words)?, parse_number(words)?, parse_number(words)?))
+}
+
+/// An `r g b` color, where a lone `r` is used for all three channels
+fn parse_color(words: &mut SplitWhitespace) -> Result<Vector3<f64>> {
+    let r = parse_number(words)?;
+    match words.next() {
+        Some(g) => Ok(Vector3::new(r, parse_float(g)?, parse_number(words)?)),
+        None => Ok(Vector3::new(r, r, r)),
+    }
+}
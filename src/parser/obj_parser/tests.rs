@@ -0,0 +1,151 @@
+use super::*;
+
+use cgmath::InnerSpace;
+
+use super::super::super::scene::objects::{Intersect, Ray, RayType};
+
+static CUBE_SIDES: &str = "
+# two faces of a unit cube
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 -1
+vn 0 -1 0
+
+g back
+f 1/1/1 4/4/1 3/3/1 2/2/1
+
+g bottom
+f -6//-1 -5//-1 -1//-1 -2//-1
+";
+
+#[test]
+fn obj_parse_test() {
+    let model = ObjModel::parse(CUBE_SIDES, "", &FileResolver).unwrap();
+    assert_eq!(model.parts().len(), 2);
+
+    // quads are split in two, with a vertex for each distinct corner
+    let back = &model.parts()[0];
+    assert_eq!(back.name, "back");
+    assert!(back.material.is_none());
+    assert_eq!(back.mesh.faces().len(), 2);
+    assert_eq!(back.mesh.vertices().len(), 4);
+    assert_eq!(back.mesh.uvs()[2], Vector2::new(1.0, 1.0));
+    assert_eq!(back.mesh.normals()[0], Vector3::new(0.0, 0.0, -1.0));
+
+    // negative indices count back from the latest element
+    let bottom = &model.parts()[1];
+    assert_eq!(bottom.mesh.vertices()[2], Vector3::new(1.0, 0.0, 1.0));
+    assert!(bottom.mesh.uvs().is_empty());
+    assert_eq!(bottom.mesh.normals()[0], Vector3::new(0.0, -1.0, 0.0));
+
+    let merged = model.merged_mesh();
+    assert_eq!(merged.faces().len(), 4);
+    assert_eq!(merged.vertices().len(), 8);
+    assert_eq!(merged.normals().len(), 8);
+    assert!(merged.uvs().is_empty());
+
+    let scene = ObjParser.parse(CUBE_SIDES.as_bytes(), "sides.obj", &FileResolver).unwrap();
+    assert_eq!(scene.object_count(), 2);
+    let ray = Ray::new(Vector3::new(0.25, 0.75, -1.0), Vector3::new(0.0, 0.0, 1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 1.0).abs() < 1e-9);
+    assert!((isect.uv_coords - Vector2::new(0.25, 0.75)).magnitude() < 1e-9);
+}
+
+#[test]
+fn obj_error_test() {
+    assert!(ObjModel::parse("v 0 0", "", &FileResolver).is_err());
+    assert!(ObjModel::parse("v 0 0 0\nv 1 0 0\nf 1 2", "", &FileResolver).is_err());
+    assert!(ObjModel::parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4", "", &FileResolver).is_err());
+    assert!(ObjModel::parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2", "", &FileResolver).is_err());
+    assert!(ObjModel::parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2/1 3/1", "", &FileResolver).is_err());
+    assert!(ObjModel::parse("usemtl missing", "", &FileResolver).is_err());
+    assert!(ObjModel::parse("mtllib missing.mtl", "", &FileResolver).is_err());
+
+    let error = ObjModel::parse("v 0 0 0\nv 0 x 0", "", &FileResolver).err().unwrap();
+    assert!(error.to_string().starts_with("line 2"));
+}
+
+#[test]
+fn mtl_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("materials.mtl", "
+newmtl red
+Kd 1 0 0
+Ks 0.5
+Ns 32
+Ni 1.5
+d 0.25
+
+newmtl plain
+");
+    resolver.insert("triangles.obj", "
+mtllib materials.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+usemtl red
+f 1 3 2
+o other
+f 3 2 1
+usemtl plain
+f 2 1 3
+");
+
+    let model = ObjModel::load("triangles.obj", &resolver).unwrap();
+    assert_eq!(model.parts().len(), 4);
+    assert!(model.parts()[0].material.is_none());
+
+    let red = model.parts()[1].material.clone().unwrap();
+    assert_eq!(red.diffuse.base_value(), Vector3::new(1.0, 0.0, 0.0));
+    assert_eq!(red.specular.base_value(), Vector3::new(0.5, 0.5, 0.5));
+    assert_eq!(red.shininess, 32.0);
+    assert_eq!(red.index, 1.5);
+    assert_eq!(red.transmissive.base_value(), Vector3::new(0.75, 0.75, 0.75));
+
+    // the material carries over into the next group
+    assert_eq!(model.parts()[2].name, "other");
+    assert!(Arc::ptr_eq(model.parts()[2].material.as_ref().unwrap(), &red));
+    assert_eq!(*model.parts()[3].material.clone().unwrap(), Material::new());
+
+    resolver.insert("broken.mtl", "Kd 1 0 0");
+    resolver.insert("broken.obj", "mtllib broken.mtl");
+    assert!(ObjModel::load("broken.obj", &resolver).is_err());
+}
+
+#[test]
+fn ray_mesh_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("sides.obj", CUBE_SIDES);
+    let scene = ParserRegistry::new().parse(b"SBT-raytracer 1.0
+        translate(0, 0, -5, mesh('sides.obj'));
+        translate(5, 0, 0, trimesh { file = 'sides.obj'; gennormals; });
+    ", "mesh.ray", &resolver).unwrap();
+    // one trimesh per part of the mesh, and one for the merged trimesh
+    assert_eq!(scene.object_count(), 3);
+
+    let ray = Ray::new(Vector3::new(0.5, 0.5, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 5.0).abs() < 1e-9);
+
+    let ray = Ray::new(Vector3::new(5.5, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 1.0).abs() < 1e-9);
+
+    let both = ParserRegistry::new().parse(b"SBT-raytracer 1.0
+        trimesh { file = 'sides.obj'; faces = ((0, 1, 2)); }
+    ", "both.ray", &resolver);
+    assert!(both.is_err());
+}
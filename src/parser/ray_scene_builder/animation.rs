@@ -0,0 +1,249 @@
+//! Parses `animation` blocks, which key the camera, and the lights, elements
+//! and materials given names, at frames of the animation:
+//!
+//! ```text
+//! animation {
+//!     frames = (1, 48);
+//!     camera {
+//!         key(1) { position = (0, 1, 6); }
+//!         key(48, bezier) { position = (4, 1, 6); fov = 40; }
+//!     }
+//!     object 'ball' {
+//!         key(1) { translate = (0, 0, 0); }
+//!         key(24, bezier) { translate = (0, 2, 0); }
+//!         key(48) { translate = (0, 0, 0); }
+//!     }
+//! }
+//! ```
+//!
+//! Each property is a track of its own, so a key only sets the properties it
+//! gives. The interpolation of a key is how its values go on to the next.
+
+use super::*;
+use super::super::super::scene::animation::{Interpolation, Keyable, Track};
+
+/// Frame and interpolation of the key block being parsed
+#[derive(Clone, Copy)]
+struct Key {
+    frame: f64,
+    interpolation: Interpolation,
+}
+
+impl Key {
+    fn add<T: Keyable>(&self, track: &mut Option<Track<T>>, value: T) {
+        match *track {
+            Some(ref mut track) => track.key(self.frame, value, self.interpolation),
+            None => *track = Some(Track::new(self.frame, value, self.interpolation)),
+        }
+    }
+}
+
+impl<'a> RaySceneBuilder<'a> {
+    /// `animation { ... }` adds its keys to those of the blocks before it
+    pub(super) fn parse_animation(&mut self, tokenizer: &mut Tokenizer) -> Result<()> {
+        tokenizer.read( Token::Animation )?;
+        tokenizer.read( Token::LBrace )?;
+
+        let context = &self.context;
+        let animation = &mut self.animation;
+        loop {
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match *token {
+                Token::Ident("frames") => {
+                    let frames = parse_list_expression(tokenizer, |tokenizer| parse_index(tokenizer, context))?;
+                    if frames.len() != 2 || frames[0] > frames[1] || frames[1] > u32::MAX as usize {
+                        return Err(ParseError::new("frames should be the first and last frame, like (1, 48)"));
+                    }
+                    animation.frames = Some(frames[0] as u32..=frames[1] as u32);
+                },
+                Token::Camera => {
+                    tokenizer.next();
+                    let camera = &mut animation.camera;
+                    parse_keys(tokenizer, context, |tokenizer, key| {
+                        match *tokenizer.peek().copied().ok_or_else(unexpected_eof)? {
+                            Token::Ident("position") => key.add(&mut camera.position, parse_vector3_expression(tokenizer, context)?),
+                            Token::Ident("viewdir") => key.add(&mut camera.view_dir, parse_vector3_expression(tokenizer, context)?),
+                            Token::Ident("updir") => key.add(&mut camera.up_dir, parse_vector3_expression(tokenizer, context)?),
+                            Token::Ident("fov") => key.add(&mut camera.fov, parse_scalar_expression(tokenizer, context)?),
+                            ref token => return Err(unexpected_token(token)),
+                        }
+                        Ok(())
+                    })?;
+                },
+                Token::Ident("light") => {
+                    tokenizer.next();
+                    let light = animation.lights.entry(parse_string(tokenizer)?.to_string()).or_default();
+                    parse_keys(tokenizer, context, |tokenizer, key| {
+                        match *tokenizer.peek().copied().ok_or_else(unexpected_eof)? {
+                            Token::Ident("position") => key.add(&mut light.position, parse_vector3_expression(tokenizer, context)?),
+                            Token::Ident("direction") => key.add(&mut light.direction, parse_vector3_expression(tokenizer, context)?),
+                            Token::Ident("color") |
+                            Token::Ident("colour") => key.add(&mut light.color, parse_vector3_expression(tokenizer, context)?),
+                            ref token => return Err(unexpected_token(token)),
+                        }
+                        Ok(())
+                    })?;
+                },
+                Token::Ident("object") => {
+                    tokenizer.next();
+                    let element = animation.elements.entry(parse_string(tokenizer)?.to_string()).or_default();
+                    parse_keys(tokenizer, context, |tokenizer, key| {
+                        match *tokenizer.peek().copied().ok_or_else(unexpected_eof)? {
+                            Token::Translate => key.add(&mut element.translate, parse_vector3_expression(tokenizer, context)?),
+                            Token::Rotate => {
+                                let rotation = parse_vector4_expression(tokenizer, context)?;
+                                if rotation.truncate().magnitude2() == 0.0 {
+                                    return Err(ParseError::new("rotation axis can't be zero"));
+                                }
+                                key.add(&mut element.rotate, rotation);
+                            },
+                            Token::Scale => key.add(&mut element.scale, parse_scale_expression(tokenizer, context)?),
+                            ref token => return Err(unexpected_token(token)),
+                        }
+                        Ok(())
+                    })?;
+                },
+                Token::Material => {
+                    tokenizer.next();
+                    let material = animation.materials.entry(parse_string(tokenizer)?.to_string()).or_default();
+                    parse_keys(tokenizer, context, |tokenizer, key| {
+                        match *tokenizer.peek().copied().ok_or_else(unexpected_eof)? {
+                            Token::Ident("shininess") => key.add(&mut material.shininess, parse_scalar_expression(tokenizer, context)?),
+                            Token::Ident("index") => key.add(&mut material.index, parse_scalar_expression(tokenizer, context)?),
+                            ref token => return Err(unexpected_token(token)),
+                        }
+                        Ok(())
+                    })?;
+                },
+                Token::RBrace => {
+                    tokenizer.read( Token::RBrace )?;
+                    return Ok(());
+                },
+                ref token => return Err(unexpected_token(token)),
+            }
+        }
+    }
+
+    /// Checks that the lights, elements and materials the animation keys are
+    /// in the scene, and that lights are only keyed by what they have
+    pub(super) fn check_animation(&self) -> Result<()> {
+        for (name, tracks) in &self.animation.lights {
+            let mut lights = self.lights.iter().filter(|builder| builder.name.as_ref() == Some(name)).peekable();
+            if lights.peek().is_none() {
+                return Err(ParseError::new(format!("the animation keys the light '{}', but no light has that name", name)));
+            }
+            for builder in lights {
+                match *builder.light.light_type() {
+                    LightType::PointLight { .. } if tracks.direction.is_some() =>
+                        return Err(ParseError::new(format!("'{}' is a point light, which has no direction to key", name))),
+                    LightType::DirectionalLight { .. } if tracks.position.is_some() =>
+                        return Err(ParseError::new(format!("'{}' is a directional light, which has no position to key", name))),
+                    _ => {},
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        for element in self.objects.iter().chain(self.media.iter().flat_map(|builder| &builder.boundary)) {
+            element.names(&mut names);
+        }
+        if let Some(name) = self.animation.elements.keys().find(|name| !names.contains(&name.as_str())) {
+            return Err(ParseError::new(format!("the animation keys the object '{}', but nothing in the scene has that name", name)));
+        }
+        // a scale going from one side of zero to the other would flatten the
+        // element to nothing on the way
+        for (name, tracks) in &self.animation.elements {
+            let points = tracks.scale.as_ref().map(|track| track.control_points()).unwrap_or_default();
+            let same_side = |axis: fn(&Vector3<f64>) -> f64| points.iter().all(|point| axis(point) > 0.0) || points.iter().all(|point| axis(point) < 0.0);
+            if !(same_side(|point| point.x) && same_side(|point| point.y) && same_side(|point| point.z)) {
+                return Err(ParseError::new(format!("the scale of '{}' passes through zero between its keys", name)));
+            }
+        }
+
+        if let Some(name) = self.animation.materials.keys().find(|&name| !self.context.materials.contains_key(name)) {
+            return Err(ParseError::new(format!("the animation keys the material '{}', but no material has that name", name)));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> AnimatedScene for RaySceneBuilder<'a> {
+    fn animation(&self) -> &Animation {
+        &self.animation
+    }
+
+    fn scene_at(&self, frame: f64) -> Scene {
+        self.create_frame(frame)
+    }
+
+    fn camera_at(&self, frame: f64) -> Camera {
+        let tracks = &self.animation.camera;
+        let mut builder = self.camera.clone().unwrap_or_default();
+        builder.position = tracks.position_at(frame).or(builder.position);
+        builder.view_dir = tracks.view_dir_at(frame).or(builder.view_dir);
+        builder.up_dir = tracks.up_dir_at(frame).or(builder.up_dir);
+        builder.fov = tracks.fov_at(frame).or(builder.fov);
+        builder.update_camera();
+        builder.camera
+    }
+
+    fn lights_at(&self, frame: f64) -> Vec<Light> {
+        self.lights.iter()
+            .map(|builder| match builder.name.as_ref().and_then(|name| self.animation.lights.get(name)) {
+                Some(tracks) => tracks.apply(&builder.light, frame),
+                None => builder.light.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Parses the `{ key(frame) { ... } key(frame, bezier) { ... } }` of an
+/// animated target, handing `property` each property of each key in turn
+fn parse_keys<'t, F>(tokenizer: &mut Tokenizer<'t>, context: &ParseContext, mut property: F) -> Result<()>
+    where F: FnMut(&mut Tokenizer<'t>, Key) -> Result<()>
+{
+    tokenizer.read( Token::LBrace )?;
+
+    loop {
+        match *tokenizer.next().ok_or_else(unexpected_eof)? {
+            Token::Ident("key") => {},
+            Token::RBrace => return Ok(()),
+            ref token => return Err(unexpected_token(token)),
+        }
+
+        tokenizer.read( Token::LParen )?;
+        let frame = parse_scalar(tokenizer, context)?;
+        let interpolation = if tokenizer.conditional_read( Token::Comma ) {
+            let name = parse_string(tokenizer)?;
+            Interpolation::from_name(&name)
+                .ok_or_else(|| ParseError::new(format!("unknown interpolation '{}', expected linear or bezier", name)))?
+        } else {
+            Interpolation::Linear
+        };
+        tokenizer.read( Token::RParen )?;
+
+        tokenizer.read( Token::LBrace )?;
+        while !tokenizer.conditional_read( Token::RBrace ) {
+            property(tokenizer, Key { frame, interpolation })?;
+        }
+    }
+}
+
+/// `scale = (x, y, z);`, or `scale = factor;` to scale evenly
+fn parse_scale_expression(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<Vector3<f64>> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let factors = match tokenizer.peek() {
+        Some(&&Token::LParen) => parse_vector3(tokenizer, context)?,
+        _ => {
+            let factor = parse_scalar(tokenizer, context)?;
+            Vector3::new(factor, factor, factor)
+        },
+    };
+    tokenizer.conditional_read( Token::Semicolon );
+
+    if factors.x == 0.0 || factors.y == 0.0 || factors.z == 0.0 {
+        return Err(ParseError::new("scale factors can't be zero"));
+    }
+    Ok(factors)
+}
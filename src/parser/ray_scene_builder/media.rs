@@ -0,0 +1,142 @@
+//! Parses `fog` and `medium` blocks, which fill the scene, or the inside of
+//! closed elements, with something light is absorbed and scattered by:
+//!
+//! ```text
+//! fog {
+//!     absorption = (0.01, 0.01, 0.01);
+//!     scattering = (0.04, 0.04, 0.04);
+//! }
+//! medium {
+//!     scattering = (0.8, 0.8, 0.8);
+//!     asymmetry = 0.3;
+//!     density = {
+//!         size = (2, 1, 2);
+//!         values = (0, 1, 1, 0);
+//!     };
+//!     translate(0, 1, -4, scale(2, box {}));
+//! }
+//! ```
+//!
+//! The coefficients are per unit of distance, and the density grid scales them
+//! across the bounds of the elements it's given with, x fastest. The elements
+//! of a medium only bound it and aren't rendered.
+
+use super::*;
+use super::super::super::scene::medium::{DensityGrid, Medium};
+
+/// A `medium` block: its medium and the elements it fills
+pub(super) struct MediumBuilder {
+    pub medium: Medium,
+    pub boundary: Vec<TransformableElementBuilder>,
+}
+
+impl<'a> RaySceneBuilder<'a> {
+    /// `fog { ... }` fills the whole scene, and there may only be one.
+    /// `medium { ... }` fills the elements in it, and adds to the others.
+    pub(super) fn parse_medium(&mut self, tokenizer: &mut Tokenizer) -> Result<()> {
+        let is_fog = tokenizer.conditional_read( Token::Fog );
+        if !is_fog {
+            tokenizer.read( Token::Medium )?;
+        } else if self.fog.is_some() {
+            return Err(ParseError::new("a scene may only have one fog block"));
+        }
+        tokenizer.read( Token::LBrace )?;
+
+        let mut medium = Medium::new();
+        let mut boundary = Vec::new();
+        loop {
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match *token {
+                Token::Ident("absorption") => medium.absorption = parse_coefficients(tokenizer, &self.context, "absorption")?,
+                Token::Ident("scattering") => medium.scattering = parse_coefficients(tokenizer, &self.context, "scattering")?,
+                Token::Ident("asymmetry") => {
+                    medium.asymmetry = parse_scalar_expression(tokenizer, &self.context)?;
+                    if !(medium.asymmetry > -1.0 && medium.asymmetry < 1.0) {
+                        return Err(ParseError::new("asymmetry must be between -1 and 1"));
+                    }
+                },
+                Token::Ident("density") if is_fog => {
+                    return Err(ParseError::new("fog fills the whole scene evenly, put the density grid in a medium block"));
+                },
+                Token::Ident("density") => medium.density = Some( Arc::new(parse_density_grid(tokenizer, &self.context)?) ),
+                Token::Sphere |
+                Token::Box |
+                Token::Square |
+                Token::Cylinder |
+                Token::Cone |
+                Token::Trimesh |
+                Token::Mesh |
+                Token::Translate |
+                Token::Rotate |
+                Token::Scale |
+                Token::Transform |
+                Token::LBrace if !is_fog => {
+                    boundary.push( TransformableElementBuilder::new(tokenizer, &self.root_transform, &Arc::new(Material::new()), &mut self.context)? );
+                },
+                Token::Ident(name) if !is_fog && self.context.definitions.contains_key(name) => {
+                    boundary.push( TransformableElementBuilder::new(tokenizer, &self.root_transform, &Arc::new(Material::new()), &mut self.context)? );
+                },
+                Token::RBrace => {
+                    tokenizer.read( Token::RBrace )?;
+                    break;
+                },
+                Token::Semicolon => {
+                    tokenizer.next();
+                },
+                ref token => return Err(unexpected_token(token)),
+            }
+        }
+
+        if is_fog {
+            self.fog = Some(medium);
+        } else if boundary.is_empty() {
+            return Err(ParseError::new("a medium needs the elements it fills"));
+        } else {
+            self.media.push(MediumBuilder { medium, boundary });
+        }
+        Ok(())
+    }
+}
+
+/// `absorption = (r, g, b);` or `scattering = ...`, which can't be negative
+fn parse_coefficients(tokenizer: &mut Tokenizer, context: &ParseContext, name: &str) -> Result<Vector3<f64>> {
+    let coefficients = parse_vector3_expression(tokenizer, context)?;
+    if coefficients.x < 0.0 || coefficients.y < 0.0 || coefficients.z < 0.0 {
+        return Err(ParseError::new(format!("{} can't be negative", name)));
+    }
+    Ok(coefficients)
+}
+
+/// `density = { size = (nx, ny, nz); values = (...); };`
+fn parse_density_grid(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<DensityGrid> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    tokenizer.read( Token::LBrace )?;
+
+    let mut size = None;
+    let mut values = None;
+    loop {
+        let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+        match *token {
+            Token::Ident("size") => {
+                let counts = parse_list_expression(tokenizer, |tokenizer| parse_index(tokenizer, context))?;
+                if counts.len() != 3 {
+                    return Err(ParseError::new("a density grid's size should be its points along x, y and z, like (4, 4, 4)"));
+                }
+                size = Some([counts[0], counts[1], counts[2]]);
+            },
+            Token::Ident("values") => values = Some( parse_list_expression(tokenizer, |tokenizer| parse_scalar(tokenizer, context))? ),
+            Token::RBrace => {
+                tokenizer.read( Token::RBrace )?;
+                break;
+            },
+            ref token => return Err(unexpected_token(token)),
+        }
+    }
+    tokenizer.conditional_read( Token::Semicolon );
+
+    match (size, values) {
+        (Some(size), Some(values)) => DensityGrid::new(size, values).map_err(ParseError::new),
+        _ => Err(ParseError::new("a density grid needs both its size and its values")),
+    }
+}
@@ -3,92 +3,371 @@
 //! a generalized Scene description, so that ray_rs::ray_tracer can render
 //! the given scene.
 
-use cgmath::{Matrix, Matrix3, Matrix4, Rad, Vector3, Vector4};
+mod animation;
+mod media;
+#[cfg(test)]
+mod tests;
+mod writer;
 
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3, Vector4, Zero};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::f64::consts;
 use std::iter::Peekable;
+use std::path::Path;
 use std::result;
+use std::str;
 use std::slice::Iter;
+use std::sync::Arc;
 
 use super::*;
-use super::error::TokenizationError;
-use super::ray_tokenizer::{RayTokenizer, Readable, Token};
-
-use super::super::scene::TransformNode;
+use super::error::ParseError;
+use super::mesh_loader::load_mesh;
+use super::obj_parser::ObjModel;
+use super::resolver::{relative_name, FileResolver, ResourceResolver};
+use super::ray_tokenizer::{unescape, RayTokenizer, Readable, Token};
+
+use super::super::render::IntegratorType;
+use super::super::scene::animation::{AnimatedScene, Animation};
+use super::super::scene::{mat3_from_mat4, Camera, FisheyeMapping, Material, MaterialParameter, Projection, SceneObject, SceneSettings, Stereo, StereoLayout, TransformNode, Transformation};
+use super::super::scene::bvh::Aggregate;
+use super::super::scene::medium::{Media, Medium, Volume};
+use super::super::scene::objects::{Cone, Cylinder, Instance, Light, LightType, Mesh, SceneBox, Sphere, Square, Trimesh, TrimeshFace};
 
 type Tokenizer<'a> = Peekable<Iter<'a, Token<'a>>>;
-type Result<T> = result::Result<T, TokenizationError>;
+type Result<T> = result::Result<T, ParseError>;
 
-pub struct RaySceneBuilder {
+pub struct RaySceneBuilder<'a> {
     lights: Vec<LightBuilder>,
     objects: Vec<TransformableElementBuilder>,
     root_transform: TransformNode,
     camera: Option<CameraBuilder>,
     ambient: Option<Vector3<f64>>,
-    // TODO?: material? Parser.cpp has some weird code for
-    //        top level material parsering
+    settings: Option<SceneSettings>,
+    /// Material given to top level objects that don't declare their own,
+    /// replaced by each top level `material = ...;` statement
+    material: Arc<Material>,
+    /// Keys of every `animation` block
+    animation: Animation,
+    fog: Option<Medium>,
+    media: Vec<media::MediumBuilder>,
+    context: ParseContext<'a>,
+}
+
+/// State that outlives the element being parsed
+struct ParseContext<'a> {
+    /// Materials declared with a `name`, referenced afterwards by that name
+    materials: HashMap<String, Arc<Material>>,
+    /// Constants declared with `let`
+    constants: HashMap<String, f64>,
+    /// Elements declared with `define`, instanced by using their name as an element
+    definitions: HashMap<String, Arc<Definition>>,
+    /// Resolved names of the files being parsed, innermost last, used to
+    /// resolve relative includes and to detect include cycles
+    sources: Vec<String>,
+    /// Names of the included files being parsed as seen from the scene,
+    /// innermost last, so file names in them can be kept relative to the scene
+    includes: Vec<String>,
+    resolver: &'a dyn ResourceResolver,
+}
+
+impl<'a> ParseContext<'a> {
+    fn new(resolver: &'a dyn ResourceResolver) -> ParseContext<'a> {
+        ParseContext {
+            materials: HashMap::new(),
+            constants: HashMap::new(),
+            definitions: HashMap::new(),
+            sources: Vec::new(),
+            includes: Vec::new(),
+            resolver,
+        }
+    }
+
+    /// Names in a file are relative to that file
+    fn resolve(&self, filename: &str) -> Result<String> {
+        self.resolver.resolve(filename, self.source())
+    }
+
+    fn source(&self) -> &str {
+        self.sources.last().map_or("", String::as_str)
+    }
+
+    /// A file name written in the file being parsed as seen from the scene,
+    /// which is how it's kept so the scene can be written back out
+    fn scene_name(&self, filename: &str) -> String {
+        match self.includes.last() {
+            Some(include) => relative_name(filename, include),
+            None => filename.to_string(),
+        }
+    }
+
+    /// Checks that a `let` or `define` name doesn't shadow anything already declared
+    fn check_unused_name(&self, name: &str) -> Result<()> {
+        if constant_value(name, self).is_some() || self.definitions.contains_key(name) {
+            return Err(ParseError::new(format!("'{}' is already declared", name)));
+        }
+        Ok(())
+    }
 }
 
-impl RaySceneBuilder {
-    pub fn new(tokens: Vec<Token>) -> Result<RaySceneBuilder> {
+impl<'a> RaySceneBuilder<'a> {
+    pub fn new(tokens: Vec<Token>) -> Result<RaySceneBuilder<'static>> {
+        RaySceneBuilder::from_source(tokens, "", &FileResolver)
+    }
+
+    /// Builds a scene from the tokens of the source named `source`, which
+    /// relative includes are resolved against through `resolver`
+    pub fn from_source(tokens: Vec<Token>, source: &str, resolver: &'a dyn ResourceResolver) -> Result<RaySceneBuilder<'a>> {
         let mut peekable_tokens = tokens.iter().peekable();
 
+        let mut context = ParseContext::new(resolver);
+        if !source.is_empty() {
+            // the source may not be a resource of the resolver, like a scene
+            // parsed from a string, and then it can't be included anyway
+            context.sources.push(resolver.resolve(source, "").unwrap_or_else(|_| source.to_string()));
+        }
+
         RaySceneBuilder {
             lights: Vec::new(),
             objects: Vec::new(),
             root_transform: TransformNode::root(),
             camera: None,
             ambient: None,
+            settings: None,
+            material: Arc::new(Material::new()),
+            animation: Animation::new(),
+            fog: None,
+            media: Vec::new(),
+            context,
         }.parse_scene(&mut peekable_tokens)
     }
 
-    fn parse_scene(mut self, tokenizer: &mut Tokenizer) -> Result<RaySceneBuilder> {
+    fn parse_scene(mut self, tokenizer: &mut Tokenizer) -> Result<RaySceneBuilder<'a>> {
         tokenizer.read( Token::SbtRaytracer )?;
+        parse_version(tokenizer)?;
 
-        if let Token::Scalar(version) = tokenizer.read( Token::Scalar(0f64) )? {
-            if version > 1.1 {
-                return Err(TokenizationError::new("Unsupported SbtRaytracer version"));
-            }
-        }
+        self.parse_statements(tokenizer)?;
+        // what's animated may be declared after the animation
+        self.check_animation()?;
 
-        // TODO: this should loop until EOF, then return
-        let token_option = tokenizer.peek().map(|t| *t);
-        match token_option {
-            Some(token) => match *token {
+        Ok(self)
+    }
+
+    fn parse_statements(&mut self, tokenizer: &mut Tokenizer) -> Result<()> {
+        while let Some(token) = tokenizer.peek().copied() {
+            match *token {
                 Token::Sphere |
                 Token::Box |
                 Token::Square |
                 Token::Cylinder |
                 Token::Cone |
                 Token::Trimesh |
+                Token::Mesh |
                 Token::Translate |
                 Token::Rotate |
                 Token::Scale |
                 Token::Transform |
                 Token::LBrace => {
-                    self.objects.push( TransformableElementBuilder::new(tokenizer, &self.root_transform)? )
+                    let element = TransformableElementBuilder::new(tokenizer, &self.root_transform, &self.material, &mut self.context)?;
+                    self.objects.push(element);
                 },
-                Token::PointLight => unimplemented!(),
-                Token::DirectionalLight => unimplemented!(),
-                Token::AmbientLight => unimplemented!(),
-                Token::Camera => unimplemented!(),
-                Token::Material => unimplemented!(),
-                _ => unimplemented!(), // Synxtax error, unexpected token
-            },
-            None => unimplemented!(), // EOF
+                Token::Ident(name) if self.context.definitions.contains_key(name) => {
+                    let element = TransformableElementBuilder::new(tokenizer, &self.root_transform, &self.material, &mut self.context)?;
+                    self.objects.push(element);
+                },
+                Token::PointLight |
+                Token::DirectionalLight => self.lights.push( LightBuilder::new(tokenizer, &self.context)? ),
+                Token::AmbientLight => {
+                    let color = parse_ambient_light(tokenizer, &self.context)?;
+                    self.ambient = Some(self.ambient.unwrap_or_else(Vector3::zero) + color);
+                },
+                Token::Camera => {
+                    if self.camera.is_some() {
+                        return Err(ParseError::new("a scene may only have one camera"));
+                    }
+                    self.camera = Some( CameraBuilder::new(tokenizer, &self.context)? );
+                },
+                Token::RenderSettings => {
+                    if self.settings.is_some() {
+                        return Err(ParseError::new("a scene may only have one render_settings block"));
+                    }
+                    self.settings = Some( parse_render_settings(tokenizer, &self.context)? );
+                },
+                Token::Material => self.material = parse_material_expression(tokenizer, &self.material, &mut self.context)?,
+                Token::Include => self.parse_include(tokenizer)?,
+                Token::Let => self.parse_let(tokenizer)?,
+                Token::Define => self.parse_define(tokenizer)?,
+                Token::Animation => self.parse_animation(tokenizer)?,
+                Token::Fog |
+                Token::Medium => self.parse_medium(tokenizer)?,
+                Token::Semicolon => {
+                    tokenizer.next();
+                },
+                ref token => return Err(unexpected_token(token)),
+            }
         }
 
-        Ok(self)
+        Ok(())
     }
 
+    /// `include 'file.ray';` parses the statements of another file as if they
+    /// were written in place of the include
+    fn parse_include(&mut self, tokenizer: &mut Tokenizer) -> Result<()> {
+        tokenizer.read( Token::Include )?;
+        let filename = parse_string(tokenizer)?;
+        tokenizer.conditional_read( Token::Semicolon );
+
+        let resolved = self.context.resolve(&filename)?;
+
+        if self.context.sources.contains(&resolved) {
+            return Err(ParseError::new(format!("include cycle through '{}'", resolved)));
+        }
+
+        let contents = self.context.resolver.read(&resolved)?;
+        let contents = str::from_utf8(&contents)
+            .map_err(|_| ParseError::new(format!("include '{}' isn't valid text", resolved)))?;
+        let tokens = RayTokenizer::new(contents).collect::<Result<Vec<_>>>()
+            .map_err(|err| err.in_source(&resolved))?;
+        let mut included = tokens.iter().peekable();
+
+        // the header is optional in included files
+        if included.conditional_read( Token::SbtRaytracer ) {
+            parse_version(&mut included)?;
+        }
+
+        let include = self.context.scene_name(&filename);
+        self.context.sources.push(resolved);
+        self.context.includes.push(include);
+        let result = self.parse_statements(&mut included);
+        self.context.includes.pop();
+        let resolved = self.context.sources.pop().unwrap_or_default();
+
+        result.map_err(|err| err.in_source(&resolved))
+    }
+
+    /// `let name = scalar;` declares a constant usable in any later expression
+    fn parse_let(&mut self, tokenizer: &mut Tokenizer) -> Result<()> {
+        tokenizer.read( Token::Let )?;
+        let name = parse_identifier(tokenizer)?;
+        self.context.check_unused_name(name)?;
+
+        tokenizer.read( Token::Equals )?;
+        let value = parse_scalar(tokenizer, &self.context)?;
+        tokenizer.conditional_read( Token::Semicolon );
+
+        self.context.constants.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// `define name = element;` declares an element that is placed in the scene
+    /// wherever `name` is used as an element, sharing its geometry between uses
+    fn parse_define(&mut self, tokenizer: &mut Tokenizer) -> Result<()> {
+        tokenizer.read( Token::Define )?;
+        let name = parse_identifier(tokenizer)?;
+        self.context.check_unused_name(name)?;
+
+        tokenizer.read( Token::Equals )?;
+        let element = TransformableElementBuilder::new(tokenizer, &TransformNode::root(), &self.material, &mut self.context)?;
+        tokenizer.conditional_read( Token::Semicolon );
+
+        // the definition's objects are created once, in its own space, and
+        // shared by every instance of it
+        let mut objects = Vec::new();
+        element.create_objects(&mut objects, &Pose::still(), None);
+        let prototype = Arc::new(Aggregate::new(objects));
+
+        self.context.definitions.insert(name.to_string(), Arc::new(Definition { element, prototype }));
+        Ok(())
+    }
+
+    /// The scene, as it is at the first frame of its animation
     pub fn create_scene(&self) -> Scene {
-        // TODO
-        unimplemented!();
+        self.create_frame(f64::from(*self.animation.frame_range().start()))
+    }
+
+    fn create_frame(&self, frame: f64) -> Scene {
+        let pose = Pose::new(&self.animation, frame, &self.context);
+        let mut objects = Vec::new();
+        for element in &self.objects {
+            element.create_objects(&mut objects, &pose, None);
+        }
+
+        let mut media = Media::new();
+        media.fog = self.fog.clone();
+        for builder in &self.media {
+            let mut boundary = Vec::new();
+            for element in &builder.boundary {
+                element.create_objects(&mut boundary, &pose, None);
+            }
+            media.volumes.push(Volume::new(boundary, builder.medium.clone()));
+        }
+
+        Scene::new(self.camera_at(frame), self.lights_at(frame), self.ambient.unwrap_or_else(Vector3::zero), objects)
+            .with_settings(self.settings.clone().unwrap_or_default())
+            .with_media(media)
+    }
+}
+
+/// What the animation does to the objects at a frame
+struct Pose<'p> {
+    frame: f64,
+    animation: Option<&'p Animation>,
+    /// Animated materials as they were declared, and as they are at the frame
+    materials: Vec<(&'p Arc<Material>, Arc<Material>)>,
+}
+
+impl<'p> Pose<'p> {
+    fn new(animation: &'p Animation, frame: f64, context: &'p ParseContext) -> Pose<'p> {
+        let materials = animation.materials.iter()
+            .filter_map(|(name, tracks)| context.materials.get(name).map(|material| (material, Arc::new(tracks.apply(material, frame)))))
+            .collect();
+        Pose { frame, animation: Some(animation), materials }
+    }
+
+    /// Everything as it was declared
+    fn still() -> Pose<'static> {
+        Pose { frame: 0.0, animation: None, materials: Vec::new() }
+    }
+
+    /// How the element named `name` and everything in it move, in world
+    /// space, given `transform`, where the element was declared, and how the
+    /// elements around it move
+    fn motion(&self, name: Option<&String>, transform: &TransformNode, outer: Option<&Matrix4<f64>>) -> Option<Matrix4<f64>> {
+        let tracks = match (self.animation, name) {
+            (Some(animation), Some(name)) => animation.elements.get(name),
+            _ => None,
+        };
+        match tracks {
+            // the element's own movement happens in its own space
+            Some(tracks) => {
+                let moved = transform.matrix() * tracks.matrix_at(self.frame) * transform.inverse_matrix();
+                Some(outer.map_or(moved, |outer| outer * moved))
+            },
+            None => outer.cloned(),
+        }
+    }
+
+    fn material(&self, material: &Arc<Material>) -> Arc<Material> {
+        self.materials.iter()
+            .find(|&&(declared, _)| Arc::ptr_eq(declared, material))
+            .map_or_else(|| material.clone(), |(_, animated)| animated.clone())
+    }
+}
+
+/// `transform` moved by `motion`, or `None` when the motion has squashed it
+/// too flat to be inverted, leaving nothing to render
+fn placed(transform: &TransformNode, motion: Option<&Matrix4<f64>>) -> Option<TransformNode> {
+    match motion {
+        Some(motion) => TransformNode::root().create_child(motion * transform.matrix()),
+        None => Some(transform.clone()),
     }
 }
 
 enum TransformableElementType {
     Geometry(Box<GeometryBuilder>),
-    Group(GroupBuilder),
+    Group(Box<GroupBuilder>),
+    Instance(Box<InstanceBuilder>),
 }
 
 struct TransformableElementBuilder {
@@ -96,14 +375,14 @@ struct TransformableElementBuilder {
 }
 
 impl TransformableElementBuilder {
-    pub fn new(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<TransformableElementBuilder> {
+    pub fn new(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<TransformableElementBuilder> {
         TransformableElementBuilder {
             element: None,
-        }.parse_transformable_element(tokenizer, transform_node)
+        }.parse_transformable_element(tokenizer, transform_node, material, context)
     }
 
-    fn parse_transformable_element(mut self, tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<TransformableElementBuilder> {
-        let token_option = tokenizer.peek().map(|t| *t);
+    fn parse_transformable_element(mut self, tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<TransformableElementBuilder> {
+        let token_option = tokenizer.peek().copied();
         match token_option {
             Some(token) => match *token {
                 Token::Sphere |
@@ -112,268 +391,728 @@ impl TransformableElementBuilder {
                 Token::Cylinder |
                 Token::Cone |
                 Token::Trimesh |
+                Token::Mesh |
+                Token::Translate |
                 Token::Rotate |
                 Token::Scale |
                 Token::Transform => {
                     self.element = Some(
-                        TransformableElementType::Geometry(Box::new(GeometryBuilder::new(tokenizer, transform_node)?))
+                        TransformableElementType::Geometry(Box::new(GeometryBuilder::new(tokenizer, transform_node, material, context)?))
                     );
                 },
                 Token::LBrace => {
                     self.element = Some(
-                        TransformableElementType::Group(GroupBuilder::new(tokenizer, transform_node)?)
+                        TransformableElementType::Group(Box::new(GroupBuilder::new(tokenizer, transform_node, material, context)?))
+                    );
+                },
+                Token::Ident(name) if context.definitions.contains_key(name) => {
+                    tokenizer.next();
+                    tokenizer.conditional_read( Token::Semicolon );
+                    self.element = Some(
+                        TransformableElementType::Instance(Box::new(InstanceBuilder {
+                            name: name.to_string(),
+                            transform: transform_node.clone(),
+                            definition: context.definitions[name].clone(),
+                        }))
                     );
                 },
-                Token::Material => unimplemented!(),
-                _ => unimplemented!(), // Syntax error
+                ref token => return Err(unexpected_token(token)),
             },
-            None => unimplemented!(), // Syntax error
+            None => return Err(unexpected_eof()),
         }
 
         Ok(self)
     }
+
+    /// Creates the element's objects as `pose` has them, moved by `motion`
+    /// along with the elements around it
+    fn create_objects(&self, objects: &mut Vec<Box<dyn SceneObject>>, pose: &Pose, motion: Option<&Matrix4<f64>>) {
+        match self.element {
+            Some(TransformableElementType::Geometry(ref geometry)) => geometry.create_objects(objects, pose, motion),
+            Some(TransformableElementType::Group(ref group)) => {
+                let motion = pose.motion(group.name.as_ref(), &group.transform, motion);
+                for element in &group.elements {
+                    element.create_objects(objects, pose, motion.as_ref());
+                }
+            },
+            Some(TransformableElementType::Instance(ref element)) => {
+                if let Some(transform) = placed(&element.transform, motion) {
+                    objects.push(Box::new(Instance::new(transform, element.definition.prototype.clone())));
+                }
+            },
+            None => {},
+        }
+    }
+
+    /// Adds the names given to the element and those within it
+    fn names<'e>(&'e self, names: &mut Vec<&'e str>) {
+        match self.element {
+            Some(TransformableElementType::Geometry(ref geometry)) => {
+                names.extend(geometry.name.as_deref());
+                if let Some(GeometryBuilderType::TransformableElement(_, _, ref subelement)) = geometry.element {
+                    subelement.names(names);
+                }
+            },
+            Some(TransformableElementType::Group(ref group)) => {
+                names.extend(group.name.as_deref());
+                for element in &group.elements {
+                    element.names(names);
+                }
+            },
+            Some(TransformableElementType::Instance(_)) | None => {},
+        }
+    }
+}
+
+/// A use of a `define`d element under the given transform
+struct InstanceBuilder {
+    name: String,
+    transform: TransformNode,
+    definition: Arc<Definition>,
+}
+
+/// An element declared with `define`, along with the objects created from it
+struct Definition {
+    element: TransformableElementBuilder,
+    prototype: Arc<Aggregate>,
+}
+
+struct LightBuilder {
+    light: Light,
+    name: Option<String>,
+}
+
+impl LightBuilder {
+    pub fn new(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<LightBuilder> {
+        let light_token = tokenizer.next().ok_or_else(unexpected_eof)?;
+        tokenizer.read( Token::LBrace )?;
+
+        let mut position = Vector3::zero();
+        let mut direction = -Vector3::unit_z();
+        let mut color = Vector3::new(1.0, 1.0, 1.0);
+        let mut coefficients = (0.0, 0.0, 0.0);
+        let mut name = None;
+
+        loop {
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match (light_token, token) {
+                (_, &Token::Ident("color")) |
+                (_, &Token::Ident("colour")) => color = parse_vector3_expression(tokenizer, context)?,
+                (&Token::PointLight, &Token::Ident("position")) => position = parse_vector3_expression(tokenizer, context)?,
+                (&Token::PointLight, &Token::Ident("constant_attenuation_coeff")) => coefficients.0 = parse_scalar_expression(tokenizer, context)?,
+                (&Token::PointLight, &Token::Ident("linear_attenuation_coeff")) => coefficients.1 = parse_scalar_expression(tokenizer, context)?,
+                (&Token::PointLight, &Token::Ident("quadratic_attenuation_coeff")) => coefficients.2 = parse_scalar_expression(tokenizer, context)?,
+                (&Token::DirectionalLight, &Token::Ident("direction")) => direction = parse_vector3_expression(tokenizer, context)?,
+                (_, &Token::Name) => name = Some( parse_string_expression(tokenizer)?.to_string() ),
+                (_, &Token::RBrace) => {
+                    tokenizer.read( Token::RBrace )?;
+                    break;
+                },
+                (_, token) => return Err(unexpected_token(token)),
+            }
+        }
+
+        let light_type = match *light_token {
+            Token::PointLight => LightType::PointLight {
+                pos: position,
+                a: coefficients.0,
+                b: coefficients.1,
+                c: coefficients.2,
+            },
+            Token::DirectionalLight => LightType::DirectionalLight { orientation: direction },
+            ref token => return Err(unexpected_token(token)),
+        };
+
+        Ok(LightBuilder { light: Light::new(light_type, color), name })
+    }
+}
+
+/// Projections the camera block can choose between
+const PROJECTIONS: [&str; 4] = ["perspective", "orthographic", "fisheye", "equirectangular"];
+
+/// Height of an orthographic camera's view when it isn't given
+const DEFAULT_VIEW_HEIGHT: f64 = 2.0;
+
+/// Angle across a fisheye's circle when it isn't given, in degrees
+const DEFAULT_FISHEYE_FOV: f64 = 180.0;
+
+/// Distance between the eyes of a stereo camera when it isn't given
+const DEFAULT_INTEROCULAR: f64 = 0.065;
+
+#[derive(Clone, Default)]
+struct CameraBuilder {
+    camera: Camera,
+    // the settings as they were given, the camera doesn't keep them all
+    position: Option<Vector3<f64>>,
+    view_dir: Option<Vector3<f64>>,
+    up_dir: Option<Vector3<f64>>,
+    aspect_ratio: Option<f64>,
+    fov: Option<f64>,
+    quaternion: Option<Vector4<f64>>,
+    projection: Option<&'static str>,
+    mapping: Option<FisheyeMapping>,
+    view_height: Option<f64>,
+    stereo: Option<StereoLayout>,
+    interocular: Option<f64>,
+}
+
+impl CameraBuilder {
+    pub fn new(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<CameraBuilder> {
+        tokenizer.read( Token::Camera )?;
+        tokenizer.read( Token::LBrace )?;
+
+        let mut builder = CameraBuilder {
+            camera: Camera::new(),
+            position: None,
+            view_dir: None,
+            up_dir: None,
+            aspect_ratio: None,
+            fov: None,
+            quaternion: None,
+            projection: None,
+            mapping: None,
+            view_height: None,
+            stereo: None,
+            interocular: None,
+        };
+
+        loop {
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match *token {
+                Token::Ident("position") => builder.position = Some( parse_vector3_expression(tokenizer, context)? ),
+                Token::Ident("viewdir") => builder.view_dir = Some( parse_vector3_expression(tokenizer, context)? ),
+                Token::Ident("updir") => builder.up_dir = Some( parse_vector3_expression(tokenizer, context)? ),
+                Token::Ident("aspectratio") => builder.aspect_ratio = Some( parse_scalar_expression(tokenizer, context)? ),
+                Token::Ident("fov") => builder.fov = Some( parse_scalar_expression(tokenizer, context)? ),
+                Token::Ident("quaternion") => builder.quaternion = Some( parse_vector4_expression(tokenizer, context)? ),
+                Token::Ident("projection") => {
+                    let name = parse_string_expression(tokenizer)?;
+                    builder.projection = Some(PROJECTIONS.iter().find(|&&projection| projection == name).copied()
+                        .ok_or_else(|| ParseError::new(format!("unknown projection '{}', expected {}", name, PROJECTIONS.join(", "))))?);
+                },
+                Token::Ident("mapping") => {
+                    let name = parse_string_expression(tokenizer)?;
+                    builder.mapping = Some(FisheyeMapping::from_name(&name)
+                        .ok_or_else(|| ParseError::new(format!("unknown fisheye mapping '{}', expected equidistant or equisolid", name)))?);
+                },
+                Token::Ident("viewheight") => builder.view_height = Some( parse_scalar_expression(tokenizer, context)? ),
+                Token::Ident("stereo") => {
+                    let name = parse_string_expression(tokenizer)?;
+                    builder.stereo = Some(StereoLayout::from_name(&name)
+                        .ok_or_else(|| ParseError::new(format!("unknown stereo layout '{}', expected side-by-side or over-under", name)))?);
+                },
+                Token::Ident("interocular") => builder.interocular = Some( parse_scalar_expression(tokenizer, context)? ),
+                Token::RBrace => {
+                    tokenizer.read( Token::RBrace )?;
+                    break;
+                },
+                ref token => return Err(unexpected_token(token)),
+            }
+        }
+
+        builder.check()?;
+        builder.update_camera();
+        Ok(builder)
+    }
+
+    /// Checks the projection and stereo settings go together
+    fn check(&self) -> Result<()> {
+        let projection = self.projection.unwrap_or("perspective");
+        if self.mapping.is_some() && projection != "fisheye" {
+            return Err(ParseError::new("only fisheye cameras have a mapping"));
+        }
+        if self.view_height.is_some() && projection != "orthographic" {
+            return Err(ParseError::new("only orthographic cameras have a viewheight"));
+        }
+        if self.interocular.is_some() && self.stereo.is_none() {
+            return Err(ParseError::new("interocular needs a stereo layout to go with it"));
+        }
+        if self.view_height.is_some_and(|height| height <= 0.0) {
+            return Err(ParseError::new("viewheight must be more than zero"));
+        }
+        if self.interocular.is_some_and(|distance| distance < 0.0) {
+            return Err(ParseError::new("interocular can't be negative"));
+        }
+        if self.aspect_ratio.is_some_and(|aspect_ratio| !aspect_ratio.is_finite() || aspect_ratio <= 0.0) {
+            return Err(ParseError::new("aspectratio must be more than zero"));
+        }
+        if projection == "fisheye" && self.fov.is_some_and(|fov| !(fov > 0.0 && fov <= 360.0)) {
+            return Err(ParseError::new("a fisheye's fov must be more than 0 and at most 360 degrees"));
+        }
+        if projection == "perspective" && self.fov.is_some_and(|fov| !(fov > 0.0 && fov < 180.0)) {
+            return Err(ParseError::new("fov must be more than 0 and less than 180 degrees"));
+        }
+        Ok(())
+    }
+
+    fn projection(&self) -> Projection {
+        match self.projection {
+            Some("orthographic") => Projection::Orthographic { height: self.view_height.unwrap_or(DEFAULT_VIEW_HEIGHT) },
+            Some("fisheye") => Projection::Fisheye {
+                mapping: self.mapping.unwrap_or(FisheyeMapping::Equidistant),
+                fov: self.fov.unwrap_or(DEFAULT_FISHEYE_FOV),
+            },
+            Some("equirectangular") => Projection::Equirectangular,
+            _ => Projection::Perspective,
+        }
+    }
+
+    fn update_camera(&mut self) {
+        let mut camera = Camera::new();
+        if let Some(position) = self.position {
+            camera.set_eye(position);
+        }
+        let projection = self.projection();
+        let stereo = self.stereo.map(|layout| Stereo { layout, interocular: self.interocular.unwrap_or(DEFAULT_INTEROCULAR) });
+        // panoramas fill a 2:1 image for each eye unless told otherwise
+        let aspect_ratio = match (self.aspect_ratio, projection, stereo) {
+            (Some(aspect_ratio), _, _) => Some(aspect_ratio),
+            (None, Projection::Equirectangular, None) => Some(2.0),
+            (None, Projection::Equirectangular, Some(Stereo { layout: StereoLayout::SideBySide, .. })) => Some(4.0),
+            (None, Projection::Equirectangular, Some(Stereo { layout: StereoLayout::OverUnder, .. })) => Some(1.0),
+            (None, _, _) => None,
+        };
+        if let Some(aspect_ratio) = aspect_ratio {
+            camera.set_aspect_ratio(aspect_ratio);
+        }
+        if let Some(fov) = self.fov {
+            camera.set_fov(fov);
+        }
+        camera.set_projection(projection);
+        camera.set_stereo(stereo);
+        if let Some(quaternion) = self.quaternion {
+            camera.set_quaternion(quaternion);
+        }
+        // a view direction takes precedence over a quaternion
+        if self.view_dir.is_some() || self.up_dir.is_some() {
+            camera.set_look(
+                self.view_dir.unwrap_or_else(|| -Vector3::unit_z()),
+                self.up_dir.unwrap_or_else(Vector3::unit_y)
+            );
+        }
+        self.camera = camera;
+    }
 }
 
-struct LightBuilder;
-struct CameraBuilder;
 struct GeometryBuilder {
    element: Option<GeometryBuilderType>,
+   /// Name given to an object, which the animation moves it by
+   name: Option<String>,
 }
 
-// TODO: these should hold materials when materials are added
 enum GeometryBuilderType {
-    ConcreteGeometryType(TransformNode, GeometryType),
-    TransformableElement(TransformNode, TransformableElementBuilder),
+    ConcreteGeometryType(TransformNode, Arc<Material>, GeometryType),
+    TransformableElement(TransformNode, Transformation, TransformableElementBuilder),
+}
+
+/// Where the normals of a `trimesh` came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TrimeshNormals {
+    /// From its file, if it has any
+    Loaded,
+    /// From a `normals` list
+    Given,
+    /// From `gennormals`
+    Generated,
 }
 
 enum GeometryType {
     Sphere,
     Box,
     Square,
-    Cylinder,
+    Cylinder { capped: bool },
     Cone { capped: bool, height: f64, bottom_radius: f64, top_radius: f64 },
-    Trimesh,
+    /// Mesh given by a `trimesh` block, which may load it from a file
+    Trimesh { mesh: Arc<Mesh>, file: Option<String>, normals: TrimeshNormals },
+    /// Meshes loaded from an `.obj` file, the element's material is used for
+    /// the parts without one of their own
+    ObjMesh { filename: String, model: Arc<ObjModel> },
+    /// Mesh loaded from a `.ply` or `.stl` file
+    MeshFile { filename: String, mesh: Arc<Mesh> },
 }
 
 // NOTE: Geometry builder doesn't have the option of being empty in a well-formed
 //       .ray file. Consider making it handle a generic type T: GeometryBuilderSubtype
 impl GeometryBuilder {
-    pub fn new(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GeometryBuilder> {
+    pub fn new(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilder> {
         GeometryBuilder {
             element: None,
-        }.parse_geometry(tokenizer, transform_node)
+            name: None,
+        }.parse_geometry(tokenizer, transform_node, material, context)
     }
 
-    fn parse_geometry(mut self, tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GeometryBuilder> {
-        let token_option = tokenizer.peek().map(|t| *t);
+    fn parse_geometry(mut self, tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilder> {
+        let token_option = tokenizer.peek().copied();
         match token_option {
             Some(token) => match *token {
                 Token::Sphere |
                 Token::Box |
                 Token::Square |
-                Token::Cylinder => self.element = Some(
-                    GeometryBuilderType::ConcreteGeometryType(
-                        transform_node.clone(),
-                        GeometryBuilder::parse_unit_object(tokenizer, token)?)
-                    ),
-                Token::Cone => self.element = Some(
-                    GeometryBuilderType::ConcreteGeometryType(
-                        transform_node.clone(),
-                        GeometryBuilder::parse_cone(tokenizer)?)
-                    ),
-                Token::Trimesh => unimplemented!(),
-                Token::Translate => self.element = Some(GeometryBuilder::parse_translate(tokenizer, transform_node)?),
-                Token::Rotate => self.element = Some(GeometryBuilder::parse_rotate(tokenizer, transform_node)?),
-                Token::Scale => self.element = Some(GeometryBuilder::parse_scale(tokenizer, transform_node)?),
-                Token::Transform => self.element = Some(GeometryBuilder::parse_transform(tokenizer, transform_node)?),
-                _ => unimplemented!(), // syntax error
+                Token::Cylinder => {
+                    let (geometry, material, name) = GeometryBuilder::parse_unit_object(tokenizer, token, material, context)?;
+                    self.name = name;
+                    self.element = Some(GeometryBuilderType::ConcreteGeometryType(transform_node.clone(), material, geometry));
+                },
+                Token::Cone => {
+                    let (geometry, material, name) = GeometryBuilder::parse_cone(tokenizer, material, context)?;
+                    self.name = name;
+                    self.element = Some(GeometryBuilderType::ConcreteGeometryType(transform_node.clone(), material, geometry));
+                },
+                Token::Trimesh => {
+                    let (geometry, material, name) = GeometryBuilder::parse_trimesh(tokenizer, material, context)?;
+                    self.name = name;
+                    self.element = Some(GeometryBuilderType::ConcreteGeometryType(transform_node.clone(), material, geometry));
+                },
+                Token::Mesh => {
+                    let (geometry, material) = GeometryBuilder::parse_mesh(tokenizer, material, context)?;
+                    self.element = Some(GeometryBuilderType::ConcreteGeometryType(transform_node.clone(), material, geometry));
+                },
+                Token::Translate => self.element = Some(GeometryBuilder::parse_translate(tokenizer, transform_node, material, context)?),
+                Token::Rotate => self.element = Some(GeometryBuilder::parse_rotate(tokenizer, transform_node, material, context)?),
+                Token::Scale => self.element = Some(GeometryBuilder::parse_scale(tokenizer, transform_node, material, context)?),
+                Token::Transform => self.element = Some(GeometryBuilder::parse_transform(tokenizer, transform_node, material, context)?),
+                ref token => return Err(unexpected_token(token)),
             },
-            None => unimplemented!(), // logic error in parsing
+            None => return Err(unexpected_eof()),
         }
 
         Ok(self)
     }
 
     // Sphere, Box, Square, Cylinder
-    fn parse_unit_object(tokenizer: &mut Tokenizer, object_type: &Token) ->  Result<GeometryType> {
+    fn parse_unit_object(tokenizer: &mut Tokenizer, object_type: &Token, material: &Arc<Material>, context: &mut ParseContext) -> Result<(GeometryType, Arc<Material>, Option<String>)> {
         // discard the next token, we already know what object we're parsing from the above
         tokenizer.next();
         tokenizer.read( Token::LBrace )?;
 
-        loop {
-            let token_option = tokenizer.peek().map(|t| *t);
-            match token_option {
-                Some(token) => match *token {
-                    Token::Material => unimplemented!(),
-                    Token::Name => unimplemented!(),
-                    Token::RBrace => {
-                        tokenizer.read( Token::RBrace )?;
-                        // TODO: add material to return_value
-                        return match *object_type {
-                            Token::Sphere => Ok(GeometryType::Sphere),
-                            Token::Box => Ok(GeometryType::Box),
-                            Token::Square => Ok(GeometryType::Square),
-                            Token::Cylinder => Ok(GeometryType::Cylinder),
-                            _ => Err(TokenizationError::new("unexpected token")),
-                        }
+        let mut material = material.clone();
+        let mut capped = true;
+        let mut name = None;
 
-                    },
-                    _ => unimplemented!(), // syntax error, unexpected token
+        loop {
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match *token {
+                Token::Material => material = parse_material_expression(tokenizer, &material, context)?,
+                Token::Name => name = Some( parse_string_expression(tokenizer)?.to_string() ),
+                Token::Ident("capped") if *object_type == Token::Cylinder => capped = parse_boolean_expression(tokenizer)?,
+                Token::RBrace => {
+                    tokenizer.read( Token::RBrace )?;
+                    let geometry = match *object_type {
+                        Token::Sphere => GeometryType::Sphere,
+                        Token::Box => GeometryType::Box,
+                        Token::Square => GeometryType::Square,
+                        Token::Cylinder => GeometryType::Cylinder { capped },
+                        ref token => return Err(unexpected_token(token)),
+                    };
+                    return Ok((geometry, material, name));
                 },
-                None => unimplemented!(), // synxtax error, EOF
+                ref token => return Err(unexpected_token(token)),
             }
         }
     }
 
-    fn parse_cone(tokenizer: &mut Tokenizer) ->  Result<GeometryType> {
+    fn parse_cone(tokenizer: &mut Tokenizer, material: &Arc<Material>, context: &mut ParseContext) -> Result<(GeometryType, Arc<Material>, Option<String>)> {
 
+        let mut material = material.clone();
         let mut capped = true;
         let mut height = 1.0f64;
         let mut bottom_radius = 1.0f64;
         let mut top_radius = 0.0f64;
+        let mut name = None;
 
         tokenizer.read( Token::Cone )?;
-        tokenizer.read( Token::RBrace )?;
+        tokenizer.read( Token::LBrace )?;
 
         loop {
-            let token_option = tokenizer.peek().map(|t| *t);
-            match token_option {
-                Some(token) => match *token {
-                    Token::Material => unimplemented!(),
-                    Token::Name => unimplemented!(),
-                    Token::Capped => capped = parse_boolean_expression(tokenizer)?,
-                    Token::Height => height = parse_scalar_expression(tokenizer)?,
-                    Token::BottomRadius => bottom_radius = parse_scalar_expression(tokenizer)?,
-                    Token::TopRadius => top_radius = parse_scalar_expression(tokenizer)?,
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match *token {
+                Token::Material => material = parse_material_expression(tokenizer, &material, context)?,
+                Token::Name => name = Some( parse_string_expression(tokenizer)?.to_string() ),
+                Token::Ident("capped") => capped = parse_boolean_expression(tokenizer)?,
+                Token::Ident("height") => height = parse_scalar_expression(tokenizer, context)?,
+                Token::Ident("bottom_radius") => bottom_radius = parse_scalar_expression(tokenizer, context)?,
+                Token::Ident("top_radius") => top_radius = parse_scalar_expression(tokenizer, context)?,
+                Token::RBrace => {
+                    tokenizer.read( Token::RBrace )?;
+                    let geometry = GeometryType::Cone {
+                        capped,
+                        height,
+                        bottom_radius,
+                        top_radius,
+                    };
+                    return Ok((geometry, material, name));
+                },
+                ref token => return Err(unexpected_token(token)),
+            }
+        }
 
-                    Token::RBrace => {
-                        tokenizer.read( Token::RBrace )?;
-                        // TODO: add material to return_value
-                        return Ok(GeometryType::Cone {
-                            capped,
-                            height,
-                            bottom_radius,
-                            top_radius,
-                        });
+    }
 
+    fn parse_trimesh(tokenizer: &mut Tokenizer, material: &Arc<Material>, context: &mut ParseContext) -> Result<(GeometryType, Arc<Material>, Option<String>)> {
+
+        let mut material = material.clone();
+        let mut points = Vec::new();
+        let mut faces = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut colors = Vec::new();
+        let mut materials = Vec::new();
+        let mut generate_normals = false;
+        let mut given_normals = false;
+        let mut file = None;
+        let mut name = None;
+
+        tokenizer.read( Token::Trimesh )?;
+        tokenizer.read( Token::LBrace )?;
 
-                    },
-                    _ => unimplemented!(), // syntax error, unexpected token
+        loop {
+            let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+            match *token {
+                Token::Material => material = parse_material_expression(tokenizer, &material, context)?,
+                Token::Name => name = Some( parse_string_expression(tokenizer)?.to_string() ),
+                Token::Ident("file") => {
+                    if file.is_some() || !points.is_empty() || !faces.is_empty() {
+                        return Err(ParseError::new("trimesh can't take a file along with other points or faces"));
+                    }
+                    let filename = parse_string_expression(tokenizer)?;
+                    let mesh = load_mesh(&context.resolve(&filename)?, context.resolver)?;
+                    points = mesh.vertices().to_vec();
+                    faces = mesh.faces().to_vec();
+                    normals = mesh.normals().to_vec();
+                    uvs = mesh.uvs().to_vec();
+                    colors = mesh.colors().to_vec();
+                    given_normals = false;
+                    file = Some(context.scene_name(&filename));
+                },
+                Token::Ident("points") |
+                Token::Ident("polypoints") if file.is_some() => {
+                    return Err(ParseError::new("trimesh can't take a file along with other points or faces"));
+                },
+                Token::Ident("faces") if file.is_some() => {
+                    return Err(ParseError::new("trimesh can't take a file along with other points or faces"));
+                },
+                Token::Ident("points") |
+                Token::Ident("polypoints") => points = parse_list_expression(tokenizer, |tokenizer| parse_vector3(tokenizer, context))?,
+                Token::Ident("normals") => {
+                    normals = parse_list_expression(tokenizer, |tokenizer| parse_vector3(tokenizer, context))?;
+                    given_normals = true;
+                },
+                Token::Ident("materials") => {
+                    let parent = material.clone();
+                    materials = parse_list_expression(tokenizer, |tokenizer| parse_material(tokenizer, &parent, context).map(|material| (*material).clone()))?;
+                },
+                Token::Ident("faces") => {
+                    for polygon in parse_list_expression(tokenizer, |tokenizer| parse_list(tokenizer, |tokenizer| parse_index(tokenizer, context)))? {
+                        if polygon.len() < 3 {
+                            return Err(ParseError::new("trimesh faces need at least three vertices"));
+                        }
+                        // triangulate polygons as a fan around their first vertex
+                        for i in 1..polygon.len() - 1 {
+                            faces.push(TrimeshFace::new(polygon[0], polygon[i], polygon[i + 1]));
+                        }
+                    }
+                },
+                Token::Ident("gennormals") => {
+                    tokenizer.next();
+                    tokenizer.conditional_read( Token::Semicolon );
+                    generate_normals = true;
+                },
+                Token::RBrace => {
+                    tokenizer.read( Token::RBrace )?;
+                    break;
                 },
-                None => unimplemented!(), // synxtax error, EOF
+                ref token => return Err(unexpected_token(token)),
             }
         }
 
+        if faces.iter().any(|face| face.vertices().iter().any(|&index| index >= points.len())) {
+            return Err(ParseError::new("trimesh face refers to a point that doesn't exist"));
+        }
+        if !normals.is_empty() && normals.len() != points.len() {
+            return Err(ParseError::new("trimesh needs exactly one normal per point"));
+        }
+        if !materials.is_empty() && materials.len() != points.len() {
+            return Err(ParseError::new("trimesh needs exactly one material per point"));
+        }
+        let mut normals_source = if given_normals { TrimeshNormals::Given } else { TrimeshNormals::Loaded };
+        if generate_normals && normals.is_empty() {
+            normals = Mesh::generate_normals(&points, &faces);
+            normals_source = TrimeshNormals::Generated;
+        }
+
+        let mesh = Mesh::new(points, faces, normals, uvs, materials).with_colors(colors);
+        let material = for_mesh(&material, &mesh);
+        Ok((GeometryType::Trimesh { mesh: Arc::new(mesh), file, normals: normals_source }, material, name))
     }
 
-    fn parse_translate(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GeometryBuilderType> {
+    /// `mesh('file.obj')` places the contents of an `.obj`, `.ply` or `.stl` file
+    fn parse_mesh(tokenizer: &mut Tokenizer, material: &Arc<Material>, context: &ParseContext) -> Result<(GeometryType, Arc<Material>)> {
+        tokenizer.read( Token::Mesh )?;
+        tokenizer.read( Token::LParen )?;
+        let filename = parse_string(tokenizer)?;
+        tokenizer.read( Token::RParen )?;
+        tokenizer.conditional_read( Token::Semicolon );
+
+        let resolved = context.resolve(&filename)?;
+        if Path::new(&resolved).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("obj")) {
+            let model = ObjModel::load(&resolved, context.resolver)?;
+            return Ok((GeometryType::ObjMesh { filename: context.scene_name(&filename), model: Arc::new(model) }, material.clone()));
+        }
+
+        let mesh = load_mesh(&resolved, context.resolver)?;
+        let material = for_mesh(material, &mesh);
+        Ok((GeometryType::MeshFile { filename: context.scene_name(&filename), mesh: Arc::new(mesh) }, material))
+    }
+
+    fn parse_translate(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilderType> {
         tokenizer.read( Token::Translate )?;
         tokenizer.read( Token::LParen )?;
-        let x = parse_scalar(tokenizer)?;
+        let x = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let y = parse_scalar(tokenizer)?;
+        let y = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let z = parse_scalar(tokenizer)?;
+        let z = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
 
-        let transform = transform_node.create_child(Matrix4::from_translation(Vector3::new(x, y, z)));
-        let subelement = TransformableElementBuilder::new(tokenizer, &transform)?;
-
-        tokenizer.read( Token::RParen )?;
-        tokenizer.conditional_read( Token::Semicolon );
-
-        Ok(GeometryBuilderType::TransformableElement(transform, subelement))
+        GeometryBuilder::parse_transformed(tokenizer, Transformation::Translate(Vector3::new(x, y, z)), transform_node, material, context)
     }
 
-    fn parse_rotate(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GeometryBuilderType> {
+    fn parse_rotate(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilderType> {
         tokenizer.read( Token::Rotate )?;
         tokenizer.read( Token::LParen )?;
-        let x = parse_scalar(tokenizer)?;
+        let x = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let y = parse_scalar(tokenizer)?;
+        let y = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let z = parse_scalar(tokenizer)?;
+        let z = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let angle = parse_scalar(tokenizer)?;
+        let angle = parse_scalar(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
 
-        // TODO: double check if glm uses radians or degrees
-        let rotation = Matrix3::from_axis_angle(Vector3::new(x, y, z), Rad(angle));
-        let transform = transform_node.create_child(Matrix4::from(rotation));
-        let subelement = TransformableElementBuilder::new(tokenizer, &transform)?;
-
-        tokenizer.read( Token::RParen )?;
-        tokenizer.conditional_read( Token::Semicolon );
+        let axis = Vector3::new(x, y, z);
+        if axis.magnitude2() == 0.0 {
+            return Err(ParseError::new("rotation axis can't be zero"));
+        }
 
-        Ok(GeometryBuilderType::TransformableElement(transform, subelement))
+        GeometryBuilder::parse_transformed(tokenizer, Transformation::Rotate(axis, angle), transform_node, material, context)
     }
 
-    fn parse_scale(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GeometryBuilderType> {
+    fn parse_scale(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilderType> {
         tokenizer.read( Token::Scale )?;
         tokenizer.read( Token::LParen )?;
-        let x = parse_scalar(tokenizer)?;
+        let x = parse_scalar(tokenizer, context)?;
+        tokenizer.read( Token::Comma )?;
         let y;
         let z;
 
-        if let Some( &Token::Scalar(_) ) = tokenizer.peek().map(|t| *t) {
-            y = parse_scalar(tokenizer)?;
+        if tokenizer.peek().is_some_and(|t| is_scalar_start(t, context)) {
+            y = parse_scalar(tokenizer, context)?;
             tokenizer.read( Token::Comma )?;
-            z = parse_scalar(tokenizer)?;
+            z = parse_scalar(tokenizer, context)?;
             tokenizer.read( Token::Comma )?;
         } else {
             y = x;
             z = x;
         }
 
-        let transform = transform_node.create_child(Matrix4::from_nonuniform_scale(x, y, z));
-        let subelement = TransformableElementBuilder::new(tokenizer, &transform)?;
-
-        tokenizer.read( Token::RParen )?;
-        tokenizer.conditional_read( Token::Semicolon );
+        if x == 0.0 || y == 0.0 || z == 0.0 {
+            return Err(ParseError::new("scale factors can't be zero"));
+        }
 
-        Ok(GeometryBuilderType::TransformableElement(transform, subelement))
+        GeometryBuilder::parse_transformed(tokenizer, Transformation::Scale(Vector3::new(x, y, z)), transform_node, material, context)
     }
 
-    fn parse_transform(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GeometryBuilderType> {
+    fn parse_transform(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilderType> {
         tokenizer.read( Token::Transform )?;
         tokenizer.read( Token::LParen )?;
-        let row1 = parse_vector4(tokenizer)?;
+        let row1 = parse_vector4(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let row2 = parse_vector4(tokenizer)?;
+        let row2 = parse_vector4(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let row3 = parse_vector4(tokenizer)?;
+        let row3 = parse_vector4(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
-        let row4 = parse_vector4(tokenizer)?;
+        let row4 = parse_vector4(tokenizer, context)?;
         tokenizer.read( Token::Comma )?;
 
-        // TODO: check that these are being put in the right order
-        let transform = transform_node.create_child(Matrix4::from_cols(row1, row2, row3, row4).transpose());
-        let subelement = TransformableElementBuilder::new(tokenizer, &transform)?;
+        // the rows are given in reading order, cgmath matrices are built from columns
+        let matrix = Matrix4::from_cols(row1, row2, row3, row4).transpose();
+        if matrix.determinant() == 0.0 || mat3_from_mat4(matrix).determinant() == 0.0 {
+            return Err(ParseError::new("transform matrix must be invertible"));
+        }
+
+        GeometryBuilder::parse_transformed(tokenizer, Transformation::Matrix(matrix), transform_node, material, context)
+    }
+
+    /// Parses the element a transformation applies to, along with the rest
+    /// of the transformation statement
+    fn parse_transformed(tokenizer: &mut Tokenizer, transformation: Transformation, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GeometryBuilderType> {
+        let transform = transform_node.create_child(transformation.matrix())
+            .ok_or_else(|| ParseError::new("the transformations around an element can't be inverted together"))?;
+        let subelement = TransformableElementBuilder::new(tokenizer, &transform, material, context)?;
 
         tokenizer.read( Token::RParen )?;
         tokenizer.conditional_read( Token::Semicolon );
 
-        Ok(GeometryBuilderType::TransformableElement(transform, subelement))
+        Ok(GeometryBuilderType::TransformableElement(transform, transformation, subelement))
     }
 
+    fn create_objects(&self, objects: &mut Vec<Box<dyn SceneObject>>, pose: &Pose, motion: Option<&Matrix4<f64>>) {
+        let (transform, material, geometry) = match self.element {
+            Some(GeometryBuilderType::ConcreteGeometryType(ref transform, ref material, ref geometry)) => (transform, material, geometry),
+            Some(GeometryBuilderType::TransformableElement(_, _, ref subelement)) => return subelement.create_objects(objects, pose, motion),
+            None => return,
+        };
+
+        let transform = match placed(transform, pose.motion(self.name.as_ref(), transform, motion).as_ref()) {
+            Some(transform) => transform,
+            None => return,
+        };
+        let material = pose.material(material);
+
+        objects.push(match *geometry {
+            GeometryType::Sphere => Box::new(Sphere::new(transform, material)),
+            GeometryType::Box => Box::new(SceneBox::new(transform, material)),
+            GeometryType::Square => Box::new(Square::new(transform, material)),
+            GeometryType::Cylinder { capped } => Box::new(Cylinder::new(transform, material, capped)),
+            GeometryType::Cone { capped, height, bottom_radius, top_radius } =>
+                Box::new(Cone::new(transform, material, capped, height, bottom_radius, top_radius)),
+            GeometryType::Trimesh { ref mesh, .. } |
+            GeometryType::MeshFile { ref mesh, .. } => Box::new(Trimesh::new(transform, material, mesh.clone())),
+            GeometryType::ObjMesh { ref model, .. } => {
+                for part in model.parts() {
+                    let material = part.material.clone().unwrap_or_else(|| material.clone());
+                    objects.push(Box::new(Trimesh::new(transform.clone(), material, part.mesh.clone())));
+                }
+                return;
+            },
+        });
+    }
 }
 
 struct GroupBuilder {
     elements: Vec<TransformableElementBuilder>,
+    /// Where the group is placed, which the animation moves it from
+    transform: TransformNode,
+    name: Option<String>,
 }
 
 impl GroupBuilder {
-    pub fn new(tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GroupBuilder> {
+    pub fn new(tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GroupBuilder> {
         GroupBuilder {
             elements: Vec::new(),
-        }.parse_group(tokenizer, transform_node)
+            transform: transform_node.clone(),
+            name: None,
+        }.parse_group(tokenizer, transform_node, material, context)
     }
 
-    fn parse_group(mut self, tokenizer: &mut Tokenizer, transform_node: &TransformNode) -> Result<GroupBuilder> {
+    fn parse_group(mut self, tokenizer: &mut Tokenizer, transform_node: &TransformNode, material: &Arc<Material>, context: &mut ParseContext) -> Result<GroupBuilder> {
         tokenizer.read( Token::LBrace )?;
 
+        // a material statement applies to the elements following it in the group
+        let mut material = material.clone();
+
         loop {
-            let token_option = tokenizer.peek().map(|t| *t);
+            let token_option = tokenizer.peek().copied();
             match token_option {
                 Some(token) => match *token {
                     Token::Sphere |
@@ -382,21 +1121,29 @@ impl GroupBuilder {
                     Token::Cylinder |
                     Token::Cone |
                     Token::Trimesh |
+                    Token::Mesh |
                     Token::Translate |
                     Token::Rotate |
                     Token::Scale |
                     Token::Transform |
                     Token::LBrace => {
-                        self.elements.push( TransformableElementBuilder::new(tokenizer, transform_node)? )
+                        self.elements.push( TransformableElementBuilder::new(tokenizer, transform_node, &material, context)? )
+                    },
+                    Token::Ident(name) if context.definitions.contains_key(name) => {
+                        self.elements.push( TransformableElementBuilder::new(tokenizer, transform_node, &material, context)? )
                     },
                     Token::RBrace => {
                         tokenizer.read( Token::RBrace )?;
                         break;
                     },
-                    Token::Material => unimplemented!(),
-                    _ => unimplemented!(), // syntax error
+                    Token::Material => material = parse_material_expression(tokenizer, &material, context)?,
+                    Token::Name => self.name = Some( parse_string_expression(tokenizer)?.to_string() ),
+                    Token::Semicolon => {
+                        tokenizer.next();
+                    },
+                    ref token => return Err(unexpected_token(token)),
                 },
-                None => unimplemented!(), // unexpected EOF
+                None => return Err(unexpected_eof()),
             }
         }
 
@@ -404,26 +1151,383 @@ impl GroupBuilder {
     }
 }
 
+fn parse_ambient_light(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<Vector3<f64>> {
+    tokenizer.read( Token::AmbientLight )?;
+    tokenizer.read( Token::LBrace )?;
+
+    let mut color = Vector3::zero();
+
+    loop {
+        let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+        match *token {
+            Token::Ident("color") |
+            Token::Ident("colour") => color = parse_vector3_expression(tokenizer, context)?,
+            Token::RBrace => {
+                tokenizer.read( Token::RBrace )?;
+                return Ok(color);
+            },
+            ref token => return Err(unexpected_token(token)),
+        }
+    }
+}
+
+/// `render_settings { ... }` says how the scene should be rendered
+fn parse_render_settings(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<SceneSettings> {
+    tokenizer.read( Token::RenderSettings )?;
+    tokenizer.read( Token::LBrace )?;
+
+    let mut settings = SceneSettings::default();
+
+    loop {
+        let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+        match *token {
+            Token::Ident("width") => settings.width = Some( parse_count_expression(tokenizer, context, 1)? ),
+            Token::Ident("height") => settings.height = Some( parse_count_expression(tokenizer, context, 1)? ),
+            Token::Ident("samples") => settings.samples = Some( parse_count_expression(tokenizer, context, 1)? ),
+            Token::Ident("max_depth") => settings.max_depth = Some( parse_count_expression(tokenizer, context, 0)? ),
+            Token::Ident("integrator") => {
+                tokenizer.next();
+                tokenizer.read( Token::Equals )?;
+                let name = parse_string(tokenizer)?;
+                tokenizer.conditional_read( Token::Semicolon );
+                settings.integrator = Some( IntegratorType::from_name(&name)
+                    .ok_or_else(|| ParseError::new(format!("unknown integrator '{}', expected whitted or path", name)))? );
+            },
+            Token::Ident("background") => settings.background = Some( parse_vector3_expression(tokenizer, context)? ),
+            Token::RBrace => {
+                tokenizer.read( Token::RBrace )?;
+                return Ok(settings);
+            },
+            ref token => return Err(unexpected_token(token)),
+        }
+    }
+}
+
+fn parse_material_expression(tokenizer: &mut Tokenizer, parent: &Material, context: &mut ParseContext) -> Result<Arc<Material>> {
+    tokenizer.read( Token::Material )?;
+    tokenizer.read( Token::Equals )?;
+    let material = parse_material(tokenizer, parent, context)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(material)
+}
+
+/// Parses either the name of a previously declared material, or a material
+/// block whose unset properties are inherited from `parent`. A named material
+/// is the same one wherever its name is used, which is how the animation finds
+/// the elements using it.
+fn parse_material(tokenizer: &mut Tokenizer, parent: &Material, context: &mut ParseContext) -> Result<Arc<Material>> {
+    match tokenizer.peek().copied() {
+        Some(&Token::StrLit(_)) |
+        Some(&Token::Ident(_)) => {
+            let name = parse_string(tokenizer)?;
+            return context.materials.get(&*name)
+                .cloned()
+                .ok_or_else(|| ParseError::new(format!("unknown material '{}'", name)));
+        },
+        _ => {},
+    }
+
+    tokenizer.read( Token::LBrace )?;
+
+    let mut material = parent.clone();
+    let mut name = None;
+
+    loop {
+        let token = tokenizer.peek().copied().ok_or_else(unexpected_eof)?;
+        match *token {
+            Token::Ident("emissive") => material.emissive = parse_material_parameter(tokenizer, context)?,
+            Token::Ident("ambient") => material.ambient = parse_material_parameter(tokenizer, context)?,
+            Token::Ident("specular") => material.specular = parse_material_parameter(tokenizer, context)?,
+            Token::Ident("reflective") => material.reflective = parse_material_parameter(tokenizer, context)?,
+            Token::Ident("diffuse") => material.diffuse = parse_material_parameter(tokenizer, context)?,
+            Token::Ident("transmissive") => material.transmissive = parse_material_parameter(tokenizer, context)?,
+            Token::Ident("shininess") => material.shininess = parse_scalar_expression(tokenizer, context)?,
+            Token::Ident("index") => material.index = parse_scalar_expression(tokenizer, context)?,
+            Token::Name => name = Some( parse_string_expression(tokenizer)? ),
+            Token::RBrace => {
+                tokenizer.read( Token::RBrace )?;
+                break;
+            },
+            ref token => return Err(unexpected_token(token)),
+        }
+    }
+
+    let material = Arc::new(material);
+    if let Some(name) = name {
+        context.materials.insert(name.to_string(), material.clone());
+    }
+
+    Ok(material)
+}
+
+/// `material` as used on `mesh`, the same material unless the mesh has
+/// vertex colors to use instead
+fn for_mesh(material: &Arc<Material>, mesh: &Mesh) -> Arc<Material> {
+    if mesh.colors().is_empty() { material.clone() } else { Arc::new(material.for_mesh(mesh)) }
+}
+
+/// Parses `keyword = (r, g, b);`, `keyword = scalar;` or `keyword = map('image');`
+fn parse_material_parameter(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<MaterialParameter> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+
+    let parameter = match tokenizer.peek().copied() {
+        Some(&Token::Ident("map")) => {
+            tokenizer.next();
+            tokenizer.read( Token::LParen )?;
+            let filename = parse_string(tokenizer)?;
+            tokenizer.read( Token::RParen )?;
+
+            MaterialParameter::from_texture_map(load_texture_map(&filename, context.source(), &context.scene_name(&filename), context.resolver)?)
+        },
+        Some(&Token::LParen) => MaterialParameter::new(parse_vector3(tokenizer, context)?),
+        _ => {
+            let value = parse_scalar(tokenizer, context)?;
+            MaterialParameter::new(Vector3::new(value, value, value))
+        },
+    };
+
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(parameter)
+}
+
+/// Parses a parenthesized, comma separated list of items
+fn parse_list<'a, T, F>(tokenizer: &mut Tokenizer<'a>, mut parse_item: F) -> Result<Vec<T>>
+    where F: FnMut(&mut Tokenizer<'a>) -> Result<T>
+{
+    tokenizer.read( Token::LParen )?;
+
+    let mut items = Vec::new();
+    if tokenizer.conditional_read( Token::RParen ) {
+        return Ok(items);
+    }
+
+    loop {
+        items.push(parse_item(tokenizer)?);
+        if !tokenizer.conditional_read( Token::Comma ) {
+            tokenizer.read( Token::RParen )?;
+            return Ok(items);
+        }
+    }
+}
+
+fn parse_list_expression<'a, T, F>(tokenizer: &mut Tokenizer<'a>, parse_item: F) -> Result<Vec<T>>
+    where F: FnMut(&mut Tokenizer<'a>) -> Result<T>
+{
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let items = parse_list(tokenizer, parse_item)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(items)
+}
+
+/// Parses a scalar that must be a non-negative integer, like a trimesh vertex index
+fn parse_index(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<usize> {
+    let value = parse_scalar(tokenizer, context)?;
+    if value < 0.0 || value.fract() != 0.0 {
+        return Err(ParseError::new(format!("expected an index, found {}", value)));
+    }
+    Ok(value as usize)
+}
+
+fn parse_version(tokenizer: &mut Tokenizer) -> Result<()> {
+    if let Token::Scalar(version) = tokenizer.read( Token::Scalar(0f64) )? {
+        if version > 1.1 {
+            return Err(ParseError::new("Unsupported SbtRaytracer version"));
+        }
+    }
+    Ok(())
+}
+
+fn parse_identifier<'a>(tokenizer: &mut Tokenizer<'a>) -> Result<&'a str> {
+    match tokenizer.next() {
+        Some(&Token::Ident(name)) => Ok(name),
+        Some(token) => Err(ParseError::new(format!("expected identifier, found {:?}", token))),
+        None => Err(unexpected_eof()),
+    }
+}
+
+fn unexpected_token(token: &Token) -> ParseError {
+    ParseError::new(format!("unexpected token {:?}", token))
+}
+
+fn unexpected_eof() -> ParseError {
+    ParseError::new("unexpected end of file")
+}
+
+// The `*_expression` functions parse a `keyword = value;` statement, the
+// keyword token is discarded as the caller has already matched on it
+
 fn parse_boolean_expression(tokenizer: &mut Tokenizer) -> Result<bool> {
-    unimplemented!();
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let value = parse_boolean(tokenizer)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(value)
 }
 
-fn parse_scalar_expression(tokenizer: &mut Tokenizer) -> Result<f64> {
-    unimplemented!();
+fn parse_scalar_expression(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<f64> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let value = parse_scalar(tokenizer, context)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(value)
 }
 
-fn parse_vector4_expression(tokenizer: &mut Tokenizer) -> Result<Vector4<f64>> {
-    unimplemented!();
+/// `name = value;` where the value is a whole number of at least `min`
+fn parse_count_expression(tokenizer: &mut Tokenizer, context: &ParseContext, min: u32) -> Result<u32> {
+    let name = match tokenizer.peek() {
+        Some(&&Token::Ident(name)) => name,
+        _ => "value",
+    };
+    let value = parse_scalar_expression(tokenizer, context)?;
+    if value.fract() != 0.0 || value < f64::from(min) || value > f64::from(u32::MAX) {
+        return Err(ParseError::new(format!("{} must be a whole number of at least {}, found {}", name, min, value)));
+    }
+    Ok(value as u32)
+}
+
+fn parse_vector3_expression(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<Vector3<f64>> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let value = parse_vector3(tokenizer, context)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(value)
+}
+
+fn parse_vector4_expression(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<Vector4<f64>> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let value = parse_vector4(tokenizer, context)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(value)
+}
+
+fn parse_string_expression<'a>(tokenizer: &mut Tokenizer<'a>) -> Result<Cow<'a, str>> {
+    tokenizer.next();
+    tokenizer.read( Token::Equals )?;
+    let value = parse_string(tokenizer)?;
+    tokenizer.conditional_read( Token::Semicolon );
+    Ok(value)
+}
+
+fn parse_string<'a>(tokenizer: &mut Tokenizer<'a>) -> Result<Cow<'a, str>> {
+    match tokenizer.next() {
+        Some(&Token::StrLit(value)) => Ok(unescape(value)),
+        Some(&Token::Ident(value)) => Ok(Cow::Borrowed(value)),
+        Some(token) => Err(ParseError::new(format!("expected string, found {:?}", token))),
+        None => Err(unexpected_eof()),
+    }
 }
 
 fn parse_boolean(tokenizer: &mut Tokenizer) -> Result<bool> {
-    unimplemented!();
+    match tokenizer.next() {
+        Some(&Token::Symtrue) => Ok(true),
+        Some(&Token::Symfalse) => Ok(false),
+        Some(token) => Err(ParseError::new(format!("expected boolean, found {:?}", token))),
+        None => Err(ParseError::new("expected boolean, found end of file")),
+    }
 }
 
-fn parse_scalar(tokenizer: &mut Tokenizer) -> Result<f64> {
-    unimplemented!();
+/// Parses an arithmetic expression made of number literals, named constants,
+/// `+ - * /` and parentheses, with the usual precedence
+fn parse_scalar(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<f64> {
+    let mut value = parse_term(tokenizer, context)?;
+
+    loop {
+        if tokenizer.conditional_read( Token::Plus ) {
+            value += parse_term(tokenizer, context)?;
+        } else if tokenizer.conditional_read( Token::Minus ) {
+            value -= parse_term(tokenizer, context)?;
+        } else {
+            return Ok(value);
+        }
+
+        if !value.is_finite() {
+            return Err(ParseError::new("the expression is too large"));
+        }
+    }
 }
 
-fn parse_vector4(tokenizer: &mut Tokenizer) -> Result<Vector4<f64>> {
-    unimplemented!();
-}
\ No newline at end of file
+fn parse_term(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<f64> {
+    let mut value = parse_factor(tokenizer, context)?;
+
+    loop {
+        if tokenizer.conditional_read( Token::Star ) {
+            value *= parse_factor(tokenizer, context)?;
+        } else if tokenizer.conditional_read( Token::Slash ) {
+            let divisor = parse_factor(tokenizer, context)?;
+            if divisor == 0.0 {
+                return Err(ParseError::new("division by zero"));
+            }
+            value /= divisor;
+        } else {
+            return Ok(value);
+        }
+
+        if !value.is_finite() {
+            return Err(ParseError::new("the expression is too large"));
+        }
+    }
+}
+
+fn parse_factor(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<f64> {
+    match tokenizer.next() {
+        Some(&Token::Scalar(value)) => Ok(value),
+        Some(&Token::Minus) => Ok(-parse_factor(tokenizer, context)?),
+        Some(&Token::Plus) => parse_factor(tokenizer, context),
+        Some(&Token::LParen) => {
+            let value = parse_scalar(tokenizer, context)?;
+            tokenizer.read( Token::RParen )?;
+            Ok(value)
+        },
+        Some(&Token::Ident(name)) => constant_value(name, context)
+            .ok_or_else(|| ParseError::new(format!("unknown constant '{}'", name))),
+        Some(token) => Err(ParseError::new(format!("expected scalar, found {:?}", token))),
+        None => Err(ParseError::new("expected scalar, found end of file")),
+    }
+}
+
+/// Returns true if the token can begin a scalar expression
+fn is_scalar_start(token: &Token, context: &ParseContext) -> bool {
+    match *token {
+        Token::Scalar(_) | Token::Minus | Token::Plus | Token::LParen => true,
+        Token::Ident(name) => constant_value(name, context).is_some(),
+        _ => false,
+    }
+}
+
+/// Looks up a built in constant, or one declared with `let`
+fn constant_value(name: &str, context: &ParseContext) -> Option<f64> {
+    match name {
+        "pi" => Some(consts::PI),
+        "tau" => Some(2.0 * consts::PI),
+        _ => context.constants.get(name).cloned(),
+    }
+}
+
+fn parse_vector3(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<Vector3<f64>> {
+    tokenizer.read( Token::LParen )?;
+    let x = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::Comma )?;
+    let y = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::Comma )?;
+    let z = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::RParen )?;
+
+    Ok(Vector3::new(x, y, z))
+}
+
+fn parse_vector4(tokenizer: &mut Tokenizer, context: &ParseContext) -> Result<Vector4<f64>> {
+    tokenizer.read( Token::LParen )?;
+    let x = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::Comma )?;
+    let y = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::Comma )?;
+    let z = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::Comma )?;
+    let w = parse_scalar(tokenizer, context)?;
+    tokenizer.read( Token::RParen )?;
+
+    Ok(Vector4::new(x, y, z, w))
+}
@@ -0,0 +1,833 @@
+use std::f64::consts;
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use super::*;
+use super::super::super::scene::objects::{Intersect, Ray, RayType};
+
+fn tokenize<'a>(input: &'a str) -> Vec<Token<'a>> {
+    RayTokenizer::new(input).collect::<result::Result<Vec<_>, _>>().unwrap()
+}
+
+fn scalar(input: &str) -> Result<f64> {
+    let tokens = tokenize(input);
+    parse_scalar(&mut tokens.iter().peekable(), &ParseContext::new(&FileResolver))
+}
+
+#[test]
+fn scalar_literal_test() {
+    assert_eq!(scalar("4").unwrap(), 4.0);
+    assert_eq!(scalar("-0.25").unwrap(), -0.25);
+    assert_eq!(scalar("--2").unwrap(), 2.0);
+}
+
+#[test]
+fn scalar_arithmetic_test() {
+    assert_eq!(scalar("1 + 2 * 3").unwrap(), 7.0);
+    assert_eq!(scalar("(1 + 2) * 3").unwrap(), 9.0);
+    assert_eq!(scalar("8 / 2 / 2").unwrap(), 2.0);
+    assert_eq!(scalar("2 - 3 - 4").unwrap(), -5.0);
+    assert_eq!(scalar("-(1 - 3)").unwrap(), 2.0);
+    assert_eq!(scalar("pi/4").unwrap(), consts::PI / 4.0);
+}
+
+#[test]
+fn scalar_error_test() {
+    assert!(scalar("").is_err());
+    assert!(scalar("(1 + 2").is_err());
+    assert!(scalar("radius").is_err());
+    assert!(scalar("* 2").is_err());
+    assert!(scalar("1 / 0").is_err());
+    assert!(scalar("1 / (2 - 2)").is_err());
+    assert!(scalar("1e300 * 1e300").is_err());
+    assert!(scalar("1.7e308 + 1.7e308").is_err());
+}
+
+#[test]
+fn boolean_test() {
+    let tokens = tokenize("true false 1");
+    let mut tokenizer = tokens.iter().peekable();
+    assert!(parse_boolean(&mut tokenizer).unwrap());
+    assert!(!parse_boolean(&mut tokenizer).unwrap());
+    assert!(parse_boolean(&mut tokenizer).is_err());
+}
+
+#[test]
+fn vector_test() {
+    let tokens = tokenize("(1, -2, pi) (1, 2 * 2, -(3), .5)");
+    let mut tokenizer = tokens.iter().peekable();
+    let context = ParseContext::new(&FileResolver);
+    assert_eq!(parse_vector3(&mut tokenizer, &context).unwrap(), Vector3::new(1.0, -2.0, consts::PI));
+    assert_eq!(parse_vector4(&mut tokenizer, &context).unwrap(), Vector4::new(1.0, 4.0, -3.0, 0.5));
+}
+
+#[test]
+fn expression_test() {
+    let tokens = tokenize("capped = false; height = 2 * 3; direction = (0, -1, 0) position = (1, 2, 3, 1);");
+    let mut tokenizer = tokens.iter().peekable();
+    let context = ParseContext::new(&FileResolver);
+    assert!(!parse_boolean_expression(&mut tokenizer).unwrap());
+    assert_eq!(parse_scalar_expression(&mut tokenizer, &context).unwrap(), 6.0);
+    assert_eq!(parse_vector3_expression(&mut tokenizer, &context).unwrap(), Vector3::new(0.0, -1.0, 0.0));
+    assert_eq!(parse_vector4_expression(&mut tokenizer, &context).unwrap(), Vector4::new(1.0, 2.0, 3.0, 1.0));
+    assert!(tokenizer.next().is_none());
+}
+
+static SCENE_SAMPLE: &str = "SBT-raytracer 1.0
+
+camera {
+    position = (0, 0, 4);
+    viewdir = (0, 0, -1);
+    updir = (0, 1, 0);
+    aspectratio = 1;
+    fov = 45;
+}
+
+point_light {
+    position = (4, 4, 0);
+    color = (.5, .5, .5);
+    constant_attenuation_coeff = 0.25;
+}
+
+directional_light {
+    direction = (0, -1, 0);
+    colour = (1.0, 1.0, 1.0);
+}
+
+ambient_light { color = (0.1, 0.1, 0.1); }
+ambient_light { color = (0.1, 0, 0); }
+
+material = { name = 'red'; diffuse = (1, 0, 0); };
+
+sphere { }
+
+translate(3, 0, 0,
+    scale(2,
+        box { material = { specular = (0.9, 0.4, 0.0); shininess = 76.8; }; }));
+
+{
+    material = 'red';
+    cylinder { capped = false; }
+    translate(0, -3, 0, rotate(1, 0, 0, pi/2, cone { height = 2; top_radius = 0.5; }));
+}
+
+trimesh {
+    points = ((0, 0, -5), (1, 0, -5), (1, 1, -5), (0, 1, -5));
+    faces = ((0, 1, 2, 3));
+    gennormals;
+}
+";
+
+fn build(input: &str) -> Result<RaySceneBuilder<'static>> {
+    RaySceneBuilder::new(tokenize(input))
+}
+
+#[test]
+fn scene_parse_test() {
+    let builder = build(SCENE_SAMPLE).unwrap();
+    assert_eq!(builder.objects.len(), 4);
+    assert_eq!(builder.lights.len(), 2);
+    assert_eq!(builder.ambient, Some(Vector3::new(0.2, 0.1, 0.1)));
+    assert!(builder.camera.is_some());
+    assert!(builder.context.materials.contains_key("red"));
+    // the top level material applies to the objects after it
+    assert_eq!(builder.material.diffuse.base_value(), Vector3::new(1.0, 0.0, 0.0));
+
+    let scene = builder.create_scene();
+    assert_eq!(scene.object_count(), 5);
+    assert_eq!(scene.lights().len(), 2);
+
+    let bounds = scene.bounds().unwrap();
+    assert_eq!(bounds.max().x, 4.0);
+    assert_eq!(bounds.min().z, -5.0);
+}
+
+#[test]
+fn scene_intersect_test() {
+    let scene = build(SCENE_SAMPLE).unwrap().create_scene();
+
+    // straight down the view direction hits the front of the unit sphere
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&scene.camera().ray_through(0.5, 0.5).unwrap(), &mut isect));
+    assert!((isect.t - 3.0).abs() < 1e-9);
+    assert!((isect.n - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+
+    // the scaled box spans x in [2, 4]
+    let ray = Ray::new(Vector3::new(3.0, 0.0, 10.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 9.0).abs() < 1e-9);
+    let material = isect.material.unwrap();
+    assert_eq!(material.shininess, 76.8);
+    // unset properties come from the top level material
+    assert_eq!(material.diffuse.base_value(), Vector3::new(1.0, 0.0, 0.0));
+
+    // the trimesh quad is behind everything else
+    let ray = Ray::new(Vector3::new(0.9, 0.9, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 5.0).abs() < 1e-9);
+    assert!((isect.n.z.abs() - 1.0).abs() < 1e-9);
+
+    let ray = Ray::new(Vector3::new(0.0, 10.0, 10.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    assert!(!scene.intersect(&ray, &mut Intersect::new()));
+}
+
+#[test]
+fn scene_error_test() {
+    assert!(build("SBT-raytracer 2.0").is_err());
+    assert!(build("SBT-raytracer 1.0 sphere {").is_err());
+    assert!(build("SBT-raytracer 1.0 sphere { radius = 2; }").is_err());
+    assert!(build("SBT-raytracer 1.0 sphere { material = 'missing'; }").is_err());
+    assert!(build("SBT-raytracer 1.0 camera {} camera {}").is_err());
+    assert!(build("SBT-raytracer 1.0 scale(0, sphere {})").is_err());
+    // each scale can be inverted, but not both together
+    let nested = build("SBT-raytracer 1.0 scale(1e-120, scale(1e-120, sphere {}))").err().map(|error| error.to_string()).unwrap_or_default();
+    assert!(nested.contains("the transformations around an element can't be inverted together"), "{}", nested);
+    assert!(build("SBT-raytracer 1.0 trimesh { points = ((0,0,0)); faces = ((0, 1, 2)); }").is_err());
+    assert!(build("SBT-raytracer 1.0 point_light { direction = (0, 1, 0); }").is_err());
+}
+
+static DEFINITION_SAMPLE: &str = "SBT-raytracer 1.0
+let radius = 2.5;
+let height = radius * 2;
+
+define post = {
+    scale(radius, 1, height, cylinder { });
+    trimesh { points = ((0, 0, 0), (1, 0, 0), (0, 1, 0)); faces = ((0, 1, 2)); }
+};
+
+post
+translate(10, 0, 0, post)
+{ rotate(0, 0, 1, pi, post) }
+";
+
+#[test]
+fn let_test() {
+    let builder = build(DEFINITION_SAMPLE).unwrap();
+    assert_eq!(builder.context.constants["height"], 5.0);
+
+    assert!(scalar("radius").is_err());
+    assert!(build("SBT-raytracer 1.0 let pi = 3;").is_err());
+    assert!(build("SBT-raytracer 1.0 let x = 1; let x = 2;").is_err());
+    assert!(build("SBT-raytracer 1.0 let x = y;").is_err());
+}
+
+#[test]
+fn define_test() {
+    let builder = build(DEFINITION_SAMPLE).unwrap();
+    assert_eq!(builder.objects.len(), 3);
+
+    // every use of the definition is a single instance object
+    let scene = builder.create_scene();
+    assert_eq!(scene.object_count(), 3);
+
+    // each instance is moved by its own transform
+    let bounds = scene.bounds().unwrap();
+    assert!((bounds.max().x - 12.5).abs() < 1e-9);
+    assert!((bounds.min().x + 2.5).abs() < 1e-9);
+    assert!((bounds.max().z - 5.0).abs() < 1e-9);
+
+    let ray = Ray::new(Vector3::new(10.25, 0.25, 10.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    assert!((isect.t - 5.0).abs() < 1e-9);
+
+    // the definition's objects are created once and shared by its instances
+    assert_eq!(Arc::strong_count(&builder.context.definitions["post"].prototype), 4);
+    match builder.context.definitions["post"].element.element {
+        Some(TransformableElementType::Group(ref group)) => match group.elements[1].element {
+            Some(TransformableElementType::Geometry(ref geometry)) => match geometry.element {
+                Some(GeometryBuilderType::ConcreteGeometryType(_, _, GeometryType::Trimesh { ref mesh, .. })) => {
+                    assert_eq!(Arc::strong_count(mesh), 2);
+                },
+                _ => panic!("expected a trimesh"),
+            },
+            _ => panic!("expected a geometry"),
+        },
+        _ => panic!("expected a group"),
+    }
+
+    // definitions can instance earlier definitions
+    let nested = build("SBT-raytracer 1.0
+        define ball = sphere { };
+        define row = { ball translate(3, 0, 0, ball) translate(6, 0, 0, ball) };
+        translate(0, 0, -10, row)
+        translate(0, 3, -10, row)").unwrap().create_scene();
+    assert_eq!(nested.object_count(), 2);
+
+    let ray = Ray::new(Vector3::new(6.0, 3.0, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(nested.intersect(&ray, &mut isect));
+    assert!((isect.t - 9.0).abs() < 1e-9);
+    assert!((isect.n - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+
+    let ray = Ray::new(Vector3::new(4.5, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    assert!(!nested.intersect(&ray, &mut Intersect::new()));
+
+    assert!(build("SBT-raytracer 1.0 define a = sphere {}; define a = box {};").is_err());
+    assert!(build("SBT-raytracer 1.0 missing").is_err());
+}
+
+#[test]
+fn forest_test() {
+    // a grid of instances of one mesh, enough for both levels of the
+    // hierarchy to have interior nodes
+    let mut input = String::from("SBT-raytracer 1.0
+        define tree = trimesh {
+            points = ((-0.5, 0, 0), (0.5, 0, 0), (0.5, 1, 0), (-0.5, 1, 0));
+            faces = ((0, 1, 2, 3));
+        };
+    ");
+    for x in 0..10 {
+        for y in 0..10 {
+            input.push_str(&format!("translate({}, {}, -{}, tree)\n", x * 2, y * 2, x + y));
+        }
+    }
+
+    let scene = build(&input).unwrap().create_scene();
+    assert_eq!(scene.object_count(), 100);
+
+    for x in 0..10 {
+        for y in 0..10 {
+            let ray = Ray::new(Vector3::new(f64::from(x * 2), f64::from(y * 2) + 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+            let mut isect = Intersect::new();
+            assert!(scene.intersect(&ray, &mut isect));
+            assert!((isect.t - f64::from(x + y + 1)).abs() < 1e-9);
+        }
+    }
+
+    let ray = Ray::new(Vector3::new(1.0, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    assert!(!scene.intersect(&ray, &mut Intersect::new()));
+}
+
+#[test]
+fn include_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("materials.ray", "material = { name = 'shiny'; shininess = 40; };\nlet size = 3;");
+    resolver.insert("objects.ray", "SBT-raytracer 1.0\ninclude 'materials.ray';\nscale(size, sphere { material = shiny; });");
+    resolver.insert("main.ray", "");
+    let build_main = |input: &str| RaySceneBuilder::from_source(tokenize(input), "main.ray", &resolver);
+
+    let builder = build_main("SBT-raytracer 1.0\ninclude 'objects.ray';\nbox { material = shiny; }").unwrap();
+    assert_eq!(builder.objects.len(), 2);
+    assert_eq!(builder.context.constants["size"], 3.0);
+    assert_eq!(builder.context.materials["shiny"].shininess, 40.0);
+    assert!(builder.context.sources.len() == 1);
+
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("cycle_a.ray", "include 'cycle_b.ray';");
+    resolver.insert("cycle_b.ray", "include 'cycle_a.ray';");
+    resolver.insert("cycle.ray", "");
+    let error = RaySceneBuilder::from_source(tokenize("SBT-raytracer 1.0\ninclude 'cycle_a.ray';"), "cycle.ray", &resolver).err().unwrap();
+    assert!(error.to_string().contains("cycle"));
+
+    resolver.insert("itself.ray", "");
+    assert!(RaySceneBuilder::from_source(tokenize("SBT-raytracer 1.0\ninclude 'itself.ray';"), "itself.ray", &resolver).is_err());
+    resolver.insert("missing.ray", "");
+    assert!(RaySceneBuilder::from_source(tokenize("SBT-raytracer 1.0\ninclude 'does_not_exist.ray';"), "missing.ray", &resolver).is_err());
+}
+
+#[test]
+fn memory_include_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("scenes/main.ray", "");
+    resolver.insert("scenes/parts/sphere.ray", "include '../size.ray';\nscale(size, sphere {});");
+    resolver.insert("scenes/size.ray", "let size = 2;");
+
+    let tokens = tokenize("SBT-raytracer 1.0\ninclude 'parts/sphere.ray';\nbox {}");
+    let builder = RaySceneBuilder::from_source(tokens, "scenes/main.ray", &resolver).unwrap();
+    assert_eq!(builder.objects.len(), 2);
+    assert_eq!(builder.context.constants["size"], 2.0);
+
+    let tokens = tokenize("SBT-raytracer 1.0\ninclude 'size.ray';");
+    assert!(RaySceneBuilder::from_source(tokens, "main.ray", &resolver).is_err());
+}
+
+static WRITE_SAMPLE: &str = "SBT-raytracer 1.0
+
+camera {
+    position = (1, 2, 8);
+    quaternion = (0, 0.1, 0, 1);
+    fov = 50;
+}
+
+render_settings { width = 320; height = 240; integrator = path; background = (0.1, 0.2, 0.3); }
+
+material = { name = 'gold'; diffuse = (0.8, 0.6, 0.1); specular = (1, 1, 1); shininess = 1 / 3; };
+material = { name = 'glass'; transmissive = (0.9, 0.9, 0.9); index = 1.5; };
+
+let radius = 0.7;
+define ball = scale(radius, sphere { material = 'gold'; });
+define pair = { translate(-1, 0, 0, ball) translate(1, 0, 0, ball) };
+
+pair
+rotate(0, 1, 1, pi/3, translate(0, 3, 0, pair));
+transform((1, 0, 0, 0), (0, 2, 0, 1), (0, 0, 1, 0), (0, 0, 0, 1),
+    { material = 'glass'; box {} scale(0.5, 1, 2, square { material = { ambient = (0.1, 0.2, 0.3); }; }) });
+translate(0, -2, 0, cone { capped = false; height = 3; top_radius = 0.25; material = { diffuse = (0.1, 0.1, 0.1); }; });
+cylinder { capped = false; }
+trimesh {
+    points = ((0, 0, -5), (1, 0, -5), (1, 1, -5), (0, 1, -5));
+    faces = ((0, 1, 2, 3));
+    normals = ((0, 0, 1), (0, 0, 1), (0, 1, 1), (0, 0, 1));
+    materials = ({ diffuse = (1, 0, 0); }, 'gold', 'glass', { shininess = 12.5; });
+}
+translate(3, 0, 0, trimesh { points = ((0, 0, 0), (1, 0, 0), (0, 1, 0)); faces = ((0, 1, 2)); gennormals; })
+";
+
+/// Casts a fan of rays at both scenes and checks they see the same things
+fn assert_same_scene(expected: &Scene, actual: &Scene) {
+    assert_eq!(expected.object_count(), actual.object_count());
+    assert_eq!(expected.lights(), actual.lights());
+    assert_eq!(expected.ambient(), actual.ambient());
+    assert_eq!(expected.settings(), actual.settings());
+    assert_eq!(expected.bounds(), actual.bounds());
+
+    for i in 0..=20 {
+        for j in 0..=20 {
+            let (x, y) = (f64::from(i) / 20.0, f64::from(j) / 20.0);
+            let ray = expected.camera().ray_through(x, y).unwrap();
+            let actual_ray = actual.camera().ray_through(x, y).unwrap();
+            assert_eq!(ray.position(), actual_ray.position());
+            assert_eq!(ray.direction(), actual_ray.direction());
+
+            let mut expected_isect = Intersect::new();
+            let mut actual_isect = Intersect::new();
+            assert_eq!(expected.intersect(&ray, &mut expected_isect), actual.intersect(&ray, &mut actual_isect));
+            assert_eq!(expected_isect.t, actual_isect.t);
+            assert_eq!(expected_isect.n, actual_isect.n);
+            assert_eq!(expected_isect.material, actual_isect.material);
+        }
+    }
+}
+
+#[test]
+fn write_round_trip_test() {
+    for input in &[SCENE_SAMPLE, WRITE_SAMPLE] {
+        let builder = build(input).unwrap();
+        let written = builder.write_ray();
+        let reread = build(&written).unwrap();
+
+        // writing again gives exactly the same text
+        assert_eq!(reread.write_ray(), written);
+        assert_eq!(reread.context.materials, builder.context.materials);
+        assert_same_scene(&builder.create_scene(), &reread.create_scene());
+    }
+}
+
+#[test]
+fn write_test() {
+    let written = build(WRITE_SAMPLE).unwrap().write_ray();
+    assert!(written.starts_with("SBT-raytracer 1.1\n"));
+
+    // definitions come before their uses, and the ones they use before them
+    let ball = written.find("define ball = scale(0.7,").unwrap();
+    let pair = written.find("define pair = {").unwrap();
+    assert!(ball < pair);
+    assert!(written.contains("    translate(-1, 0, 0, ball)\n"));
+    assert!(written.contains("        'gold',\n        'glass',\n"));
+
+    // named materials are declared once and referred to by name
+    assert_eq!(written.matches("name = 'gold';").count(), 1);
+    assert!(written.contains("material = 'glass';"));
+    assert!(written.contains("rotate(0, 1, 1, 1.0471975511965976,"));
+    assert!(written.contains("transform((1, 0, 0, 0), (0, 2, 0, 1), (0, 0, 1, 0), (0, 0, 0, 1),"));
+    assert!(written.contains("quaternion = (0, 0.1, 0, 1);"));
+    assert!(written.contains("gennormals;"));
+    assert!(written.contains("render_settings {\n    width = 320;\n    height = 240;\n    integrator = 'path';\n"));
+    assert!(!written.contains("let "));
+}
+
+#[test]
+fn render_settings_test() {
+    let scene = build("SBT-raytracer 1.0
+        let size = 100;
+        render_settings {
+            width = size * 2;
+            height = size;
+            samples = 16;
+            max_depth = 0;
+            integrator = 'path';
+            background = (0, 0, 0.5);
+        }").unwrap().create_scene();
+    assert_eq!(*scene.settings(), SceneSettings {
+        width: Some(200),
+        height: Some(100),
+        samples: Some(16),
+        max_depth: Some(0),
+        integrator: Some(IntegratorType::Path),
+        background: Some(Vector3::new(0.0, 0.0, 0.5)),
+    });
+
+    // settings that are left out are up to the renderer
+    let scene = build("SBT-raytracer 1.0 render_settings { samples = 4; }").unwrap().create_scene();
+    assert_eq!(*scene.settings(), SceneSettings { samples: Some(4), ..SceneSettings::default() });
+    assert_eq!(*build("SBT-raytracer 1.0").unwrap().create_scene().settings(), SceneSettings::default());
+
+    let error = |input: &str| build(input).err().map(|error| error.to_string()).unwrap_or_default();
+    assert!(error("SBT-raytracer 1.0 render_settings {} render_settings {}").contains("only have one render_settings block"));
+    assert!(error("SBT-raytracer 1.0 render_settings { samples = 0; }").contains("samples must be a whole number of at least 1, found 0"));
+    assert!(error("SBT-raytracer 1.0 render_settings { width = 2.5; }").contains("width must be a whole number of at least 1, found 2.5"));
+    assert!(error("SBT-raytracer 1.0 render_settings { integrator = 'raster'; }").contains("unknown integrator 'raster'"));
+    assert!(build("SBT-raytracer 1.0 render_settings { fov = 30; }").is_err());
+}
+
+#[test]
+fn write_mesh_file_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("scenes/quad.ply", "ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+");
+    resolver.insert("scenes/main.ray", "");
+
+    let input = "SBT-raytracer 1.0
+        material = { name = 'matte'; diffuse = (0.5, 0.5, 0.5); };
+        mesh('quad.ply');
+        define quad = mesh('quad.ply');
+        translate(0, 0, 1, quad);
+        translate(0, 0, -1, trimesh { file = 'quad.ply'; gennormals; material = { shininess = 3; }; });
+    ";
+    let builder = RaySceneBuilder::from_source(tokenize(input), "scenes/main.ray", &resolver).unwrap();
+    let written = builder.write_ray();
+    assert!(written.contains("\n\nmaterial = 'matte';\nmesh('quad.ply')\n"));
+    assert!(written.contains("define quad = {\n    material = 'matte';\n    mesh('quad.ply')\n};"));
+    assert!(written.contains("file = 'quad.ply';"));
+    assert!(!written.contains("points"));
+
+    let reread = RaySceneBuilder::from_source(tokenize(&written), "scenes/main.ray", &resolver).unwrap();
+    assert_eq!(reread.write_ray(), written);
+    assert_same_scene(&builder.create_scene(), &reread.create_scene());
+
+    // file names in included files are written as seen from the scene
+    let quad = resolver.read("scenes/quad.ply").unwrap();
+    resolver.insert("scenes/parts/quad.ply", quad);
+    resolver.insert("scenes/parts/part.ray", "mesh('quad.ply'); trimesh { file = './quad.ply'; };");
+    let input = "SBT-raytracer 1.0 include 'parts/part.ray';";
+    let builder = RaySceneBuilder::from_source(tokenize(input), "scenes/main.ray", &resolver).unwrap();
+    let written = builder.write_ray();
+    assert!(written.contains("mesh('parts/quad.ply')"));
+    assert!(written.contains("file = 'parts/quad.ply';"));
+
+    let reread = RaySceneBuilder::from_source(tokenize(&written), "scenes/main.ray", &resolver).unwrap();
+    assert_eq!(reread.write_ray(), written);
+}
+
+#[test]
+fn write_escaped_string_test() {
+    let builder = build(r"SBT-raytracer 1.1
+        material = { name = 'it\'s'; shininess = 5; };
+        sphere { name = 'back\\slash'; material = 'it\'s'; }
+        box { name = 'C:\dir'; }").unwrap();
+    assert_eq!(builder.context.materials["it's"].shininess, 5.0);
+
+    let written = builder.write_ray();
+    assert!(written.contains(r"name = 'it\'s';"));
+    assert!(written.contains(r"name = 'back\\slash';"));
+    assert!(written.contains(r"name = 'C:\\dir';"));
+
+    let reread = build(&written).unwrap();
+    assert_eq!(reread.write_ray(), written);
+    assert_eq!(reread.context.materials, builder.context.materials);
+}
+
+static ANIMATION_SAMPLE: &str = "SBT-raytracer 1.1
+camera { position = (0, 0, 10); viewdir = (0, 0, -1); fov = 40; }
+point_light { name = 'lamp'; position = (0, 5, 0); color = (1, 1, 1); }
+directional_light { name = 'sun'; direction = (0, -1, 0); color = (1, 1, 1); }
+material = { name = 'shiny'; diffuse = (0.5, 0.5, 0.5); shininess = 10; }
+
+translate(0, 0, -2, { name = 'pair'; sphere { name = 'ball'; material = 'shiny'; } })
+box { material = { diffuse = (0.5, 0.5, 0.5); }; }
+
+animation {
+    frames = (1, 11);
+    camera {
+        key(1) { position = (0, 0, 10); }
+        key(11, bezier) { position = (0, 0, 20); fov = 60; }
+    }
+    light 'lamp' { key(1) { position = (0, 5, 0); } key(11) { position = (10, 5, 0); } }
+    light 'sun' { key(1) { color = (1, 1, 1); } key(11) { color = (0, 0, 0); } }
+    object 'ball' { key(1) { translate = (0, 0, 0); } key(11) { translate = (0, 10, 0); } }
+    object 'pair' { key(1) { rotate = (0, 0, 1, 0); scale = 1; } }
+    material 'shiny' { key(1) { shininess = 10; } key(11) { shininess = 110; } }
+}
+";
+
+/// Distance along a ray from above `x` straight down to what it hits
+fn height_at(scene: &Scene, x: f64, z: f64) -> Option<f64> {
+    let ray = Ray::new(Vector3::new(x, 50.0, z), Vector3::new(0.0, -1.0, 0.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    if scene.intersect(&ray, &mut isect) { Some(50.0 - isect.t) } else { None }
+}
+
+#[test]
+fn animation_test() {
+    let builder = build(ANIMATION_SAMPLE).unwrap();
+    let animation = builder.animation();
+    assert_eq!(animation.frame_range(), 1..=11);
+    assert!(animation.moves_objects());
+
+    // the scene as read is the first frame
+    let first = builder.create_scene();
+    assert_eq!(first.camera().eye(), Vector3::new(0.0, 0.0, 10.0));
+    assert_eq!(height_at(&first, 0.0, -2.0), Some(1.0));
+
+    // named elements move within the transforms around them
+    let middle = builder.scene_at(6.0);
+    assert_eq!(middle.camera().eye(), Vector3::new(0.0, 0.0, 15.0));
+    assert!((height_at(&middle, 0.0, -2.0).unwrap() - 6.0).abs() < 1e-9);
+    assert_eq!(middle.lights()[0], Light::new(LightType::PointLight { pos: Vector3::new(5.0, 5.0, 0.0), a: 0.0, b: 0.0, c: 0.0 }, Vector3::new(1.0, 1.0, 1.0)));
+    assert_eq!(middle.lights()[1].color(), Vector3::new(0.5, 0.5, 0.5));
+
+    // as do the materials of what has them
+    let ray = Ray::new(Vector3::new(0.0, 20.0, -2.0), Vector3::new(0.0, -1.0, 0.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(middle.intersect(&ray, &mut isect));
+    assert_eq!(isect.material.unwrap().shininess, 60.0);
+    let last = builder.scene_at(11.0);
+    assert!((height_at(&last, 0.0, -2.0).unwrap() - 11.0).abs() < 1e-9);
+    assert_eq!(height_at(&last, 0.0, 0.0), Some(0.5));
+    assert_eq!(last.camera().eye(), Vector3::new(0.0, 0.0, 20.0));
+}
+
+/// Shininess of the material of what's straight below `(x, 50, z)`
+fn shininess_at(scene: &Scene, x: f64, z: f64) -> f64 {
+    let ray = Ray::new(Vector3::new(x, 50.0, z), Vector3::new(0.0, -1.0, 0.0), RayType::Visibility);
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&ray, &mut isect));
+    isect.material.unwrap().shininess
+}
+
+#[test]
+fn animated_material_test() {
+    // an inline material that happens to be the same as the animated one is
+    // still a material of its own, but elements taking the named material as
+    // the current one use it
+    let builder = build("SBT-raytracer 1.1
+        material = { name = 'shiny'; diffuse = (0.5, 0.5, 0.5); shininess = 10; }
+        sphere { }
+        translate(3, 0, 0, box { material = { diffuse = (0.5, 0.5, 0.5); shininess = 10; }; })
+        translate(6, 0, 0, box { material = { shininess = 20; }; })
+        animation { material 'shiny' { key(1) { shininess = 10; } key(11) { shininess = 110; } } }").unwrap();
+    let last = builder.scene_at(11.0);
+    assert_eq!(shininess_at(&last, 0.0, 0.0), 110.0);
+    assert_eq!(shininess_at(&last, 3.0, 0.0), 10.0);
+    assert_eq!(shininess_at(&last, 6.0, 0.0), 20.0);
+
+    // which the written scene keeps
+    let reread = build(&builder.write_ray()).unwrap();
+    let last = reread.scene_at(11.0);
+    assert_eq!(shininess_at(&last, 0.0, 0.0), 110.0);
+    assert_eq!(shininess_at(&last, 3.0, 0.0), 10.0);
+}
+
+#[test]
+fn animated_camera_test() {
+    let builder = build("SBT-raytracer 1.1
+        camera { position = (0, 0, 10); }
+        sphere { }
+        animation { camera { key(1) { position = (0, 0, 10); } key(5) { position = (0, 0, 6); } } }").unwrap();
+    assert!(!builder.animation().moves_objects());
+    assert_eq!(builder.animation().frame_range(), 1..=5);
+
+    // only the camera changes, so the objects and their hierarchy are kept
+    let scene = builder.create_scene();
+    let objects = scene.objects().as_ptr();
+    let scene = builder.advance(scene, 2.0);
+    assert_eq!(scene.objects().as_ptr(), objects);
+    assert_eq!(scene.camera().eye(), Vector3::new(0.0, 0.0, 9.0));
+
+    // and the other settings of the camera are as they were
+    assert_eq!(scene.camera().ray_through(0.5, 0.5).unwrap().direction(), Vector3::new(0.0, 0.0, -1.0));
+    assert_same_scene(&builder.scene_at(2.0), &scene);
+}
+
+#[test]
+fn animation_error_test() {
+    let error = |input: &str| build(&(String::from("SBT-raytracer 1.1
+        point_light { name = 'lamp'; position = (0, 1, 0); }
+        directional_light { name = 'sun'; direction = (0, -1, 0); }
+        material = { name = 'shiny'; };
+        sphere { name = 'ball'; }
+        ") + input)).err().map(|error| error.to_string()).unwrap_or_default();
+    assert_eq!(error("animation { object 'ball' { key(1) { translate = (1, 0, 0); } } }"), "");
+    assert!(error("animation { light 'lantern' { key(1) { color = (1, 1, 1); } } }")
+        .contains("the animation keys the light 'lantern', but no light has that name"));
+    assert!(error("animation { light 'lamp' { key(1) { direction = (1, 0, 0); } } }")
+        .contains("'lamp' is a point light, which has no direction to key"));
+    assert!(error("animation { light 'sun' { key(1) { position = (1, 0, 0); } } }")
+        .contains("'sun' is a directional light, which has no position to key"));
+    assert!(error("animation { object 'cube' { key(1) { scale = 2; } } }")
+        .contains("the animation keys the object 'cube', but nothing in the scene has that name"));
+    assert!(error("animation { material 'matte' { key(1) { shininess = 2; } } }")
+        .contains("the animation keys the material 'matte', but no material has that name"));
+    assert!(error("animation { camera { key(1, smooth) { fov = 30; } } }")
+        .contains("unknown interpolation 'smooth', expected linear or bezier"));
+    assert!(error("animation { frames = (10, 1); }").contains("frames should be the first and last frame, like (1, 48)"));
+    assert!(error("animation { object 'ball' { key(1) { scale = (1, 0, 1); } } }").contains("scale factors can't be zero"));
+    assert!(error("animation { object 'ball' { key(1) { rotate = (0, 0, 0, 1); } } }").contains("rotation axis can't be zero"));
+    // scales that flip over, or dip through zero on a curve between positive keys
+    assert!(error("animation { object 'ball' { key(1) { scale = 1; } key(3) { scale = -1; } } }")
+        .contains("the scale of 'ball' passes through zero between its keys"));
+    assert!(error("animation { object 'ball' { key(1, bezier) { scale = 0.1; } key(2, bezier) { scale = 1; } key(3) { scale = 10; } } }")
+        .contains("the scale of 'ball' passes through zero between its keys"));
+    assert_eq!(error("animation { object 'ball' { key(1, bezier) { scale = -1; } key(2, bezier) { scale = -2; } key(3) { scale = (-1, -3, -1); } } }"), "");
+    assert!(!error("animation { camera { key(1) { aperture = 2; } } }").is_empty());
+}
+
+#[test]
+fn write_animation_test() {
+    let builder = build(ANIMATION_SAMPLE).unwrap();
+    let written = builder.write_ray();
+    assert!(written.contains("name = 'lamp';"));
+    assert!(written.contains("name = 'pair';"));
+    assert!(written.contains("name = 'ball';"));
+    assert!(written.contains("animation {\n    frames = (1, 11);\n    camera {\n        key(1) {\n            position = (0, 0, 10);\n        }\n"));
+    assert!(written.contains("key(11, bezier) {"));
+
+    let reread = build(&written).unwrap();
+    assert_eq!(reread.write_ray(), written);
+    assert_eq!(reread.animation(), builder.animation());
+    for &frame in &[1.0, 4.5, 11.0] {
+        assert_same_scene(&builder.scene_at(frame), &reread.scene_at(frame));
+    }
+}
+
+#[test]
+fn camera_projection_test() {
+    let camera = |block: &str| build(&format!("SBT-raytracer 1.1 camera {{ {} }}", block)).unwrap().create_scene().camera().clone();
+    assert_eq!(camera("").projection(), Projection::Perspective);
+    assert_eq!(camera("projection = 'orthographic'; viewheight = 6;").projection(), Projection::Orthographic { height: 6.0 });
+    assert_eq!(camera("projection = 'orthographic';").projection(), Projection::Orthographic { height: 2.0 });
+    assert_eq!(camera("projection = 'fisheye';").projection(), Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 });
+    assert_eq!(camera("projection = 'fisheye'; mapping = 'equisolid'; fov = 220;").projection(),
+        Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 220.0 });
+
+    // panoramas fill a 2:1 image for each eye
+    let panorama = camera("projection = 'equirectangular'; stereo = 'over-under'; interocular = 0.1;");
+    assert_eq!(panorama.projection(), Projection::Equirectangular);
+    assert_eq!(panorama.stereo(), Some(Stereo { layout: StereoLayout::OverUnder, interocular: 0.1 }));
+    assert_eq!(panorama.aspect_ratio(), 1.0);
+    assert_eq!(camera("projection = 'equirectangular';").aspect_ratio(), 2.0);
+    assert_eq!(camera("projection = 'equirectangular'; aspectratio = 3;").aspect_ratio(), 3.0);
+    assert_eq!(camera("stereo = 'side-by-side';").stereo(), Some(Stereo { layout: StereoLayout::SideBySide, interocular: 0.065 }));
+
+    let error = |block: &str| build(&format!("SBT-raytracer 1.1 camera {{ {} }}", block)).err().map(|error| error.to_string()).unwrap_or_default();
+    assert!(error("projection = 'cylindrical';").contains("unknown projection 'cylindrical', expected perspective, orthographic, fisheye, equirectangular"));
+    assert!(error("projection = 'fisheye'; mapping = 'stereographic';").contains("unknown fisheye mapping 'stereographic', expected equidistant or equisolid"));
+    assert!(error("stereo = 'anaglyph';").contains("unknown stereo layout 'anaglyph', expected side-by-side or over-under"));
+    assert!(error("mapping = 'equisolid';").contains("only fisheye cameras have a mapping"));
+    assert!(error("projection = 'fisheye'; viewheight = 2;").contains("only orthographic cameras have a viewheight"));
+    assert!(error("interocular = 0.06;").contains("interocular needs a stereo layout to go with it"));
+    assert!(error("projection = 'orthographic'; viewheight = 0;").contains("viewheight must be more than zero"));
+    assert!(error("stereo = 'over-under'; interocular = -1;").contains("interocular can't be negative"));
+    assert!(error("projection = 'fisheye'; fov = 400;").contains("a fisheye's fov must be more than 0 and at most 360 degrees"));
+    assert!(error("aspectratio = 0;").contains("aspectratio must be more than zero"));
+    assert!(error("projection = 'orthographic'; aspectratio = -1;").contains("aspectratio must be more than zero"));
+    assert!(error("fov = 180;").contains("fov must be more than 0 and less than 180 degrees"));
+    assert!(error("fov = 0;").contains("fov must be more than 0 and less than 180 degrees"));
+
+    // the projection is written out as it was given
+    let builder = build("SBT-raytracer 1.1
+        camera { position = (0, 1, 0); projection = 'fisheye'; mapping = 'equisolid'; fov = 200; stereo = 'side-by-side'; interocular = 0.07; }
+        sphere { }").unwrap();
+    let written = builder.write_ray();
+    assert!(written.contains("    projection = 'fisheye';\n    mapping = 'equisolid';\n    stereo = 'side-by-side';\n    interocular = 0.07;\n"));
+    let reread = build(&written).unwrap();
+    assert_eq!(reread.write_ray(), written);
+    assert_eq!(reread.create_scene().camera().projection(), builder.create_scene().camera().projection());
+}
+
+static MEDIA_SAMPLE: &str = "SBT-raytracer 1.1
+fog {
+    absorption = (0.01, 0.02, 0.03);
+    scattering = (0.1, 0.1, 0.1);
+    asymmetry = -0.2;
+}
+medium {
+    scattering = (0.5, 0.5, 0.5);
+    asymmetry = 0.3;
+    density = {
+        size = (2, 1, 1);
+        values = (0, 2);
+    };
+    {
+        name = 'smoke';
+        translate(0, 0, -4, scale(2, box {}));
+    }
+}
+sphere {}
+animation { object 'smoke' { key(1) { translate = (0, 0, 0); } key(11) { translate = (0, 3, 0); } } }
+";
+
+#[test]
+fn media_test() {
+    let builder = build(MEDIA_SAMPLE).unwrap();
+    let scene = builder.create_scene();
+    // the medium's box bounds it without being rendered
+    assert_eq!(scene.object_count(), 1);
+
+    let media = scene.media();
+    let fog = media.fog.as_ref().unwrap();
+    assert_eq!(fog.absorption, Vector3::new(0.01, 0.02, 0.03));
+    assert_eq!(fog.asymmetry, -0.2);
+    assert!(fog.density.is_none());
+
+    assert_eq!(media.volumes.len(), 1);
+    let volume = &media.volumes[0];
+    assert_eq!(volume.medium().absorption, Vector3::new(0.0, 0.0, 0.0));
+    assert_eq!(volume.medium().density.as_ref().unwrap().values(), &[0.0, 2.0]);
+    assert!((volume.density_at(Vector3::new(0.5, 0.0, -4.0)) - 1.5).abs() < 1e-9);
+    assert_eq!(volume.density_at(Vector3::new(0.5, 0.0, 0.0)), 0.0);
+
+    // the animation moves the box the medium fills
+    let moved = builder.scene_at(11.0);
+    assert!((moved.media().volumes[0].density_at(Vector3::new(0.5, 3.0, -4.0)) - 1.5).abs() < 1e-9);
+}
+
+#[test]
+fn media_error_test() {
+    let error = |input: &str| build(&(String::from("SBT-raytracer 1.1\n") + input)).err().map(|error| error.to_string()).unwrap_or_default();
+    assert_eq!(error("medium { sphere {} }"), "");
+    assert!(error("fog {} fog {}").contains("a scene may only have one fog block"));
+    assert!(error("medium { absorption = (1, 1, 1); }").contains("a medium needs the elements it fills"));
+    assert!(error("fog { scattering = (0, -1, 0); }").contains("scattering can't be negative"));
+    assert!(error("fog { asymmetry = 1; }").contains("asymmetry must be between -1 and 1"));
+    assert!(error("fog { density = { size = (1, 1, 1); values = (1); }; }").contains("put the density grid in a medium block"));
+    assert!(error("medium { density = { size = (2, 2); values = (1, 1, 1, 1); }; box {} }").contains("a density grid's size should be"));
+    assert!(error("medium { density = { size = (2, 2, 2); values = (1, 1); }; box {} }").contains("a 2x2x2 density grid needs 8 values, found 2"));
+    assert!(error("medium { density = { values = (1); }; box {} }").contains("a density grid needs both its size and its values"));
+    assert!(error("medium { density = { size = (1, 1, 1); values = (-1); }; box {} }").contains("densities must be zero or more"));
+    assert!(!error("fog { sphere {} }").is_empty());
+}
+
+#[test]
+fn write_media_test() {
+    let builder = build(MEDIA_SAMPLE).unwrap();
+    let written = builder.write_ray();
+    assert!(written.contains("fog {\n    absorption = (0.01, 0.02, 0.03);\n    scattering = (0.1, 0.1, 0.1);\n    asymmetry = -0.2;\n}\n"));
+    assert!(written.contains("    density = {\n        size = (2, 1, 1);\n        values = (0, 2);\n    };\n"));
+
+    let reread = build(&written).unwrap();
+    assert_eq!(reread.write_ray(), written);
+    let (scene, rescene) = (builder.create_scene(), reread.create_scene());
+    assert_eq!(rescene.media().fog, scene.media().fog);
+    assert_eq!(rescene.media().volumes[0].medium(), scene.media().volumes[0].medium());
+    assert_eq!(rescene.media().volumes[0].bounds(), scene.media().volumes[0].bounds());
+}
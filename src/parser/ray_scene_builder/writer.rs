@@ -0,0 +1,612 @@
+//! Writes a parsed `.ray` scene back out as `.ray` text. Expressions,
+//! constants and includes have already been evaluated by the parser, so
+//! they're written as the values they produced, but the element hierarchy,
+//! transformations, names, named materials, definitions, media and animation
+//! are kept. File names are written relative to the scene, so the output
+//! parses back from the scene's directory.
+
+use cgmath::{Vector3, Vector4};
+
+use std::collections::HashSet;
+
+use super::*;
+use super::super::super::scene::animation::{Interpolation, Keyable, Track};
+use super::super::super::scene::medium::Medium;
+
+impl<'a> RaySceneBuilder<'a> {
+    /// The scene as pretty-printed `SBT-raytracer 1.1` text, which parses
+    /// back into the same scene
+    pub fn write_ray(&self) -> String {
+        let mut writer = RayWriter::new(&self.context);
+        writer.write_scene(self);
+        writer.output
+    }
+}
+
+struct RayWriter<'a, 'b: 'a> {
+    output: String,
+    indent: usize,
+    context: &'a ParseContext<'b>,
+    /// Names of the named materials, sorted so the output is stable
+    material_names: Vec<&'a str>,
+}
+
+impl<'a, 'b> RayWriter<'a, 'b> {
+    fn new(context: &'a ParseContext<'b>) -> RayWriter<'a, 'b> {
+        let mut material_names: Vec<&str> = context.materials.keys().map(String::as_str).collect();
+        material_names.sort();
+
+        RayWriter {
+            output: String::new(),
+            indent: 0,
+            context,
+            material_names,
+        }
+    }
+
+    fn write_scene(&mut self, builder: &RaySceneBuilder) {
+        self.line("SBT-raytracer 1.1");
+
+        if let Some(ref camera) = builder.camera {
+            self.line("");
+            self.write_camera(camera);
+        }
+
+        if let Some(ref settings) = builder.settings {
+            self.line("");
+            self.write_render_settings(settings);
+        }
+
+        if let Some(ambient) = builder.ambient {
+            self.line("");
+            self.line(&format!("ambient_light {{ color = {}; }}", vector3(ambient)));
+        }
+
+        for light in &builder.lights {
+            self.line("");
+            self.write_light(&light.light, light.name.as_deref());
+        }
+
+        // named materials are declared up front, every element states its
+        // material so they don't rely on the material that's current
+        for &name in &self.material_names.clone() {
+            self.line("");
+            self.begin_line("material = ");
+            self.write_material_block(&self.context.materials[name], Some(name));
+            self.output.push_str(";\n");
+        }
+
+        let mut names: Vec<&String> = self.context.definitions.keys().collect();
+        names.sort();
+        let mut written = HashSet::new();
+        for name in names {
+            self.write_definition(name, &self.context.definitions[name], &mut written);
+        }
+
+        for element in &builder.objects {
+            self.line("");
+            self.write_statement(element);
+            self.output.push('\n');
+        }
+
+        if let Some(ref fog) = builder.fog {
+            self.line("");
+            self.write_medium("fog", fog, &[]);
+        }
+
+        for medium in &builder.media {
+            self.line("");
+            self.write_medium("medium", &medium.medium, &medium.boundary);
+        }
+
+        if builder.animation != Animation::new() {
+            self.line("");
+            self.write_animation(&builder.animation);
+        }
+    }
+
+    fn write_render_settings(&mut self, settings: &SceneSettings) {
+        self.line("render_settings {");
+        self.indent += 1;
+        let counts = [("width", settings.width), ("height", settings.height), ("samples", settings.samples), ("max_depth", settings.max_depth)];
+        for &(name, count) in &counts {
+            if let Some(count) = count {
+                self.line(&format!("{} = {};", name, count));
+            }
+        }
+        if let Some(integrator) = settings.integrator {
+            self.line(&format!("integrator = {};", string(integrator.name())));
+        }
+        if let Some(background) = settings.background {
+            self.line(&format!("background = {};", vector3(background)));
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn write_medium(&mut self, keyword: &str, medium: &Medium, boundary: &[TransformableElementBuilder]) {
+        self.line(&format!("{} {{", keyword));
+        self.indent += 1;
+        self.line(&format!("absorption = {};", vector3(medium.absorption)));
+        self.line(&format!("scattering = {};", vector3(medium.scattering)));
+        self.line(&format!("asymmetry = {};", medium.asymmetry));
+        if let Some(ref grid) = medium.density {
+            let size = grid.size();
+            let values: Vec<String> = grid.values().iter().map(f64::to_string).collect();
+            self.line("density = {");
+            self.indent += 1;
+            self.line(&format!("size = ({}, {}, {});", size[0], size[1], size[2]));
+            self.line(&format!("values = ({});", values.join(", ")));
+            self.indent -= 1;
+            self.line("};");
+        }
+        for element in boundary {
+            self.write_statement(element);
+            self.output.push('\n');
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn write_camera(&mut self, camera: &CameraBuilder) {
+        self.line("camera {");
+        self.indent += 1;
+        if let Some(position) = camera.position {
+            self.line(&format!("position = {};", vector3(position)));
+        }
+        if let Some(view_dir) = camera.view_dir {
+            self.line(&format!("viewdir = {};", vector3(view_dir)));
+        }
+        if let Some(up_dir) = camera.up_dir {
+            self.line(&format!("updir = {};", vector3(up_dir)));
+        }
+        if let Some(aspect_ratio) = camera.aspect_ratio {
+            self.line(&format!("aspectratio = {};", aspect_ratio));
+        }
+        if let Some(fov) = camera.fov {
+            self.line(&format!("fov = {};", fov));
+        }
+        if let Some(quaternion) = camera.quaternion {
+            self.line(&format!("quaternion = {};", vector4(quaternion)));
+        }
+        if let Some(projection) = camera.projection {
+            self.line(&format!("projection = {};", string(projection)));
+        }
+        if let Some(mapping) = camera.mapping {
+            self.line(&format!("mapping = {};", string(mapping.name())));
+        }
+        if let Some(view_height) = camera.view_height {
+            self.line(&format!("viewheight = {};", view_height));
+        }
+        if let Some(stereo) = camera.stereo {
+            self.line(&format!("stereo = {};", string(stereo.name())));
+        }
+        if let Some(interocular) = camera.interocular {
+            self.line(&format!("interocular = {};", interocular));
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn write_light(&mut self, light: &Light, name: Option<&str>) {
+        match *light.light_type() {
+            LightType::PointLight { pos, a, b, c } => {
+                self.line("point_light {");
+                self.indent += 1;
+                self.write_name(name);
+                self.line(&format!("position = {};", vector3(pos)));
+                self.line(&format!("color = {};", vector3(light.color())));
+                self.line(&format!("constant_attenuation_coeff = {};", a));
+                self.line(&format!("linear_attenuation_coeff = {};", b));
+                self.line(&format!("quadratic_attenuation_coeff = {};", c));
+            },
+            LightType::DirectionalLight { orientation } => {
+                self.line("directional_light {");
+                self.indent += 1;
+                self.write_name(name);
+                self.line(&format!("direction = {};", vector3(orientation)));
+                self.line(&format!("color = {};", vector3(light.color())));
+            },
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// Definitions are written after the ones their element uses
+    fn write_definition(&mut self, name: &str, definition: &Definition, written: &mut HashSet<String>) {
+        if !written.insert(name.to_string()) {
+            return;
+        }
+
+        let mut dependencies = Vec::new();
+        used_definitions(&definition.element, &mut dependencies);
+        for dependency in dependencies {
+            self.write_definition(&dependency.0, &dependency.1, written);
+        }
+
+        self.line("");
+        self.begin_line(&format!("define {} = ", name));
+        self.write_element(&definition.element);
+        self.output.push_str(";\n");
+    }
+
+    /// Writes an element where statements may go, at the top level or in a
+    /// group, on lines of its own but without a newline after it
+    fn write_statement(&mut self, element: &TransformableElementBuilder) {
+        // mesh nodes take the current material, which a statement can set
+        if let Some(TransformableElementType::Geometry(ref geometry)) = element.element {
+            if let Some(GeometryBuilderType::ConcreteGeometryType(_, ref material, ref geometry)) = geometry.element {
+                if let Some(filename) = mesh_filename(geometry) {
+                    self.write_material_statement(material);
+                    self.begin_line(&format!("mesh({})", string(filename)));
+                    return;
+                }
+            }
+        }
+
+        self.begin_line("");
+        self.write_element(element);
+    }
+
+    /// Writes an element starting at the current position, without a newline
+    /// after it
+    fn write_element(&mut self, element: &TransformableElementBuilder) {
+        match element.element {
+            Some(TransformableElementType::Geometry(ref geometry)) => self.write_geometry(geometry),
+            Some(TransformableElementType::Group(ref group)) => {
+                self.output.push_str("{\n");
+                self.indent += 1;
+                self.write_name(group.name.as_deref());
+                for element in &group.elements {
+                    self.write_statement(element);
+                    self.output.push('\n');
+                }
+                self.indent -= 1;
+                self.begin_line("}");
+            },
+            Some(TransformableElementType::Instance(ref instance)) => self.output.push_str(&instance.name),
+            None => self.output.push_str("{}"),
+        }
+    }
+
+    fn write_geometry(&mut self, geometry: &GeometryBuilder) {
+        match geometry.element {
+            Some(GeometryBuilderType::ConcreteGeometryType(_, ref material, ref object)) => self.write_object(object, material, geometry.name.as_deref()),
+            Some(GeometryBuilderType::TransformableElement(_, ref transformation, ref subelement)) => {
+                let arguments = match *transformation {
+                    Transformation::Translate(offset) => format!("translate({}, {}, {},", offset.x, offset.y, offset.z),
+                    Transformation::Rotate(axis, angle) => format!("rotate({}, {}, {}, {},", axis.x, axis.y, axis.z, angle),
+                    Transformation::Scale(factors) if factors.x == factors.y && factors.y == factors.z => format!("scale({},", factors.x),
+                    Transformation::Scale(factors) => format!("scale({}, {}, {},", factors.x, factors.y, factors.z),
+                    Transformation::Matrix(matrix) => {
+                        let rows: Vec<String> = (0..4).map(|i| vector4(Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]))).collect();
+                        format!("transform({},", rows.join(", "))
+                    },
+                };
+                self.output.push_str(&arguments);
+
+                // instances are short enough to stay on the same line
+                if let Some(TransformableElementType::Instance(ref instance)) = subelement.element {
+                    self.output.push_str(&format!(" {})", instance.name));
+                    return;
+                }
+
+                self.output.push('\n');
+                self.indent += 1;
+                self.begin_line("");
+                self.write_element(subelement);
+                self.output.push(')');
+                self.indent -= 1;
+            },
+            None => self.output.push_str("{}"),
+        }
+    }
+
+    fn write_object(&mut self, geometry: &GeometryType, material: &Arc<Material>, name: Option<&str>) {
+        let keyword = match *geometry {
+            GeometryType::Sphere => "sphere",
+            GeometryType::Box => "box",
+            GeometryType::Square => "square",
+            GeometryType::Cylinder { .. } => "cylinder",
+            GeometryType::Cone { .. } => "cone",
+            GeometryType::Trimesh { .. } => "trimesh",
+            // where a mesh node can't follow a material statement it's put
+            // in a group of its own, which reads back the same way
+            GeometryType::ObjMesh { ref filename, .. } |
+            GeometryType::MeshFile { ref filename, .. } => {
+                self.output.push_str("{\n");
+                self.indent += 1;
+                self.write_material_statement(material);
+                self.line(&format!("mesh({})", string(filename)));
+                self.indent -= 1;
+                self.begin_line("}");
+                return;
+            },
+        };
+
+        self.output.push_str(keyword);
+        self.output.push_str(" {\n");
+        self.indent += 1;
+        self.write_name(name);
+
+        match *geometry {
+            GeometryType::Cylinder { capped } => self.line(&format!("capped = {};", capped)),
+            GeometryType::Cone { capped, height, bottom_radius, top_radius } => {
+                self.line(&format!("capped = {};", capped));
+                self.line(&format!("height = {};", height));
+                self.line(&format!("bottom_radius = {};", bottom_radius));
+                self.line(&format!("top_radius = {};", top_radius));
+            },
+            GeometryType::Trimesh { ref mesh, ref file, normals } => self.write_trimesh(mesh, file.as_deref(), normals),
+            _ => {},
+        }
+        self.write_material_statement(material);
+
+        self.indent -= 1;
+        self.begin_line("}");
+    }
+
+    fn write_trimesh(&mut self, mesh: &Mesh, file: Option<&str>, normals: TrimeshNormals) {
+        match file {
+            Some(file) => self.line(&format!("file = {};", string(file))),
+            None => {
+                let points: Vec<String> = mesh.vertices().iter().map(|&point| vector3(point)).collect();
+                let faces: Vec<String> = mesh.faces().iter()
+                    .map(|face| {
+                        let [a, b, c] = face.vertices();
+                        format!("({}, {}, {})", a, b, c)
+                    })
+                    .collect();
+                self.write_list("points", &points);
+                self.write_list("faces", &faces);
+            },
+        }
+
+        match normals {
+            TrimeshNormals::Loaded => {},
+            TrimeshNormals::Given => {
+                let normals: Vec<String> = mesh.normals().iter().map(|&normal| vector3(normal)).collect();
+                self.write_list("normals", &normals);
+            },
+            TrimeshNormals::Generated => self.line("gennormals;"),
+        }
+
+        if !mesh.materials().is_empty() {
+            self.line("materials = (");
+            self.indent += 1;
+            let count = mesh.materials().len();
+            for (i, material) in mesh.materials().iter().enumerate() {
+                match self.material_name(material) {
+                    Some(name) => self.begin_line(&string(name)),
+                    None => {
+                        self.begin_line("");
+                        self.write_material_block(material, None);
+                    },
+                }
+                self.output.push_str(if i + 1 < count { ",\n" } else { "\n" });
+            }
+            self.indent -= 1;
+            self.line(");");
+        }
+    }
+
+    fn write_name(&mut self, name: Option<&str>) {
+        if let Some(name) = name {
+            self.line(&format!("name = {};", string(name)));
+        }
+    }
+
+    fn write_animation(&mut self, animation: &Animation) {
+        self.line("animation {");
+        self.indent += 1;
+        if let Some(ref frames) = animation.frames {
+            self.line(&format!("frames = ({}, {});", frames.start(), frames.end()));
+        }
+
+        let camera = &animation.camera;
+        let mut keys = Vec::new();
+        track_keys(&mut keys, "position", &camera.position, vector3);
+        track_keys(&mut keys, "viewdir", &camera.view_dir, vector3);
+        track_keys(&mut keys, "updir", &camera.up_dir, vector3);
+        track_keys(&mut keys, "fov", &camera.fov, |fov| fov.to_string());
+        self.write_keys("camera", keys);
+
+        for (name, light) in &animation.lights {
+            let mut keys = Vec::new();
+            track_keys(&mut keys, "position", &light.position, vector3);
+            track_keys(&mut keys, "direction", &light.direction, vector3);
+            track_keys(&mut keys, "color", &light.color, vector3);
+            self.write_keys(&format!("light {}", string(name)), keys);
+        }
+        for (name, element) in &animation.elements {
+            let mut keys = Vec::new();
+            track_keys(&mut keys, "translate", &element.translate, vector3);
+            track_keys(&mut keys, "rotate", &element.rotate, vector4);
+            track_keys(&mut keys, "scale", &element.scale, vector3);
+            self.write_keys(&format!("object {}", string(name)), keys);
+        }
+        for (name, material) in &animation.materials {
+            let mut keys = Vec::new();
+            track_keys(&mut keys, "shininess", &material.shininess, |shininess| shininess.to_string());
+            track_keys(&mut keys, "index", &material.index, |index| index.to_string());
+            self.write_keys(&format!("material {}", string(name)), keys);
+        }
+
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// Writes the keys of a target, a key block for the properties keyed at
+    /// the same frame with the same interpolation
+    fn write_keys(&mut self, target: &str, mut keys: Vec<KeyLine>) {
+        if keys.is_empty() {
+            return;
+        }
+        // sorting is stable, so properties stay in the order they're listed
+        keys.sort_by(|a, b| a.frame.partial_cmp(&b.frame).unwrap());
+
+        self.line(&format!("{} {{", target));
+        self.indent += 1;
+        let mut start = 0;
+        while start < keys.len() {
+            let (frame, interpolation) = (keys[start].frame, keys[start].interpolation);
+            let end = keys[start..].iter()
+                .position(|key| key.frame != frame || key.interpolation != interpolation)
+                .map_or(keys.len(), |length| start + length);
+
+            match interpolation {
+                Interpolation::Linear => self.line(&format!("key({}) {{", frame)),
+                _ => self.line(&format!("key({}, {}) {{", frame, interpolation.name())),
+            }
+            self.indent += 1;
+            for key in &keys[start..end] {
+                self.line(&key.text);
+            }
+            self.indent -= 1;
+            self.line("}");
+            start = end;
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn write_list(&mut self, keyword: &str, items: &[String]) {
+        self.line(&format!("{} = (", keyword));
+        self.indent += 1;
+        for (i, item) in items.iter().enumerate() {
+            let separator = if i + 1 < items.len() { "," } else { "" };
+            self.line(&format!("{}{}", item, separator));
+        }
+        self.indent -= 1;
+        self.line(");");
+    }
+
+    /// Refers to the named material when it's one, otherwise states the
+    /// material in full
+    fn write_material_statement(&mut self, material: &Arc<Material>) {
+        // vertex colors come back from the mesh when it's read again
+        let mut plain = (**material).clone();
+        let name = if plain.diffuse.uses_vertex_colors() {
+            plain.diffuse = MaterialParameter::new(plain.diffuse.base_value());
+            self.material_name(&plain)
+        } else {
+            let context = self.context;
+            self.material_names.iter().find(|&&name| Arc::ptr_eq(&context.materials[name], material)).cloned()
+        };
+
+        match name {
+            Some(name) => self.line(&format!("material = {};", string(name))),
+            None => {
+                self.begin_line("material = ");
+                self.write_material_block(&plain, None);
+                self.output.push_str(";\n");
+            },
+        }
+    }
+
+    /// The first named material that's the same as `material`
+    fn material_name(&self, material: &Material) -> Option<&'a str> {
+        let context = self.context;
+        self.material_names.iter()
+            .find(|&&name| *context.materials[name] == *material)
+            .cloned()
+    }
+
+    /// Writes every field of the material, so it doesn't inherit any from
+    /// the material that's current where it's read
+    fn write_material_block(&mut self, material: &Material, name: Option<&str>) {
+        self.output.push_str("{\n");
+        self.indent += 1;
+        if let Some(name) = name {
+            self.line(&format!("name = {};", string(name)));
+        }
+        for &(keyword, parameter) in &[
+            ("emissive", &material.emissive),
+            ("ambient", &material.ambient),
+            ("specular", &material.specular),
+            ("reflective", &material.reflective),
+            ("diffuse", &material.diffuse),
+            ("transmissive", &material.transmissive),
+        ] {
+            let value = match parameter.texture_map() {
+                Some(texture_map) => format!("map({})", string(texture_map.filename())),
+                None => vector3(parameter.base_value()),
+            };
+            self.line(&format!("{} = {};", keyword, value));
+        }
+        self.line(&format!("shininess = {};", material.shininess));
+        self.line(&format!("index = {};", material.index));
+        self.indent -= 1;
+        self.begin_line("}");
+    }
+
+    fn line(&mut self, text: &str) {
+        if text.is_empty() {
+            self.output.push('\n');
+            return;
+        }
+        self.begin_line(text);
+        self.output.push('\n');
+    }
+
+    fn begin_line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.output.push_str("    ");
+        }
+        self.output.push_str(text);
+    }
+}
+
+/// A keyed property, as it's written in its key block
+struct KeyLine {
+    frame: f64,
+    interpolation: Interpolation,
+    text: String,
+}
+
+fn track_keys<T: Keyable, F>(keys: &mut Vec<KeyLine>, keyword: &str, track: &Option<Track<T>>, format: F)
+    where F: Fn(T) -> String
+{
+    for key in track.iter().flat_map(|track| track.keys()) {
+        keys.push(KeyLine { frame: key.frame, interpolation: key.interpolation, text: format!("{} = {};", keyword, format(key.value)) });
+    }
+}
+
+/// The definitions an element places instances of, directly or within its children
+fn used_definitions(element: &TransformableElementBuilder, used: &mut Vec<(String, Arc<Definition>)>) {
+    match element.element {
+        Some(TransformableElementType::Geometry(ref geometry)) => {
+            if let Some(GeometryBuilderType::TransformableElement(_, _, ref subelement)) = geometry.element {
+                used_definitions(subelement, used);
+            }
+        },
+        Some(TransformableElementType::Group(ref group)) => {
+            for element in &group.elements {
+                used_definitions(element, used);
+            }
+        },
+        Some(TransformableElementType::Instance(ref instance)) => used.push((instance.name.clone(), instance.definition.clone())),
+        None => {},
+    }
+}
+
+fn mesh_filename(geometry: &GeometryType) -> Option<&str> {
+    match *geometry {
+        GeometryType::ObjMesh { ref filename, .. } |
+        GeometryType::MeshFile { ref filename, .. } => Some(filename),
+        _ => None,
+    }
+}
+
+fn vector3(v: Vector3<f64>) -> String {
+    format!("({}, {}, {})", v.x, v.y, v.z)
+}
+
+fn vector4(v: Vector4<f64>) -> String {
+    format!("({}, {}, {}, {})", v.x, v.y, v.z, v.w)
+}
+
+/// A string literal the tokenizer reads back as `text`
+fn string(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
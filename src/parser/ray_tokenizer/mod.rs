@@ -4,18 +4,19 @@ mod token;
 
 pub use self::token::Token;
 
+use std::borrow::Cow;
 use std::iter::{Iterator, Peekable};
 use std::slice::Iter;
 
 use regex::Regex;
 
-use super::error::TokenizationError;
+use super::error::ParseError;
 
 pub trait Readable<'a> {
     /// Given an input token, returns that token if the next token matches it.
     /// If it doesn't match, returns an error.
     /// Use when there is only one possible token that should be read next.
-    fn read(&mut self, pattern: Token<'a>) -> Result<Token<'a>, TokenizationError>;
+    fn read(&mut self, pattern: Token<'a>) -> Result<Token<'a>, ParseError>;
     fn conditional_read(&mut self, pattern: Token<'a>) -> bool;
 }
 
@@ -32,192 +33,194 @@ impl<'a> RayTokenizer<'a>  {
         RayTokenizer { input, position: 0}
     }
 
-    fn next_token(&mut self) -> Result<Token<'a>, TokenizationError> {
-        // Eliminate whitespace
+    fn next_token(&mut self) -> Result<Token<'a>, ParseError> {
+        // Eliminate whitespace and comments
         self.lex_whitespace();
         if self.position >= self.input.len() {
-            return Err(TokenizationError::new("Out"));
+            return Ok(Token::Eofsym);
         }
-        match self.input.chars().nth(self.position).unwrap() {
-            '\'' => self.lex_bareword(),
-            ';' => {
-                self.position += 1;
-                Ok(Token::Semicolon)
-            },
-            ',' => {
-                self.position += 1;
-                Ok(Token::Comma)
-            },
-            '{' => {
-                self.position += 1;
-                Ok(Token::LBrace)
-            },
-            '}' => {
-                self.position += 1;
-                Ok(Token::RBrace)
-            },
-            '(' => {
-                self.position += 1;
-                Ok(Token::LParen)
-            },
-            ')' => {
-                self.position += 1;
-                Ok(Token::RParen)
-            },
-            '=' => {
-                self.position += 1;
-                Ok(Token::Equals)
-            },
-            '-' => {
-                self.position += 1;
-                self.lex_numlit()
-            },
-            '.' => {
-                Err(TokenizationError::new("blah"))
-            },
+        match self.input[self.position..].chars().next().unwrap() {
+            '\'' => self.lex_strlit(),
+            ';' => self.lex_punctuation(Token::Semicolon),
+            ',' => self.lex_punctuation(Token::Comma),
+            '{' => self.lex_punctuation(Token::LBrace),
+            '}' => self.lex_punctuation(Token::RBrace),
+            '(' => self.lex_punctuation(Token::LParen),
+            ')' => self.lex_punctuation(Token::RParen),
+            '[' => self.lex_punctuation(Token::LBracket),
+            ']' => self.lex_punctuation(Token::RBracket),
+            '=' => self.lex_punctuation(Token::Equals),
+            '+' => self.lex_punctuation(Token::Plus),
+            '-' => self.lex_punctuation(Token::Minus),
+            '*' => self.lex_punctuation(Token::Star),
+            '/' => self.lex_punctuation(Token::Slash),
+            '.' => self.lex_numlit(),
             x if x.is_alphabetic() => self.lex_bareword(),
             x if x.is_numeric() => self.lex_numlit(),
-            x => Err(TokenizationError::new(format!("Couldn't parse at {}:{}", self.position, x)))
+            x => Err(ParseError::new(format!("Couldn't parse at {}:{}", self.position, x)))
         }
     }
 
+    fn lex_punctuation(&mut self, token: Token<'a>) -> Result<Token<'a>, ParseError> {
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Skips whitespace along with `//` line comments and `/* */` block comments
     fn lex_whitespace(&mut self) {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^\s*").unwrap();
+            static ref RE: Regex = Regex::new(r"^(\s+|//[^\n]*|/\*(?s:.*?)\*/)*").unwrap();
         }
 
-       if let Some(result) = RE.find(&self.input[self.position..]) {
+        if let Some(result) = RE.find(&self.input[self.position..]) {
             self.position += result.end();
         }
     }
 
-    fn lex_strlit(&mut self) -> Result<Token<'a>, TokenizationError> {
+    fn lex_strlit(&mut self) -> Result<Token<'a>, ParseError> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^'([^']|\\')*'").unwrap();
+            static ref RE: Regex = Regex::new(r"^'(\\['\\]|[^'])*'").unwrap();
         }
         if let Some(result) = RE.find(&self.input[self.position..]) {
             let start = self.position + result.start();
             let end = self.position + result.end();
-            let token = Token::StrLit(&self.input[start..end]);
+            // strip the surrounding quotes
+            let token = Token::StrLit(&self.input[start + 1..end - 1]);
             self.position = end;
             return Ok(token)
         }
-        Err(TokenizationError::new("Did not find valid string literal"))
+        Err(ParseError::new("Did not find valid string literal"))
     }
-    fn lex_numlit(&mut self) -> Result<Token<'a>, TokenizationError> {
-       lazy_static! {
-            static ref RE: Regex = Regex::new(r"[[:digit:]]").unwrap();
+
+    /// Lexes an unsigned number literal, the sign of a number is handled
+    /// as a unary minus when parsing expressions
+    fn lex_numlit(&mut self) -> Result<Token<'a>, ParseError> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^([[:digit:]]+\.?[[:digit:]]*|\.[[:digit:]]+)([eE][-+]?[[:digit:]]+)?").unwrap();
         }
         if let Some(result) = RE.find(&self.input[self.position..]) {
             let start = self.position + result.start();
             let end = self.position + result.end();
-            let value = self.input[start..end].parse().unwrap();
+            let value = self.input[start..end].parse()
+                .map_err(|_| ParseError::new(format!("Invalid number at {}", start)))?;
             let token = Token::Scalar(value);
             self.position = end;
             return Ok(token)
         }
-        Err(TokenizationError::new("Did not find valid number"))
+        Err(ParseError::new("Did not find valid number"))
     }
-    fn lex_bareword(&mut self) -> Result<Token<'a>, TokenizationError> {
+    fn lex_bareword(&mut self) -> Result<Token<'a>, ParseError> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"[[:alpha:]]+[[[:alpha:]]-_]*").unwrap();
+            static ref RE: Regex = Regex::new(r"^(SBT-raytracer|[[:alpha:]][[:alnum:]_]*)").unwrap();
         }
         if let Some(result) = RE.find(&self.input[self.position..]) {
             let start = self.position + result.start();
             let end = self.position + result.end();
             let value = &self.input[start..end];
-            //println!("{}:{}", start, value);
             let token = match value {
                 "camera" => Token::Camera,
                 "point_light" => Token::PointLight,
                 "directional_light" => Token::DirectionalLight,
                 "ambient_light" => Token::AmbientLight,
+                "render_settings" => Token::RenderSettings,
                 "sphere" => Token::Sphere,
                 "box" => Token::Box,
+                "square" => Token::Square,
                 "cylinder" => Token::Cylinder,
                 "cone" => Token::Cone,
                 "trimesh" => Token::Trimesh,
+                "mesh" => Token::Mesh,
+                "translate" => Token::Translate,
+                "rotate" => Token::Rotate,
+                "scale" => Token::Scale,
+                "transform" => Token::Transform,
+                "material" => Token::Material,
+                "name" => Token::Name,
+                "include" => Token::Include,
+                "let" => Token::Let,
+                "define" => Token::Define,
+                "animation" => Token::Animation,
+                "fog" => Token::Fog,
+                "medium" => Token::Medium,
                 "SBT-raytracer" => Token::SbtRaytracer,
+                "true" => Token::Symtrue,
+                "false" => Token::Symfalse,
                 _ => Token::Ident(value)
             };
             self.position = end;
             return Ok(token)
         }
-        Err(TokenizationError::new("Couldn't pares bareword"))
+        Err(ParseError::new("Couldn't parse bareword"))
     }
 }
 
 impl<'a> Iterator for RayTokenizer<'a> {
-    type Item = Result<Token<'a>, TokenizationError>;
+    type Item = Result<Token<'a>, ParseError>;
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.next_token();
-        // TODO: Correctly distinguish between the out of tokens state and an error
-        if next.is_ok(){
-            return Some(next)
+        match self.next_token() {
+            Ok(Token::Eofsym) => None,
+            Ok(token) => Some(Ok(token)),
+            Err(err) => {
+                // stop tokenizing after the first error
+                self.position = self.input.len();
+                Some(Err(err))
+            }
         }
-        return None
     }
 }
 
 impl<'a> Readable<'a> for Peekable<Iter<'a, Token<'a>>> {
     /// Given an input token, returns that token if the iterator's next()
     /// call also return that token, else it returns an error
-    fn read(&mut self, pattern: Token<'a>) -> Result<Token<'a>, TokenizationError> {
+    fn read(&mut self, pattern: Token<'a>) -> Result<Token<'a>, ParseError> {
         if let Some(next_token) = self.next() {
-            let copied_token = (*next_token).clone();
-            if token_discriminant_equal(pattern, copied_token) {
-                return Ok((*next_token).clone());
+            if token_discriminant_equal(&pattern, next_token) {
+                Ok(next_token.clone())
             } else {
-                return Err(TokenizationError::new("unexpected token"));
+                Err(ParseError::new(format!("unexpected token {:?}, expected {:?}", next_token, pattern)))
             }
         } else {
-            return Err(TokenizationError::new("out of tokens"));
+            Err(ParseError::new("out of tokens"))
         }
     }
 
     fn conditional_read(&mut self, pattern: Token<'a>) -> bool {
-        if let Some(token) = self.peek().map(|t| *t) {
-            if token_discriminant_equal(pattern, (*token).clone()) {
+        if let Some(token) = self.peek().copied() {
+            if token_discriminant_equal(&pattern, token) {
                 self.next();
                 return true;
-            } else {
-                return false;
             }
-        } else {
-            return false;
         }
+        false
     }
 }
 
-fn token_discriminant_equal(lhs: Token, rhs: Token) -> bool {
-    match lhs {
-        Token::Ident(_) => {
-            if let Token::Ident(_) = rhs {
-                return true;
-            } else {
-                return false;
-            }
-        },
-        Token::StrLit(_) => {
-            if let Token::StrLit(_) = rhs {
-                return true;
-            } else {
-                return false;
-            }
-        },
-        Token::Scalar(_) => {
-            if let Token::Scalar(_) = rhs {
-                return true;
-            } else {
-                return false;
-            }
-        },
-        lhs => {
-            if lhs == rhs {
-                return true;
-            } else {
-                return false;
-            }
+/// The text of a string literal, where `\'` stands for a quote and `\\` for a
+/// backslash. Any other backslash is kept as it is.
+pub fn unescape(literal: &str) -> Cow<'_, str> {
+    if !literal.contains('\\') {
+        return Cow::Borrowed(literal);
+    }
+
+    let mut text = String::with_capacity(literal.len());
+    let mut chars = literal.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&escaped)) if escaped == '\'' || escaped == '\\' => {
+                text.push(escaped);
+                chars.next();
+            },
+            (c, _) => text.push(c),
         }
     }
-}
\ No newline at end of file
+    Cow::Owned(text)
+}
+
+fn token_discriminant_equal(lhs: &Token, rhs: &Token) -> bool {
+    match *lhs {
+        Token::Ident(_) => matches!(*rhs, Token::Ident(_)),
+        Token::StrLit(_) => matches!(*rhs, Token::StrLit(_)),
+        Token::Scalar(_) => matches!(*rhs, Token::Scalar(_)),
+        ref lhs => lhs == rhs,
+    }
+}
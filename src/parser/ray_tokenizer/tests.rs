@@ -1,11 +1,15 @@
 #[cfg(test)]
 use super::token::Token::*;
-static KEYWORD_SAMPLE: &'static str = "camera point_light";
-static WHITESPACE_SAMPLE: &'static str = " \t\t\n\r";
-static NUMBERS_SAMPLE: &'static str = "-10 500.00001 -0.0 9";
-static PUNCTUATION_SAMPLE: &'static str = ",;(){}";
-
-static CAMERA_SAMPLE: &'static str = "camera {
+static KEYWORD_SAMPLE: &str = "camera point_light";
+static WHITESPACE_SAMPLE: &str = " \t\t\n\r";
+static NUMBERS_SAMPLE: &str = "-10 500.00001 -0.0 9";
+static PUNCTUATION_SAMPLE: &str = ",;(){}";
+static ARITHMETIC_SAMPLE: &str = "pi/4 + .5*(2-1e2)";
+static COMMENT_SAMPLE: &str = "true // false
+/* camera
+   point_light */ false";
+
+static CAMERA_SAMPLE: &str = "camera {
 	position = (4,0,0);
 	viewdir = (-1,0,0);
 	aspectratio = 1;
@@ -13,9 +17,9 @@ static CAMERA_SAMPLE: &'static str = "camera {
 }";
 
 
-static RAY_HEADER: &'static str = "SBT-raytracer 1.0";
+static RAY_HEADER: &str = "SBT-raytracer 1.0";
 
-static POINT_LIGHT_SAMPLE: &'static str = "point_light {
+static POINT_LIGHT_SAMPLE: &str = "point_light {
     position = (4, 4, 0);
     color = (.5, .5, .5);
     constant_attenuation_coeff= 0.25;
@@ -23,12 +27,12 @@ static POINT_LIGHT_SAMPLE: &'static str = "point_light {
     quadratic_attenuation_coeff = 0.000045492;
 }";
 
-static DIRECTIONAL_LIGHT_SAMPLE: &'static str = " directional_light {
+static DIRECTIONAL_LIGHT_SAMPLE: &str = " directional_light {
     direction = (0, -1, 0);
     colour = (1.0, 1.0, 1.0);
 }";
 
-static BOX_SAMPLE: &'static str = "
+static BOX_SAMPLE: &str = "
 box {
     material = {
         //diffuse = (0.7, 0, 1.0);
@@ -58,7 +62,7 @@ fn number_tokenize_test() {
     use super::*;
     let tokenizer = RayTokenizer::new(NUMBERS_SAMPLE);
     let tokens: Result<Vec<token::Token>, _> = tokenizer.collect();
-    let expected = [Scalar(-10f64), Scalar(500.00001f64), Scalar(0.0f64), Scalar(9f64)];
+    let expected = [Minus, Scalar(10f64), Scalar(500.00001f64), Minus, Scalar(0.0f64), Scalar(9f64)];
     assert!(tokens.unwrap().iter().eq(expected.iter()));
 }
 #[test]
@@ -72,6 +76,46 @@ fn punctuation_tokenize_test() {
     assert!(tokens.unwrap().iter().eq(expected.iter()));
 }
 
+#[test]
+fn arithmetic_tokenize_test() {
+    use super::*;
+    let tokenizer = RayTokenizer::new(ARITHMETIC_SAMPLE);
+    let tokens: Result<Vec<token::Token>, _> = tokenizer.collect();
+    let expected = [Ident("pi"), Slash, Scalar(4f64), Plus, Scalar(0.5f64), Star,
+        LParen, Scalar(2f64), Minus, Scalar(100f64), RParen];
+    assert!(tokens.unwrap().iter().eq(expected.iter()));
+}
+
+#[test]
+fn comment_tokenize_test() {
+    use super::*;
+    let tokenizer = RayTokenizer::new(COMMENT_SAMPLE);
+    let tokens: Result<Vec<token::Token>, _> = tokenizer.collect();
+    let expected = [Symtrue, Symfalse];
+    assert!(tokens.unwrap().iter().eq(expected.iter()));
+}
+
+#[test]
+fn string_tokenize_test() {
+    use super::*;
+    let tokenizer = RayTokenizer::new(r"'it\'s' 'a\\' 'C:\dir' 'b'");
+    let tokens: Result<Vec<token::Token>, _> = tokenizer.collect();
+    let expected = [StrLit(r"it\'s"), StrLit(r"a\\"), StrLit(r"C:\dir"), StrLit("b")];
+    assert!(tokens.unwrap().iter().eq(expected.iter()));
+
+    assert_eq!(unescape(r"it\'s"), "it's");
+    assert_eq!(unescape(r"a\\"), r"a\");
+    assert_eq!(unescape(r"C:\dir"), r"C:\dir");
+}
+
+#[test]
+fn invalid_character_tokenize_test() {
+    use super::*;
+    let tokenizer = RayTokenizer::new("camera # point_light");
+    let tokens: Result<Vec<token::Token>, _> = tokenizer.collect();
+    assert!(tokens.is_err());
+}
+
 #[test]
 fn camera_parse_test() {
     use super::*;
@@ -91,7 +135,7 @@ fn camera_parse_test() {
 #[test]
 fn peekable_read_test() {
     use super::*;
-    let tokens = vec![token::Token::Camera, token::Token::LBrace, token::Token::RBrace];
+    let tokens = [token::Token::Camera, token::Token::LBrace, token::Token::RBrace];
     let mut peekable = tokens.iter().peekable();
 
     // attempt to read expected tokens
@@ -106,7 +150,7 @@ fn peekable_read_test() {
 #[test]
 fn peekable_read_value_test() {
     use super::*;
-    let tokens = vec![
+    let tokens = [
         token::Token::Ident("foo"),
         token::Token::StrLit("bar"),
         token::Token::Scalar(845f64)
@@ -125,7 +169,7 @@ fn peekable_read_value_test() {
 #[should_panic]
 fn peekable_failed_read_test() {
     use super::*;
-    let tokens = vec![token::Token::Camera];
+    let tokens = [token::Token::Camera];
     let mut peekable = tokens.iter().peekable();
 
     // do one fault read, this should panic on unwrap
@@ -135,7 +179,7 @@ fn peekable_failed_read_test() {
 #[test]
 fn peekable_conditional_read_test() {
     use super::*;
-    let tokens = vec![
+    let tokens = [
         token::Token::Camera,
         token::Token::LBrace,
         token::Token::RBrace,
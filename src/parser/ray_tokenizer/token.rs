@@ -13,7 +13,8 @@ pub enum Token<'a> {
     LBrace, RBrace,
     LBracket, RBracket,
     Comma,
-    Minus,
+    Plus, Minus,                // Arithmetic
+    Star, Slash,
     Equals,
     Semicolon,
     StrLit(& 'a str),
@@ -24,6 +25,8 @@ pub enum Token<'a> {
     DirectionalLight,
     AmbientLight,
 
+    RenderSettings,             // Settings For Rendering The Scene
+
     ConstantAttenuationCoeff, // Terms Affecting The Intensity Dropoff
     LinearAttenuationCoeff,   // Of Point Lights (see The Pointlight 
     QuadraticAttenuationCoeff,// Class)
@@ -34,6 +37,7 @@ pub enum Token<'a> {
     Cylinder,
     Cone,
     Trimesh,
+    Mesh,
 
     Position, Viewdir,          // Keywords Affecting Primitives
     Updir, Aspectratio,
@@ -60,4 +64,13 @@ pub enum Token<'a> {
     Shininess, Index,
     Name,
     Map,
+
+    Include,                    // Scene Composition
+    Let,
+    Define,
+
+    Animation,                  // Keyframes Changing The Scene Over Time
+
+    Fog,                        // Participating Media
+    Medium,
 }
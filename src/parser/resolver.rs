@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::error::ParseError;
+
+/// Finds and reads the resources a scene refers to, like includes, texture
+/// maps and mesh files
+pub trait ResourceResolver {
+    /// The name of the resource `name` refers to from within the source named
+    /// `source`. Resolved names are unique for every resource, so they can be
+    /// compared to detect include cycles.
+    fn resolve(&self, name: &str, source: &str) -> Result<String, ParseError>;
+
+    /// The contents of a resolved resource
+    fn read(&self, resolved: &str) -> Result<Vec<u8>, ParseError>;
+}
+
+/// Resolves names as paths relative to the file they're named in
+pub struct FileResolver;
+
+impl ResourceResolver for FileResolver {
+    fn resolve(&self, name: &str, source: &str) -> Result<String, ParseError> {
+        let path = relative_path(name, source);
+        let path = path.canonicalize()
+            .map_err(|err| ParseError::new(format!("couldn't find '{}': {}", path.display(), err)))?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    fn read(&self, resolved: &str) -> Result<Vec<u8>, ParseError> {
+        fs::read(resolved)
+            .map_err(|err| ParseError::new(format!("couldn't read '{}': {}", resolved, err)))
+    }
+}
+
+/// Resolves names from a fixed set of in-memory resources, keyed by their
+/// path. Useful for scenes that don't come from the file system.
+#[derive(Default)]
+pub struct MemoryResolver {
+    resources: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> MemoryResolver {
+        MemoryResolver { resources: HashMap::new() }
+    }
+
+    pub fn insert<S: Into<String>, D: Into<Vec<u8>>>(&mut self, name: S, data: D) {
+        let name = normalize(Path::new(&name.into()));
+        self.resources.insert(name, data.into());
+    }
+}
+
+impl ResourceResolver for MemoryResolver {
+    fn resolve(&self, name: &str, source: &str) -> Result<String, ParseError> {
+        let resolved = relative_name(name, source);
+        if !self.resources.contains_key(&resolved) {
+            return Err(ParseError::new(format!("couldn't find '{}'", resolved)));
+        }
+        Ok(resolved)
+    }
+
+    fn read(&self, resolved: &str) -> Result<Vec<u8>, ParseError> {
+        self.resources.get(resolved)
+            .cloned()
+            .ok_or_else(|| ParseError::new(format!("couldn't read '{}'", resolved)))
+    }
+}
+
+/// `name` as seen from the directory `source` is in, without `.` and `..`
+/// components
+pub fn relative_name(name: &str, source: &str) -> String {
+    normalize(&relative_path(name, source))
+}
+
+/// `name` as seen from the directory `source` is in
+fn relative_path(name: &str, source: &str) -> PathBuf {
+    Path::new(source).parent().unwrap_or_else(|| Path::new("")).join(name)
+}
+
+/// Removes `.` and `..` components without touching the file system
+fn normalize(path: &Path) -> String {
+    let absolute = path.has_root();
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir | Component::RootDir => {},
+            Component::ParentDir if components.last().is_some_and(|last| *last != "..") => {
+                components.pop();
+            },
+            // there's nothing above the root
+            Component::ParentDir if absolute && components.is_empty() => {},
+            component => components.push(component.as_os_str().to_str().unwrap_or("")),
+        }
+    }
+
+    if absolute {
+        format!("/{}", components.join("/"))
+    } else {
+        components.join("/")
+    }
+}
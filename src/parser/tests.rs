@@ -0,0 +1,113 @@
+use super::*;
+
+static RAY_SCENE: &str = "// a comment before the header
+SBT-raytracer 1.0
+sphere {}
+";
+
+static OBJ_SCENE: &str = "# a triangle
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+";
+
+static GLTF_SCENE: &str = r#"{ "asset": { "version": "2.0" } }"#;
+
+fn parser_name(registry: &ParserRegistry, source: &str, input: &str) -> Option<String> {
+    registry.parser_for(source, input.as_bytes()).map(|parser| parser.extensions()[0].to_string())
+}
+
+#[test]
+fn detect_test() {
+    assert!(RayParser.detect(RAY_SCENE.as_bytes()));
+    assert!(!RayParser.detect(OBJ_SCENE.as_bytes()));
+    // the first 4096 bytes end inside the 'é'
+    let long_comment = format!("SBT-raytracer 1.0\n// {}é\n", "-".repeat(4095 - 21));
+    assert_eq!(long_comment.find('é'), Some(4095));
+    assert!(RayParser.detect(long_comment.as_bytes()));
+    assert!(ObjParser.detect(OBJ_SCENE.as_bytes()));
+    assert!(!ObjParser.detect(RAY_SCENE.as_bytes()));
+    assert!(!ObjParser.detect(GLTF_SCENE.as_bytes()));
+    assert!(GltfParser.detect(GLTF_SCENE.as_bytes()));
+    assert!(GltfParser.detect(b"glTF\x02\x00\x00\x00"));
+    assert!(!GltfParser.detect(RAY_SCENE.as_bytes()));
+}
+
+#[test]
+fn registry_test() {
+    let registry = ParserRegistry::default();
+
+    // extensions win over the contents, ignoring case
+    assert_eq!(parser_name(&registry, "scene.RAY", OBJ_SCENE), Some("ray".to_string()));
+    assert_eq!(parser_name(&registry, "scene.glb", ""), Some("gltf".to_string()));
+    assert_eq!(parser_name(&registry, "dir.obj/scene.ray", ""), Some("ray".to_string()));
+
+    // and without one the format is detected
+    assert_eq!(parser_name(&registry, "scene", RAY_SCENE), Some("ray".to_string()));
+    assert_eq!(parser_name(&registry, "scene.txt", OBJ_SCENE), Some("obj".to_string()));
+    assert_eq!(parser_name(&registry, "", GLTF_SCENE), Some("gltf".to_string()));
+    assert_eq!(parser_name(&registry, "scene", "hello"), None);
+
+    let resolver = MemoryResolver::new();
+    assert_eq!(registry.parse(RAY_SCENE.as_bytes(), "scene", &resolver).unwrap().object_count(), 1);
+    assert_eq!(registry.parse(OBJ_SCENE.as_bytes(), "scene", &resolver).unwrap().object_count(), 1);
+    assert_eq!(registry.parse(GLTF_SCENE.as_bytes(), "scene", &resolver).unwrap().object_count(), 0);
+
+    let error = registry.parse(b"hello", "scene.txt", &resolver).err().unwrap();
+    assert_eq!(error.to_string(), "scene.txt: unrecognized scene format");
+    assert!(ParserRegistry::empty().parse(RAY_SCENE.as_bytes(), "scene.ray", &resolver).is_err());
+
+    // errors name the source they happened in
+    let error = registry.parse(b"SBT-raytracer 1.0 sphere {", "broken.ray", &resolver).err().unwrap();
+    assert!(error.to_string().starts_with("broken.ray: "));
+}
+
+/// Parses every scene as a single sphere, whatever its format
+struct SphereParser;
+
+impl Parser for SphereParser {
+    fn parse(&self, _input: &[u8], source: &str, resolver: &dyn ResourceResolver) -> Result<Scene, ParseError> {
+        RayParser.parse(RAY_SCENE.as_bytes(), source, resolver)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["obj", "sphere"]
+    }
+
+    fn detect(&self, _input: &[u8]) -> bool {
+        false
+    }
+}
+
+#[test]
+fn register_test() {
+    let mut registry = ParserRegistry::new();
+    registry.register(Box::new(SphereParser));
+
+    // later parsers take priority
+    assert_eq!(parser_name(&registry, "scene.obj", OBJ_SCENE), Some("obj".to_string()));
+    let scene = registry.parse(b"", "scene.sphere", &MemoryResolver::new()).unwrap();
+    assert_eq!(scene.object_count(), 1);
+    let scene = registry.parse(b"", "scene.obj", &MemoryResolver::new()).unwrap();
+    assert_eq!(scene.object_count(), 1);
+}
+
+#[test]
+fn memory_resolver_test() {
+    let mut resolver = MemoryResolver::new();
+    resolver.insert("./scenes/textures/../wood.png", vec![1, 2, 3]);
+
+    assert_eq!(resolver.resolve("wood.png", "scenes/main.ray").unwrap(), "scenes/wood.png");
+    assert_eq!(resolver.resolve("../scenes/wood.png", "scenes/main.ray").unwrap(), "scenes/wood.png");
+    assert_eq!(resolver.resolve("scenes/wood.png", "").unwrap(), "scenes/wood.png");
+    assert!(resolver.resolve("wood.png", "main.ray").is_err());
+    assert_eq!(resolver.read("scenes/wood.png").unwrap(), vec![1, 2, 3]);
+    assert!(resolver.read("wood.png").is_err());
+
+    // absolute paths keep a single leading separator
+    resolver.insert("/a", vec![4]);
+    assert_eq!(resolver.resolve("/a", "scenes/main.ray").unwrap(), "/a");
+    assert_eq!(resolver.resolve("../../a", "/b/main.ray").unwrap(), "/a");
+    assert_eq!(resolver.read("/a").unwrap(), vec![4]);
+}
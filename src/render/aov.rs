@@ -0,0 +1,181 @@
+use cgmath::Vector3;
+
+use std::f32;
+
+use super::*;
+
+/// An extra pass rendered along with the image, recording what the camera
+/// ray through the middle of each pixel hits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance to the hit, infinite where nothing was hit
+    Depth,
+    /// World space normal at the hit
+    Normal,
+    /// Texture coordinates at the hit
+    Uv,
+    /// Barycentric coordinates of the hit on a mesh triangle
+    Barycentrics,
+    /// Diffuse color at the hit, without lighting
+    Albedo,
+    /// One more than the index of the top level object hit, 0 where nothing was hit
+    ObjectId,
+    /// Number of the material hit, counting from 1 through the distinct
+    /// materials of the top level objects. 0 where nothing was hit, or where
+    /// the material isn't one of those, such as those blended across a mesh.
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [Aov::Depth, Aov::Normal, Aov::Uv, Aov::Barycentrics, Aov::Albedo, Aov::ObjectId, Aov::MaterialId];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Uv => "uv",
+            Aov::Barycentrics => "barycentrics",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().find(|aov| aov.name() == name).cloned()
+    }
+
+    /// Names of the values the pass has at every pixel
+    pub fn channels(&self) -> &'static [&'static str] {
+        match *self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Barycentrics => &["X", "Y", "Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+
+    /// The pass's values where nothing was hit
+    fn missed(&self) -> [f32; 3] {
+        match *self {
+            Aov::Depth => [f32::INFINITY; 3],
+            _ => [0.0; 3],
+        }
+    }
+}
+
+/// A rendered pass, with each channel's values row by row from the top left
+#[derive(Clone, Debug, PartialEq)]
+pub struct AovBuffer {
+    pub aov: Aov,
+    pub width: u32,
+    pub height: u32,
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl AovBuffer {
+    fn new(aov: Aov, width: u32, height: u32) -> AovBuffer {
+        let missed = aov.missed();
+        let channels = (0..aov.channels().len())
+            .map(|channel| vec![missed[channel]; width as usize * height as usize])
+            .collect();
+        AovBuffer { aov, width, height, channels }
+    }
+
+    /// The values of the pass at pixel `(x, y)`, in the order of its channels
+    pub fn get(&self, x: u32, y: u32) -> Vec<f32> {
+        let index = (y * self.width + x) as usize;
+        self.channels.iter().map(|channel| channel[index]).collect()
+    }
+
+    /// The pass cut down to the given columns and rows
+    pub fn crop(&self, columns: Range<u32>, rows: Range<u32>) -> AovBuffer {
+        let channels = self.channels.iter()
+            .map(|channel| rows.clone()
+                .flat_map(|y| columns.clone().map(move |x| (y * self.width + x) as usize))
+                .map(|index| channel[index])
+                .collect())
+            .collect();
+        AovBuffer { aov: self.aov, width: columns.len() as u32, height: rows.len() as u32, channels }
+    }
+
+    fn set(&mut self, x: u32, y: u32, values: [f32; 3]) {
+        let index = (y * self.width + x) as usize;
+        for (channel, &value) in self.channels.iter_mut().zip(&values) {
+            channel[index] = value;
+        }
+    }
+}
+
+/// Numbers for the materials of the scene's top level objects
+struct MaterialIds<'a> {
+    materials: Vec<&'a Arc<Material>>,
+}
+
+impl<'a> MaterialIds<'a> {
+    fn new(scene: &'a Scene) -> MaterialIds<'a> {
+        let mut materials: Vec<&Arc<Material>> = Vec::new();
+        for material in scene.objects().iter().filter_map(|object| object.material()) {
+            if !materials.contains(&material) {
+                materials.push(material);
+            }
+        }
+        MaterialIds { materials }
+    }
+
+    fn id(&self, material: &Arc<Material>) -> usize {
+        self.materials.iter()
+            .position(|&known| Arc::ptr_eq(known, material))
+            .or_else(|| self.materials.iter().position(|&known| known == material))
+            .map_or(0, |index| index + 1)
+    }
+}
+
+impl<'a> Renderer<'a> {
+    /// Renders the passes, leaving pixels outside the crop window as if
+    /// nothing was hit
+    pub fn render_aovs(&self, aovs: &[Aov]) -> Vec<AovBuffer> {
+        if aovs.is_empty() {
+            return Vec::new();
+        }
+
+        let settings = &self.settings;
+        let camera = self.camera();
+        let material_ids = MaterialIds::new(self.scene);
+
+        let (columns, rows) = settings.pixels();
+        let rendered = self.render_rows(columns.clone(), rows, |x, y| {
+            let mut isect = Intersect::new();
+            let hit = camera_ray(&camera, settings, x, y, 0.5, 0.5)
+                .and_then(|ray| self.scene.intersect_object(&ray, &mut isect));
+            aovs.iter().map(|&aov| match hit {
+                Some(object) => aov_values(aov, &isect, object, &material_ids),
+                None => aov.missed(),
+            }).collect::<Vec<_>>()
+        });
+
+        let mut buffers: Vec<AovBuffer> = aovs.iter().map(|&aov| AovBuffer::new(aov, settings.width, settings.height)).collect();
+        for (y, row) in rendered {
+            for (x, values) in columns.clone().zip(row) {
+                for (buffer, &values) in buffers.iter_mut().zip(&values) {
+                    buffer.set(x, y, values);
+                }
+            }
+        }
+        buffers
+    }
+}
+
+fn aov_values(aov: Aov, isect: &Intersect, object: usize, material_ids: &MaterialIds) -> [f32; 3] {
+    let vector = |v: Vector3<f64>| [v.x as f32, v.y as f32, v.z as f32];
+    match aov {
+        Aov::Depth => [isect.t as f32; 3],
+        Aov::Normal => vector(isect.n),
+        Aov::Uv => [isect.uv_coords.x as f32, isect.uv_coords.y as f32, 0.0],
+        Aov::Barycentrics => vector(isect.bary_coords),
+        Aov::Albedo => vector(surface_material(isect).diffuse.value(isect)),
+        Aov::ObjectId => [(object + 1) as f32; 3],
+        Aov::MaterialId => [isect.material.as_ref().map_or(0, |material| material_ids.id(material)) as f32; 3],
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{Film, Renderer};
+
+/// Samples a pixel needs before its noise can be told apart from luck
+const MIN_NOISE_SAMPLES: u32 = 8;
+
+/// What a render can spend, stopping it before it has all its samples
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderBudget {
+    /// Time the passes can take. A pass that would run past it isn't started,
+    /// going by how long the last one took.
+    pub time: Option<Duration>,
+    /// Noise the film is rendered down to, as in `Film::noise`
+    pub noise: Option<f64>,
+}
+
+impl RenderBudget {
+    /// Samples per pixel a render with a budget stops at when nothing else
+    /// says how many to take
+    pub const MAX_SAMPLES: u32 = 4096;
+
+    /// Whether the render only stops once it has all its samples
+    pub fn is_unlimited(&self) -> bool {
+        self.time.is_none() && self.noise.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// Every pixel had the samples the settings ask for
+    Samples,
+    /// Another pass would have taken the render past its time
+    Time,
+    /// The film was as smooth as the budget asks for
+    Noise,
+    /// The render was told to stop after a pass
+    Stopped,
+}
+
+impl StopReason {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StopReason::Samples => "samples",
+            StopReason::Time => "time",
+            StopReason::Noise => "noise",
+            StopReason::Stopped => "stopped",
+        }
+    }
+}
+
+/// How a render went
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderReport {
+    /// Samples every rendered pixel has, including those of a resumed film
+    pub samples: u32,
+    /// Pixels rendered, those in the crop window
+    pub pixels: u64,
+    /// Passes taken by this render
+    pub passes: u32,
+    pub time: Duration,
+    /// The film's noise at the end, infinite with fewer than two samples
+    pub noise: f64,
+    pub stopped: StopReason,
+}
+
+impl RenderReport {
+    /// Samples taken across the image
+    pub fn total_samples(&self) -> u64 {
+        u64::from(self.samples) * self.pixels
+    }
+}
+
+impl fmt::Display for RenderReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stopped = match self.stopped {
+            StopReason::Samples => "with every sample",
+            StopReason::Time => "at the time limit",
+            StopReason::Noise => "at the noise threshold",
+            StopReason::Stopped => "early",
+        };
+        write!(f, "{} samples per pixel, {} in all, stopping {}", self.samples, self.total_samples(), stopped)?;
+        if self.noise.is_finite() {
+            write!(f, ", noise {:.4}", self.noise)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Renderer<'a> {
+    /// Renders progressively like `render_progressive`, until the film has
+    /// its samples or the budget runs out, whichever comes first. The budget
+    /// is checked after every pass.
+    pub fn render_with_budget<F>(&self, film: &mut Film, budget: &RenderBudget, mut after_pass: F) -> RenderReport
+        where F: FnMut(&Film) -> bool
+    {
+        let start = Instant::now();
+        let mut pass_start = start;
+        let mut passes = 0;
+        let mut stopped = StopReason::Samples;
+
+        self.render_progressive(film, |film| {
+            passes += 1;
+            let pass_time = pass_start.elapsed();
+            pass_start = Instant::now();
+
+            if !after_pass(film) {
+                stopped = StopReason::Stopped;
+                return false;
+            }
+            if film.samples() >= self.settings.samples {
+                return true;
+            }
+            if let Some(time) = budget.time {
+                if start.elapsed() + pass_time > time {
+                    stopped = StopReason::Time;
+                    return false;
+                }
+            }
+            if let Some(noise) = budget.noise {
+                if film.samples() >= MIN_NOISE_SAMPLES && film.noise() <= noise {
+                    stopped = StopReason::Noise;
+                    return false;
+                }
+            }
+            true
+        });
+
+        let (columns, rows) = self.settings.pixels();
+        RenderReport {
+            samples: film.samples(),
+            pixels: columns.len() as u64 * rows.len() as u64,
+            passes,
+            time: start.elapsed(),
+            noise: film.noise(),
+            stopped,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use cgmath::{InnerSpace, Vector3, Zero};
+use image::Rgb;
+
+use super::{Aov, AovBuffer, Framebuffer};
+
+/// Weights of the B3 spline the filter spreads out at every pass
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// How far the denoiser smooths. Each sigma is the difference between two
+/// pixels at which they count for about a third as much as identical ones,
+/// so smaller values keep more detail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DenoiseSettings {
+    /// Passes of the filter, each reaching twice as far as the one before
+    pub iterations: u32,
+    /// Difference in brightness, in multiples of the noise around the pixel
+    pub color_sigma: f64,
+    /// Distance between normals
+    pub normal_sigma: f64,
+    /// Difference in depth, as a fraction of the depth
+    pub depth_sigma: f64,
+    /// Difference in albedo
+    pub albedo_sigma: f64,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> DenoiseSettings {
+        DenoiseSettings {
+            iterations: 5,
+            color_sigma: 4.0,
+            normal_sigma: 0.3,
+            depth_sigma: 0.05,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+impl DenoiseSettings {
+    /// The passes the denoiser is guided by
+    pub const GUIDES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+    /// The most passes there can be, the last reaching 65536 pixels
+    pub const MAX_ITERATIONS: u32 = 16;
+}
+
+/// Smooths the noise out of a render with an edge-avoiding à-trous wavelet
+/// filter. Pixels are blurred together less the more they differ in
+/// brightness than the noise around them explains, or in albedo, normal and
+/// depth where those passes are among `guides`, so the edges of objects and
+/// textures stay sharp.
+pub fn denoise(image: &Framebuffer, guides: &[AovBuffer], settings: &DenoiseSettings) -> Framebuffer {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let guide = |aov: Aov| guides.iter()
+        .find(|buffer| buffer.aov == aov && i64::from(buffer.width) == width && i64::from(buffer.height) == height);
+    let (albedo, normal, depth) = (guide(Aov::Albedo), guide(Aov::Normal), guide(Aov::Depth));
+    let vector = |buffer: &AovBuffer, index: usize| Vector3::new(
+        f64::from(buffer.channels[0][index]), f64::from(buffer.channels[1][index]), f64::from(buffer.channels[2][index]));
+
+    let mut colors: Vec<Vector3<f64>> = image.pixels()
+        .map(|pixel| Vector3::new(f64::from(pixel.data[0]), f64::from(pixel.data[1]), f64::from(pixel.data[2])))
+        .collect();
+
+    for iteration in 0..settings.iterations.min(DenoiseSettings::MAX_ITERATIONS) {
+        let step = 1i64 << iteration;
+        // passes reaching past the image leave it as it is
+        if step >= width.max(height) {
+            break;
+        }
+        let luminances: Vec<f64> = colors.iter().map(|&color| luminance(color)).collect();
+        let noise = local_noise(&luminances, width, height);
+        let mut filtered = Vec::with_capacity(colors.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = (y * width + x) as usize;
+                let color_sigma = settings.color_sigma * noise[p];
+                let mut sum = Vector3::zero();
+                let mut total = 0.0;
+
+                for (dy, &ky) in KERNEL.iter().enumerate() {
+                    let qy = y + (dy as i64 - 2) * step;
+                    if qy < 0 || qy >= height {
+                        continue;
+                    }
+                    for (dx, &kx) in KERNEL.iter().enumerate() {
+                        let qx = x + (dx as i64 - 2) * step;
+                        if qx < 0 || qx >= width {
+                            continue;
+                        }
+                        let q = (qy * width + qx) as usize;
+
+                        let brightness = (luminances[p] - luminances[q]).abs() / (color_sigma + 1e-4);
+                        let mut weight = kx * ky * (-brightness).exp();
+                        if let Some(albedo) = albedo {
+                            weight *= edge_weight((vector(albedo, p) - vector(albedo, q)).magnitude2(), settings.albedo_sigma);
+                        }
+                        if let Some(normal) = normal {
+                            weight *= edge_weight((vector(normal, p) - vector(normal, q)).magnitude2(), settings.normal_sigma);
+                        }
+                        if let Some(depth) = depth {
+                            weight *= depth_weight(depth.channels[0][p], depth.channels[0][q], settings.depth_sigma);
+                        }
+
+                        sum += colors[q] * weight;
+                        total += weight;
+                    }
+                }
+
+                // the pixel itself always has some weight
+                filtered.push(sum / total);
+            }
+        }
+        colors = filtered;
+    }
+
+    Framebuffer::from_fn(image.width(), image.height(), |x, y| {
+        let color = colors[(y * image.width() + x) as usize];
+        Rgb([color.x as f32, color.y as f32, color.z as f32])
+    })
+}
+
+/// Brightness brought into the range 0 to 1, so that bright pixels differ
+/// from each other about as much as dark ones
+fn luminance(color: Vector3<f64>) -> f64 {
+    let luminance = (0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z).max(0.0);
+    luminance / (1.0 + luminance)
+}
+
+/// Standard deviation of the luminances around each pixel
+fn local_noise(luminances: &[f64], width: i64, height: i64) -> Vec<f64> {
+    let mut noise = Vec::with_capacity(luminances.len());
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut sum_squares, mut count) = (0.0, 0.0, 0.0);
+            for qy in (y - 1).max(0)..(y + 2).min(height) {
+                for qx in (x - 1).max(0)..(x + 2).min(width) {
+                    let luminance = luminances[(qy * width + qx) as usize];
+                    sum += luminance;
+                    sum_squares += luminance * luminance;
+                    count += 1.0;
+                }
+            }
+            let mean = sum / count;
+            noise.push((sum_squares / count - mean * mean).max(0.0).sqrt());
+        }
+    }
+    noise
+}
+
+/// Weight of a neighbor whose squared difference from the pixel is
+/// `difference`
+fn edge_weight(difference: f64, sigma: f64) -> f64 {
+    if sigma <= 0.0 {
+        return if difference == 0.0 { 1.0 } else { 0.0 };
+    }
+    (-difference / (sigma * sigma)).exp()
+}
+
+/// Weight of a neighbor by depth, where infinite depths only match each other
+fn depth_weight(p: f32, q: f32, sigma: f64) -> f64 {
+    match (p.is_finite(), q.is_finite()) {
+        (true, true) => {
+            let (p, q) = (f64::from(p), f64::from(q));
+            let difference = (p - q) / p.abs().max(q.abs()).max(1e-6);
+            edge_weight(difference * difference, sigma)
+        },
+        (false, false) => 1.0,
+        _ => 0.0,
+    }
+}
@@ -0,0 +1,259 @@
+use cgmath::{Vector3, Zero};
+use image::Rgb;
+
+use std::cmp::Ordering;
+use std::f64;
+use std::io::{self, Read, Write};
+
+use super::{CropWindow, Framebuffer, IntegratorType, RenderSettings, Sampler};
+
+/// Start of every checkpoint file, with the version of its layout
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RAYRSCK3";
+
+/// Brightness that darker pixels' noise is measured against, as their noise
+/// is hard to see anyway
+const NOISE_FLOOR: f64 = 0.01;
+
+/// Fraction of the pixels whose noise is under the film's noise, so that a
+/// few rare bright paths don't hold up the whole image
+const NOISE_QUANTILE: f64 = 0.99;
+
+/// The samples taken so far, added up for every pixel, along with where each
+/// pixel's random numbers got to. Renders build it up a pass at a time, and
+/// it can be saved as a checkpoint and picked up again later to carry on
+/// exactly where it left off.
+#[derive(Clone, Debug)]
+pub struct Film {
+    /// The settings the render was started with
+    settings: RenderSettings,
+    /// Whether samples are spread across the pixels, rather than all through
+    /// their middle
+    jittered: bool,
+    /// Samples taken in every pixel
+    samples: u32,
+    sums: Vec<Vector3<f64>>,
+    /// Squares of the samples' luminances, for estimating the noise
+    squares: Vec<f64>,
+    samplers: Vec<Sampler>,
+}
+
+impl Film {
+    pub fn new(settings: &RenderSettings) -> Film {
+        let pixels = settings.width as usize * settings.height as usize;
+        Film {
+            settings: settings.clone(),
+            jittered: settings.samples > 1,
+            samples: 0,
+            sums: vec![Vector3::zero(); pixels],
+            squares: vec![0.0; pixels],
+            samplers: (0..pixels as u64).map(|pixel| Sampler::new(settings.seed, pixel)).collect(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.settings.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.settings.height
+    }
+
+    /// Samples taken in every pixel so far
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn jittered(&self) -> bool {
+        self.jittered
+    }
+
+    /// The random numbers of pixel `(x, y)`, from where its last sample left them
+    pub fn sampler(&self, x: u32, y: u32) -> Sampler {
+        self.samplers[self.index(x, y)].clone()
+    }
+
+    /// Adds a sample of pixel `(x, y)`, and where its random numbers got to
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Vector3<f64>, sampler: Sampler) {
+        let index = self.index(x, y);
+        self.sums[index] += color;
+        self.squares[index] += luminance(color) * luminance(color);
+        self.samplers[index] = sampler;
+    }
+
+    /// Counts a sample as taken in every pixel
+    pub fn end_pass(&mut self) {
+        self.samples += 1;
+    }
+
+    /// The average of the samples in every pixel, black before there are any
+    pub fn image(&self) -> Framebuffer {
+        let samples = f64::from(self.samples.max(1));
+        Framebuffer::from_fn(self.width(), self.height(), |x, y| {
+            let color = self.sums[self.index(x, y)] / samples;
+            Rgb([color.x as f32, color.y as f32, color.z as f32])
+        })
+    }
+
+    /// Estimated noise of the rendered pixels, as the standard error of
+    /// their average luminance relative to it. Nearly all the pixels are at
+    /// least this smooth, and it's infinite until there are two samples.
+    pub fn noise(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+
+        let (columns, rows) = self.settings.pixels();
+        let mut noise: Vec<f64> = rows
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .map(|(x, y)| self.pixel_noise(self.index(x, y)))
+            .collect();
+        if noise.is_empty() {
+            return 0.0;
+        }
+
+        let at = ((noise.len() - 1) as f64 * NOISE_QUANTILE).round() as usize;
+        *noise.select_nth_unstable_by(at, |a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal)).1
+    }
+
+    fn pixel_noise(&self, index: usize) -> f64 {
+        let samples = f64::from(self.samples);
+        let mean = luminance(self.sums[index]) / samples;
+        let variance = (self.squares[index] / samples - mean * mean).max(0.0) * samples / (samples - 1.0);
+        (variance / samples).sqrt() / mean.max(NOISE_FLOOR)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.settings.width as usize + x as usize
+    }
+
+    /// Saves everything needed to carry on the render later, along with
+    /// `scene`, a fingerprint of the scene rendered
+    pub fn write_checkpoint<W: Write>(&self, writer: &mut W, scene: u64) -> io::Result<()> {
+        let settings = &self.settings;
+        let mut output = Vec::with_capacity(96 + self.sums.len() * 40);
+        output.extend_from_slice(CHECKPOINT_MAGIC);
+        for &value in &[settings.width, settings.height, settings.samples, settings.max_depth, self.samples] {
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+        output.extend_from_slice(&settings.seed.to_le_bytes());
+        output.extend_from_slice(&scene.to_le_bytes());
+        output.push(settings.integrator.name().len() as u8);
+        output.extend_from_slice(settings.integrator.name().as_bytes());
+        for &value in &[settings.background.x, settings.background.y, settings.background.z] {
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+        match settings.crop {
+            Some(crop) => {
+                output.push(1);
+                for &value in &[crop.x_min, crop.y_min, crop.x_max, crop.y_max] {
+                    output.extend_from_slice(&value.to_le_bytes());
+                }
+            },
+            None => output.push(0),
+        }
+        output.push(self.jittered as u8);
+
+        for ((sum, square), sampler) in self.sums.iter().zip(&self.squares).zip(&self.samplers) {
+            for &value in &[sum.x, sum.y, sum.z, *square] {
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+            output.extend_from_slice(&sampler.state().to_le_bytes());
+        }
+
+        writer.write_all(&output)?;
+        writer.flush()
+    }
+
+    /// Picks up a render saved by `write_checkpoint`, which has to have been
+    /// started with the same settings, apart from the samples to take and the
+    /// threads taking them, on the scene with the fingerprint `scene`
+    pub fn read_checkpoint<R: Read>(reader: &mut R, settings: &RenderSettings, scene: u64) -> io::Result<Film> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let mut input = CheckpointReader { bytes: &bytes, at: 0 };
+
+        if input.take(CHECKPOINT_MAGIC.len())? != CHECKPOINT_MAGIC {
+            return Err(invalid_data("not a checkpoint"));
+        }
+        let (width, height, _, max_depth, samples) = (input.u32()?, input.u32()?, input.u32()?, input.u32()?, input.u32()?);
+        let seed = input.u64()?;
+        let checkpoint_scene = input.u64()?;
+        let name_length = input.take(1)?[0] as usize;
+        let name = String::from_utf8_lossy(input.take(name_length)?).into_owned();
+        let integrator = IntegratorType::from_name(&name)
+            .ok_or_else(|| invalid_data(format!("unknown integrator '{}'", name)))?;
+        let background = Vector3::new(input.f64()?, input.f64()?, input.f64()?);
+        let crop = match input.take(1)?[0] {
+            0 => None,
+            _ => Some(CropWindow { x_min: input.f64()?, y_min: input.f64()?, x_max: input.f64()?, y_max: input.f64()? }),
+        };
+        let jittered = input.take(1)?[0] != 0;
+
+        let differences = [
+            ("scene", checkpoint_scene != scene),
+            ("image size", (width, height) != (settings.width, settings.height)),
+            ("seed", seed != settings.seed),
+            ("max depth", max_depth != settings.max_depth),
+            ("integrator", integrator != settings.integrator),
+            ("background", background != settings.background),
+            ("crop window", crop != settings.crop),
+        ];
+        if let Some(&(name, _)) = differences.iter().find(|&&(_, different)| different) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the checkpoint was rendered with a different {}", name)));
+        }
+
+        let pixels = width as usize * height as usize;
+        let mut sums = Vec::with_capacity(pixels);
+        let mut squares = Vec::with_capacity(pixels);
+        let mut samplers = Vec::with_capacity(pixels);
+        for _ in 0..pixels {
+            sums.push(Vector3::new(input.f64()?, input.f64()?, input.f64()?));
+            squares.push(input.f64()?);
+            samplers.push(Sampler::from_state(input.u64()?));
+        }
+        if input.at != bytes.len() {
+            return Err(invalid_data("the checkpoint has more pixels than its size"));
+        }
+
+        Ok(Film { settings: settings.clone(), jittered, samples, sums, squares, samplers })
+    }
+}
+
+fn luminance(color: Vector3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+fn invalid_data<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct CheckpointReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> CheckpointReader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.at < count {
+            return Err(invalid_data("the checkpoint ends too soon"));
+        }
+        self.at += count;
+        Ok(&self.bytes[self.at - count..self.at])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+}
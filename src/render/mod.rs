@@ -0,0 +1,406 @@
+//! Turns a scene into an image. The image is split into rows shared out
+//! between threads, and every pixel is traced by an integrator, which works
+//! out the light arriving along camera rays. Samples are taken a pass at a
+//! time and added up on a film, which can be saved to carry on later, and
+//! renders can stop early once they run out of time or are smooth enough.
+//! Extra passes, such as depth and normals, can be rendered alongside it,
+//! and guide a denoiser.
+
+mod aov;
+mod budget;
+mod denoise;
+mod film;
+mod path;
+mod sampler;
+mod whitted;
+#[cfg(test)]
+mod tests;
+
+pub use self::aov::{Aov, AovBuffer};
+pub use self::budget::{RenderBudget, RenderReport, StopReason};
+pub use self::denoise::{denoise, DenoiseSettings};
+pub use self::film::Film;
+pub use self::path::PathIntegrator;
+pub use self::sampler::Sampler;
+pub use self::whitted::WhittedIntegrator;
+
+use cgmath::{ElementWise, InnerSpace, Vector3, Zero};
+use image::{ImageBuffer, Rgb};
+
+use std::f64;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::scene::{Camera, Material, Scene};
+use super::scene::objects::{Intersect, Ray, RayType};
+use super::stats::{self, Counters};
+
+/// Distance rays leaving a surface start away from it, so they don't hit it
+/// again straight away
+const SURFACE_EPSILON: f64 = 1e-6;
+
+/// Most surfaces a shadow ray passes through before the light counts as
+/// blocked
+const MAX_SHADOW_HITS: usize = 16;
+
+/// A render in linear floating point color, kept at its full range
+pub type Framebuffer = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// Works out the light arriving along rays
+pub trait Integrator: Sync {
+    fn radiance(&self, scene: &Scene, ray: &Ray, sampler: &mut Sampler) -> Vector3<f64>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntegratorType {
+    /// Recursive ray tracing with reflections, refractions and shadows
+    Whitted,
+    /// Unbiased path tracing with indirect light and area lights
+    Path,
+}
+
+impl IntegratorType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            IntegratorType::Whitted => "whitted",
+            IntegratorType::Path => "path",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<IntegratorType> {
+        match name {
+            "whitted" => Some(IntegratorType::Whitted),
+            "path" => Some(IntegratorType::Path),
+            _ => None,
+        }
+    }
+}
+
+/// Part of the image to render, in fractions of its size from the top left
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropWindow {
+    pub x_min: f64,
+    pub y_min: f64,
+    pub x_max: f64,
+    pub y_max: f64,
+}
+
+/// How far a crop window's edge can be inside a pixel and still count as
+/// being on its edge, so that windows given in pixels cover just those pixels
+const CROP_EPSILON: f64 = 1e-9;
+
+impl CropWindow {
+    /// The window covering the pixels `x0` to `x1` and `y0` to `y1` of a
+    /// `width` by `height` image, from the top left and leaving out the ends
+    pub fn from_pixels(x0: u32, y0: u32, x1: u32, y1: u32, width: u32, height: u32) -> CropWindow {
+        let (width, height) = (f64::from(width), f64::from(height));
+        CropWindow {
+            x_min: f64::from(x0) / width,
+            y_min: f64::from(y0) / height,
+            x_max: f64::from(x1) / width,
+            y_max: f64::from(y1) / height,
+        }
+    }
+
+    /// Columns and rows of the pixels the window covers any part of
+    pub fn pixels(&self, width: u32, height: u32) -> (Range<u32>, Range<u32>) {
+        let range = |min: f64, max: f64, size: u32| {
+            let size = f64::from(size);
+            ((min * size + CROP_EPSILON).floor() as u32)..((max * size - CROP_EPSILON).ceil() as u32)
+        };
+        (range(self.x_min, self.x_max, width), range(self.y_min, self.y_max, height))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Camera rays traced per pixel, jittered within it when there's more than one
+    pub samples: u32,
+    /// Bounces of reflected and refracted light followed
+    pub max_depth: u32,
+    pub threads: usize,
+    pub seed: u64,
+    pub integrator: IntegratorType,
+    /// Color of rays that miss everything
+    pub background: Vector3<f64>,
+    /// Leaves the pixels outside the window black
+    pub crop: Option<CropWindow>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 512,
+            height: 512,
+            samples: 1,
+            max_depth: 5,
+            threads: thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+            seed: 0,
+            integrator: IntegratorType::Whitted,
+            background: Vector3::zero(),
+            crop: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Columns and rows of the pixels rendered, those in the crop window
+    pub fn pixels(&self) -> (Range<u32>, Range<u32>) {
+        match self.crop {
+            Some(crop) => crop.pixels(self.width, self.height),
+            None => (0..self.width, 0..self.height),
+        }
+    }
+}
+
+pub struct Renderer<'a> {
+    scene: &'a Scene,
+    settings: RenderSettings,
+    /// What the render threads counted
+    counters: Mutex<Counters>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(scene: &'a Scene, settings: RenderSettings) -> Renderer<'a> {
+        Renderer { scene, settings, counters: Mutex::new(Counters::default()) }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// The work done by everything rendered so far
+    pub fn counters(&self) -> Counters {
+        *self.counters.lock().unwrap()
+    }
+
+    pub fn render(&self) -> Framebuffer {
+        let mut film = Film::new(&self.settings);
+        self.render_progressive(&mut film, |_| true);
+        film.image()
+    }
+
+    /// Adds a sample to every pixel of the film at a time until they have
+    /// as many as the settings ask for. `after_pass` is called with the film
+    /// after every pass, and stops the render early by returning false.
+    pub fn render_progressive<F>(&self, film: &mut Film, mut after_pass: F)
+        where F: FnMut(&Film) -> bool
+    {
+        assert!((film.width(), film.height()) == (self.settings.width, self.settings.height), "the film is a different size to the image");
+
+        let settings = &self.settings;
+        let integrator = self.integrator();
+        let camera = self.camera();
+        let (columns, rows) = settings.pixels();
+
+        while film.samples() < settings.samples {
+            let rendered = {
+                let film = &*film;
+                self.render_rows(columns.clone(), rows.clone(), |x, y| {
+                    let mut sampler = film.sampler(x, y);
+                    let color = self.sample_pixel(&*integrator, &camera, film.jittered(), x, y, &mut sampler);
+                    (color, sampler)
+                })
+            };
+
+            for (y, row) in rendered {
+                for (x, (color, sampler)) in columns.clone().zip(row) {
+                    film.add_sample(x, y, color, sampler);
+                }
+            }
+            film.end_pass();
+
+            if !after_pass(film) {
+                break;
+            }
+        }
+    }
+
+    /// Renders every sample of the pixels in `columns` and `rows` at once,
+    /// into an image of just those pixels. They come out the same as they do
+    /// in a render of the whole image, as the pixels' random numbers don't
+    /// depend on what else is rendered.
+    pub fn render_bucket(&self, columns: Range<u32>, rows: Range<u32>) -> Framebuffer {
+        let settings = &self.settings;
+        let integrator = self.integrator();
+        let camera = self.camera();
+        let jittered = settings.samples > 1;
+
+        let rendered = self.render_rows(columns.clone(), rows.clone(), |x, y| {
+            let mut sampler = Sampler::new(settings.seed, u64::from(y) * u64::from(settings.width) + u64::from(x));
+            let mut sum = Vector3::zero();
+            for _ in 0..settings.samples {
+                sum += self.sample_pixel(&*integrator, &camera, jittered, x, y, &mut sampler);
+            }
+            sum / f64::from(settings.samples.max(1))
+        });
+
+        let mut image = Framebuffer::new(columns.len() as u32, rows.len() as u32);
+        for (y, row) in rendered {
+            for (x, color) in row.into_iter().enumerate() {
+                image.put_pixel(x as u32, y - rows.start, Rgb([color.x as f32, color.y as f32, color.z as f32]));
+            }
+        }
+        image
+    }
+
+    fn integrator(&self) -> Box<dyn Integrator> {
+        match self.settings.integrator {
+            IntegratorType::Whitted => Box::new(WhittedIntegrator::new(&self.settings)),
+            IntegratorType::Path => Box::new(PathIntegrator::new(self.scene, &self.settings)),
+        }
+    }
+
+    /// The scene's camera, with the image deciding the shape of the view like
+    /// the camera's film
+    fn camera(&self) -> Camera {
+        let mut camera = self.scene.camera().clone();
+        camera.set_aspect_ratio(f64::from(self.settings.width) / f64::from(self.settings.height));
+        camera
+    }
+
+    /// Runs `pixel` over every pixel in `columns` and `rows`, giving the rows
+    /// of results. Threads take the next row still to do until there are
+    /// none left.
+    fn render_rows<T, F>(&self, columns: Range<u32>, rows: Range<u32>, pixel: F) -> Vec<(u32, Vec<T>)>
+        where T: Send, F: Fn(u32, u32) -> T + Sync
+    {
+        let settings = &self.settings;
+        let next_row = AtomicUsize::new(rows.start as usize);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..settings.threads.max(1)).map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let y = next_row.fetch_add(1, Ordering::Relaxed) as u32;
+                    if y >= rows.end {
+                        self.counters.lock().unwrap().add(&stats::take());
+                        return done;
+                    }
+                    let row = columns.clone().map(|x| pixel(x, y)).collect();
+                    done.push((y, row));
+                }
+            })).collect();
+
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        })
+    }
+
+    /// A sample through pixel `(x, y)`, counted from the top left, either
+    /// through its middle or `jittered` across it
+    fn sample_pixel(&self, integrator: &dyn Integrator, camera: &Camera, jittered: bool, x: u32, y: u32, sampler: &mut Sampler) -> Vector3<f64> {
+        let (dx, dy) = if jittered {
+            let offset = sampler.next_2d();
+            (offset.x, offset.y)
+        } else {
+            (0.5, 0.5)
+        };
+
+        // nothing is seen where the camera's projection doesn't reach
+        let color = match camera_ray(camera, &self.settings, x, y, dx, dy) {
+            Some(ray) => integrator.radiance(self.scene, &ray, sampler),
+            None => Vector3::zero(),
+        };
+        stats::count_sample();
+        color
+    }
+}
+
+/// Camera ray through the point `(dx, dy)` across pixel `(x, y)`, if the
+/// camera sees anything there
+fn camera_ray(camera: &Camera, settings: &RenderSettings, x: u32, y: u32, dx: f64, dy: f64) -> Option<Ray> {
+    let (width, height) = (f64::from(settings.width), f64::from(settings.height));
+    // the camera counts from the bottom left
+    camera.ray_through((f64::from(x) + dx) / width, 1.0 - (f64::from(y) + dy) / height)
+}
+
+/// The material at a hit, objects without one get the default material
+fn surface_material(isect: &Intersect) -> Arc<Material> {
+    isect.material.clone().unwrap_or_default()
+}
+
+/// `n` turned to face back along the direction `d`
+fn facing(n: Vector3<f64>, d: Vector3<f64>) -> Vector3<f64> {
+    if n.dot(d) > 0.0 { -n } else { n }
+}
+
+fn reflect(d: Vector3<f64>, n: Vector3<f64>) -> Vector3<f64> {
+    d - n * (2.0 * d.dot(n))
+}
+
+/// Direction of `d` refracted through a surface with normal `n`, going into
+/// the material if `n` faces against `d` and out of it otherwise. None on
+/// total internal reflection.
+fn refract(d: Vector3<f64>, n: Vector3<f64>, index: f64) -> Option<Vector3<f64>> {
+    let (n, eta) = if n.dot(d) < 0.0 { (n, 1.0 / index) } else { (-n, index) };
+    let cos_i = -d.dot(n);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        return None;
+    }
+    Some((d * eta + n * (eta * cos_i - k.sqrt())).normalize())
+}
+
+/// Ray leaving the surface at `p` with normal `n`, moved off it on the side
+/// it leaves from
+fn spawn_ray(p: Vector3<f64>, n: Vector3<f64>, d: Vector3<f64>, ray_type: RayType) -> Ray {
+    let offset = if n.dot(d) < 0.0 { -n } else { n };
+    Ray::new(p + offset * SURFACE_EPSILON, d, ray_type)
+}
+
+/// Fraction of the light from direction `l` that makes it to `p` across
+/// `distance`, filtered by the transmissive surfaces in between
+fn shadow_attenuation(scene: &Scene, p: Vector3<f64>, n: Vector3<f64>, l: Vector3<f64>, distance: f64) -> Vector3<f64> {
+    let mut ray = spawn_ray(p, n, l, RayType::Shadow);
+    let mut attenuation = if scene.media().is_empty() { Vector3::new(1.0, 1.0, 1.0) } else { scene.media().transmittance(&ray, distance) };
+    let mut remaining = distance;
+
+    for _ in 0..MAX_SHADOW_HITS {
+        let mut isect = Intersect::new();
+        if !scene.intersect(&ray, &mut isect) || isect.t >= remaining {
+            return attenuation;
+        }
+
+        let material = surface_material(&isect);
+        attenuation.mul_assign_element_wise(material.transmissive.value(&isect));
+        if attenuation.x.max(attenuation.y).max(attenuation.z) <= 0.0 {
+            break;
+        }
+
+        remaining -= isect.t;
+        ray = spawn_ray(ray.at(isect.t), isect.n, l, RayType::Shadow);
+    }
+    Vector3::zero()
+}
+
+/// Phong diffuse and specular light from the scene's lights at the hit, with
+/// shadows
+fn direct_lighting(scene: &Scene, ray: &Ray, isect: &Intersect, material: &Material) -> Vector3<f64> {
+    let p = ray.at(isect.t);
+    let n = facing(isect.n, ray.direction());
+    let v = -ray.direction();
+    let diffuse = material.diffuse.value(isect);
+    let specular = material.specular.value(isect);
+
+    let mut color = Vector3::zero();
+    for light in scene.lights() {
+        let l = light.direction(p);
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+
+        let attenuation = shadow_attenuation(scene, p, n, l, light.distance(p)) * light.distance_attenuation(p);
+        if attenuation.is_zero() {
+            continue;
+        }
+
+        let highlight = reflect(-l, n).dot(v).max(0.0).powf(material.shininess);
+        let reflected = diffuse * n_dot_l + specular * highlight;
+        color += attenuation.mul_element_wise(light.color()).mul_element_wise(reflected);
+    }
+    color
+}
@@ -0,0 +1,274 @@
+use cgmath::{ElementWise, InnerSpace, Vector2, Vector3, Zero};
+
+use std::f64::consts;
+
+use super::super::scene::medium::{self, Media};
+
+use super::*;
+
+/// Bounces after which paths may be ended early, in proportion to how little
+/// light they can still carry
+const ROULETTE_DEPTH: u32 = 3;
+
+/// Most tentative collisions a path looks at on its way through media before
+/// carrying on as if it got through
+const MAX_MEDIUM_COLLISIONS: usize = 4096;
+
+/// Path tracing: follows one randomly chosen bounce at every hit, adding up
+/// light from the scene's lights and from emissive objects along the way.
+/// Light colors count the same as in the Whitted shader, so directly lit
+/// scenes look alike in both, but ambient light is left out as the path
+/// tracer finds the indirect light it stands in for. The background lights
+/// the scene from every direction. Paths through media scatter off into
+/// them at random in proportion to how thick they are, and are lit where
+/// they do like surfaces are.
+pub struct PathIntegrator {
+    max_depth: u32,
+    background: Vector3<f64>,
+    /// Indices of the top level objects with an emissive material that can be
+    /// sampled, which light the scene like area lights
+    emitters: Vec<usize>,
+}
+
+impl PathIntegrator {
+    pub fn new(scene: &Scene, settings: &RenderSettings) -> PathIntegrator {
+        let emitters = scene.objects().iter().enumerate()
+            .filter(|&(_, object)| {
+                object.material().is_some_and(|material| !material.emissive.base_value().is_zero())
+                    && object.sample_surface(Vector2::new(0.5, 0.5)).is_some()
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        PathIntegrator { max_depth: settings.max_depth, background: settings.background, emitters }
+    }
+
+    /// Whether the hit is on one of the emitters, whose light was already
+    /// counted by sampling them
+    fn is_emitter(&self, scene: &Scene, isect: &Intersect) -> bool {
+        let material = match isect.material {
+            Some(ref material) => material,
+            None => return false,
+        };
+        self.emitters.iter()
+            .filter_map(|&index| scene.objects()[index].material())
+            .any(|emitter| Arc::ptr_eq(emitter, material))
+    }
+
+    /// Light scattered at `p` from a point picked on one of the emitters,
+    /// where `scattering` gives the fraction of the light from direction `l`
+    /// scattered back along the path per unit of solid angle. Shadow rays
+    /// start off `p` on the side of `n` towards the emitter.
+    fn sample_emitters<F>(&self, scene: &Scene, p: Vector3<f64>, n: Vector3<f64>, sampler: &mut Sampler, scattering: F) -> Vector3<f64>
+        where F: Fn(Vector3<f64>) -> Vector3<f64>
+    {
+        if self.emitters.is_empty() {
+            return Vector3::zero();
+        }
+
+        let pick = ((sampler.next_f64() * self.emitters.len() as f64) as usize).min(self.emitters.len() - 1);
+        let emitter = &scene.objects()[self.emitters[pick]];
+        let u = sampler.next_2d();
+        let (sample, material) = match (emitter.sample_surface(u), emitter.material()) {
+            (Some(sample), Some(material)) => (sample, material),
+            _ => return Vector3::zero(),
+        };
+
+        let to_light = sample.p - p;
+        let distance = to_light.magnitude();
+        if distance <= SURFACE_EPSILON {
+            return Vector3::zero();
+        }
+        let l = to_light / distance;
+        let cos_emitter = sample.n.dot(l).abs();
+        let scattered = scattering(l);
+        if cos_emitter <= 0.0 || scattered.is_zero() {
+            return Vector3::zero();
+        }
+
+        // stop short of the emitter's own surface
+        let visibility = shadow_attenuation(scene, p, n, l, distance * (1.0 - 1e-4));
+        let pdf = sample.pdf / self.emitters.len() as f64;
+        let geometry = cos_emitter / (distance * distance * pdf);
+        scattered.mul_element_wise(material.emissive.base_value()).mul_element_wise(visibility) * geometry
+    }
+
+    /// Follows `ray` through the media up to `t_max` by delta tracking,
+    /// weighting `throughput` by what it goes through. Gives where it scatters
+    /// and the asymmetry of the media there, or None if it carries on to
+    /// `t_max`.
+    fn track_media(&self, scene: &Scene, ray: &Ray, t_max: f64, throughput: &mut Vector3<f64>, sampler: &mut Sampler) -> Option<(f64, f64)> {
+        let spans = scene.media().spans(ray, t_max);
+        // no point along the ray is thicker than all of its media together
+        let majorant: f64 = spans.iter().map(|span| span.max_extinction()).sum();
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        let end = spans.iter().map(|span| span.end).fold(0.0, f64::max);
+        let mut t = spans.iter().map(|span| span.start).fold(f64::INFINITY, f64::min);
+        for _ in 0..MAX_MEDIUM_COLLISIONS {
+            t -= (1.0 - sampler.next_f64()).ln() / majorant;
+            if t >= end {
+                return None;
+            }
+
+            // scatter or carry on, with absorption left to the weights
+            let coefficients = Media::coefficients_at(&spans, ray, t);
+            let extinction = coefficients.absorption + coefficients.scattering;
+            let null = Vector3::new((majorant - extinction.x).max(0.0), (majorant - extinction.y).max(0.0), (majorant - extinction.z).max(0.0));
+            let (scatter, carry_on) = (average(coefficients.scattering), average(null));
+            if scatter + carry_on <= 0.0 {
+                *throughput = Vector3::zero();
+                return None;
+            }
+
+            let probability = scatter / (scatter + carry_on);
+            if sampler.next_f64() < probability {
+                throughput.mul_assign_element_wise(coefficients.scattering / (majorant * probability));
+                return Some((t, coefficients.asymmetry));
+            }
+            throughput.mul_assign_element_wise(null / (majorant * (1.0 - probability)));
+        }
+        None
+    }
+
+    /// Light from the lights and emitters scattered at `p` in a medium with
+    /// asymmetry `g` back along direction of travel `d`
+    fn medium_lighting(&self, scene: &Scene, p: Vector3<f64>, d: Vector3<f64>, g: f64, sampler: &mut Sampler) -> Vector3<f64> {
+        let mut color = Vector3::zero();
+        for light in scene.lights() {
+            let l = light.direction(p);
+            let attenuation = shadow_attenuation(scene, p, l, l, light.distance(p)) * light.distance_attenuation(p);
+            // a light's color counts for its irradiance over π
+            color += light.color().mul_element_wise(attenuation) * (consts::PI * medium::phase(g, d.dot(l)));
+        }
+
+        let scattering = |l: Vector3<f64>| {
+            let value = medium::phase(g, d.dot(l));
+            Vector3::new(value, value, value)
+        };
+        color + self.sample_emitters(scene, p, d, sampler, scattering)
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn radiance(&self, scene: &Scene, ray: &Ray, sampler: &mut Sampler) -> Vector3<f64> {
+        let mut radiance = Vector3::zero();
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        // emitters seen straight after a diffuse bounce were already sampled
+        let mut count_emitters = true;
+
+        for depth in 0..=self.max_depth {
+            stats::count_depth(depth);
+            let mut isect = Intersect::new();
+            let hit = scene.intersect(&ray, &mut isect);
+
+            if !scene.media().is_empty() {
+                let t_max = if hit { isect.t } else { f64::INFINITY };
+                if let Some((t, g)) = self.track_media(scene, &ray, t_max, &mut throughput, sampler) {
+                    let (p, d) = (ray.at(t), ray.direction());
+                    radiance += throughput.mul_element_wise(self.medium_lighting(scene, p, d, g, sampler));
+                    if depth == self.max_depth || !survives(&mut throughput, depth, sampler) {
+                        break;
+                    }
+
+                    // the phase function is sampled exactly, so the weight stays as it is
+                    ray = Ray::new(p, medium::sample_phase(g, d, sampler.next_2d()), RayType::Reflection);
+                    count_emitters = false;
+                    continue;
+                }
+                if throughput.is_zero() {
+                    break;
+                }
+            }
+
+            if !hit {
+                radiance += throughput.mul_element_wise(self.background);
+                break;
+            }
+
+            let material = surface_material(&isect);
+            if count_emitters || !self.is_emitter(scene, &isect) {
+                radiance += throughput.mul_element_wise(material.emissive.value(&isect));
+            }
+
+            let p = ray.at(isect.t);
+            let d = ray.direction();
+            let n = facing(isect.n, d);
+            let diffuse = material.diffuse.value(&isect);
+            let scattering = |l: Vector3<f64>| diffuse * (n.dot(l).max(0.0) / consts::PI);
+            let direct = direct_lighting(scene, &ray, &isect, &material) + self.sample_emitters(scene, p, n, sampler, scattering);
+            radiance += throughput.mul_element_wise(direct);
+
+            if depth == self.max_depth {
+                break;
+            }
+
+            // pick one of the bounces by how much light it carries
+            let reflective = material.reflective.value(&isect);
+            let transmissive = material.transmissive.value(&isect);
+            let weights = [average(reflective), average(transmissive), average(diffuse)];
+            let total: f64 = weights.iter().sum();
+            if total <= 0.0 {
+                break;
+            }
+
+            let choice = sampler.next_f64() * total;
+            if choice < weights[0] {
+                throughput.mul_assign_element_wise(reflective * (total / weights[0]));
+                ray = spawn_ray(p, n, reflect(d, n), RayType::Reflection);
+                count_emitters = true;
+            } else if choice < weights[0] + weights[1] {
+                throughput.mul_assign_element_wise(transmissive * (total / weights[1]));
+                ray = match refract(d, isect.n, material.index) {
+                    Some(direction) => spawn_ray(p, isect.n, direction, RayType::Refraction),
+                    None => spawn_ray(p, n, reflect(d, n), RayType::Reflection),
+                };
+                count_emitters = true;
+            } else {
+                // cosine weighted, which leaves the diffuse color as the weight
+                throughput.mul_assign_element_wise(diffuse * (total / weights[2]));
+                ray = spawn_ray(p, n, cosine_direction(n, sampler.next_2d()), RayType::Reflection);
+                count_emitters = false;
+            }
+
+            if !survives(&mut throughput, depth, sampler) {
+                break;
+            }
+        }
+
+        radiance
+    }
+}
+
+/// Russian roulette past `ROULETTE_DEPTH`: whether the path goes on, weighting
+/// it up to make up for the ones that don't
+fn survives(throughput: &mut Vector3<f64>, depth: u32, sampler: &mut Sampler) -> bool {
+    if depth < ROULETTE_DEPTH {
+        return true;
+    }
+    let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+    if sampler.next_f64() >= survival {
+        return false;
+    }
+    *throughput /= survival;
+    true
+}
+
+fn average(color: Vector3<f64>) -> f64 {
+    ((color.x + color.y + color.z) / 3.0).max(0.0)
+}
+
+/// Direction about `n` picked with a density proportional to its cosine with `n`
+fn cosine_direction(n: Vector3<f64>, u: Vector2<f64>) -> Vector3<f64> {
+    let radius = u.x.sqrt();
+    let phi = 2.0 * consts::PI * u.y;
+    let (x, y, z) = (radius * phi.cos(), radius * phi.sin(), (1.0 - u.x).max(0.0).sqrt());
+
+    let helper = if n.x.abs() > 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let tangent = helper.cross(n).normalize();
+    let bitangent = n.cross(tangent);
+    (tangent * x + bitangent * y + n * z).normalize()
+}
@@ -0,0 +1,51 @@
+use cgmath::Vector2;
+
+/// Small, fast pseudo random number generator (xorshift64*). Every pixel gets
+/// its own stream derived from the render seed, so images come out the same
+/// whatever the number of threads rendering them.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(seed: u64, stream: u64) -> Sampler {
+        Sampler::from_state(splitmix64(seed ^ splitmix64(stream)))
+    }
+
+    /// Where the stream has got to, for picking it up again later with
+    /// `from_state`
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn from_state(state: u64) -> Sampler {
+        // xorshift gets stuck on zero
+        Sampler { state: if state == 0 { 0x9e37_79b9_7f4a_7c15 } else { state } }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `[0, 1)²`
+    pub fn next_2d(&mut self) -> Vector2<f64> {
+        let x = self.next_f64();
+        Vector2::new(x, self.next_f64())
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
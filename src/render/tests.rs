@@ -0,0 +1,448 @@
+use cgmath::{Vector3, Zero};
+
+use std::f64::consts;
+use std::time::Duration;
+
+use scene::{Camera, FisheyeMapping, Material, MaterialParameter, Projection, Scene};
+use scene::builder::{Element, SceneBuilder};
+use scene::medium::{DensityGrid, Medium};
+use scene::objects::{Light, LightType};
+
+use super::*;
+
+fn material(diffuse: f64) -> Material {
+    let mut material = Material::new();
+    material.diffuse = MaterialParameter::new(Vector3::new(diffuse, diffuse, diffuse));
+    material
+}
+
+/// A sphere in front of the camera, lit from behind the camera, with a mirror
+/// behind it and a glass ball beside it
+fn sample_scene() -> Scene {
+    let mut mirror = material(0.1);
+    mirror.reflective = MaterialParameter::new(Vector3::new(0.8, 0.8, 0.8));
+    let mut glass = material(0.0);
+    glass.transmissive = MaterialParameter::new(Vector3::new(0.9, 0.9, 0.9));
+    glass.index = 1.5;
+    let mut ambient = material(0.5);
+    ambient.ambient = MaterialParameter::new(Vector3::new(0.2, 0.2, 0.2));
+
+    SceneBuilder::new()
+        .ambient_light(Vector3::new(0.1, 0.1, 0.1))
+        .add_light(Light::new(LightType::DirectionalLight { orientation: -Vector3::unit_z() }, Vector3::new(1.0, 1.0, 1.0)))
+        .add(Element::sphere().material(ambient).translate(Vector3::new(0.0, 0.0, -5.0)))
+        .add(Element::sphere().material(glass).translate(Vector3::new(1.5, 0.5, -4.0)))
+        .add(Element::square().material(mirror).scale(Vector3::new(8.0, 8.0, 1.0)).translate(Vector3::new(0.0, 0.0, -8.0)))
+        .build()
+}
+
+fn settings(width: u32, height: u32, integrator: IntegratorType) -> RenderSettings {
+    RenderSettings { width, height, samples: 1, max_depth: 5, threads: 1, seed: 0, integrator, background: Vector3::zero(), crop: None }
+}
+
+#[test]
+fn whitted_test() {
+    let image = Renderer::new(&sample_scene(), settings(3, 3, IntegratorType::Whitted)).render();
+
+    let assert_gray = |x: u32, y: u32, expected: f32| {
+        assert!(image.get_pixel(x, y).data.iter().all(|&value| (value - expected).abs() < 1e-6),
+            "expected {} at ({}, {}), got {:?}", expected, x, y, image.get_pixel(x, y).data);
+    };
+    // the sphere faces the light head on: diffuse plus ambient
+    assert_gray(1, 1, 0.52);
+    // the mirror reflects the unlit space behind the camera
+    assert_gray(0, 2, 0.1);
+}
+
+#[test]
+fn path_direct_test() {
+    // without bounces or ambient light, both integrators see the same direct light
+    let scene = SceneBuilder::new()
+        .add_light(Light::new(LightType::PointLight { pos: Vector3::new(2.0, 3.0, 0.0), a: 1.0, b: 0.0, c: 0.0 }, Vector3::new(1.0, 0.9, 0.8)))
+        .add(Element::sphere().material(material(0.7)).translate(Vector3::new(0.0, 0.0, -4.0)))
+        .add(Element::sphere().material(material(0.3)).scale(Vector3::new(0.3, 0.3, 0.3)).translate(Vector3::new(0.8, 1.2, -2.5)))
+        .build();
+
+    let mut whitted = settings(16, 16, IntegratorType::Whitted);
+    whitted.max_depth = 0;
+    let mut path = whitted.clone();
+    path.integrator = IntegratorType::Path;
+    assert_eq!(Renderer::new(&scene, whitted).render().into_raw(), Renderer::new(&scene, path).render().into_raw());
+}
+
+#[test]
+fn path_emitter_test() {
+    // inside a glowing sphere every direction sees the same light, so a
+    // diffuse surface reflects exactly its own color
+    let mut glow = material(0.0);
+    glow.emissive = MaterialParameter::new(Vector3::new(1.0, 1.0, 1.0));
+    let mut camera = Camera::new();
+    camera.set_eye(Vector3::new(0.0, 0.0, 5.0));
+    let scene = SceneBuilder::new()
+        .camera(camera)
+        .add(Element::sphere().material(glow).scale(Vector3::new(10.0, 10.0, 10.0)))
+        .add(Element::square().material(material(0.6)))
+        .build();
+
+    let ray = scene.camera().ray_through(0.5, 0.5).unwrap();
+    for &max_depth in &[0, 3] {
+        let integrator = PathIntegrator::new(&scene, &RenderSettings { max_depth, ..settings(1, 1, IntegratorType::Path) });
+        let mut sampler = Sampler::new(7, 0);
+        let samples = 8000;
+        let total = (0..samples).fold(Vector3::zero(), |total, _| total + integrator.radiance(&scene, &ray, &mut sampler));
+        let average = total.x / f64::from(samples);
+        assert!((average - 0.6).abs() < 0.6 * 0.03, "expected 0.6 at depth {}, got {}", max_depth, average);
+    }
+
+    // seen directly, the emitter is just its own color
+    let mut sampler = Sampler::new(0, 0);
+    let outward = Ray::new(Vector3::zero(), Vector3::new(1.0, 0.0, consts::FRAC_1_SQRT_2).normalize(), RayType::Visibility);
+    let integrator = PathIntegrator::new(&scene, &RenderSettings { max_depth: 0, ..settings(1, 1, IntegratorType::Path) });
+    assert_eq!(integrator.radiance(&scene, &outward, &mut sampler), Vector3::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn threads_test() {
+    let scene = sample_scene();
+    let mut single = settings(24, 16, IntegratorType::Path);
+    single.samples = 4;
+    single.seed = 3;
+    let mut several = single.clone();
+    several.threads = 4;
+
+    let image = Renderer::new(&scene, single.clone()).render().into_raw();
+    assert_eq!(image, Renderer::new(&scene, several).render().into_raw());
+
+    // another seed picks other samples
+    single.seed = 4;
+    assert!(image != Renderer::new(&scene, single).render().into_raw());
+}
+
+#[test]
+fn crop_test() {
+    let scene = sample_scene();
+    let full = Renderer::new(&scene, settings(20, 10, IntegratorType::Whitted)).render();
+
+    let mut cropped = settings(20, 10, IntegratorType::Whitted);
+    cropped.crop = Some(CropWindow { x_min: 0.22, y_min: 0.5, x_max: 0.5, y_max: 1.0 });
+    let (columns, rows) = cropped.crop.unwrap().pixels(20, 10);
+    assert_eq!((columns.clone(), rows.clone()), (4..10, 5..10));
+
+    // windows given in pixels cover exactly those pixels, whatever the size
+    for &(width, height) in &[(7, 5), (1920, 1080), (3, 3)] {
+        let window = CropWindow::from_pixels(1, 1, width - 1, height, width, height);
+        assert_eq!(window.pixels(width, height), (1..width - 1, 1..height));
+    }
+
+    let image = Renderer::new(&scene, cropped).render();
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if columns.contains(&x) && rows.contains(&y) {
+            assert_eq!(pixel, full.get_pixel(x, y));
+        } else {
+            assert_eq!(pixel.data, [0.0, 0.0, 0.0]);
+        }
+    }
+}
+
+#[test]
+fn bucket_test() {
+    // buckets come out the same as their part of the whole image
+    let scene = sample_scene();
+    let mut jittered = settings(20, 10, IntegratorType::Path);
+    jittered.samples = 3;
+    let image = Renderer::new(&scene, jittered.clone()).render();
+
+    let bucket = Renderer::new(&scene, jittered).render_bucket(5..13, 2..10);
+    assert_eq!(bucket.dimensions(), (8, 8));
+    for (x, y, pixel) in bucket.enumerate_pixels() {
+        assert_eq!(pixel, image.get_pixel(x + 5, y + 2));
+    }
+}
+
+#[test]
+fn background_test() {
+    // rays that miss the empty scene see the background, from both integrators
+    let scene = SceneBuilder::new().build();
+    for &integrator in &[IntegratorType::Whitted, IntegratorType::Path] {
+        let mut with_background = settings(2, 2, integrator);
+        with_background.background = Vector3::new(1.0, 0.5, 0.0);
+        let image = Renderer::new(&scene, with_background).render();
+        assert!(image.pixels().all(|pixel| pixel.data == [1.0, 0.5, 0.0]));
+    }
+}
+
+fn fog(absorption: f64, scattering: f64) -> Medium {
+    let mut fog = Medium::new();
+    fog.absorption = Vector3::new(absorption, absorption, absorption);
+    fog.scattering = Vector3::new(scattering, scattering, scattering);
+    fog
+}
+
+/// Average radiance of the path tracer along `ray`
+fn path_average(scene: &Scene, ray: &Ray, max_depth: u32, samples: u32) -> f64 {
+    let integrator = PathIntegrator::new(scene, &RenderSettings { max_depth, ..settings(1, 1, IntegratorType::Path) });
+    let mut sampler = Sampler::new(3, 0);
+    let total = (0..samples).fold(Vector3::zero(), |total, _| total + integrator.radiance(scene, ray, &mut sampler));
+    total.x / f64::from(samples)
+}
+
+#[test]
+fn fog_test() {
+    // a glowing sphere 4 away through fog absorbing a quarter of the light per unit
+    let mut glow = material(0.0);
+    glow.emissive = MaterialParameter::new(Vector3::new(1.0, 1.0, 1.0));
+    let scene = SceneBuilder::new()
+        .add(Element::sphere().material(glow).translate(Vector3::new(0.0, 0.0, -5.0)))
+        .fog(fog(0.25, 0.0))
+        .build();
+    let ray = scene.camera().ray_through(0.5, 0.5).unwrap();
+
+    let whitted = WhittedIntegrator::new(&settings(1, 1, IntegratorType::Whitted));
+    let color = whitted.radiance(&scene, &ray, &mut Sampler::new(0, 0));
+    assert!((color.x - (-1.0f64).exp()).abs() < 1e-9, "{}", color.x);
+    let average = path_average(&scene, &ray, 0, 20000);
+    assert!((average - (-1.0f64).exp()).abs() < 0.03 * (-1.0f64).exp(), "{}", average);
+
+    // scattering fog in front of a black sphere glows with the ambient light
+    // and a quarter of the lights
+    let scene = SceneBuilder::new()
+        .ambient_light(Vector3::new(0.2, 0.2, 0.2))
+        .add_light(Light::new(LightType::DirectionalLight { orientation: -Vector3::unit_y() }, Vector3::new(1.0, 1.0, 1.0)))
+        .add(Element::sphere().material(material(0.0)).translate(Vector3::new(0.0, 0.0, -5.0)))
+        .fog(fog(0.0, 0.1))
+        .build();
+    let color = whitted.radiance(&scene, &ray, &mut Sampler::new(0, 0));
+    assert!((color.x - (1.0 - (-0.4f64).exp()) * 0.45).abs() < 1e-9, "{}", color.x);
+}
+
+#[test]
+fn path_medium_test() {
+    // media that scatter without absorbing pass on all of the light of the
+    // glowing sphere around them however often it's scattered, when they're
+    // even or gridded, which takes null collisions to track through
+    let mut glow = material(0.0);
+    glow.emissive = MaterialParameter::new(Vector3::new(1.0, 1.0, 1.0));
+    let mut haze = fog(0.0, 0.5);
+    haze.asymmetry = 0.3;
+    let mut cloud = haze.clone();
+    cloud.density = Some(Arc::new(DensityGrid::new([2, 2, 2], vec![0.0, 1.0, 2.0, 0.5, 1.5, 0.0, 1.0, 3.0]).unwrap()));
+
+    for medium in [haze, cloud] {
+        let scene = SceneBuilder::new()
+            .add(Element::sphere().material(glow.clone()).scale(Vector3::new(10.0, 10.0, 10.0)))
+            .add_medium(Element::cube().scale(Vector3::new(4.0, 4.0, 4.0)).translate(Vector3::new(0.0, 0.0, -3.0)), medium)
+            .build();
+        let ray = scene.camera().ray_through(0.5, 0.5).unwrap();
+        let average = path_average(&scene, &ray, 64, 20000);
+        assert!((average - 1.0).abs() < 0.03, "{}", average);
+    }
+}
+
+#[test]
+fn projection_test() {
+    // a fisheye sees nothing outside its circle, not even the background
+    let mut camera = Camera::new();
+    camera.set_projection(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 });
+    let scene = SceneBuilder::new().camera(camera).add(Element::sphere().material(material(0.5)).scale(Vector3::new(3.0, 3.0, 3.0)).translate(Vector3::new(0.0, 0.0, -5.0))).build();
+    let mut settings = settings(8, 4, IntegratorType::Whitted);
+    settings.background = Vector3::new(1.0, 1.0, 1.0);
+    let image = Renderer::new(&scene, settings.clone()).render();
+    assert_eq!(image.get_pixel(0, 0).data, [0.0, 0.0, 0.0]);
+    assert_eq!(image.get_pixel(4, 0).data, [1.0, 1.0, 1.0]);
+
+    let aovs = Renderer::new(&scene, settings).render_aovs(&[Aov::Depth]);
+    assert_eq!(aovs[0].get(0, 0), vec![f32::INFINITY]);
+    assert!(aovs[0].get(4, 2)[0].is_finite());
+}
+
+#[test]
+fn aov_test() {
+    let mut red = material(0.0);
+    red.diffuse = MaterialParameter::new(Vector3::new(1.0, 0.0, 0.0));
+    let mut camera = Camera::new();
+    camera.set_eye(Vector3::new(0.0, 0.0, 5.0));
+    let scene = SceneBuilder::new()
+        .camera(camera)
+        .add(Element::square().material(material(0.5)).scale(Vector3::new(4.0, 4.0, 1.0)).translate(Vector3::new(0.0, 0.0, -1.0)))
+        .add(Element::sphere().material(red).scale(Vector3::new(0.5, 0.5, 0.5)))
+        .add(Element::sphere().material(material(0.5)).translate(Vector3::new(0.0, 0.0, -10.0)))
+        .build();
+
+    let mut aov_settings = settings(9, 9, IntegratorType::Whitted);
+    aov_settings.crop = Some(CropWindow { x_min: 0.0, y_min: 0.0, x_max: 1.0, y_max: 0.8 });
+    let buffers = Renderer::new(&scene, aov_settings).render_aovs(&Aov::ALL);
+    let get = |aov: Aov, x: u32, y: u32| buffers[Aov::ALL.iter().position(|&other| other == aov).unwrap()].get(x, y);
+    assert_eq!(buffers.len(), 7);
+    assert_eq!(buffers[1].channels.len(), 3);
+
+    // the sphere straight ahead
+    assert!((get(Aov::Depth, 4, 4)[0] - 4.5).abs() < 1e-6);
+    assert_eq!(get(Aov::Normal, 4, 4), vec![0.0, 0.0, 1.0]);
+    assert_eq!(get(Aov::Albedo, 4, 4), vec![1.0, 0.0, 0.0]);
+    assert_eq!(get(Aov::ObjectId, 4, 4), vec![2.0]);
+    assert_eq!(get(Aov::MaterialId, 4, 4), vec![2.0]);
+
+    // the square around it, whose material is the same as the far sphere's
+    assert_eq!(get(Aov::ObjectId, 2, 4), vec![1.0]);
+    assert_eq!(get(Aov::MaterialId, 2, 4), vec![1.0]);
+    assert_eq!(get(Aov::Normal, 2, 4), vec![0.0, 0.0, 1.0]);
+    let uv = get(Aov::Uv, 2, 4);
+    assert!(uv[0] > 0.0 && uv[0] < 0.5 && (uv[1] - 0.5).abs() < 1e-6, "got {:?}", uv);
+
+    // past the square there's nothing, and outside the crop window is as if nothing was hit
+    for &(x, y) in &[(0, 0), (4, 8)] {
+        assert_eq!(get(Aov::Depth, x, y), vec![f32::INFINITY]);
+        assert_eq!(get(Aov::ObjectId, x, y), vec![0.0]);
+        assert_eq!(get(Aov::Albedo, x, y), vec![0.0, 0.0, 0.0]);
+    }
+}
+
+#[test]
+fn denoise_test() {
+    // a path traced sphere lit by a glowing ceiling, noisy at few samples
+    let mut glow = material(0.0);
+    glow.emissive = MaterialParameter::new(Vector3::new(2.0, 2.0, 2.0));
+    let scene = SceneBuilder::new()
+        .add(Element::square().material(glow).scale(Vector3::new(6.0, 6.0, 1.0)).rotate(Vector3::unit_x(), consts::FRAC_PI_2).translate(Vector3::new(0.0, 3.0, -4.0)))
+        .add(Element::sphere().material(material(0.8)).translate(Vector3::new(0.0, 0.0, -4.0)))
+        .add(Element::square().material(material(0.4)).scale(Vector3::new(20.0, 20.0, 1.0)).translate(Vector3::new(0.0, 0.0, -6.0)))
+        .build();
+
+    let mut noisy = settings(24, 24, IntegratorType::Path);
+    noisy.samples = 4;
+    let reference = Renderer::new(&scene, RenderSettings { samples: 256, ..noisy.clone() }).render();
+    let renderer = Renderer::new(&scene, noisy);
+    let image = renderer.render();
+    let denoised = denoise(&image, &renderer.render_aovs(&DenoiseSettings::GUIDES), &DenoiseSettings::default());
+
+    let error = |image: &Framebuffer| image.pixels().zip(reference.pixels())
+        .map(|(pixel, expected)| pixel.data.iter().zip(&expected.data).map(|(a, b)| f64::from((a - b) * (a - b))).sum::<f64>())
+        .sum::<f64>();
+    assert!(error(&denoised) < error(&image) * 0.25, "noisy {}, denoised {}", error(&image), error(&denoised));
+}
+
+#[test]
+fn denoise_edge_test() {
+    // noise on two halves that differ in albedo is smoothed within each half, not across
+    let image = Framebuffer::from_fn(16, 8, |x, y| {
+        let base = if x < 8 { 0.2 } else { 0.8 };
+        let noise = if (x + y) % 2 == 0 { 0.1 } else { -0.1 };
+        Rgb([base + noise; 3])
+    });
+    let albedo_values: Vec<f32> = (0..16 * 8).map(|index| if index % 16 < 8 { 0.2 } else { 0.8 }).collect();
+    let albedo = AovBuffer { aov: Aov::Albedo, width: 16, height: 8, channels: vec![albedo_values; 3] };
+
+    let denoised = denoise(&image, &[albedo], &DenoiseSettings::default());
+    for (x, y, pixel) in denoised.enumerate_pixels() {
+        let expected = if x < 8 { 0.2 } else { 0.8 };
+        assert!((pixel.data[0] - expected).abs() < 0.03, "expected {} at ({}, {}), got {}", expected, x, y, pixel.data[0]);
+    }
+
+    // passes reaching past the 16 pixel wide image change nothing
+    let passes = |iterations: u32| denoise(&image, &[], &DenoiseSettings { iterations, ..DenoiseSettings::default() }).into_raw();
+    assert_eq!(passes(u32::MAX), passes(4));
+
+    // no passes leaves the image as it is
+    let untouched = denoise(&image, &[], &DenoiseSettings { iterations: 0, ..DenoiseSettings::default() });
+    assert_eq!(untouched.into_raw(), image.into_raw());
+}
+
+#[test]
+fn checkpoint_test() {
+    let scene = sample_scene();
+    let mut settings = settings(12, 8, IntegratorType::Path);
+    settings.samples = 6;
+    settings.seed = 5;
+    let straight = Renderer::new(&scene, settings.clone()).render().into_raw();
+
+    // stop after a couple of passes, and carry on from the saved film on more threads
+    let mut film = Film::new(&settings);
+    Renderer::new(&scene, settings.clone()).render_progressive(&mut film, |film| film.samples() < 2);
+    assert_eq!(film.samples(), 2);
+    let mut checkpoint = Vec::new();
+    film.write_checkpoint(&mut checkpoint, 7).unwrap();
+
+    let resumed_settings = RenderSettings { threads: 3, ..settings.clone() };
+    let mut resumed = Film::read_checkpoint(&mut checkpoint.as_slice(), &resumed_settings, 7).unwrap();
+    assert_eq!(resumed.samples(), 2);
+    Renderer::new(&scene, resumed_settings).render_progressive(&mut resumed, |_| true);
+    assert_eq!(resumed.samples(), 6);
+    assert_eq!(resumed.image().into_raw(), straight);
+}
+
+#[test]
+fn checkpoint_error_test() {
+    let settings = RenderSettings { samples: 4, ..settings(4, 4, IntegratorType::Path) };
+    let mut checkpoint = Vec::new();
+    Film::new(&settings).write_checkpoint(&mut checkpoint, 7).unwrap();
+    let error = |bytes: &[u8], settings: &RenderSettings| Film::read_checkpoint(&mut &bytes[..], settings, 7).unwrap_err().to_string();
+
+    assert_eq!(error(&checkpoint, &RenderSettings { seed: 1, ..settings.clone() }), "the checkpoint was rendered with a different seed");
+    assert_eq!(error(&checkpoint, &RenderSettings { width: 5, ..settings.clone() }), "the checkpoint was rendered with a different image size");
+    assert_eq!(error(&checkpoint, &RenderSettings { integrator: IntegratorType::Whitted, ..settings.clone() }),
+        "the checkpoint was rendered with a different integrator");
+    assert_eq!(Film::read_checkpoint(&mut checkpoint.as_slice(), &settings, 8).unwrap_err().to_string(),
+        "the checkpoint was rendered with a different scene");
+    assert_eq!(error(&checkpoint[..checkpoint.len() - 1], &settings), "the checkpoint ends too soon");
+    assert_eq!(error(b"P6 4 4 255", &settings), "not a checkpoint");
+
+    // more samples can be asked for than were to begin with
+    assert!(Film::read_checkpoint(&mut checkpoint.as_slice(), &RenderSettings { samples: 16, ..settings }, 7).is_ok());
+}
+
+#[test]
+fn budget_test() {
+    let scene = sample_scene();
+    let settings = RenderSettings { samples: 64, ..settings(12, 8, IntegratorType::Path) };
+    let renderer = Renderer::new(&scene, settings.clone());
+
+    // no time at all still takes a pass
+    let mut film = Film::new(&settings);
+    let report = renderer.render_with_budget(&mut film, &RenderBudget { time: Some(Duration::from_secs(0)), noise: None }, |_| true);
+    assert_eq!((report.samples, report.passes, report.stopped), (1, 1, StopReason::Time));
+    assert_eq!(report.total_samples(), 12 * 8);
+
+    // the noise goes down with more samples, until it's under the threshold
+    let mut film = Film::new(&settings);
+    let coarse = RenderBudget { time: None, noise: Some(0.5) };
+    let report = renderer.render_with_budget(&mut film, &coarse, |_| true);
+    assert_eq!(report.stopped, StopReason::Noise);
+    assert!(report.noise <= 0.5 && report.samples < 64, "{}", report);
+    let fine = RenderBudget { time: None, noise: Some(report.noise / 2.0) };
+    let finer = renderer.render_with_budget(&mut film, &fine, |_| true);
+    assert!(finer.samples > report.samples && finer.noise < report.noise, "{}", finer);
+    assert_eq!(finer.passes, finer.samples - report.samples);
+
+    // a threshold out of reach stops at the samples asked for
+    let mut film = Film::new(&settings);
+    let report = renderer.render_with_budget(&mut film, &RenderBudget { time: None, noise: Some(1e-9) }, |_| true);
+    assert_eq!((report.samples, report.stopped), (64, StopReason::Samples));
+    assert_eq!(film.image().into_raw(), renderer.render().into_raw());
+}
+
+#[test]
+fn noise_test() {
+    // without anything random, every sample of a pixel is the same
+    let mut settings = settings(8, 8, IntegratorType::Whitted);
+    settings.samples = 2;
+    let mut film = Film::new(&settings);
+    assert_eq!(film.noise(), f64::INFINITY);
+    for (x, y) in (0..8).flat_map(|y| (0..8).map(move |x| (x, y))) {
+        for _ in 0..2 {
+            film.add_sample(x, y, Vector3::new(0.5, 0.5, 0.5), film.sampler(x, y));
+        }
+    }
+    film.end_pass();
+    film.end_pass();
+    assert_eq!(film.noise(), 0.0);
+
+    // a pixel that's sometimes bright and sometimes dark is noisy
+    let mut noisy = Film::new(&settings);
+    for (x, y) in (0..8).flat_map(|y| (0..8).map(move |x| (x, y))) {
+        noisy.add_sample(x, y, Vector3::new(1.0, 1.0, 1.0), noisy.sampler(x, y));
+        noisy.add_sample(x, y, Vector3::zero(), noisy.sampler(x, y));
+    }
+    noisy.end_pass();
+    noisy.end_pass();
+    assert!((noisy.noise() - 1.0).abs() < 1e-9, "{}", noisy.noise());
+}
@@ -0,0 +1,77 @@
+use cgmath::{ElementWise, Vector3, Zero};
+
+use super::*;
+
+/// Classic recursive ray tracing, as in the SBT ray tracer: Phong lighting
+/// with shadows from every light, plus mirror reflection and refraction
+/// followed up to `max_depth` bounces. Media dim what's behind them and glow
+/// with the ambient light and the lights they scatter, as if every light
+/// reached all of them.
+pub struct WhittedIntegrator {
+    max_depth: u32,
+    background: Vector3<f64>,
+}
+
+impl WhittedIntegrator {
+    pub fn new(settings: &RenderSettings) -> WhittedIntegrator {
+        WhittedIntegrator { max_depth: settings.max_depth, background: settings.background }
+    }
+
+    fn trace(&self, scene: &Scene, ray: &Ray, depth: u32) -> Vector3<f64> {
+        stats::count_depth(depth);
+        let mut isect = Intersect::new();
+        let hit = scene.intersect(ray, &mut isect);
+        let color = if hit { self.shade(scene, ray, &isect, depth) } else { self.background };
+        if scene.media().is_empty() {
+            return color;
+        }
+
+        let attenuation = scene.media().attenuation(ray, if hit { isect.t } else { f64::INFINITY });
+        attenuation.transmittance.mul_element_wise(color) + attenuation.scattered.mul_element_wise(in_scattered(scene))
+    }
+
+    /// Light leaving the hit back along `ray`
+    fn shade(&self, scene: &Scene, ray: &Ray, isect: &Intersect, depth: u32) -> Vector3<f64> {
+        let material = surface_material(isect);
+        let mut color = material.emissive.value(isect)
+            + material.ambient.value(isect).mul_element_wise(scene.ambient())
+            + direct_lighting(scene, ray, isect, &material);
+
+        if depth >= self.max_depth {
+            return color;
+        }
+
+        let p = ray.at(isect.t);
+        let d = ray.direction();
+
+        let reflective = material.reflective.value(isect);
+        if !reflective.is_zero() {
+            let n = facing(isect.n, d);
+            let reflected = spawn_ray(p, n, reflect(d, n), RayType::Reflection);
+            color += reflective.mul_element_wise(self.trace(scene, &reflected, depth + 1));
+        }
+
+        let transmissive = material.transmissive.value(isect);
+        if !transmissive.is_zero() {
+            if let Some(direction) = refract(d, isect.n, material.index) {
+                let refracted = spawn_ray(p, isect.n, direction, RayType::Refraction);
+                color += transmissive.mul_element_wise(self.trace(scene, &refracted, depth + 1));
+            }
+        }
+
+        color
+    }
+}
+
+/// Light the media scatter back along a ray, for each unit of scattering: the
+/// ambient light, and the lights as seen through a medium scattering evenly in
+/// every direction
+fn in_scattered(scene: &Scene) -> Vector3<f64> {
+    scene.lights().iter().fold(scene.ambient(), |total, light| total + light.color() / 4.0)
+}
+
+impl Integrator for WhittedIntegrator {
+    fn radiance(&self, scene: &Scene, ray: &Ray, _sampler: &mut Sampler) -> Vector3<f64> {
+        self.trace(scene, ray, 0)
+    }
+}
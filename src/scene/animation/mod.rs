@@ -0,0 +1,347 @@
+//! Keyframed changes to a scene over the frames of an animation. Every
+//! property that changes has a track of keys, values at given frames, and
+//! between keys the value follows a straight line or a smooth Bézier curve.
+//! Before its first key and after its last a track holds their values.
+
+#[cfg(test)]
+mod tests;
+
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use std::collections::BTreeMap;
+use std::ops::{Add, Mul, RangeInclusive, Sub};
+
+use super::{Camera, Material, Scene, Transformation};
+use super::objects::{Light, LightType};
+
+/// How a value goes from a key to the next
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// In a straight line, at a steady rate
+    Linear,
+    /// Along a cubic Bézier curve whose handles follow the keys either side,
+    /// so the value passes smoothly through keys and eases into and out of
+    /// the first and last
+    Bezier,
+}
+
+impl Interpolation {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Interpolation::Linear => "linear",
+            Interpolation::Bezier => "bezier",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        match name {
+            "linear" => Some(Interpolation::Linear),
+            "bezier" => Some(Interpolation::Bezier),
+            _ => None,
+        }
+    }
+}
+
+/// Values that can be keyed, anything that can be scaled and added up
+pub trait Keyable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> {}
+
+impl<T> Keyable for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {}
+
+/// A value a track takes at a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe<T> {
+    pub frame: f64,
+    pub value: T,
+    /// How the value goes on to the next key
+    pub interpolation: Interpolation,
+}
+
+/// The keys of one property, in order of their frames
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Keyable> Track<T> {
+    pub fn new(frame: f64, value: T, interpolation: Interpolation) -> Track<T> {
+        Track { keys: vec![Keyframe { frame, value, interpolation }] }
+    }
+
+    /// Adds a key, replacing any already at `frame`
+    pub fn key(&mut self, frame: f64, value: T, interpolation: Interpolation) {
+        let key = Keyframe { frame, value, interpolation };
+        match self.keys.iter().position(|key| key.frame >= frame) {
+            Some(index) if self.keys[index].frame == frame => self.keys[index] = key,
+            Some(index) => self.keys.insert(index, key),
+            None => self.keys.push(key),
+        }
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn value_at(&self, frame: f64) -> T {
+        let keys = &self.keys;
+        let index = match keys.iter().position(|key| key.frame > frame) {
+            Some(0) => return keys[0].value,
+            Some(next) => next - 1,
+            None => return keys[keys.len() - 1].value,
+        };
+
+        let (from, to) = (&keys[index], &keys[index + 1]);
+        let span = to.frame - from.frame;
+        let t = (frame - from.frame) / span;
+        match from.interpolation {
+            Interpolation::Linear => from.value + (to.value - from.value) * t,
+            Interpolation::Bezier => {
+                let (first, second) = self.handles(index);
+                let s = 1.0 - t;
+                from.value * (s * s * s) + first * (3.0 * s * s * t) + second * (3.0 * s * t * t) + to.value * (t * t * t)
+            },
+        }
+    }
+
+    /// The keys and the handles of the curves between them. The track never
+    /// goes outside of these, as a curve stays between its key and handles.
+    pub fn control_points(&self) -> Vec<T> {
+        let mut points = Vec::new();
+        for (index, key) in self.keys.iter().enumerate() {
+            points.push(key.value);
+            if key.interpolation == Interpolation::Bezier && index + 1 < self.keys.len() {
+                let (first, second) = self.handles(index);
+                points.push(first);
+                points.push(second);
+            }
+        }
+        points
+    }
+
+    /// The handles of the curve from the key at `index` to the next, a third
+    /// of the way to the next key along the slope through each key
+    fn handles(&self, index: usize) -> (T, T) {
+        let (from, to) = (&self.keys[index], &self.keys[index + 1]);
+        let span = to.frame - from.frame;
+        (from.value + self.slope(index) * (span / 3.0), to.value - self.slope(index + 1) * (span / 3.0))
+    }
+
+    /// Change of the value per frame through the key at `index`, from the
+    /// keys either side of it, and none at the first and last keys
+    fn slope(&self, index: usize) -> T {
+        let keys = &self.keys;
+        if index == 0 || index + 1 == keys.len() {
+            return keys[index].value * 0.0;
+        }
+        let (before, after) = (&keys[index - 1], &keys[index + 1]);
+        (after.value - before.value) * (1.0 / (after.frame - before.frame))
+    }
+}
+
+/// The value of a track that may not be keyed at all
+fn value_at<T: Keyable>(track: &Option<Track<T>>, frame: f64) -> Option<T> {
+    track.as_ref().map(|track| track.value_at(frame))
+}
+
+/// The first and last frames a track is keyed at
+fn keyed_frames<T>(track: &Option<Track<T>>) -> Option<(f64, f64)> {
+    track.as_ref().map(|track| (track.keys[0].frame, track.keys[track.keys.len() - 1].frame))
+}
+
+/// Keyed settings of the camera, which replace those it was given
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraTracks {
+    pub position: Option<Track<Vector3<f64>>>,
+    pub view_dir: Option<Track<Vector3<f64>>>,
+    pub up_dir: Option<Track<Vector3<f64>>>,
+    /// Vertical field of view in degrees
+    pub fov: Option<Track<f64>>,
+}
+
+impl CameraTracks {
+    pub fn position_at(&self, frame: f64) -> Option<Vector3<f64>> {
+        value_at(&self.position, frame)
+    }
+
+    pub fn view_dir_at(&self, frame: f64) -> Option<Vector3<f64>> {
+        value_at(&self.view_dir, frame)
+    }
+
+    pub fn up_dir_at(&self, frame: f64) -> Option<Vector3<f64>> {
+        value_at(&self.up_dir, frame)
+    }
+
+    pub fn fov_at(&self, frame: f64) -> Option<f64> {
+        value_at(&self.fov, frame)
+    }
+}
+
+/// Keyed settings of a light. Point lights move by their position and
+/// directional lights turn by their direction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LightTracks {
+    pub position: Option<Track<Vector3<f64>>>,
+    pub direction: Option<Track<Vector3<f64>>>,
+    pub color: Option<Track<Vector3<f64>>>,
+}
+
+impl LightTracks {
+    /// `light` as it is at `frame`
+    pub fn apply(&self, light: &Light, frame: f64) -> Light {
+        let light_type = match *light.light_type() {
+            LightType::PointLight { pos, a, b, c } => LightType::PointLight { pos: value_at(&self.position, frame).unwrap_or(pos), a, b, c },
+            LightType::DirectionalLight { orientation } =>
+                LightType::DirectionalLight { orientation: value_at(&self.direction, frame).unwrap_or(orientation) },
+        };
+        Light::new(light_type, value_at(&self.color, frame).unwrap_or_else(|| light.color()))
+    }
+}
+
+/// Keyed movement of an element, in its own space, within the
+/// transformations around it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransformTracks {
+    pub translate: Option<Track<Vector3<f64>>>,
+    /// Rotation about the axis `(x, y, z)` by the angle `w` in radians, which
+    /// turns the whole way when keyed from 0 to tau
+    pub rotate: Option<Track<Vector4<f64>>>,
+    pub scale: Option<Track<Vector3<f64>>>,
+}
+
+impl TransformTracks {
+    /// The element's movement at `frame`, scaled, then rotated, then
+    /// translated
+    pub fn matrix_at(&self, frame: f64) -> Matrix4<f64> {
+        let mut matrix = Matrix4::identity();
+        if let Some(offset) = value_at(&self.translate, frame) {
+            matrix = matrix * Transformation::Translate(offset).matrix();
+        }
+        if let Some(rotation) = value_at(&self.rotate, frame) {
+            let axis = rotation.truncate();
+            if axis.magnitude2() > 0.0 {
+                matrix = matrix * Transformation::Rotate(axis, rotation.w).matrix();
+            }
+        }
+        if let Some(factors) = value_at(&self.scale, frame) {
+            matrix = matrix * Transformation::Scale(factors).matrix();
+        }
+        matrix
+    }
+}
+
+/// Keyed scalar parameters of a material
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaterialTracks {
+    pub shininess: Option<Track<f64>>,
+    pub index: Option<Track<f64>>,
+}
+
+impl MaterialTracks {
+    /// `material` as it is at `frame`
+    pub fn apply(&self, material: &Material, frame: f64) -> Material {
+        let mut material = material.clone();
+        if let Some(shininess) = value_at(&self.shininess, frame) {
+            material.shininess = shininess;
+        }
+        if let Some(index) = value_at(&self.index, frame) {
+            material.index = index;
+        }
+        material
+    }
+}
+
+/// Everything keyed in a scene. Lights, elements and materials are keyed by
+/// the names they're given in the scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    /// Frames rendered when no others are asked for, those from the first key
+    /// to the last when not given
+    pub frames: Option<RangeInclusive<u32>>,
+    pub camera: CameraTracks,
+    pub lights: BTreeMap<String, LightTracks>,
+    pub elements: BTreeMap<String, TransformTracks>,
+    pub materials: BTreeMap<String, MaterialTracks>,
+}
+
+impl Animation {
+    /// An animation that doesn't change anything
+    pub fn new() -> Animation {
+        Animation {
+            frames: None,
+            camera: CameraTracks::default(),
+            lights: BTreeMap::new(),
+            elements: BTreeMap::new(),
+            materials: BTreeMap::new(),
+        }
+    }
+
+    /// Whether nothing is keyed
+    pub fn is_empty(&self) -> bool {
+        self.keyed_frames().is_none()
+    }
+
+    /// Whether the scene's objects change between frames, rather than just
+    /// its camera and lights
+    pub fn moves_objects(&self) -> bool {
+        !self.elements.is_empty() || !self.materials.is_empty()
+    }
+
+    /// The frames to render when no others are asked for
+    pub fn frame_range(&self) -> RangeInclusive<u32> {
+        self.frames.clone()
+            .or_else(|| self.keyed_frames())
+            .unwrap_or(1..=1)
+    }
+
+    /// The frames from the first key of any track to the last, rounded out to
+    /// whole frames
+    fn keyed_frames(&self) -> Option<RangeInclusive<u32>> {
+        let camera = &self.camera;
+        let mut spans = vec![keyed_frames(&camera.position), keyed_frames(&camera.view_dir), keyed_frames(&camera.up_dir), keyed_frames(&camera.fov)];
+        for light in self.lights.values() {
+            spans.extend(&[keyed_frames(&light.position), keyed_frames(&light.direction), keyed_frames(&light.color)]);
+        }
+        for element in self.elements.values() {
+            spans.extend(&[keyed_frames(&element.translate), keyed_frames(&element.rotate), keyed_frames(&element.scale)]);
+        }
+        for material in self.materials.values() {
+            spans.extend(&[keyed_frames(&material.shininess), keyed_frames(&material.index)]);
+        }
+
+        spans.into_iter().flatten()
+            .fold(None, |frames: Option<(f64, f64)>, (first, last)| match frames {
+                Some((start, end)) => Some((start.min(first), end.max(last))),
+                None => Some((first, last)),
+            })
+            .map(|(first, last)| (first.floor().max(0.0) as u32)..=(last.ceil().max(0.0) as u32))
+    }
+}
+
+impl Default for Animation {
+    fn default() -> Animation {
+        Animation::new()
+    }
+}
+
+/// A scene along with the animation that changes it from frame to frame
+pub trait AnimatedScene {
+    fn animation(&self) -> &Animation;
+
+    /// The whole scene as it is at `frame`
+    fn scene_at(&self, frame: f64) -> Scene;
+
+    fn camera_at(&self, frame: f64) -> Camera;
+
+    fn lights_at(&self, frame: f64) -> Vec<Light>;
+
+    /// `scene`, as it was at another frame, moved on to `frame`. When the
+    /// animation only changes the camera and lights those are all that's
+    /// replaced, and the objects and their hierarchy are kept.
+    fn advance(&self, mut scene: Scene, frame: f64) -> Scene {
+        if self.animation().moves_objects() {
+            return self.scene_at(frame);
+        }
+        scene.set_camera(self.camera_at(frame));
+        scene.set_lights(self.lights_at(frame));
+        scene
+    }
+}
@@ -0,0 +1,128 @@
+use std::f64::consts;
+
+use cgmath::{Vector3, Vector4};
+
+use scene::{Material, MaterialParameter};
+use scene::objects::{Light, LightType};
+
+use super::*;
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+}
+
+#[test]
+fn linear_track_test() {
+    let mut track = Track::new(10.0, 2.0, Interpolation::Linear);
+    track.key(20.0, 4.0, Interpolation::Linear);
+    track.key(0.0, -2.0, Interpolation::Linear);
+    assert_eq!(track.keys().iter().map(|key| key.frame).collect::<Vec<_>>(), vec![0.0, 10.0, 20.0]);
+
+    assert_close(track.value_at(5.0), 0.0);
+    assert_close(track.value_at(10.0), 2.0);
+    assert_close(track.value_at(12.5), 2.5);
+
+    // the ends hold their values
+    assert_close(track.value_at(-4.0), -2.0);
+    assert_close(track.value_at(30.0), 4.0);
+
+    // a key at a frame already keyed replaces it
+    track.key(10.0, 0.0, Interpolation::Linear);
+    assert_eq!(track.keys().len(), 3);
+    assert_close(track.value_at(15.0), 2.0);
+}
+
+#[test]
+fn bezier_track_test() {
+    let mut track = Track::new(0.0, Vector3::new(0.0, 0.0, 0.0), Interpolation::Bezier);
+    track.key(10.0, Vector3::new(10.0, 4.0, 0.0), Interpolation::Bezier);
+    track.key(20.0, Vector3::new(20.0, 0.0, 0.0), Interpolation::Linear);
+
+    // keys are passed through exactly
+    assert_eq!(track.value_at(10.0), Vector3::new(10.0, 4.0, 0.0));
+
+    // x keeps its pace through the middle key, then eases into the last
+    let value = track.value_at(15.0);
+    assert_close(value.x, 16.25);
+    assert_close(value.y, 2.0);
+
+    // and eases out of the first key
+    let start = track.value_at(1.0);
+    assert!(start.x < 1.0 && start.y < 0.4, "{:?}", start);
+
+    // a span is shaped by the interpolation of the key it starts from
+    let mut mixed = Track::new(0.0, 0.0, Interpolation::Linear);
+    mixed.key(10.0, 10.0, Interpolation::Bezier);
+    assert_close(mixed.value_at(2.0), 2.0);
+
+    // the keys of linear spans, with handles where the track curves
+    assert_eq!(mixed.control_points(), vec![0.0, 10.0]);
+    let mut curve = Track::new(0.0, 0.0, Interpolation::Bezier);
+    curve.key(3.0, 6.0, Interpolation::Bezier);
+    curve.key(6.0, 0.0, Interpolation::Bezier);
+    assert_eq!(curve.control_points(), vec![0.0, 0.0, 6.0, 6.0, 6.0, 0.0, 0.0]);
+}
+
+#[test]
+fn transform_tracks_test() {
+    let tracks = TransformTracks {
+        translate: Some(Track::new(0.0, Vector3::new(2.0, 0.0, 0.0), Interpolation::Linear)),
+        rotate: Some(Track::new(0.0, Vector4::new(0.0, 0.0, 1.0, consts::FRAC_PI_2), Interpolation::Linear)),
+        scale: Some(Track::new(0.0, Vector3::new(3.0, 3.0, 3.0), Interpolation::Linear)),
+    };
+
+    // scaled, then rotated, then translated
+    let point = tracks.matrix_at(0.0) * Vector4::new(1.0, 0.0, 0.0, 1.0);
+    assert_close(point.x, 2.0);
+    assert_close(point.y, 3.0);
+    assert_close(point.z, 0.0);
+
+    assert_eq!(TransformTracks::default().matrix_at(4.0), Matrix4::identity());
+}
+
+#[test]
+fn light_and_material_tracks_test() {
+    let point = Light::new(LightType::PointLight { pos: Vector3::new(0.0, 1.0, 0.0), a: 1.0, b: 0.0, c: 0.0 }, Vector3::new(1.0, 1.0, 1.0));
+    let mut tracks = LightTracks {
+        position: Some(Track::new(0.0, Vector3::new(0.0, 0.0, 0.0), Interpolation::Linear)),
+        ..LightTracks::default()
+    };
+    tracks.position.as_mut().unwrap().key(4.0, Vector3::new(4.0, 0.0, 0.0), Interpolation::Linear);
+    tracks.color = Some(Track::new(0.0, Vector3::new(0.5, 0.5, 0.5), Interpolation::Linear));
+    let moved = tracks.apply(&point, 1.0);
+    assert_eq!(*moved.light_type(), LightType::PointLight { pos: Vector3::new(1.0, 0.0, 0.0), a: 1.0, b: 0.0, c: 0.0 });
+    assert_eq!(moved.color(), Vector3::new(0.5, 0.5, 0.5));
+
+    // directional lights don't have a position to move
+    let directional = Light::new(LightType::DirectionalLight { orientation: Vector3::new(0.0, -1.0, 0.0) }, Vector3::new(1.0, 1.0, 1.0));
+    assert_eq!(tracks.apply(&directional, 1.0).light_type(), directional.light_type());
+
+    let mut material = Material::new();
+    material.diffuse = MaterialParameter::new(Vector3::new(0.2, 0.4, 0.6));
+    let tracks = MaterialTracks { shininess: Some(Track::new(2.0, 64.0, Interpolation::Linear)), index: None };
+    let keyed = tracks.apply(&material, 0.0);
+    assert_eq!(keyed.shininess, 64.0);
+    assert_eq!(keyed.index, material.index);
+    assert_eq!(keyed.diffuse, material.diffuse);
+}
+
+#[test]
+fn frame_range_test() {
+    let mut animation = Animation::new();
+    assert!(animation.is_empty());
+    assert_eq!(animation.frame_range(), 1..=1);
+
+    animation.camera.fov = Some(Track::new(2.5, 30.0, Interpolation::Linear));
+    let mut tracks = MaterialTracks::default();
+    let mut shininess = Track::new(4.0, 1.0, Interpolation::Linear);
+    shininess.key(11.2, 8.0, Interpolation::Linear);
+    tracks.shininess = Some(shininess);
+    animation.materials.insert("gold".to_string(), tracks);
+    assert!(!animation.is_empty());
+    assert!(animation.moves_objects());
+    assert_eq!(animation.frame_range(), 2..=12);
+
+    // frames given replace the keyed ones
+    animation.frames = Some(1..=48);
+    assert_eq!(animation.frame_range(), 1..=48);
+}
@@ -0,0 +1,311 @@
+//! Builds scenes from Rust rather than from scene files. Elements nest,
+//! transform and take their materials the same way they do in a `.ray` file,
+//! so a scene built here is the same as the one the parser builds from the
+//! equivalent `.ray` text.
+//!
+//! ```
+//! # extern crate cgmath;
+//! # extern crate ray_rs;
+//! # use cgmath::Vector3;
+//! # use ray_rs::scene::{Material, MaterialParameter};
+//! # use ray_rs::scene::builder::{Element, SceneBuilder};
+//! # use ray_rs::scene::objects::{Light, LightType};
+//! # fn main() {
+//! let mut red = Material::new();
+//! red.diffuse = MaterialParameter::new(Vector3::new(1.0, 0.0, 0.0));
+//!
+//! let scene = SceneBuilder::new()
+//!     .add_light(Light::new(LightType::DirectionalLight { orientation: -Vector3::unit_y() }, Vector3::new(1.0, 1.0, 1.0)))
+//!     .add(Element::sphere().material(red).translate(Vector3::new(0.0, 0.0, -5.0)))
+//!     .build();
+//! assert_eq!(scene.object_count(), 1);
+//! # }
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Zero};
+
+use std::sync::Arc;
+
+use super::{mat3_from_mat4, Camera, Material, Scene, SceneObject, SceneSettings, TransformNode, Transformation};
+use super::bvh::Aggregate;
+use super::medium::{Media, Medium, Volume};
+use super::objects::{Cone, Cylinder, Instance, Light, Mesh, SceneBox, Sphere, Square, Trimesh};
+
+pub struct SceneBuilder {
+    camera: Camera,
+    lights: Vec<Light>,
+    ambient: Vector3<f64>,
+    settings: SceneSettings,
+    /// Material for the elements added from now on that don't have their own
+    material: Material,
+    elements: Vec<(Element, Material)>,
+    fog: Option<Medium>,
+    /// Elements bounding a medium each
+    media: Vec<(Element, Medium)>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder {
+            camera: Camera::new(),
+            lights: Vec::new(),
+            ambient: Vector3::zero(),
+            settings: SceneSettings::default(),
+            material: Material::new(),
+            elements: Vec::new(),
+            fog: None,
+            media: Vec::new(),
+        }
+    }
+
+    pub fn camera(mut self, camera: Camera) -> SceneBuilder {
+        self.camera = camera;
+        self
+    }
+
+    /// Adds ambient light, like an `ambient_light` block
+    pub fn ambient_light(mut self, color: Vector3<f64>) -> SceneBuilder {
+        self.ambient += color;
+        self
+    }
+
+    pub fn add_light(mut self, light: Light) -> SceneBuilder {
+        self.lights.push(light);
+        self
+    }
+
+    /// How the scene would like to be rendered, like a `render_settings` block
+    pub fn settings(mut self, settings: SceneSettings) -> SceneBuilder {
+        self.settings = settings;
+        self
+    }
+
+    /// Gives the elements added after this the material, unless they have
+    /// their own, like a top level `material = ...;` statement
+    pub fn material(mut self, material: Material) -> SceneBuilder {
+        self.material = material;
+        self
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, element: Element) -> SceneBuilder {
+        self.elements.push((element, self.material.clone()));
+        self
+    }
+
+    /// Fills the whole scene with the medium, like a `fog` block
+    pub fn fog(mut self, medium: Medium) -> SceneBuilder {
+        self.fog = Some(medium);
+        self
+    }
+
+    /// Fills the inside of the element's objects with the medium, like a
+    /// `medium` block. They bound it without being rendered themselves, so
+    /// should be closed.
+    pub fn add_medium(mut self, element: Element, medium: Medium) -> SceneBuilder {
+        self.media.push((element, medium));
+        self
+    }
+
+    /// Builds the scene's objects and the hierarchy over them
+    ///
+    /// # Panics
+    ///
+    /// If the transformations around an element together can't be inverted,
+    /// like scales that multiply to nothing
+    pub fn build(self) -> Scene {
+        let root = TransformNode::root();
+        let mut objects = Vec::new();
+        for (element, material) in &self.elements {
+            element.create_objects(&root, material, &mut objects);
+        }
+
+        let mut media = Media::new();
+        media.fog = self.fog;
+        for (element, medium) in self.media {
+            let mut boundary = Vec::new();
+            element.create_objects(&root, &Material::new(), &mut boundary);
+            media.volumes.push(Volume::new(boundary, medium));
+        }
+
+        Scene::new(self.camera, self.lights, self.ambient, objects).with_settings(self.settings).with_media(media)
+    }
+}
+
+impl Default for SceneBuilder {
+    fn default() -> SceneBuilder {
+        SceneBuilder::new()
+    }
+}
+
+/// A part of the scene: an object, a group of elements or an instance of a
+/// prototype, along with its material and transformations
+#[derive(Clone)]
+pub struct Element {
+    element_type: ElementType,
+    material: Option<Material>,
+    /// Outermost first, the order they're applied to the element's parent in
+    transformations: Vec<Transformation>,
+}
+
+#[derive(Clone)]
+enum ElementType {
+    Sphere,
+    Box,
+    Square,
+    Cylinder { capped: bool },
+    Cone { capped: bool, height: f64, bottom_radius: f64, top_radius: f64 },
+    Trimesh(Arc<Mesh>),
+    Group(Vec<Element>),
+    Instance(Arc<Aggregate>),
+    Custom(Arc<dyn Fn(TransformNode, Arc<Material>) -> Box<dyn SceneObject> + Send + Sync>),
+}
+
+impl Element {
+    fn new(element_type: ElementType) -> Element {
+        Element { element_type, material: None, transformations: Vec::new() }
+    }
+
+    /// Unit sphere centered on the origin
+    pub fn sphere() -> Element {
+        Element::new(ElementType::Sphere)
+    }
+
+    /// Unit cube centered on the origin, the `box` of `.ray` files
+    pub fn cube() -> Element {
+        Element::new(ElementType::Box)
+    }
+
+    /// Unit square in the xy plane, centered on the origin
+    pub fn square() -> Element {
+        Element::new(ElementType::Square)
+    }
+
+    /// Unit cylinder along the z axis, from 0 to 1
+    pub fn cylinder(capped: bool) -> Element {
+        Element::new(ElementType::Cylinder { capped })
+    }
+
+    /// Cone along the z axis, from `bottom_radius` at 0 to `top_radius` at `height`
+    pub fn cone(capped: bool, height: f64, bottom_radius: f64, top_radius: f64) -> Element {
+        Element::new(ElementType::Cone { capped, height, bottom_radius, top_radius })
+    }
+
+    /// Triangle mesh, which may be shared with other elements. Meshes with
+    /// vertex colors take their diffuse color from them.
+    pub fn trimesh(mesh: Arc<Mesh>) -> Element {
+        Element::new(ElementType::Trimesh(mesh))
+    }
+
+    /// Elements transformed together, which take the group's material unless
+    /// they have their own
+    pub fn group(elements: Vec<Element>) -> Element {
+        Element::new(ElementType::Group(elements))
+    }
+
+    /// A primitive defined outside this crate, created by `create` from the
+    /// element's transform and material
+    pub fn custom<F>(create: F) -> Element
+        where F: Fn(TransformNode, Arc<Material>) -> Box<dyn SceneObject> + Send + Sync + 'static
+    {
+        Element::new(ElementType::Custom(Arc::new(create)))
+    }
+
+    /// Places the prototype's objects, which keep their own materials
+    pub fn instance(prototype: &Prototype) -> Element {
+        Element::new(ElementType::Instance(prototype.aggregate.clone()))
+    }
+
+    pub fn material(mut self, material: Material) -> Element {
+        self.material = Some(material);
+        self
+    }
+
+    /// Moves the element and everything applied to it so far, like
+    /// `translate(x, y, z, element)`
+    pub fn translate(self, offset: Vector3<f64>) -> Element {
+        self.transformed(Transformation::Translate(offset))
+    }
+
+    /// Rotates the element about `axis` by `angle` radians
+    ///
+    /// # Panics
+    ///
+    /// If the axis is zero
+    pub fn rotate(self, axis: Vector3<f64>, angle: f64) -> Element {
+        assert!(axis.magnitude2() != 0.0, "rotation axis can't be zero");
+        self.transformed(Transformation::Rotate(axis, angle))
+    }
+
+    /// Scales the element by separate factors along each axis
+    ///
+    /// # Panics
+    ///
+    /// If any of the factors are zero
+    pub fn scale(self, factors: Vector3<f64>) -> Element {
+        assert!(factors.x != 0.0 && factors.y != 0.0 && factors.z != 0.0, "scale factors can't be zero");
+        self.transformed(Transformation::Scale(factors))
+    }
+
+    /// Transforms the element by an arbitrary matrix
+    ///
+    /// # Panics
+    ///
+    /// If the matrix can't be inverted
+    pub fn transform(self, matrix: Matrix4<f64>) -> Element {
+        assert!(matrix.determinant() != 0.0 && mat3_from_mat4(matrix).determinant() != 0.0, "transform matrix must be invertible");
+        self.transformed(Transformation::Matrix(matrix))
+    }
+
+    fn transformed(mut self, transformation: Transformation) -> Element {
+        self.transformations.insert(0, transformation);
+        self
+    }
+
+    fn create_objects(&self, parent: &TransformNode, material: &Material, objects: &mut Vec<Box<dyn SceneObject>>) {
+        let mut transform = parent.clone();
+        for transformation in &self.transformations {
+            transform = transform.create_child(transformation.matrix())
+                .expect("the transformations around an element must be invertible together");
+        }
+        let material = self.material.as_ref().unwrap_or(material);
+
+        objects.push(match self.element_type {
+            ElementType::Sphere => Box::new(Sphere::new(transform, Arc::new(material.clone()))),
+            ElementType::Box => Box::new(SceneBox::new(transform, Arc::new(material.clone()))),
+            ElementType::Square => Box::new(Square::new(transform, Arc::new(material.clone()))),
+            ElementType::Cylinder { capped } => Box::new(Cylinder::new(transform, Arc::new(material.clone()), capped)),
+            ElementType::Cone { capped, height, bottom_radius, top_radius } =>
+                Box::new(Cone::new(transform, Arc::new(material.clone()), capped, height, bottom_radius, top_radius)),
+            ElementType::Trimesh(ref mesh) => Box::new(Trimesh::new(transform, Arc::new(material.for_mesh(mesh)), mesh.clone())),
+            ElementType::Instance(ref aggregate) => Box::new(Instance::new(transform, aggregate.clone())),
+            ElementType::Custom(ref create) => create(transform, Arc::new(material.clone())),
+            ElementType::Group(ref elements) => {
+                for element in elements {
+                    element.create_objects(&transform, material, objects);
+                }
+                return;
+            },
+        });
+    }
+}
+
+/// Objects built once and shared by every instance placing them, like an
+/// element declared with `define`
+#[derive(Clone)]
+pub struct Prototype {
+    aggregate: Arc<Aggregate>,
+}
+
+impl Prototype {
+    /// Builds the element's objects in their own space. Objects without a
+    /// material get the default one.
+    pub fn new(element: &Element) -> Prototype {
+        let mut objects = Vec::new();
+        element.create_objects(&TransformNode::root(), &Material::new(), &mut objects);
+        Prototype { aggregate: Arc::new(Aggregate::new(objects)) }
+    }
+}
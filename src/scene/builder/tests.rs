@@ -0,0 +1,218 @@
+use std::f64::consts;
+
+use cgmath::{Matrix4, Vector2, Vector3};
+
+use parser::{MemoryResolver, Parser, RayParser};
+use scene::MaterialParameter;
+use scene::objects::{intersect_transformed, BoundingBox, Intersect, LightType, Ray, TrimeshFace};
+
+use super::*;
+
+static SCENE: &str = "SBT-raytracer 1.0
+
+camera {
+    position = (1, 2, 8);
+    viewdir = (0, -0.2, -1);
+    updir = (0, 1, 0);
+    fov = 50;
+}
+
+ambient_light { color = (0.1, 0.1, 0.1); }
+point_light {
+    position = (2, 4, 3);
+    color = (1, 0.9, 0.8);
+    constant_attenuation_coeff = 0.5;
+    linear_attenuation_coeff = 0.1;
+    quadratic_attenuation_coeff = 0.01;
+}
+directional_light { direction = (0, -1, 0); color = (0.3, 0.3, 0.3); }
+
+material = { diffuse = (0.8, 0.6, 0.1); specular = (1, 1, 1); shininess = 20; };
+
+define ball = scale(0.7, sphere {});
+define pair = { translate(-1, 0, 0, ball) translate(1, 0, 0, ball) };
+
+pair
+rotate(0, 1, 1, pi/3, translate(0, 3, 0, pair));
+transform((1, 0, 0, 0), (0, 2, 0, 1), (0, 0, 1, 0), (0, 0, 0, 1),
+    { material = { transmissive = (0.9, 0.9, 0.9); index = 1.5; }; box {} scale(0.5, 1, 2, square { material = { ambient = (0.1, 0.2, 0.3); }; }) });
+translate(0, -2, 0, cone { capped = false; height = 3; top_radius = 0.25; material = { diffuse = (0.1, 0.1, 0.1); }; });
+cylinder { capped = false; }
+trimesh {
+    points = ((0, 0, -5), (1, 0, -5), (1, 1, -5), (0, 1, -5));
+    faces = ((0, 1, 2), (0, 2, 3));
+}
+";
+
+fn color(r: f64, g: f64, b: f64) -> MaterialParameter {
+    MaterialParameter::new(Vector3::new(r, g, b))
+}
+
+/// The same scene as `SCENE`, built in code
+fn build_scene() -> Scene {
+    let mut camera = Camera::new();
+    camera.set_eye(Vector3::new(1.0, 2.0, 8.0));
+    camera.set_fov(50.0);
+    camera.set_look(Vector3::new(0.0, -0.2, -1.0), Vector3::unit_y());
+
+    let mut gold = Material::new();
+    gold.diffuse = color(0.8, 0.6, 0.1);
+    gold.specular = color(1.0, 1.0, 1.0);
+    gold.shininess = 20.0;
+    // materials given in a .ray element change the one it would otherwise get
+    let mut glass = gold.clone();
+    glass.transmissive = color(0.9, 0.9, 0.9);
+    glass.index = 1.5;
+    let mut tinted = glass.clone();
+    tinted.ambient = color(0.1, 0.2, 0.3);
+    let mut dark = gold.clone();
+    dark.diffuse = color(0.1, 0.1, 0.1);
+
+    // prototypes take their materials when they're defined
+    let ball = Prototype::new(&Element::sphere().material(gold.clone()).scale(Vector3::new(0.7, 0.7, 0.7)));
+    let pair = Prototype::new(&Element::group(vec![
+        Element::instance(&ball).translate(Vector3::new(-1.0, 0.0, 0.0)),
+        Element::instance(&ball).translate(Vector3::new(1.0, 0.0, 0.0)),
+    ]));
+
+    let quad = Mesh::new(
+        vec![Vector3::new(0.0, 0.0, -5.0), Vector3::new(1.0, 0.0, -5.0), Vector3::new(1.0, 1.0, -5.0), Vector3::new(0.0, 1.0, -5.0)],
+        vec![TrimeshFace::new(0, 1, 2), TrimeshFace::new(0, 2, 3)],
+        Vec::new(), Vec::new(), Vec::new());
+
+    SceneBuilder::new()
+        .camera(camera)
+        .ambient_light(Vector3::new(0.1, 0.1, 0.1))
+        .add_light(Light::new(LightType::PointLight { pos: Vector3::new(2.0, 4.0, 3.0), a: 0.5, b: 0.1, c: 0.01 }, Vector3::new(1.0, 0.9, 0.8)))
+        .add_light(Light::new(LightType::DirectionalLight { orientation: -Vector3::unit_y() }, Vector3::new(0.3, 0.3, 0.3)))
+        .material(gold)
+        .add(Element::instance(&pair))
+        .add(Element::instance(&pair).translate(Vector3::new(0.0, 3.0, 0.0)).rotate(Vector3::new(0.0, 1.0, 1.0), consts::PI / 3.0))
+        .add(Element::group(vec![Element::cube(), Element::square().material(tinted).scale(Vector3::new(0.5, 1.0, 2.0))])
+            .material(glass)
+            .transform(Matrix4::new(1.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0)))
+        .add(Element::cone(false, 3.0, 1.0, 0.25).material(dark).translate(Vector3::new(0.0, -2.0, 0.0)))
+        .add(Element::cylinder(false))
+        .add(Element::trimesh(Arc::new(quad)))
+        .build()
+}
+
+/// Casts a fan of rays at both scenes and checks they see the same things
+fn assert_same_scene(expected: &Scene, actual: &Scene) {
+    assert_eq!(expected.object_count(), actual.object_count());
+    assert_eq!(expected.lights(), actual.lights());
+    assert_eq!(expected.ambient(), actual.ambient());
+    assert_eq!(expected.bounds(), actual.bounds());
+
+    let mut hits = 0;
+    for i in 0..=20 {
+        for j in 0..=20 {
+            let (x, y) = (f64::from(i) / 20.0, f64::from(j) / 20.0);
+            let ray = expected.camera().ray_through(x, y).unwrap();
+            let actual_ray = actual.camera().ray_through(x, y).unwrap();
+            assert_eq!(ray.position(), actual_ray.position());
+            assert_eq!(ray.direction(), actual_ray.direction());
+
+            let mut expected_isect = Intersect::new();
+            let mut actual_isect = Intersect::new();
+            let hit = expected.intersect(&ray, &mut expected_isect);
+            assert_eq!(hit, actual.intersect(&ray, &mut actual_isect));
+            assert_eq!(expected_isect.t, actual_isect.t);
+            assert_eq!(expected_isect.n, actual_isect.n);
+            assert_eq!(expected_isect.material, actual_isect.material);
+            hits += hit as usize;
+        }
+    }
+    assert!(hits > 0);
+}
+
+#[test]
+fn same_as_parser_test() {
+    let parsed = RayParser.parse(SCENE.as_bytes(), "scene.ray", &MemoryResolver::new()).unwrap();
+    assert_same_scene(&parsed, &build_scene());
+}
+
+#[test]
+fn vertex_colors_test() {
+    let mesh = Mesh::new(
+        vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)],
+        vec![TrimeshFace::new(0, 1, 2)],
+        Vec::new(), vec![Vector2::new(0.0, 0.0); 3], Vec::new())
+        .with_colors(vec![Vector3::new(1.0, 0.0, 0.0); 3]);
+
+    let mut camera = Camera::new();
+    camera.set_eye(Vector3::new(0.25, 0.25, 1.0));
+    let scene = SceneBuilder::new()
+        .camera(camera)
+        .add(Element::trimesh(Arc::new(mesh)))
+        .build();
+
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&scene.camera().ray_through(0.5, 0.5).unwrap(), &mut isect));
+    assert!(isect.material.unwrap().diffuse.uses_vertex_colors());
+}
+
+/// Unit disc in the xy plane, as a primitive defined outside the crate would be
+struct Disc {
+    transform: TransformNode,
+    material: Arc<Material>,
+}
+
+impl SceneObject for Disc {
+    fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+        intersect_transformed(&self.transform, ray, isect, |ray, isect| {
+            let t = -ray.position().z / ray.direction().z;
+            let p = ray.at(t);
+            if t.is_nan() || t <= 0.0 || p.x * p.x + p.y * p.y > 1.0 {
+                return false;
+            }
+            isect.t = t;
+            isect.n = Vector3::unit_z();
+            isect.material = Some(self.material.clone());
+            true
+        })
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::new(Vector3::new(-1.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 0.0)).transform(&self.transform)
+    }
+
+    fn material(&self) -> Option<&Arc<Material>> {
+        Some(&self.material)
+    }
+}
+
+#[test]
+fn custom_test() {
+    let mut red = Material::new();
+    red.diffuse = color(1.0, 0.0, 0.0);
+    let disc = Element::custom(|transform, material| Box::new(Disc { transform, material }) as Box<dyn SceneObject>);
+
+    let scene = SceneBuilder::new()
+        .material(red.clone())
+        .add(disc.clone().translate(Vector3::new(0.0, 0.0, -5.0)))
+        .add(disc.scale(Vector3::new(0.5, 0.5, 0.5)).translate(Vector3::new(3.0, 0.0, -5.0)))
+        .add(Element::sphere().translate(Vector3::new(-3.0, 0.0, -5.0)))
+        .build();
+
+    assert_eq!(scene.object_count(), 3);
+    assert_eq!(scene.objects()[0].material().map(|material| &**material), Some(&red));
+    assert_eq!(scene.bounds().map(|bounds| (bounds.min(), bounds.max())), Some((Vector3::new(-4.0, -1.0, -6.0), Vector3::new(3.5, 1.0, -4.0))));
+
+    let mut isect = Intersect::new();
+    assert!(scene.intersect(&scene.camera().ray_through(0.5, 0.5).unwrap(), &mut isect));
+    assert_eq!((isect.t, isect.n), (5.0, Vector3::unit_z()));
+    assert_eq!(isect.material.map(|material| material.diffuse.clone()), Some(red.diffuse));
+}
+
+#[test]
+#[should_panic(expected = "scale factors can't be zero")]
+fn zero_scale_test() {
+    Element::sphere().scale(Vector3::new(1.0, 0.0, 1.0));
+}
+
+#[test]
+#[should_panic(expected = "rotation axis can't be zero")]
+fn zero_axis_test() {
+    Element::sphere().rotate(Vector3::new(0.0, 0.0, 0.0), 1.0);
+}
@@ -0,0 +1,224 @@
+//! Bounding volume hierarchies, used both over the objects of a scene and
+//! over the faces of a trimesh
+
+use cgmath::Vector3;
+
+use std::collections::HashSet;
+use std::f64;
+use std::mem;
+use std::time::Instant;
+
+use super::SceneObject;
+use super::objects::{BoundingBox, Intersect, Ray};
+use super::super::stats;
+
+/// Most primitives kept in a single leaf
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Hierarchy over the bounds of a list of primitives, it only knows about
+/// primitives through their index in that list
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    // primitive indices, grouped so that every leaf covers a contiguous range
+    order: Vec<usize>,
+}
+
+struct BvhNode {
+    bounds: BoundingBox,
+    kind: BvhNodeKind,
+}
+
+enum BvhNodeKind {
+    Leaf { first: usize, count: usize },
+    // the left child directly follows its parent in `nodes`
+    Interior { right: usize, axis: usize },
+}
+
+impl Bvh {
+    pub fn new(bounds: &[BoundingBox]) -> Bvh {
+        let start = Instant::now();
+        let centroids: Vec<Vector3<f64>> = bounds.iter().map(|b| (b.min() + b.max()) * 0.5).collect();
+        let mut order: Vec<usize> = (0..bounds.len()).collect();
+        let mut nodes = Vec::with_capacity(2 * bounds.len() / MAX_LEAF_SIZE + 1);
+
+        if !bounds.is_empty() {
+            Bvh::build(&mut nodes, &mut order, 0, bounds, &centroids);
+        }
+
+        stats::count_bvh_build(start.elapsed());
+        Bvh { nodes, order }
+    }
+
+    /// Bytes of memory the hierarchy takes
+    pub fn memory(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<BvhNode>() + self.order.capacity() * mem::size_of::<usize>()
+    }
+
+    /// Bounds of everything in the hierarchy
+    pub fn bounds(&self) -> Option<&BoundingBox> {
+        self.nodes.first().map(|node| &node.bounds)
+    }
+
+    /// Splits at the median centroid along the widest axis, which keeps the
+    /// build at O(n log n) for large meshes
+    fn build(nodes: &mut Vec<BvhNode>, order: &mut [usize], offset: usize, bounds: &[BoundingBox], centroids: &[Vector3<f64>]) -> usize {
+        let node_bounds = order[1..].iter()
+            .fold(bounds[order[0]].clone(), |node_bounds, &i| node_bounds.merge(&bounds[i]));
+
+        let index = nodes.len();
+        nodes.push(BvhNode {
+            bounds: node_bounds,
+            kind: BvhNodeKind::Leaf { first: offset, count: order.len() },
+        });
+
+        if order.len() <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        let first = centroids[order[0]];
+        let centroid_bounds = order[1..].iter()
+            .fold(BoundingBox::new(first, first), |b, &i| b.merge(&BoundingBox::new(centroids[i], centroids[i])));
+        let extent = centroid_bounds.max() - centroid_bounds.min();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        if extent[axis] == 0.0 {
+            // every centroid is in the same place, splitting won't help
+            return index;
+        }
+
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |&a, &b| {
+            centroids[a][axis].partial_cmp(&centroids[b][axis]).unwrap()
+        });
+
+        let (left, right) = order.split_at_mut(mid);
+        Bvh::build(nodes, left, offset, bounds, centroids);
+        let right = Bvh::build(nodes, right, offset + mid, bounds, centroids);
+        nodes[index].kind = BvhNodeKind::Interior { right, axis };
+
+        index
+    }
+
+    /// Walks the nodes the ray passes through, nearest first, calling
+    /// `intersect_primitive` with a primitive index and the closest distance
+    /// found so far. It returns the distance of a closer hit, if there is one,
+    /// so that nodes beyond it can be skipped.
+    pub fn traverse<F>(&self, ray: &Ray, mut intersect_primitive: F)
+        where F: FnMut(usize, f64) -> Option<f64>
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let direction = ray.direction();
+        let mut closest = f64::INFINITY;
+        let mut stack = vec![0];
+        let mut visited = 0;
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            visited += 1;
+            match node.bounds.intersect(ray) {
+                Some((t_min, _)) if t_min <= closest => {},
+                _ => continue,
+            }
+
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for &primitive in &self.order[first..first + count] {
+                        if let Some(t) = intersect_primitive(primitive, closest) {
+                            closest = t;
+                        }
+                    }
+                },
+                BvhNodeKind::Interior { right, axis } => {
+                    // visit the child on the near side of the split first
+                    if direction[axis] > 0.0 {
+                        stack.push(right);
+                        stack.push(index + 1);
+                    } else {
+                        stack.push(index + 1);
+                        stack.push(right);
+                    }
+                },
+            }
+        }
+        stats::count_bvh_nodes(visited);
+    }
+}
+
+/// Scene objects together with a hierarchy over them. This is the top level
+/// of a scene, and the shared contents placed by instances.
+pub struct Aggregate {
+    objects: Vec<Box<dyn SceneObject>>,
+    bvh: Bvh,
+}
+
+impl Aggregate {
+    pub fn new(objects: Vec<Box<dyn SceneObject>>) -> Aggregate {
+        let bounds: Vec<BoundingBox> = objects.iter().map(|object| object.bounding_box()).collect();
+        let bvh = Bvh::new(&bounds);
+
+        Aggregate { objects, bvh }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn objects(&self) -> &[Box<dyn SceneObject>] {
+        &self.objects
+    }
+
+    pub fn bounds(&self) -> Option<&BoundingBox> {
+        self.bvh.bounds()
+    }
+
+    /// Bytes of memory the objects and their hierarchy take, counting what
+    /// they share only the first time its address goes into `shared`
+    pub fn memory(&self, shared: &mut HashSet<usize>) -> usize {
+        let objects: usize = self.objects.iter().map(|object| object.memory(shared)).sum();
+        mem::size_of::<Aggregate>() + self.objects.capacity() * mem::size_of::<Box<dyn SceneObject>>() + objects + self.bvh.memory()
+    }
+
+    /// Finds the closest intersection of `ray` with the objects, if any
+    pub fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+        self.intersect_object(ray, isect).is_some()
+    }
+
+    /// Like `intersect`, giving the index of the object that was hit
+    pub fn intersect_object(&self, ray: &Ray, isect: &mut Intersect) -> Option<usize> {
+        let mut hit = None;
+        let (mut tests, mut hits) = (0, 0);
+
+        self.bvh.traverse(ray, |index, closest| {
+            let mut current = Intersect::new();
+            tests += 1;
+            if !self.objects[index].intersect(ray, &mut current) {
+                return None;
+            }
+            hits += 1;
+            if current.t < closest {
+                let t = current.t;
+                *isect = current;
+                hit = Some(index);
+                Some(t)
+            } else {
+                None
+            }
+        });
+
+        stats::count_intersections(tests, hits);
+        hit
+    }
+}
@@ -0,0 +1,454 @@
+//! Participating media: fog filling the whole scene and volumes of smoke,
+//! cloud or murky water inside closed objects. Light going through a medium
+//! is absorbed and scattered in proportion to how far it goes, by
+//! coefficients per unit of distance that a density grid can vary from
+//! place to place. Scattered light goes off in directions given by a
+//! Henyey-Greenstein phase function.
+
+#[cfg(test)]
+mod tests;
+
+use cgmath::{ElementWise, InnerSpace, Vector2, Vector3, Zero};
+
+use std::f64::{self, consts};
+use std::sync::Arc;
+
+use super::SceneObject;
+use super::bvh::Aggregate;
+use super::objects::{BoundingBox, Intersect, Ray, RayType};
+
+/// Distance a ray looking for the next crossing of a volume's boundary
+/// starts past the last one
+const BOUNDARY_EPSILON: f64 = 1e-6;
+
+/// Most times a ray crosses a volume's boundary before the rest of it counts
+/// as outside
+const MAX_CROSSINGS: usize = 64;
+
+/// Most steps taken through the cells of a density grid when working out
+/// how much light gets through it
+const MAX_MARCH_STEPS: usize = 512;
+
+/// Densities on a regular grid of points, filling the bounds of the volume
+/// they're given to and looked up between the points by trilinear
+/// interpolation
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityGrid {
+    size: [usize; 3],
+    /// x fastest, then y, then z
+    values: Vec<f64>,
+    max: f64,
+}
+
+impl DensityGrid {
+    /// A grid of `size[0]` by `size[1]` by `size[2]` points
+    pub fn new(size: [usize; 3], values: Vec<f64>) -> Result<DensityGrid, String> {
+        if size.contains(&0) {
+            return Err("a density grid needs at least one point along each axis".to_string());
+        }
+        let count = size[0] * size[1] * size[2];
+        if values.len() != count {
+            return Err(format!("a {}x{}x{} density grid needs {} values, found {}", size[0], size[1], size[2], count, values.len()));
+        }
+        if values.iter().any(|&value| !value.is_finite() || value < 0.0) {
+            return Err("densities must be zero or more".to_string());
+        }
+        let max = values.iter().cloned().fold(0.0, f64::max);
+        Ok(DensityGrid { size, values, max })
+    }
+
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// The densest point of the grid, which nowhere between them is denser
+    /// than
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Density at `p`, from `(0, 0, 0)` at the first point of the grid to
+    /// `(1, 1, 1)` at the last, and none outside it
+    pub fn density_at(&self, p: Vector3<f64>) -> f64 {
+        if (0..3).any(|axis| !(0.0..=1.0).contains(&p[axis])) {
+            return 0.0;
+        }
+
+        // the points either side along each axis, and how far between them
+        let mut lower = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let last = self.size[axis] - 1;
+            let position = p[axis] * last as f64;
+            lower[axis] = (position.floor() as usize).min(last.saturating_sub(1));
+            fraction[axis] = if last == 0 { 0.0 } else { position - lower[axis] as f64 };
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                let upper = corner & (1 << axis) != 0;
+                index[axis] = (lower[axis] + upper as usize).min(self.size[axis] - 1);
+                weight *= if upper { fraction[axis] } else { 1.0 - fraction[axis] };
+            }
+            if weight > 0.0 {
+                density += weight * self.values[index[0] + self.size[0] * (index[1] + self.size[1] * index[2])];
+            }
+        }
+        density
+    }
+
+    /// Fraction of the grid's extent between neighbouring points along the
+    /// axis where they're closest
+    fn spacing(&self) -> f64 {
+        self.size.iter().map(|&count| 1.0 / (count.max(2) - 1) as f64).fold(1.0, f64::min)
+    }
+}
+
+/// What a medium does to light going through it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Medium {
+    /// Fraction of light absorbed per unit of distance, for each channel
+    pub absorption: Vector3<f64>,
+    /// Fraction of light scattered per unit of distance, for each channel
+    pub scattering: Vector3<f64>,
+    /// The Henyey-Greenstein `g`, from -1 scattering light straight back,
+    /// through 0 scattering it evenly in every direction, to 1 letting it
+    /// carry straight on
+    pub asymmetry: f64,
+    /// Scales both coefficients from place to place when given
+    pub density: Option<Arc<DensityGrid>>,
+}
+
+impl Medium {
+    /// A medium that lets all light through
+    pub fn new() -> Medium {
+        Medium { absorption: Vector3::zero(), scattering: Vector3::zero(), asymmetry: 0.0, density: None }
+    }
+
+    /// Fraction of light absorbed or scattered per unit of distance, where
+    /// the density is 1
+    pub fn extinction(&self) -> Vector3<f64> {
+        self.absorption + self.scattering
+    }
+
+    /// Most light taken out of a ray per unit of distance anywhere in the
+    /// medium, in any channel
+    pub fn max_extinction(&self) -> f64 {
+        let extinction = self.extinction();
+        let densest = self.density.as_ref().map_or(1.0, |grid| grid.max());
+        extinction.x.max(extinction.y).max(extinction.z) * densest
+    }
+
+    /// Density of light scattered into directions at an angle with a cosine
+    /// of `cos_theta` to the way it was going, per unit of solid angle
+    pub fn phase(&self, cos_theta: f64) -> f64 {
+        phase(self.asymmetry, cos_theta)
+    }
+}
+
+impl Default for Medium {
+    fn default() -> Medium {
+        Medium::new()
+    }
+}
+
+/// A medium filling the inside of closed objects, which aren't rendered
+/// themselves. A density grid fills the bounds of the objects.
+pub struct Volume {
+    boundary: Aggregate,
+    medium: Medium,
+}
+
+impl Volume {
+    pub fn new(boundary: Vec<Box<dyn SceneObject>>, medium: Medium) -> Volume {
+        Volume { boundary: Aggregate::new(boundary), medium }
+    }
+
+    pub fn medium(&self) -> &Medium {
+        &self.medium
+    }
+
+    pub fn bounds(&self) -> Option<&BoundingBox> {
+        self.boundary.bounds()
+    }
+
+    /// Density of the medium at `p`, 1 without a density grid
+    pub fn density_at(&self, p: Vector3<f64>) -> f64 {
+        match (&self.medium.density, self.boundary.bounds()) {
+            (Some(grid), Some(bounds)) => {
+                let (min, size) = (bounds.min(), bounds.max() - bounds.min());
+                let fraction = |axis: usize| if size[axis] > 0.0 { (p[axis] - min[axis]) / size[axis] } else { 0.0 };
+                grid.density_at(Vector3::new(fraction(0), fraction(1), fraction(2)))
+            },
+            (Some(_), None) => 0.0,
+            (None, _) => 1.0,
+        }
+    }
+
+    /// The stretches of `ray` up to `t_max` inside the boundary, in order.
+    /// A ray is inside from where it goes in through the boundary, or from
+    /// its start when it first comes out through it, to where it comes out.
+    pub fn segments(&self, ray: &Ray, t_max: f64) -> Vec<(f64, f64)> {
+        let mut segments = Vec::new();
+        if self.boundary.is_empty() {
+            return segments;
+        }
+
+        let d = ray.direction();
+        let mut start = None;
+        let mut t = 0.0;
+        for crossing in 0..MAX_CROSSINGS {
+            let offset = if crossing == 0 { 0.0 } else { BOUNDARY_EPSILON };
+            let from = Ray::new(ray.at(t + offset), d, RayType::Visibility);
+            let mut isect = Intersect::new();
+            if !self.boundary.intersect(&from, &mut isect) || t + offset + isect.t >= t_max {
+                break;
+            }
+
+            t += offset + isect.t;
+            if isect.n.dot(d) < 0.0 {
+                start = start.or(Some(t));
+            } else {
+                segments.push((start.take().unwrap_or(0.0), t));
+            }
+        }
+        if let Some(start) = start {
+            segments.push((start, t_max));
+        }
+        segments
+    }
+}
+
+/// Part of a ray going through a medium
+pub struct Span<'m> {
+    pub start: f64,
+    pub end: f64,
+    pub medium: &'m Medium,
+    /// The volume the medium fills, None for fog
+    volume: Option<&'m Volume>,
+}
+
+impl<'m> Span<'m> {
+    /// Density of the medium at `p` along the span
+    pub fn density_at(&self, p: Vector3<f64>) -> f64 {
+        self.volume.map_or(1.0, |volume| volume.density_at(p))
+    }
+
+    /// Most light the medium takes out per unit of distance along the span
+    pub fn max_extinction(&self) -> f64 {
+        self.medium.max_extinction()
+    }
+}
+
+/// Absorption and scattering coefficients where media overlap, added up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coefficients {
+    pub absorption: Vector3<f64>,
+    pub scattering: Vector3<f64>,
+    /// Asymmetry of the media, weighted by how much each scatters
+    pub asymmetry: f64,
+}
+
+/// Light that makes it along a stretch of ray through media, and light
+/// scattered towards its start along the way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+    /// Fraction of the light from the end that reaches the start
+    pub transmittance: Vector3<f64>,
+    /// Fraction of light lighting the media evenly that's scattered along
+    /// the stretch to reach its start
+    pub scattered: Vector3<f64>,
+}
+
+/// Every medium of a scene
+pub struct Media {
+    /// Fills all of space
+    pub fog: Option<Medium>,
+    pub volumes: Vec<Volume>,
+    /// Sphere around everything, from the edge of which light from the
+    /// background and directional lights comes through the fog
+    extent: Option<(Vector3<f64>, f64)>,
+}
+
+impl Media {
+    /// No media, light goes straight through the scene
+    pub fn new() -> Media {
+        Media { fog: None, volumes: Vec::new(), extent: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fog.is_none() && self.volumes.is_empty()
+    }
+
+    /// Rays that go on forever pass through the fog as far as the far side of
+    /// the sphere around `bounds`
+    pub fn set_extent(&mut self, bounds: Option<&BoundingBox>) {
+        self.extent = bounds.map(|bounds| ((bounds.min() + bounds.max()) / 2.0, (bounds.max() - bounds.min()).magnitude() / 2.0));
+    }
+
+    /// The parts of `ray` up to `t_max` in each medium
+    pub fn spans(&self, ray: &Ray, t_max: f64) -> Vec<Span<'_>> {
+        let mut spans = Vec::new();
+        if let Some(ref fog) = self.fog {
+            let end = if t_max.is_finite() { t_max } else { self.exit_distance(ray) };
+            if end > 0.0 {
+                spans.push(Span { start: 0.0, end, medium: fog, volume: None });
+            }
+        }
+        for volume in &self.volumes {
+            for (start, end) in volume.segments(ray, t_max) {
+                spans.push(Span { start, end, medium: &volume.medium, volume: Some(volume) });
+            }
+        }
+        spans
+    }
+
+    /// Where the ray leaves the sphere around the scene, 0 if it's never in it
+    fn exit_distance(&self, ray: &Ray) -> f64 {
+        let (center, radius) = match self.extent {
+            Some(extent) => extent,
+            None => return 0.0,
+        };
+        let offset = ray.position() - center;
+        let b = offset.dot(ray.direction());
+        let discriminant = b * b - (offset.magnitude2() - radius * radius);
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        (discriminant.sqrt() - b).max(0.0)
+    }
+
+    /// Coefficients at distance `t` along `ray`, from the spans of it
+    pub fn coefficients_at(spans: &[Span], ray: &Ray, t: f64) -> Coefficients {
+        let p = ray.at(t);
+        let mut coefficients = Coefficients { absorption: Vector3::zero(), scattering: Vector3::zero(), asymmetry: 0.0 };
+        let mut weight = 0.0;
+        for span in spans.iter().filter(|span| span.start <= t && t < span.end) {
+            let density = span.density_at(p);
+            let scattering = span.medium.scattering * density;
+            coefficients.absorption += span.medium.absorption * density;
+            coefficients.scattering += scattering;
+
+            let share = scattering.x + scattering.y + scattering.z;
+            coefficients.asymmetry += span.medium.asymmetry * share;
+            weight += share;
+        }
+        if weight > 0.0 {
+            coefficients.asymmetry /= weight;
+        }
+        coefficients
+    }
+
+    /// Fraction of the light at distance `t_max` along `ray` that makes it
+    /// back to its start
+    pub fn transmittance(&self, ray: &Ray, t_max: f64) -> Vector3<f64> {
+        self.attenuation(ray, t_max).transmittance
+    }
+
+    /// What the media do to light along `ray` up to `t_max`. Where every
+    /// medium is homogeneous this is exact, and through density grids it's
+    /// added up in steps half as long as the gaps between their points.
+    pub fn attenuation(&self, ray: &Ray, t_max: f64) -> Attenuation {
+        let mut attenuation = Attenuation { transmittance: Vector3::new(1.0, 1.0, 1.0), scattered: Vector3::zero() };
+        let spans = self.spans(ray, t_max);
+        if spans.is_empty() {
+            return attenuation;
+        }
+
+        // within each stretch between the ends of spans, the same media apply
+        let mut ends: Vec<f64> = spans.iter().flat_map(|span| vec![span.start, span.end]).collect();
+        ends.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ends.dedup();
+        for stretch in ends.windows(2) {
+            let (start, end) = (stretch[0], stretch[1]);
+            let active: Vec<&Span> = spans.iter().filter(|span| span.start <= start && end <= span.end).collect();
+            if active.is_empty() {
+                continue;
+            }
+
+            let step = active.iter()
+                .filter_map(|span| span.volume.and_then(|volume| Some((volume, volume.medium.density.as_ref()?))))
+                .filter_map(|(volume, grid)| volume.bounds().map(|bounds| grid.spacing() * min_extent(bounds) / 2.0))
+                .fold(f64::INFINITY, f64::min);
+            let steps = if step.is_finite() && step > 0.0 { ((end - start) / step).ceil().max(1.0).min(MAX_MARCH_STEPS as f64) as usize } else { 1 };
+            let length = (end - start) / steps as f64;
+
+            for index in 0..steps {
+                let middle = start + length * (index as f64 + 0.5);
+                let p = ray.at(middle);
+                let (mut extinction, mut scattering) = (Vector3::zero(), Vector3::zero());
+                for span in &active {
+                    let density = if steps == 1 && span.volume.is_none() { 1.0 } else { span.density_at(p) };
+                    extinction += span.medium.extinction() * density;
+                    scattering += span.medium.scattering * density;
+                }
+
+                let through = Vector3::new(pass(extinction.x, length), pass(extinction.y, length), pass(extinction.z, length));
+                let scattered = Vector3::new(
+                    gathered(scattering.x, extinction.x, through.x),
+                    gathered(scattering.y, extinction.y, through.y),
+                    gathered(scattering.z, extinction.z, through.z));
+                attenuation.scattered += attenuation.transmittance.mul_element_wise(scattered);
+                attenuation.transmittance = attenuation.transmittance.mul_element_wise(through);
+            }
+        }
+        attenuation
+    }
+}
+
+impl Default for Media {
+    fn default() -> Media {
+        Media::new()
+    }
+}
+
+/// The Henyey-Greenstein phase function with asymmetry `g`: density of light
+/// going along `d` scattered into direction `d'`, per unit of solid angle,
+/// given the cosine of the angle between them
+pub fn phase(g: f64, cos_theta: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * consts::PI * denominator * denominator.sqrt())
+}
+
+/// Direction light going along `d` scatters into, picked from `u`, uniform
+/// in `[0, 1)²`, with the density of the phase function with asymmetry `g`
+pub fn sample_phase(g: f64, d: Vector3<f64>, u: Vector2<f64>) -> Vector3<f64> {
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * u.x
+    } else {
+        let square = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.x);
+        ((1.0 + g * g - square * square) / (2.0 * g)).clamp(-1.0, 1.0)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * consts::PI * u.y;
+
+    let helper = if d.x.abs() > 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let tangent = helper.cross(d).normalize();
+    let bitangent = d.cross(tangent);
+    (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + d * cos_theta).normalize()
+}
+
+/// Fraction of light getting through `length` of a medium taking out
+/// `extinction` per unit of distance
+fn pass(extinction: f64, length: f64) -> f64 {
+    if extinction <= 0.0 { 1.0 } else { (-extinction * length).exp() }
+}
+
+/// Fraction of evenly lit light scattered towards the start of a stretch
+/// that lets `through` of the light through
+fn gathered(scattering: f64, extinction: f64, through: f64) -> f64 {
+    if extinction <= 0.0 { 0.0 } else { scattering / extinction * (1.0 - through) }
+}
+
+/// Shortest side of `bounds`
+fn min_extent(bounds: &BoundingBox) -> f64 {
+    let size = bounds.max() - bounds.min();
+    size.x.min(size.y).min(size.z)
+}
@@ -0,0 +1,115 @@
+use std::f64::consts;
+use std::sync::Arc;
+
+use cgmath::{Matrix4, Vector2, Vector3};
+
+use scene::{Material, SceneObject, TransformNode};
+use scene::objects::{BoundingBox, Ray, RayType, SceneBox};
+
+use super::*;
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+}
+
+/// A box from -1 to 1 along every axis
+fn boundary() -> Vec<Box<dyn SceneObject>> {
+    let transform = TransformNode::root().create_child(Matrix4::from_scale(2.0)).unwrap();
+    vec![Box::new(SceneBox::new(transform, Arc::new(Material::new())))]
+}
+
+fn medium(absorption: f64, scattering: f64) -> Medium {
+    let mut medium = Medium::new();
+    medium.absorption = Vector3::new(absorption, absorption, absorption);
+    medium.scattering = Vector3::new(scattering, scattering, scattering);
+    medium
+}
+
+fn down_z(z: f64) -> Ray {
+    Ray::new(Vector3::new(0.0, 0.0, z), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility)
+}
+
+#[test]
+fn density_grid_test() {
+    let grid = DensityGrid::new([2, 1, 1], vec![0.0, 2.0]).unwrap();
+    assert_eq!(grid.max(), 2.0);
+    assert_close(grid.density_at(Vector3::new(0.25, 0.5, 0.5)), 0.5);
+    assert_close(grid.density_at(Vector3::new(1.0, 0.0, 1.0)), 2.0);
+    // nothing outside the grid
+    assert_eq!(grid.density_at(Vector3::new(1.5, 0.5, 0.5)), 0.0);
+
+    let grid = DensityGrid::new([2, 2, 2], vec![0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]).unwrap();
+    assert_close(grid.density_at(Vector3::new(0.3, 0.9, 0.75)), 0.75);
+
+    assert!(DensityGrid::new([2, 2, 2], vec![1.0; 7]).is_err());
+    assert!(DensityGrid::new([0, 1, 1], Vec::new()).is_err());
+    assert!(DensityGrid::new([1, 1, 1], vec![-1.0]).is_err());
+}
+
+#[test]
+fn phase_test() {
+    assert_close(phase(0.0, 0.3), 1.0 / (4.0 * consts::PI));
+
+    // the phase function is a density over every direction, and sampling it
+    // gives directions whose average cosine is its asymmetry
+    let g = 0.6;
+    let steps = 2000;
+    let mut total = 0.0;
+    let mut cosines = 0.0;
+    for i in 0..steps {
+        let u = (i as f64 + 0.5) / steps as f64;
+        total += phase(g, 1.0 - 2.0 * u) * 4.0 * consts::PI / steps as f64;
+        let d = sample_phase(g, Vector3::unit_z(), Vector2::new(u, 0.3));
+        assert_close(d.magnitude(), 1.0);
+        cosines += d.z / steps as f64;
+    }
+    assert!((total - 1.0).abs() < 1e-3, "{}", total);
+    assert!((cosines - g).abs() < 1e-3, "{}", cosines);
+}
+
+#[test]
+fn volume_segments_test() {
+    let volume = Volume::new(boundary(), medium(1.0, 0.0));
+    assert_eq!(volume.segments(&down_z(5.0), f64::INFINITY), vec![(4.0, 6.0)]);
+    // cut short, and from inside
+    assert_eq!(volume.segments(&down_z(5.0), 4.5), vec![(4.0, 4.5)]);
+    assert_eq!(volume.segments(&down_z(0.0), f64::INFINITY), vec![(0.0, 1.0)]);
+
+    let miss = Ray::new(Vector3::new(3.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0), RayType::Visibility);
+    assert!(volume.segments(&miss, f64::INFINITY).is_empty());
+}
+
+#[test]
+fn attenuation_test() {
+    let mut media = Media::new();
+    media.volumes.push(Volume::new(boundary(), medium(0.5, 0.5)));
+    let attenuation = media.attenuation(&down_z(5.0), f64::INFINITY);
+    assert_close(attenuation.transmittance.x, (-2.0f64).exp());
+    assert_close(attenuation.scattered.y, 0.5 * (1.0 - (-2.0f64).exp()));
+    // the start of the ray is in front of the volume
+    assert_eq!(media.transmittance(&down_z(5.0), 3.0), Vector3::new(1.0, 1.0, 1.0));
+
+    // fog goes on to the far side of the sphere around the scene
+    let mut media = Media::new();
+    media.fog = Some(medium(0.25, 0.0));
+    assert_close(media.transmittance(&down_z(5.0), 2.0).z, (-0.5f64).exp());
+    assert_eq!(media.transmittance(&down_z(5.0), f64::INFINITY), Vector3::new(1.0, 1.0, 1.0));
+    media.set_extent(Some(&BoundingBox::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0))));
+    assert_close(media.transmittance(&down_z(5.0), f64::INFINITY).z, (-0.25 * (5.0 + 3.0f64.sqrt())).exp());
+}
+
+#[test]
+fn density_grid_attenuation_test() {
+    // thickening from nothing at the back of the box to 1 at the front
+    let mut thickening = medium(1.0, 0.0);
+    thickening.density = Some(Arc::new(DensityGrid::new([1, 1, 2], vec![0.0, 1.0]).unwrap()));
+    let mut media = Media::new();
+    media.volumes.push(Volume::new(boundary(), thickening));
+
+    assert_close(media.volumes[0].density_at(Vector3::new(0.0, 0.0, 0.5)), 0.75);
+    assert_close(media.transmittance(&down_z(5.0), f64::INFINITY).x, (-1.0f64).exp());
+    assert_close(media.transmittance(&down_z(5.0), 5.0).x, (-0.75f64).exp());
+
+    let coefficients = Media::coefficients_at(&media.spans(&down_z(5.0), f64::INFINITY), &down_z(5.0), 5.5);
+    assert_close(coefficients.absorption.x, 0.25);
+}
@@ -1,23 +1,137 @@
+pub mod animation;
+pub mod builder;
+pub mod bvh;
+pub mod medium;
 pub mod objects;
+#[cfg(test)]
+mod tests;
 
-use cgmath::{Matrix4, Matrix3, Vector3, SquareMatrix, Matrix, Zero};
+use cgmath::{Matrix4, Matrix3, Rad, Vector2, Vector3, Vector4, SquareMatrix, Matrix, InnerSpace, Zero};
+use image::RgbImage;
 
-use std::rc::Weak;
+use std::collections::HashSet;
+use std::f64::consts;
+use std::mem;
+use std::sync::Arc;
 
+use self::bvh::Aggregate;
+use self::medium::Media;
 use self::objects::*;
+use super::render::IntegratorType;
+use super::stats;
 
 pub struct Scene {
     transform_root: TransformNode,
-    objects: Vec<Box<SceneObject>>,
+    objects: Aggregate,
     lights: Vec<Light>,
     camera: Camera,
-    // TODO: KdTree
+    ambient: Vector3<f64>,
+    settings: SceneSettings,
+    media: Media,
     // TODO: texture map
-    scene_bounds: Option<BoundingBox>,
+}
+
+/// How a scene asks to be rendered, like the size of its reference image.
+/// Anything left out is up to the renderer, and the command line overrides
+/// what's given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneSettings {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub integrator: Option<IntegratorType>,
+    /// Color of rays that miss everything
+    pub background: Option<Vector3<f64>>,
+}
+
+impl Scene {
+    pub fn new(camera: Camera, lights: Vec<Light>, ambient: Vector3<f64>, objects: Vec<Box<dyn SceneObject>>) -> Scene {
+        Scene {
+            transform_root: TransformNode::root(),
+            objects: Aggregate::new(objects),
+            lights,
+            camera,
+            ambient,
+            settings: SceneSettings::default(),
+            media: Media::new(),
+        }
+    }
+
+    pub fn with_settings(mut self, settings: SceneSettings) -> Scene {
+        self.settings = settings;
+        self
+    }
+
+    /// Fills the scene with fog and volumes of media
+    pub fn with_media(mut self, mut media: Media) -> Scene {
+        media.set_extent(self.objects.bounds());
+        self.media = media;
+        self
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Replaces the camera, keeping the objects as they are
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    /// Replaces the lights, keeping the objects as they are
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.lights = lights;
+    }
+
+    pub fn ambient(&self) -> Vector3<f64> {
+        self.ambient
+    }
+
+    pub fn settings(&self) -> &SceneSettings {
+        &self.settings
+    }
+
+    pub fn media(&self) -> &Media {
+        &self.media
+    }
+
+    pub fn bounds(&self) -> Option<&BoundingBox> {
+        self.objects.bounds()
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn objects(&self) -> &[Box<dyn SceneObject>] {
+        self.objects.objects()
+    }
+
+    /// Bytes of memory the objects, meshes and hierarchies of the scene take
+    pub fn geometry_memory(&self) -> usize {
+        self.objects.memory(&mut HashSet::new())
+    }
 
+    /// Finds the closest intersection of `ray` with the scene, if any
+    pub fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+        stats::count_ray(ray.ray_type());
+        self.objects.intersect(ray, isect)
+    }
+
+    /// Like `intersect`, giving the index in `objects()` of the object that
+    /// was hit
+    pub fn intersect_object(&self, ray: &Ray, isect: &mut Intersect) -> Option<usize> {
+        stats::count_ray(ray.ray_type());
+        self.objects.intersect_object(ray, isect)
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct TransformNode {
     // TODO: better names once we know what these are
     xform: Matrix4<f64>,
@@ -27,37 +141,217 @@ pub struct TransformNode {
 
 impl TransformNode {
     pub fn root() -> TransformNode {
-        TransformNode::new(None, Matrix4::identity())
-    }
-
-    pub fn create_child(&self, xform: Matrix4<f64>) -> TransformNode {
-        TransformNode::new(Some(&self), xform)
+        TransformNode {
+            xform: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+            normi: Matrix3::identity(),
+        }
     }
 
-    fn new(parent: Option<&TransformNode>, xform: Matrix4<f64>) -> TransformNode {
-        let xform = if let Some(node) = parent {
-            // use the parents xform if it exists
-            node.xform * xform
-        } else {
-            xform
-        };
-
-        // TODO: don't unwrap, do error checking? Probably should always be able to though
-        let inverse = xform.invert().unwrap();
-        let normi = mat3_from_mat4(xform).invert().unwrap().transpose();
+    /// The node `xform` places within this one, or `None` when the two
+    /// together can't be inverted, like a scale that's rounded to nothing
+    pub fn create_child(&self, xform: Matrix4<f64>) -> Option<TransformNode> {
+        let xform = self.xform * xform;
+        let inverse = xform.invert()?;
+        let normi = mat3_from_mat4(xform).invert()?.transpose();
 
-        TransformNode {
+        Some(TransformNode {
             xform,
             inverse,
             normi,
+        })
+    }
+
+    /// The accumulated object to world matrix
+    pub fn matrix(&self) -> Matrix4<f64> {
+        self.xform
+    }
+
+    /// The accumulated world to object matrix
+    pub fn inverse_matrix(&self) -> Matrix4<f64> {
+        self.inverse
+    }
+
+    pub fn world_to_local_point(&self, p: Vector3<f64>) -> Vector3<f64> {
+        (self.inverse * p.extend(1.0)).truncate()
+    }
+
+    pub fn world_to_local_vector(&self, d: Vector3<f64>) -> Vector3<f64> {
+        (self.inverse * d.extend(0.0)).truncate()
+    }
+
+    pub fn local_to_world_point(&self, p: Vector3<f64>) -> Vector3<f64> {
+        (self.xform * p.extend(1.0)).truncate()
+    }
+
+    pub fn local_to_world_vector(&self, d: Vector3<f64>) -> Vector3<f64> {
+        (self.xform * d.extend(0.0)).truncate()
+    }
+
+    pub fn local_to_world_normal(&self, n: Vector3<f64>) -> Vector3<f64> {
+        (self.normi * n).normalize()
+    }
+
+    pub fn world_to_local_normal(&self, n: Vector3<f64>) -> Vector3<f64> {
+        (mat3_from_mat4(self.xform).transpose() * n).normalize()
+    }
+
+    /// How much the transform stretches a small patch of surface with the
+    /// given unit normal, going from object to world space
+    pub fn area_scale(&self, n: Vector3<f64>) -> f64 {
+        mat3_from_mat4(self.xform).determinant().abs() * (self.normi * n).magnitude()
+    }
+}
+
+/// One transformation step of the scene hierarchy, kept in the form it was
+/// given in so scenes can be described again the way they were written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transformation {
+    Translate(Vector3<f64>),
+    /// Rotation about an axis by an angle in radians
+    Rotate(Vector3<f64>, f64),
+    Scale(Vector3<f64>),
+    Matrix(Matrix4<f64>),
+}
+
+impl Transformation {
+    pub fn matrix(&self) -> Matrix4<f64> {
+        match *self {
+            Transformation::Translate(offset) => Matrix4::from_translation(offset),
+            Transformation::Rotate(axis, angle) => Matrix4::from(Matrix3::from_axis_angle(axis.normalize(), Rad(angle))),
+            Transformation::Scale(factors) => Matrix4::from_nonuniform_scale(factors.x, factors.y, factors.z),
+            Transformation::Matrix(matrix) => matrix,
         }
     }
 }
 
-trait SceneObject {
+/// Geometry placed in a scene. Scenes and instances keep their objects in a
+/// bounding volume hierarchy built from `bounding_box`, so anything that can
+/// intersect a ray and bound itself can be rendered, including primitives
+/// defined outside this crate.
+pub trait SceneObject: Send + Sync {
+    /// Intersects the ray with the object, filling `isect` and returning true on a hit
     fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool;
+    /// World space bounds of the object
+    fn bounding_box(&self) -> BoundingBox;
+
+    /// The material of the whole object, if it's made of a single one
+    fn material(&self) -> Option<&Arc<Material>> {
+        None
+    }
+
+    /// Picks a world space point on the surface from `u`, uniform in
+    /// `[0, 1)²`, for lighting by emissive objects. Objects that can't be
+    /// sampled return None.
+    fn sample_surface(&self, _u: Vector2<f64>) -> Option<SurfaceSample> {
+        None
+    }
+
+    /// Texture coordinates of a hit this object reported
+    fn uv(&self, isect: &Intersect) -> Vector2<f64> {
+        isect.uv_coords
+    }
+
+    /// World space tangent along increasing u at a hit this object reported,
+    /// for objects that have one
+    fn tangent(&self, _isect: &Intersect) -> Option<Vector3<f64>> {
+        None
+    }
+
+    /// Bytes of memory the object takes, counting what it shares with other
+    /// objects only the first time its address goes into `shared`
+    fn memory(&self, _shared: &mut HashSet<usize>) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+/// How a camera maps the image onto directions out of the eye
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// A pinhole, seeing the camera's field of view
+    Perspective,
+    /// Parallel rays across a view `height` units high
+    Orthographic { height: f64 },
+    /// A circle as tall as the image, seeing `fov` degrees across
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    /// Every direction, longitude across the image and latitude up it, with
+    /// the view direction in the middle
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic { .. } => "orthographic",
+            Projection::Fisheye { .. } => "fisheye",
+            Projection::Equirectangular => "equirectangular",
+        }
+    }
+}
+
+/// How far from the middle of a fisheye image directions off the view
+/// direction land
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// In proportion to the angle
+    Equidistant,
+    /// So that equal areas of the image see equal solid angles
+    Equisolid,
+}
+
+impl FisheyeMapping {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FisheyeMapping::Equidistant => "equidistant",
+            FisheyeMapping::Equisolid => "equisolid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FisheyeMapping> {
+        match name {
+            "equidistant" => Some(FisheyeMapping::Equidistant),
+            "equisolid" => Some(FisheyeMapping::Equisolid),
+            _ => None,
+        }
+    }
+}
+
+/// Where each eye's view goes in a stereo image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// The left eye on the left
+    SideBySide,
+    /// The left eye on top
+    OverUnder,
 }
 
+impl StereoLayout {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StereoLayout::SideBySide => "side-by-side",
+            StereoLayout::OverUnder => "over-under",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            "over-under" => Some(StereoLayout::OverUnder),
+            _ => None,
+        }
+    }
+}
+
+/// A view for each eye in one image, the eyes `interocular` apart either
+/// side of the camera's position and looking the same way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    pub interocular: f64,
+}
+
+#[derive(Clone, Debug)]
 pub struct Camera {
     // TODO: better names once we know what these are
     m: Matrix3<f64>,
@@ -66,37 +360,318 @@ pub struct Camera {
     u: Vector3<f64>,
     v: Vector3<f64>,
     normalized_height: f64,
+    /// Width over height of the whole image, both eyes of a stereo image
     aspect_ratio: f64,
+    projection: Projection,
+    stereo: Option<Stereo>,
 }
 
 impl Camera {
     pub fn new() -> Camera {
-        Camera {
+        let mut camera = Camera {
             m: Matrix3::identity(),
-            eye: Vector3::zero(), // TODO: double check out glm does default vector init
-            look: Vector3::zero(),
-            u: Vector3::zero(),
-            v: Vector3::zero(),
-            normalized_height: 0.0,
-            aspect_ratio: 0.0,
-        }
+            eye: Vector3::zero(),
+            look: Vector3::new(0.0, 0.0, -1.0),
+            u: Vector3::unit_x(),
+            v: Vector3::unit_y(),
+            normalized_height: 1.0,
+            aspect_ratio: 1.0,
+            projection: Projection::Perspective,
+            stereo: None,
+        };
+        camera.update();
+        camera
+    }
+
+    pub fn eye(&self) -> Vector3<f64> {
+        self.eye
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.aspect_ratio
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn stereo(&self) -> Option<Stereo> {
+        self.stereo
+    }
+
+    pub fn set_eye(&mut self, eye: Vector3<f64>) {
+        self.eye = eye;
+    }
+
+    /// Orients the camera from a view direction and an up direction
+    pub fn set_look(&mut self, view_dir: Vector3<f64>, up_dir: Vector3<f64>) {
+        let z = -view_dir;
+        let x = up_dir.cross(z);
+        let y = z.cross(x);
+
+        self.m = Matrix3::from_cols(x.normalize(), y.normalize(), z.normalize());
+        self.update();
+    }
+
+    /// Orients the camera from a rotation quaternion given as `(x, y, z, w)`
+    pub fn set_quaternion(&mut self, q: Vector4<f64>) {
+        let q = q.normalize();
+        let (x, y, z, w) = (q.x, q.y, q.z, q.w);
+
+        self.m = Matrix3::new(
+            1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + z * w), 2.0 * (x * z - y * w),
+            2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + x * w),
+            2.0 * (x * z + y * w), 2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y)
+        );
+        self.update();
+    }
+
+    /// Sets the vertical field of view, given in degrees
+    pub fn set_fov(&mut self, fov: f64) {
+        self.normalized_height = 2.0 * (fov.to_radians() / 2.0).tan();
+        self.update();
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        self.aspect_ratio = aspect_ratio;
+        self.update();
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn set_stereo(&mut self, stereo: Option<Stereo>) {
+        self.stereo = stereo;
+    }
+
+    /// Creates a ray from the eye through normalized image coordinates,
+    /// where `(0, 0)` is the bottom left of the image and `(1, 1)` the top
+    /// right. None where the projection doesn't reach, outside the circle of
+    /// a fisheye.
+    pub fn ray_through(&self, x: f64, y: f64) -> Option<Ray> {
+        // where the point is in its eye's view, how much wider that view is
+        // than the image, and how far right of the camera the eye is
+        let (x, y, widen, offset) = match self.stereo {
+            None => (x, y, 1.0, 0.0),
+            Some(Stereo { layout: StereoLayout::SideBySide, interocular }) if x < 0.5 => (x * 2.0, y, 0.5, -interocular / 2.0),
+            Some(Stereo { layout: StereoLayout::SideBySide, interocular }) => (x * 2.0 - 1.0, y, 0.5, interocular / 2.0),
+            Some(Stereo { layout: StereoLayout::OverUnder, interocular }) if y >= 0.5 => (x, y * 2.0 - 1.0, 2.0, -interocular / 2.0),
+            Some(Stereo { layout: StereoLayout::OverUnder, interocular }) => (x, y * 2.0, 2.0, interocular / 2.0),
+        };
+        let (x, y) = (x - 0.5, y - 0.5);
+        let right = self.m * Vector3::unit_x();
+        let up = self.m * Vector3::unit_y();
+        let aspect_ratio = self.aspect_ratio * widen;
+
+        let (origin, dir) = match self.projection {
+            Projection::Perspective => (self.eye + right * offset, self.look + x * widen * self.u + y * self.v),
+            Projection::Orthographic { height } => {
+                let across = right * (x * height * aspect_ratio) + up * (y * height);
+                (self.eye + right * offset + across, self.look)
+            },
+            Projection::Fisheye { mapping, fov } => {
+                // in radii of the circle from its middle
+                let (x, y) = (x * 2.0 * aspect_ratio, y * 2.0);
+                let r = x.hypot(y);
+                if r > 1.0 {
+                    return None;
+                }
+                let widest = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * widest,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (widest / 2.0).sin()).asin(),
+                };
+                let toward = if r > 0.0 { (right * x + up * y) / r } else { Vector3::zero() };
+                (self.eye + right * offset, self.look * theta.cos() + toward * theta.sin())
+            },
+            Projection::Equirectangular => {
+                let longitude = x * 2.0 * consts::PI;
+                let latitude = y * consts::PI;
+                let level = self.look * longitude.cos() + right * longitude.sin();
+                // the eyes turn with the view, so they're apart whichever
+                // way it looks
+                let beside = right * longitude.cos() - self.look * longitude.sin();
+                (self.eye + beside * offset, level * latitude.cos() + up * latitude.sin())
+            },
+        };
+        Some(Ray::new(origin, dir.normalize(), RayType::Visibility))
+    }
+
+    fn update(&mut self) {
+        self.u = self.m * Vector3::unit_x() * self.normalized_height * self.aspect_ratio;
+        self.v = self.m * Vector3::unit_y() * self.normalized_height;
+        self.look = self.m * -Vector3::unit_z();
     }
 }
 
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Material {
-    // TODO: really not sure how this should be written, maybe options?
+    pub emissive: MaterialParameter,
+    pub ambient: MaterialParameter,
+    pub specular: MaterialParameter,
+    pub reflective: MaterialParameter,
+    pub diffuse: MaterialParameter,
+    pub transmissive: MaterialParameter,
+    pub shininess: f64,
+    pub index: f64,
+}
 
+impl Material {
+    pub fn new() -> Material {
+        Material {
+            emissive: MaterialParameter::new(Vector3::zero()),
+            ambient: MaterialParameter::new(Vector3::zero()),
+            specular: MaterialParameter::new(Vector3::zero()),
+            reflective: MaterialParameter::new(Vector3::zero()),
+            diffuse: MaterialParameter::new(Vector3::new(1.0, 1.0, 1.0)),
+            transmissive: MaterialParameter::new(Vector3::zero()),
+            shininess: 0.0,
+            index: 1.0,
+        }
+    }
+
+    /// Blends three materials by the given weights, as used for the per-vertex
+    /// materials of a trimesh. Texture maps are taken from the first material.
+    pub fn interpolate(materials: [&Material; 3], weights: Vector3<f64>) -> Material {
+        let blend = |param: fn(&Material) -> &MaterialParameter| MaterialParameter {
+            value: param(materials[0]).value * weights.x
+                + param(materials[1]).value * weights.y
+                + param(materials[2]).value * weights.z,
+            texture_map: param(materials[0]).texture_map.clone(),
+            vertex_colors: param(materials[0]).vertex_colors,
+        };
+
+        Material {
+            emissive: blend(|m| &m.emissive),
+            ambient: blend(|m| &m.ambient),
+            specular: blend(|m| &m.specular),
+            reflective: blend(|m| &m.reflective),
+            diffuse: blend(|m| &m.diffuse),
+            transmissive: blend(|m| &m.transmissive),
+            shininess: materials[0].shininess * weights.x
+                + materials[1].shininess * weights.y
+                + materials[2].shininess * weights.z,
+            index: materials[0].index * weights.x
+                + materials[1].index * weights.y
+                + materials[2].index * weights.z,
+        }
+    }
+
+    /// The material as used on `mesh`: meshes with vertex colors use them as
+    /// their diffuse color
+    pub fn for_mesh(&self, mesh: &Mesh) -> Material {
+        let mut material = self.clone();
+        if !mesh.colors().is_empty() {
+            material.diffuse = MaterialParameter::from_vertex_colors(material.diffuse.base_value());
+        }
+        material
+    }
 }
 
+impl Default for Material {
+    fn default() -> Material {
+        Material::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct MaterialParameter {
-    // TODO: really not sure how this should be written, maybe enums?
     value: Vector3<f64>,
     texture_map: Option<TextureMap>,
+    // take the value from the colors of the mesh vertices around the hit
+    vertex_colors: bool,
 }
 
+impl MaterialParameter {
+    pub fn new(value: Vector3<f64>) -> MaterialParameter {
+        MaterialParameter { value, texture_map: None, vertex_colors: false }
+    }
+
+    pub fn from_texture_map(texture_map: TextureMap) -> MaterialParameter {
+        MaterialParameter { value: Vector3::zero(), texture_map: Some(texture_map), vertex_colors: false }
+    }
+
+    /// Uses the vertex colors of the mesh that was hit, or `value` for
+    /// objects without any
+    pub fn from_vertex_colors(value: Vector3<f64>) -> MaterialParameter {
+        MaterialParameter { value, texture_map: None, vertex_colors: true }
+    }
+
+    pub fn uses_vertex_colors(&self) -> bool {
+        self.vertex_colors
+    }
+
+    /// The constant value of the parameter, ignoring any texture map
+    pub fn base_value(&self) -> Vector3<f64> {
+        self.value
+    }
+
+    pub fn texture_map(&self) -> Option<&TextureMap> {
+        self.texture_map.as_ref()
+    }
+
+    /// The value of the parameter at the given intersection
+    pub fn value(&self, isect: &Intersect) -> Vector3<f64> {
+        match self.texture_map {
+            Some(ref texture_map) => texture_map.mapped_value(isect.uv_coords),
+            None if self.vertex_colors => isect.vertex_color.unwrap_or(self.value),
+            None => self.value,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct TextureMap {
-    // TODO: probably use generic image buffer
-    //       look at cpp code to understand usage before implementing
+    filename: String,
+    image: Arc<RgbImage>,
+}
+
+impl TextureMap {
+    pub fn new<S: Into<String>>(filename: S, image: RgbImage) -> TextureMap {
+        TextureMap {
+            filename: filename.into(),
+            image: Arc::new(image),
+        }
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Bilinearly samples the texture at the given uv coordinates
+    pub fn mapped_value(&self, uv: Vector2<f64>) -> Vector3<f64> {
+        let (width, height) = self.image.dimensions();
+        if width == 0 || height == 0 {
+            return Vector3::zero();
+        }
+
+        let x = uv.x.clamp(0.0, 1.0) * f64::from(width - 1);
+        let y = (1.0 - uv.y).clamp(0.0, 1.0) * f64::from(height - 1);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+
+        let pixel = |x: u32, y: u32| {
+            let p = self.image.get_pixel(x, y).data;
+            Vector3::new(f64::from(p[0]), f64::from(p[1]), f64::from(p[2])) / 255.0
+        };
+
+        (pixel(x0, y0) * (1.0 - fx) + pixel(x1, y0) * fx) * (1.0 - fy)
+            + (pixel(x0, y1) * (1.0 - fx) + pixel(x1, y1) * fx) * fy
+    }
+}
+
+impl PartialEq for TextureMap {
+    fn eq(&self, other: &TextureMap) -> bool {
+        self.filename == other.filename
+    }
 }
 
 // TODO: move this to a math helpers file
@@ -104,6 +679,6 @@ pub fn mat3_from_mat4(mat4: Matrix4<f64>) -> Matrix3<f64> {
     Matrix3::new(
         mat4.x.x, mat4.x.y, mat4.x.z,
         mat4.y.x, mat4.y.y, mat4.y.z,
-        mat4.z.y, mat4.z.y, mat4.z.z
+        mat4.z.x, mat4.z.y, mat4.z.z
     )
 }
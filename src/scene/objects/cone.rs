@@ -0,0 +1,179 @@
+use cgmath::{Vector2, Vector3, InnerSpace};
+
+use std::f64::consts;
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+
+/// Cone around the z-axis of its transform, running from `bottom_radius` at
+/// z = 0 to `top_radius` at z = `height`
+pub struct Cone {
+	transform: TransformNode,
+	material: Arc<Material>,
+	capped: bool,
+	height: f64,
+	bottom_radius: f64,
+	top_radius: f64,
+}
+
+impl Cone {
+	pub fn new(transform: TransformNode, material: Arc<Material>, capped: bool, height: f64, bottom_radius: f64, top_radius: f64) -> Cone {
+		Cone {
+			transform,
+			material,
+			capped,
+			height,
+			bottom_radius,
+			top_radius,
+		}
+	}
+}
+
+impl SceneObject for Cone {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| {
+			if intersect_frustum(ray, isect, self.capped, self.height, self.bottom_radius, self.top_radius) {
+				isect.material = Some(self.material.clone());
+				true
+			} else {
+				false
+			}
+		})
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		let radius = self.bottom_radius.max(self.top_radius);
+		BoundingBox::new(Vector3::new(-radius, -radius, 0.0), Vector3::new(radius, radius, self.height))
+			.transform(&self.transform)
+	}
+
+	fn material(&self) -> Option<&Arc<Material>> {
+		Some(&self.material)
+	}
+
+	fn sample_surface(&self, u: Vector2<f64>) -> Option<SurfaceSample> {
+		let (p, n, pdf) = sample_frustum(u, self.capped, self.height, self.bottom_radius, self.top_radius);
+		Some(sample_transformed(&self.transform, p, n, pdf))
+	}
+
+	fn tangent(&self, isect: &Intersect) -> Option<Vector3<f64>> {
+		Some(frustum_tangent(&self.transform, isect))
+	}
+}
+
+/// Intersects an object space ray with a (possibly truncated) cone around the z-axis,
+/// filling in the distance, normal and uv coordinates of the closest hit
+pub fn intersect_frustum(ray: &Ray, isect: &mut Intersect, capped: bool, height: f64, bottom_radius: f64, top_radius: f64) -> bool {
+	let (p, d) = (ray.p, ray.d);
+	let slope = (top_radius - bottom_radius) / height;
+	let radius_at = |z: f64| bottom_radius + slope * z;
+
+	let mut best: Option<(f64, Vector3<f64>, Vector2<f64>)> = None;
+	let mut consider = |t: f64, n: Vector3<f64>, uv: Vector2<f64>| {
+		if t > RAY_EPSILON && best.is_none_or(|(best_t, _, _)| t < best_t) {
+			best = Some((t, n, uv));
+		}
+	};
+
+	// body of the cone
+	let a = d.x * d.x + d.y * d.y - slope * slope * d.z * d.z;
+	let b = 2.0 * (p.x * d.x + p.y * d.y - slope * d.z * radius_at(p.z));
+	let c = p.x * p.x + p.y * p.y - radius_at(p.z) * radius_at(p.z);
+
+	let roots = if a.abs() < RAY_EPSILON {
+		if b.abs() < RAY_EPSILON { vec![] } else { vec![-c / b] }
+	} else {
+		let discriminant = b * b - 4.0 * a * c;
+		if discriminant < 0.0 {
+			vec![]
+		} else {
+			let root = discriminant.sqrt();
+			vec![(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+		}
+	};
+
+	for t in roots {
+		let hit = ray.at(t);
+		if hit.z < 0.0 || hit.z > height || radius_at(hit.z) < 0.0 {
+			continue;
+		}
+
+		let mut n = Vector3::new(hit.x, hit.y, -slope * radius_at(hit.z)).normalize();
+		if !capped && n.dot(d) > 0.0 {
+			// seen from the inside of an open tube
+			n = -n;
+		}
+
+		let uv = Vector2::new(0.5 + hit.y.atan2(hit.x) / (2.0 * consts::PI), hit.z / height);
+		consider(t, n, uv);
+	}
+
+	// caps of the cone
+	if capped && d.z != 0.0 {
+		for &(z, radius, normal_z) in &[(0.0, bottom_radius, -1.0), (height, top_radius, 1.0)] {
+			let t = (z - p.z) / d.z;
+			let hit = ray.at(t);
+			if radius > 0.0 && hit.x * hit.x + hit.y * hit.y <= radius * radius {
+				let uv = Vector2::new(hit.x / (2.0 * radius) + 0.5, hit.y / (2.0 * radius) + 0.5);
+				consider(t, Vector3::new(0.0, 0.0, normal_z), uv);
+			}
+		}
+	}
+
+	match best {
+		Some((t, n, uv)) => {
+			isect.t = t;
+			isect.n = n;
+			isect.uv_coords = uv;
+			true
+		},
+		None => false,
+	}
+}
+
+/// Picks a point uniformly over the area of the surface `intersect_frustum`
+/// intersects, returning it with its normal and density
+pub fn sample_frustum(u: Vector2<f64>, capped: bool, height: f64, bottom_radius: f64, top_radius: f64) -> (Vector3<f64>, Vector3<f64>, f64) {
+	let slope = (top_radius - bottom_radius) / height;
+	let side = consts::PI * (bottom_radius + top_radius) * (height * height + (top_radius - bottom_radius).powi(2)).sqrt();
+	let (bottom, top) = if capped {
+		(consts::PI * bottom_radius * bottom_radius, consts::PI * top_radius * top_radius)
+	} else {
+		(0.0, 0.0)
+	};
+	let total = side + bottom + top;
+
+	let phi = 2.0 * consts::PI * u.y;
+	let (cos, sin) = (phi.cos(), phi.sin());
+	let pick = u.x * total;
+
+	let (p, n) = if pick < side {
+		// the area around each height grows with the radius there, so the
+		// square of the radius is spread evenly
+		let v = pick / side;
+		let (radius, z) = if top_radius == bottom_radius {
+			(bottom_radius, v * height)
+		} else {
+			let radius = (bottom_radius * bottom_radius + v * (top_radius * top_radius - bottom_radius * bottom_radius)).sqrt();
+			(radius, (radius - bottom_radius) / slope)
+		};
+		(Vector3::new(radius * cos, radius * sin, z), Vector3::new(cos, sin, -slope).normalize())
+	} else if pick < side + bottom {
+		let radius = bottom_radius * ((pick - side) / bottom).sqrt();
+		(Vector3::new(radius * cos, radius * sin, 0.0), -Vector3::unit_z())
+	} else {
+		let radius = top_radius * ((pick - side - bottom) / top).sqrt();
+		(Vector3::new(radius * cos, radius * sin, height), Vector3::unit_z())
+	};
+
+	(p, n, 1.0 / total)
+}
+
+/// World space tangent of a hit on a frustum: around the z-axis on its body
+/// and along x on its caps, following their uv coordinates
+pub fn frustum_tangent(transform: &TransformNode, isect: &Intersect) -> Vector3<f64> {
+	let n = transform.world_to_local_normal(isect.n);
+	let tangent = if n.x == 0.0 && n.y == 0.0 { Vector3::unit_x() } else { Vector3::new(-n.y, n.x, 0.0) };
+	transform.local_to_world_vector(tangent).normalize()
+}
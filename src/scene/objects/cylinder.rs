@@ -0,0 +1,50 @@
+use cgmath::{Vector2, Vector3};
+
+use std::sync::Arc;
+
+use super::*;
+use super::cone::{frustum_tangent, intersect_frustum, sample_frustum};
+use super::super::SceneObject;
+
+/// Cylinder of radius 1 around the z-axis of its transform, from z = 0 to z = 1
+pub struct Cylinder {
+	transform: TransformNode,
+	material: Arc<Material>,
+	capped: bool,
+}
+
+impl Cylinder {
+	pub fn new(transform: TransformNode, material: Arc<Material>, capped: bool) -> Cylinder {
+		Cylinder { transform, material, capped }
+	}
+}
+
+impl SceneObject for Cylinder {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| {
+			if intersect_frustum(ray, isect, self.capped, 1.0, 1.0, 1.0) {
+				isect.material = Some(self.material.clone());
+				true
+			} else {
+				false
+			}
+		})
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		BoundingBox::new(Vector3::new(-1.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 1.0)).transform(&self.transform)
+	}
+
+	fn material(&self) -> Option<&Arc<Material>> {
+		Some(&self.material)
+	}
+
+	fn sample_surface(&self, u: Vector2<f64>) -> Option<SurfaceSample> {
+		let (p, n, pdf) = sample_frustum(u, self.capped, 1.0, 1.0, 1.0);
+		Some(sample_transformed(&self.transform, p, n, pdf))
+	}
+
+	fn tangent(&self, isect: &Intersect) -> Option<Vector3<f64>> {
+		Some(frustum_tangent(&self.transform, isect))
+	}
+}
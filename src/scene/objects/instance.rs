@@ -0,0 +1,45 @@
+use cgmath::{Vector3, Zero};
+
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+use super::super::bvh::Aggregate;
+
+/// Places a shared aggregate of objects in the scene under its own transform.
+/// Rays are moved into the instance's space and traced against the aggregate's
+/// own hierarchy, so any number of instances only cost a transform each.
+pub struct Instance {
+	transform: TransformNode,
+	prototype: Arc<Aggregate>,
+}
+
+impl Instance {
+	pub fn new(transform: TransformNode, prototype: Arc<Aggregate>) -> Instance {
+		Instance { transform, prototype }
+	}
+
+	pub fn prototype(&self) -> &Arc<Aggregate> {
+		&self.prototype
+	}
+}
+
+impl SceneObject for Instance {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| self.prototype.intersect(ray, isect))
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		self.prototype.bounds()
+			.cloned()
+			.unwrap_or_else(|| BoundingBox::new(Vector3::zero(), Vector3::zero()))
+			.transform(&self.transform)
+	}
+
+	fn memory(&self, shared: &mut HashSet<usize>) -> usize {
+		let prototype = if shared.insert(Arc::as_ptr(&self.prototype) as usize) { self.prototype.memory(shared) } else { 0 };
+		mem::size_of::<Instance>() + prototype
+	}
+}
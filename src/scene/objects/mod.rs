@@ -1,10 +1,109 @@
-use cgmath::{Vector3, Vector2};
+mod cone;
+mod cylinder;
+mod instance;
+mod scene_box;
+mod sphere;
+mod square;
+mod trimesh;
+#[cfg(test)]
+mod tests;
 
+pub use self::cone::Cone;
+pub use self::cylinder::Cylinder;
+pub use self::instance::Instance;
+pub use self::scene_box::SceneBox;
+pub use self::sphere::Sphere;
+pub use self::square::Square;
+pub use self::trimesh::{Mesh, Trimesh, TrimeshFace};
+
+use cgmath::{Vector3, Vector2, InnerSpace, Zero};
+
+use std::f64;
+use std::sync::Arc;
+
+use super::{Material, TransformNode};
+
+/// Minimum distance along a ray for an intersection to count, avoids
+/// surfaces intersecting the rays they spawn
+pub const RAY_EPSILON: f64 = 0.00000001;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct BoundingBox {
 	box_min: Vector3<f64>,
 	box_max: Vector3<f64>,
 }
 
+impl BoundingBox {
+	pub fn new(box_min: Vector3<f64>, box_max: Vector3<f64>) -> BoundingBox {
+		BoundingBox { box_min, box_max }
+	}
+
+	pub fn min(&self) -> Vector3<f64> {
+		self.box_min
+	}
+
+	pub fn max(&self) -> Vector3<f64> {
+		self.box_max
+	}
+
+	pub fn merge(&self, other: &BoundingBox) -> BoundingBox {
+		BoundingBox {
+			box_min: Vector3::new(
+				self.box_min.x.min(other.box_min.x),
+				self.box_min.y.min(other.box_min.y),
+				self.box_min.z.min(other.box_min.z)),
+			box_max: Vector3::new(
+				self.box_max.x.max(other.box_max.x),
+				self.box_max.y.max(other.box_max.y),
+				self.box_max.z.max(other.box_max.z)),
+		}
+	}
+
+	/// The world space bounds of this object space box under the given transform
+	pub fn transform(&self, transform: &TransformNode) -> BoundingBox {
+		let mut corners = (0..8).map(|i| transform.local_to_world_point(Vector3::new(
+			if i & 1 == 0 { self.box_min.x } else { self.box_max.x },
+			if i & 2 == 0 { self.box_min.y } else { self.box_max.y },
+			if i & 4 == 0 { self.box_min.z } else { self.box_max.z },
+		)));
+
+		let first = corners.next().unwrap();
+		corners.fold(BoundingBox::new(first, first), |bounds, corner| {
+			bounds.merge(&BoundingBox::new(corner, corner))
+		})
+	}
+
+	/// Slab test, returns the entry and exit distances along the ray on a hit
+	pub fn intersect(&self, ray: &Ray) -> Option<(f64, f64)> {
+		let mut t_min = f64::NEG_INFINITY;
+		let mut t_max = f64::INFINITY;
+
+		for axis in 0..3 {
+			let (p, d) = (ray.p[axis], ray.d[axis]);
+			let (lo, hi) = (self.box_min[axis], self.box_max[axis]);
+
+			if d == 0.0 {
+				if p < lo || p > hi {
+					return None;
+				}
+				continue;
+			}
+
+			let t1 = (lo - p) / d;
+			let t2 = (hi - p) / d;
+			t_min = t_min.max(t1.min(t2));
+			t_max = t_max.min(t1.max(t2));
+
+			if t_min > t_max || t_max < RAY_EPSILON {
+				return None;
+			}
+		}
+
+		Some((t_min, t_max))
+	}
+}
+
+#[derive(Clone, Debug)]
 pub struct Ray {
 	// TODO: rename once we know what these are
 	p: Vector3<f64>,
@@ -14,6 +113,36 @@ pub struct Ray {
 	ray_type: RayType,
 }
 
+impl Ray {
+	pub fn new(p: Vector3<f64>, d: Vector3<f64>, ray_type: RayType) -> Ray {
+		Ray {
+			p,
+			d,
+			atten: Vector3::new(1.0, 1.0, 1.0),
+			ctr: 0,
+			ray_type,
+		}
+	}
+
+	pub fn position(&self) -> Vector3<f64> {
+		self.p
+	}
+
+	pub fn direction(&self) -> Vector3<f64> {
+		self.d
+	}
+
+	pub fn ray_type(&self) -> RayType {
+		self.ray_type
+	}
+
+	/// The point at distance `t` along the ray
+	pub fn at(&self, t: f64) -> Vector3<f64> {
+		self.p + self.d * t
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RayType {
 	Visibility,
 	Reflection,
@@ -21,34 +150,129 @@ pub enum RayType {
 	Shadow,
 }
 
+#[derive(Clone, Debug)]
 pub struct Intersect {
 	// TODO: rename once we know what these are
 	// NOTE: capital N in cpp raytracer
-	n: Vector3<f64>,
-	bary_coords: Vector3<f64>,
-	uv_coords: Vector2<f64>,
-	t: f64,
+	pub n: Vector3<f64>,
+	pub bary_coords: Vector3<f64>,
+	pub uv_coords: Vector2<f64>,
+	pub t: f64,
+	pub material: Option<Arc<Material>>,
+	/// Interpolated color of the vertices around the hit, for meshes that have them
+	pub vertex_color: Option<Vector3<f64>>,
+}
 
-	//TODO: Object pointer, material
+impl Intersect {
+	pub fn new() -> Intersect {
+		Intersect {
+			n: Vector3::zero(),
+			bary_coords: Vector3::zero(),
+			uv_coords: Vector2::zero(),
+			t: f64::INFINITY,
+			material: None,
+			vertex_color: None,
+		}
+	}
+}
 
+impl Default for Intersect {
+	fn default() -> Intersect {
+		Intersect::new()
+	}
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Light {
 	light_type: LightType,
 	color: Vector3<f64>,
 }
 
+impl Light {
+	pub fn new(light_type: LightType, color: Vector3<f64>) -> Light {
+		Light { light_type, color }
+	}
+
+	pub fn light_type(&self) -> &LightType {
+		&self.light_type
+	}
+
+	pub fn color(&self) -> Vector3<f64> {
+		self.color
+	}
+
+	/// Attenuation of the light's intensity over the distance to `p`
+	pub fn distance_attenuation(&self, p: Vector3<f64>) -> f64 {
+		match self.light_type {
+			LightType::DirectionalLight { .. } => 1.0,
+			LightType::PointLight { pos, a, b, c } => {
+				let d = (pos - p).magnitude();
+				(1.0 / (a + b * d + c * d * d)).min(1.0)
+			},
+		}
+	}
+
+	/// Distance from `p` to the light, infinite for directional lights
+	pub fn distance(&self, p: Vector3<f64>) -> f64 {
+		match self.light_type {
+			LightType::DirectionalLight { .. } => f64::INFINITY,
+			LightType::PointLight { pos, .. } => (pos - p).magnitude(),
+		}
+	}
+
+	/// Normalized direction from `p` towards the light
+	pub fn direction(&self, p: Vector3<f64>) -> Vector3<f64> {
+		match self.light_type {
+			LightType::DirectionalLight { orientation } => -orientation.normalize(),
+			LightType::PointLight { pos, .. } => (pos - p).normalize(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum LightType {
 	DirectionalLight { orientation: Vector3<f64> },
 	PointLight { pos: Vector3<f64>, a: f64, b: f64, c: f64 }, // pos, a, b, c
 }
 
-// TODO: move these to their own files
-// rename of Box because Box is a heap pointer type in Rust
-struct SceneBox;
-struct Cylinder;
-struct Sphere;
-struct Square;
-struct Cone;
-struct Trimesh;
-struct TrimeshFace;
+/// A point picked on the surface of an object
+#[derive(Clone, Debug, PartialEq)]
+pub struct SurfaceSample {
+	pub p: Vector3<f64>,
+	/// Unit normal of the surface at `p`
+	pub n: Vector3<f64>,
+	/// Probability density of picking `p`, per unit of world space area
+	pub pdf: f64,
+}
+
+/// Shared by all geometry: moves the ray into the object space of `transform`,
+/// intersects it there and moves the resulting normal and distance back out
+pub fn intersect_transformed<F>(transform: &TransformNode, ray: &Ray, isect: &mut Intersect, intersect_local: F) -> bool
+	where F: FnOnce(&Ray, &mut Intersect) -> bool
+{
+	let p = transform.world_to_local_point(ray.p);
+	let d = transform.world_to_local_vector(ray.d);
+	let length = d.magnitude();
+
+	let mut local_ray = ray.clone();
+	local_ray.p = p;
+	local_ray.d = d / length;
+
+	if !intersect_local(&local_ray, isect) {
+		return false;
+	}
+
+	isect.t /= length;
+	isect.n = transform.local_to_world_normal(isect.n);
+	true
+}
+
+/// Moves a point picked on an object space surface with the given density out
+/// into world space, where the transform may stretch the area around it
+pub fn sample_transformed(transform: &TransformNode, p: Vector3<f64>, n: Vector3<f64>, pdf: f64) -> SurfaceSample {
+	SurfaceSample {
+		p: transform.local_to_world_point(p),
+		n: transform.local_to_world_normal(n),
+		pdf: pdf / transform.area_scale(n),
+	}
+}
@@ -0,0 +1,83 @@
+use cgmath::{Vector2, Vector3, InnerSpace, Zero};
+
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+
+/// Unit cube centered on the origin of its transform
+// rename of Box because Box is a heap pointer type in Rust
+pub struct SceneBox {
+	transform: TransformNode,
+	material: Arc<Material>,
+}
+
+impl SceneBox {
+	pub fn new(transform: TransformNode, material: Arc<Material>) -> SceneBox {
+		SceneBox { transform, material }
+	}
+
+	fn local_bounds() -> BoundingBox {
+		BoundingBox::new(Vector3::new(-0.5, -0.5, -0.5), Vector3::new(0.5, 0.5, 0.5))
+	}
+
+	fn intersect_local(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		let (t_min, t_max) = match SceneBox::local_bounds().intersect(ray) {
+			Some(range) => range,
+			None => return false,
+		};
+
+		let t = if t_min > RAY_EPSILON { t_min } else { t_max };
+		let p = ray.at(t);
+
+		// the face hit is the axis where the point lies furthest out
+		let axis = (0..3).fold(0, |best, axis| if p[axis].abs() > p[best].abs() { axis } else { best });
+		let mut n = Vector3::zero();
+		n[axis] = p[axis].signum();
+
+		let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+		isect.t = t;
+		isect.n = n;
+		isect.uv_coords = Vector2::new(p[u] + 0.5, p[v] + 0.5);
+		isect.material = Some(self.material.clone());
+		true
+	}
+}
+
+impl SceneObject for SceneBox {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| self.intersect_local(ray, isect))
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		SceneBox::local_bounds().transform(&self.transform)
+	}
+
+	fn material(&self) -> Option<&Arc<Material>> {
+		Some(&self.material)
+	}
+
+	fn sample_surface(&self, u: Vector2<f64>) -> Option<SurfaceSample> {
+		// pick one of the six faces, then a point on it
+		let face = ((u.x * 6.0) as usize).min(5);
+		let axis = face % 3;
+		let side = if face < 3 { 1.0 } else { -1.0 };
+
+		let mut p = Vector3::zero();
+		p[axis] = 0.5 * side;
+		p[(axis + 1) % 3] = u.x * 6.0 - face as f64 - 0.5;
+		p[(axis + 2) % 3] = u.y - 0.5;
+		let mut n = Vector3::zero();
+		n[axis] = side;
+		Some(sample_transformed(&self.transform, p, n, 1.0 / 6.0))
+	}
+
+	fn tangent(&self, isect: &Intersect) -> Option<Vector3<f64>> {
+		let n = self.transform.world_to_local_normal(isect.n);
+		let axis = (0..3).fold(0, |best, axis| if n[axis].abs() > n[best].abs() { axis } else { best });
+		let mut tangent = Vector3::zero();
+		tangent[(axis + 1) % 3] = 1.0;
+		Some(self.transform.local_to_world_vector(tangent).normalize())
+	}
+}
@@ -0,0 +1,73 @@
+use cgmath::{Vector2, Vector3, InnerSpace};
+
+use std::f64::consts;
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+
+/// Unit sphere centered on the origin of its transform
+pub struct Sphere {
+	transform: TransformNode,
+	material: Arc<Material>,
+}
+
+impl Sphere {
+	pub fn new(transform: TransformNode, material: Arc<Material>) -> Sphere {
+		Sphere { transform, material }
+	}
+
+	fn intersect_local(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		let v = -ray.p;
+		let b = v.dot(ray.d);
+		let discriminant = b * b - v.dot(v) + 1.0;
+
+		if discriminant < 0.0 {
+			return false;
+		}
+
+		let discriminant = discriminant.sqrt();
+		let t2 = b + discriminant;
+		if t2 <= RAY_EPSILON {
+			return false;
+		}
+
+		let t1 = b - discriminant;
+		isect.t = if t1 > RAY_EPSILON { t1 } else { t2 };
+		isect.n = ray.at(isect.t).normalize();
+		isect.uv_coords = Vector2::new(
+			0.5 + isect.n.y.atan2(isect.n.x) / (2.0 * consts::PI),
+			0.5 + isect.n.z.clamp(-1.0, 1.0).asin() / consts::PI,
+		);
+		isect.material = Some(self.material.clone());
+		true
+	}
+}
+
+impl SceneObject for Sphere {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| self.intersect_local(ray, isect))
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		BoundingBox::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)).transform(&self.transform)
+	}
+
+	fn material(&self) -> Option<&Arc<Material>> {
+		Some(&self.material)
+	}
+
+	fn sample_surface(&self, u: Vector2<f64>) -> Option<SurfaceSample> {
+		let z = 1.0 - 2.0 * u.x;
+		let r = (1.0 - z * z).max(0.0).sqrt();
+		let phi = 2.0 * consts::PI * u.y;
+		let n = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+		Some(sample_transformed(&self.transform, n, n, 1.0 / (4.0 * consts::PI)))
+	}
+
+	fn tangent(&self, isect: &Intersect) -> Option<Vector3<f64>> {
+		let n = self.transform.world_to_local_normal(isect.n);
+		let tangent = if n.x == 0.0 && n.y == 0.0 { Vector3::unit_y() } else { Vector3::new(-n.y, n.x, 0.0) };
+		Some(self.transform.local_to_world_vector(tangent).normalize())
+	}
+}
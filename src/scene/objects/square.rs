@@ -0,0 +1,62 @@
+use cgmath::{Vector2, Vector3, InnerSpace};
+
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+
+/// Unit square in the xy-plane, centered on the origin of its transform
+pub struct Square {
+	transform: TransformNode,
+	material: Arc<Material>,
+}
+
+impl Square {
+	pub fn new(transform: TransformNode, material: Arc<Material>) -> Square {
+		Square { transform, material }
+	}
+
+	fn intersect_local(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		if ray.d.z == 0.0 {
+			return false;
+		}
+
+		let t = -ray.p.z / ray.d.z;
+		if t <= RAY_EPSILON {
+			return false;
+		}
+
+		let p = ray.at(t);
+		if p.x < -0.5 || p.x > 0.5 || p.y < -0.5 || p.y > 0.5 {
+			return false;
+		}
+
+		isect.t = t;
+		isect.n = if ray.d.z > 0.0 { -Vector3::unit_z() } else { Vector3::unit_z() };
+		isect.uv_coords = Vector2::new(p.x + 0.5, p.y + 0.5);
+		isect.material = Some(self.material.clone());
+		true
+	}
+}
+
+impl SceneObject for Square {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| self.intersect_local(ray, isect))
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		BoundingBox::new(Vector3::new(-0.5, -0.5, 0.0), Vector3::new(0.5, 0.5, 0.0)).transform(&self.transform)
+	}
+
+	fn material(&self) -> Option<&Arc<Material>> {
+		Some(&self.material)
+	}
+
+	fn sample_surface(&self, u: Vector2<f64>) -> Option<SurfaceSample> {
+		Some(sample_transformed(&self.transform, Vector3::new(u.x - 0.5, u.y - 0.5, 0.0), Vector3::unit_z(), 1.0))
+	}
+
+	fn tangent(&self, _isect: &Intersect) -> Option<Vector3<f64>> {
+		Some(self.transform.local_to_world_vector(Vector3::unit_x()).normalize())
+	}
+}
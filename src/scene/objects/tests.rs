@@ -0,0 +1,81 @@
+use cgmath::{Matrix4, Vector2, Vector3, InnerSpace};
+
+use std::f64::consts;
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+
+fn transform(x: f64, y: f64, z: f64) -> TransformNode {
+	TransformNode::root()
+		.create_child(Matrix4::from_translation(Vector3::new(1.0, -2.0, 3.0))).unwrap()
+		.create_child(Matrix4::from_nonuniform_scale(x, y, z)).unwrap()
+}
+
+/// Samples the object on a grid, checking every point lies on its surface, and
+/// returns the area the densities add up to
+fn sampled_area(object: &dyn SceneObject) -> f64 {
+	let steps = 100;
+	let mut area = 0.0;
+	for i in 0..steps {
+		for j in 0..steps {
+			let u = Vector2::new((f64::from(i) + 0.5) / f64::from(steps), (f64::from(j) + 0.5) / f64::from(steps));
+			let sample = object.sample_surface(u).unwrap();
+			assert!((sample.n.magnitude() - 1.0).abs() < 1e-9);
+
+			// a ray from just off the surface comes straight back to the point
+			let ray = Ray::new(sample.p + sample.n * 0.001, -sample.n, RayType::Visibility);
+			let mut isect = Intersect::new();
+			assert!(object.intersect(&ray, &mut isect));
+			assert!((isect.t - 0.001).abs() < 1e-6, "{:?} isn't on the surface", sample.p);
+
+			area += 1.0 / sample.pdf;
+		}
+	}
+	area / f64::from(steps * steps)
+}
+
+fn assert_close(actual: f64, expected: f64) {
+	assert!((actual - expected).abs() < expected * 0.01, "expected {}, got {}", expected, actual);
+}
+
+#[test]
+fn sample_surface_test() {
+	let material = Arc::new(Material::new());
+
+	assert_close(sampled_area(&Sphere::new(transform(2.0, 2.0, 2.0), material.clone())), 16.0 * consts::PI);
+	// a prolate spheroid with semi-axes 1 and 2
+	let e = 3.0f64.sqrt() / 2.0;
+	assert_close(sampled_area(&Sphere::new(transform(1.0, 1.0, 2.0), material.clone())), 2.0 * consts::PI * (1.0 + 2.0 / e * e.asin()));
+
+	assert_close(sampled_area(&Square::new(transform(2.0, 3.0, 1.0), material.clone())), 6.0);
+	assert_close(sampled_area(&SceneBox::new(transform(1.0, 2.0, 3.0), material.clone())), 22.0);
+	assert_close(sampled_area(&Cylinder::new(transform(2.0, 2.0, 2.0), material.clone(), true)), 16.0 * consts::PI);
+	assert_close(sampled_area(&Cone::new(transform(1.0, 1.0, 1.0), material.clone(), true, 3.0, 1.0, 0.25)),
+		consts::PI * (1.25 * (9.0f64 + 0.5625).sqrt() + 1.0 + 0.0625));
+
+	let mesh = Mesh::new(
+		vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 4.0)],
+		vec![TrimeshFace::new(0, 1, 2), TrimeshFace::new(0, 3, 1)],
+		Vec::new(), Vec::new(), Vec::new());
+	assert_close(sampled_area(&Trimesh::new(transform(1.0, 1.0, 1.0), material.clone(), Arc::new(mesh))), 5.0);
+}
+
+#[test]
+fn tangent_test() {
+	let material = Arc::new(Material::new());
+	let sphere = Sphere::new(transform(2.0, 2.0, 2.0), material.clone());
+
+	let mut isect = Intersect::new();
+	assert!(sphere.intersect(&Ray::new(Vector3::new(10.0, -2.0, 3.0), -Vector3::unit_x(), RayType::Visibility), &mut isect));
+	assert_eq!(sphere.material(), Some(&material));
+	assert_eq!(sphere.uv(&isect), isect.uv_coords);
+	let tangent = sphere.tangent(&isect).unwrap();
+	assert!((tangent - Vector3::unit_y()).magnitude() < 1e-9);
+	assert!(tangent.dot(isect.n).abs() < 1e-9);
+
+	let instance = Instance::new(TransformNode::root(), Arc::new(::scene::bvh::Aggregate::new(vec![Box::new(sphere)])));
+	assert!(instance.material().is_none());
+	assert!(instance.sample_surface(Vector2::new(0.5, 0.5)).is_none());
+	assert!(instance.tangent(&isect).is_none());
+}
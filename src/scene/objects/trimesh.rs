@@ -0,0 +1,305 @@
+use cgmath::{Vector2, Vector3, InnerSpace, Zero};
+
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+
+use super::*;
+use super::super::SceneObject;
+use super::super::bvh::Bvh;
+use super::super::super::stats;
+
+/// Triangle mesh, intersected in the object space of its transform
+pub struct Trimesh {
+	transform: TransformNode,
+	material: Arc<Material>,
+	mesh: Arc<Mesh>,
+}
+
+/// Vertex and face data of a trimesh, shared by every trimesh placing it in the scene
+pub struct Mesh {
+	vertices: Vec<Vector3<f64>>,
+	faces: Vec<TrimeshFace>,
+	// per-vertex normals, texture coordinates and materials, either empty or
+	// one for every vertex
+	normals: Vec<Vector3<f64>>,
+	uvs: Vec<Vector2<f64>>,
+	materials: Vec<Material>,
+	colors: Vec<Vector3<f64>>,
+	// hierarchy over the faces, built once with the mesh
+	bvh: Bvh,
+	// running total of the face areas, for picking points on the surface
+	area_sums: Vec<f64>,
+}
+
+/// Indices of a triangle's vertices within its trimesh
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrimeshFace {
+	vertices: [usize; 3],
+}
+
+impl TrimeshFace {
+	pub fn new(a: usize, b: usize, c: usize) -> TrimeshFace {
+		TrimeshFace { vertices: [a, b, c] }
+	}
+
+	pub fn vertices(&self) -> [usize; 3] {
+		self.vertices
+	}
+}
+
+impl Trimesh {
+	pub fn new(transform: TransformNode, material: Arc<Material>, mesh: Arc<Mesh>) -> Trimesh {
+		Trimesh { transform, material, mesh }
+	}
+
+	pub fn mesh(&self) -> &Arc<Mesh> {
+		&self.mesh
+	}
+
+	fn intersect_local(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		if !self.mesh.intersect(ray, isect) {
+			return false;
+		}
+
+		if isect.material.is_none() {
+			isect.material = Some(self.material.clone());
+		}
+		true
+	}
+}
+
+impl Mesh {
+	pub fn new(vertices: Vec<Vector3<f64>>, faces: Vec<TrimeshFace>, normals: Vec<Vector3<f64>>, uvs: Vec<Vector2<f64>>, materials: Vec<Material>) -> Mesh {
+		let bounds: Vec<BoundingBox> = faces.iter().map(|face| {
+			let [a, b, c] = face.vertices;
+			BoundingBox::new(vertices[a], vertices[a])
+				.merge(&BoundingBox::new(vertices[b], vertices[b]))
+				.merge(&BoundingBox::new(vertices[c], vertices[c]))
+		}).collect();
+		let bvh = Bvh::new(&bounds);
+
+		let area_sums = faces.iter().scan(0.0, |sum, face| {
+			let [a, b, c] = face.vertices;
+			*sum += (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]).magnitude() / 2.0;
+			Some(*sum)
+		}).collect();
+
+		Mesh {
+			vertices,
+			faces,
+			normals,
+			uvs,
+			materials,
+			colors: Vec::new(),
+			bvh,
+			area_sums,
+		}
+	}
+
+	/// Adds a color to every vertex, used by materials with vertex color
+	/// parameters
+	pub fn with_colors(mut self, colors: Vec<Vector3<f64>>) -> Mesh {
+		self.colors = colors;
+		self
+	}
+
+	pub fn vertices(&self) -> &[Vector3<f64>] {
+		&self.vertices
+	}
+
+	pub fn faces(&self) -> &[TrimeshFace] {
+		&self.faces
+	}
+
+	pub fn normals(&self) -> &[Vector3<f64>] {
+		&self.normals
+	}
+
+	pub fn uvs(&self) -> &[Vector2<f64>] {
+		&self.uvs
+	}
+
+	pub fn materials(&self) -> &[Material] {
+		&self.materials
+	}
+
+	pub fn colors(&self) -> &[Vector3<f64>] {
+		&self.colors
+	}
+
+	/// Object space bounds of the vertices
+	pub fn bounding_box(&self) -> BoundingBox {
+		let mut vertices = self.vertices.iter();
+		let first = match vertices.next() {
+			Some(&first) => first,
+			None => return BoundingBox::new(Vector3::zero(), Vector3::zero()),
+		};
+
+		vertices.fold(BoundingBox::new(first, first), |bounds, &vertex| bounds.merge(&BoundingBox::new(vertex, vertex)))
+	}
+
+	/// Computes per-vertex normals by averaging the normals of the adjacent faces
+	pub fn generate_normals(vertices: &[Vector3<f64>], faces: &[TrimeshFace]) -> Vec<Vector3<f64>> {
+		let mut normals = vec![Vector3::zero(); vertices.len()];
+
+		for face in faces {
+			let [a, b, c] = face.vertices;
+			let normal = (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]);
+			if normal.magnitude2() > 0.0 {
+				let normal = normal.normalize();
+				normals[a] += normal;
+				normals[b] += normal;
+				normals[c] += normal;
+			}
+		}
+
+		normals.into_iter()
+			.map(|n| if n.magnitude2() > 0.0 { n.normalize() } else { n })
+			.collect()
+	}
+
+	/// Bytes of memory the mesh takes
+	pub fn memory(&self) -> usize {
+		fn size<T>(values: &Vec<T>) -> usize {
+			values.capacity() * mem::size_of::<T>()
+		}
+		mem::size_of::<Mesh>() + size(&self.vertices) + size(&self.faces) + size(&self.normals) + size(&self.uvs)
+			+ size(&self.materials) + size(&self.colors) + size(&self.area_sums) + self.bvh.memory()
+	}
+
+	/// Möller-Trumbore intersection of one face, returns the distance and the
+	/// barycentric coordinates of the hit
+	fn intersect_face(&self, ray: &Ray, face: &TrimeshFace) -> Option<(f64, Vector3<f64>)> {
+		let [a, b, c] = face.vertices;
+		let (a, b, c) = (self.vertices[a], self.vertices[b], self.vertices[c]);
+
+		let edge1 = b - a;
+		let edge2 = c - a;
+		let pvec = ray.d.cross(edge2);
+		let det = edge1.dot(pvec);
+		if det.abs() < RAY_EPSILON {
+			return None;
+		}
+
+		let inv_det = 1.0 / det;
+		let tvec = ray.p - a;
+		let u = tvec.dot(pvec) * inv_det;
+		if !(0.0..=1.0).contains(&u) {
+			return None;
+		}
+
+		let qvec = tvec.cross(edge1);
+		let v = ray.d.dot(qvec) * inv_det;
+		if v < 0.0 || u + v > 1.0 {
+			return None;
+		}
+
+		let t = edge2.dot(qvec) * inv_det;
+		if t <= RAY_EPSILON {
+			return None;
+		}
+
+		Some((t, Vector3::new(1.0 - u - v, u, v)))
+	}
+
+	/// Picks an object space point evenly over the area of the faces,
+	/// returning it with the face's normal and the density of picking it
+	pub fn sample_surface(&self, u: Vector2<f64>) -> Option<(Vector3<f64>, Vector3<f64>, f64)> {
+		let total = *self.area_sums.last()?;
+		if total <= 0.0 {
+			return None;
+		}
+
+		// pick a face by its share of the area, then reuse what's left of u.x
+		let pick = u.x * total;
+		let index = self.area_sums.partition_point(|&sum| sum <= pick).min(self.faces.len() - 1);
+		let start = if index == 0 { 0.0 } else { self.area_sums[index - 1] };
+		let v = ((pick - start) / (self.area_sums[index] - start)).clamp(0.0, 1.0);
+
+		let [a, b, c] = self.faces[index].vertices;
+		let (a, b, c) = (self.vertices[a], self.vertices[b], self.vertices[c]);
+		let root = v.sqrt();
+		let p = a * (1.0 - root) + b * (root * (1.0 - u.y)) + c * (root * u.y);
+		Some((p, (b - a).cross(c - a).normalize(), 1.0 / total))
+	}
+
+	/// Intersects an object space ray with the closest face. The material is
+	/// only filled in when the mesh has per-vertex materials.
+	pub fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		let mut closest = None;
+		let (mut tests, mut hits) = (0, 0);
+		self.bvh.traverse(ray, |index, closest_t| {
+			let face = &self.faces[index];
+			tests += 1;
+			let (t, bary) = self.intersect_face(ray, face)?;
+			hits += 1;
+			if t < closest_t {
+				closest = Some((face, t, bary));
+				Some(t)
+			} else {
+				None
+			}
+		});
+		stats::count_intersections(tests, hits);
+
+		let (face, t, bary) = match closest {
+			Some(hit) => hit,
+			None => return false,
+		};
+		let [a, b, c] = face.vertices;
+
+		isect.t = t;
+		isect.bary_coords = bary;
+		isect.uv_coords = if self.uvs.is_empty() {
+			Vector2::new(bary.y, bary.z)
+		} else {
+			self.uvs[a] * bary.x + self.uvs[b] * bary.y + self.uvs[c] * bary.z
+		};
+		isect.n = if self.normals.is_empty() {
+			(self.vertices[b] - self.vertices[a]).cross(self.vertices[c] - self.vertices[a]).normalize()
+		} else {
+			(self.normals[a] * bary.x + self.normals[b] * bary.y + self.normals[c] * bary.z).normalize()
+		};
+		isect.vertex_color = if self.colors.is_empty() {
+			None
+		} else {
+			Some(self.colors[a] * bary.x + self.colors[b] * bary.y + self.colors[c] * bary.z)
+		};
+		isect.material = if self.materials.is_empty() {
+			None
+		} else {
+			Some(Arc::new(Material::interpolate([&self.materials[a], &self.materials[b], &self.materials[c]], bary)))
+		};
+		true
+	}
+}
+
+impl SceneObject for Trimesh {
+	fn intersect(&self, ray: &Ray, isect: &mut Intersect) -> bool {
+		intersect_transformed(&self.transform, ray, isect, |ray, isect| self.intersect_local(ray, isect))
+	}
+
+	fn bounding_box(&self) -> BoundingBox {
+		self.mesh.bounding_box().transform(&self.transform)
+	}
+
+	fn material(&self) -> Option<&Arc<Material>> {
+		// per-vertex materials take over from the trimesh's own
+		if self.mesh.materials.is_empty() {
+			Some(&self.material)
+		} else {
+			None
+		}
+	}
+
+	fn sample_surface(&self, u: Vector2<f64>) -> Option<SurfaceSample> {
+		let (p, n, pdf) = self.mesh.sample_surface(u)?;
+		Some(sample_transformed(&self.transform, p, n, pdf))
+	}
+
+	fn memory(&self, shared: &mut HashSet<usize>) -> usize {
+		let mesh = if shared.insert(Arc::as_ptr(&self.mesh) as usize) { self.mesh.memory() } else { 0 };
+		mem::size_of::<Trimesh>() + mesh
+	}
+}
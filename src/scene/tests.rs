@@ -0,0 +1,111 @@
+use std::f64::consts;
+
+use cgmath::{InnerSpace, Vector3};
+
+use super::*;
+
+fn assert_near(actual: Vector3<f64>, expected: Vector3<f64>) {
+    assert!((actual - expected).magnitude() < 1e-9, "{:?} != {:?}", actual, expected);
+}
+
+/// A camera at `(0, 0, 5)` looking down -z with `projection`
+fn camera(projection: Projection, aspect_ratio: f64) -> Camera {
+    let mut camera = Camera::new();
+    camera.set_eye(Vector3::new(0.0, 0.0, 5.0));
+    camera.set_aspect_ratio(aspect_ratio);
+    camera.set_projection(projection);
+    camera
+}
+
+fn direction(camera: &Camera, x: f64, y: f64) -> Vector3<f64> {
+    camera.ray_through(x, y).unwrap().direction()
+}
+
+#[test]
+fn perspective_test() {
+    let mut camera = camera(Projection::Perspective, 2.0);
+    camera.set_fov(90.0);
+    assert_near(direction(&camera, 0.5, 0.5), Vector3::new(0.0, 0.0, -1.0));
+    assert_near(direction(&camera, 0.5, 1.0), Vector3::new(0.0, 1.0, -1.0).normalize());
+    assert_near(direction(&camera, 1.0, 0.5), Vector3::new(2.0, 0.0, -1.0).normalize());
+}
+
+#[test]
+fn orthographic_test() {
+    let camera = camera(Projection::Orthographic { height: 4.0 }, 1.5);
+    for &(x, y) in &[(0.5, 0.5), (0.0, 0.0), (1.0, 0.25)] {
+        let ray = camera.ray_through(x, y).unwrap();
+        assert_near(ray.position(), Vector3::new((x - 0.5) * 6.0, (y - 0.5) * 4.0, 5.0));
+        assert_near(ray.direction(), Vector3::new(0.0, 0.0, -1.0));
+    }
+}
+
+#[test]
+fn fisheye_test() {
+    let equidistant = camera(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 }, 1.0);
+    assert_near(direction(&equidistant, 0.5, 0.5), Vector3::new(0.0, 0.0, -1.0));
+    // the edge of the circle is 90 degrees off
+    assert_near(direction(&equidistant, 1.0, 0.5), Vector3::new(1.0, 0.0, 0.0));
+    assert_near(direction(&equidistant, 0.5, 0.0), Vector3::new(0.0, -1.0, 0.0));
+    // and halfway out half of that
+    assert_near(direction(&equidistant, 0.75, 0.5), Vector3::new(1.0, 0.0, -1.0).normalize());
+    // the corners are outside it
+    assert!(equidistant.ray_through(0.0, 0.0).is_none());
+
+    // equal solid angles take equal areas, so half the area of the circle
+    // sees half of the hemisphere, out to 60 degrees
+    let equisolid = camera(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 180.0 }, 1.0);
+    let edge = direction(&equisolid, 0.5 + 0.5 / 2f64.sqrt(), 0.5);
+    assert!((edge.angle(Vector3::new(0.0, 0.0, -1.0)).0 - consts::FRAC_PI_3).abs() < 1e-9);
+    assert_near(direction(&equisolid, 1.0, 0.5), Vector3::new(1.0, 0.0, 0.0));
+
+    // the circle is as tall as the image, in the middle of a wide one
+    let wide = camera(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 }, 2.0);
+    assert_near(direction(&wide, 0.75, 0.5), Vector3::new(1.0, 0.0, 0.0));
+    assert!(wide.ray_through(0.9, 0.5).is_none());
+}
+
+#[test]
+fn equirectangular_test() {
+    let mut camera = camera(Projection::Equirectangular, 2.0);
+    camera.set_look(Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    assert_near(direction(&camera, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0));
+    // longitude goes right across the image, all the way round
+    assert_near(direction(&camera, 0.75, 0.5), Vector3::new(0.0, 0.0, 1.0));
+    assert_near(direction(&camera, 0.0, 0.5), Vector3::new(-1.0, 0.0, 0.0));
+    assert_near(direction(&camera, 1.0, 0.5), Vector3::new(-1.0, 0.0, 0.0));
+    // and latitude from straight down to straight up
+    assert_near(direction(&camera, 0.3, 1.0), Vector3::new(0.0, 1.0, 0.0));
+    assert_near(direction(&camera, 0.5, 0.25), Vector3::new(1.0, -1.0, 0.0).normalize());
+}
+
+#[test]
+fn stereo_test() {
+    let mut side_by_side = camera(Projection::Perspective, 2.0);
+    side_by_side.set_fov(90.0);
+    side_by_side.set_stereo(Some(Stereo { layout: StereoLayout::SideBySide, interocular: 0.5 }));
+
+    // each half is a view of its own, from an eye either side
+    let left = side_by_side.ray_through(0.25, 0.5).unwrap();
+    let right = side_by_side.ray_through(0.75, 0.5).unwrap();
+    assert_near(left.position(), Vector3::new(-0.25, 0.0, 5.0));
+    assert_near(right.position(), Vector3::new(0.25, 0.0, 5.0));
+    assert_near(left.direction(), Vector3::new(0.0, 0.0, -1.0));
+    assert_near(right.direction(), left.direction());
+    // and square, the image being twice as wide as it's tall
+    assert_near(direction(&side_by_side, 0.5 - 1e-12, 0.5), Vector3::new(1.0, 0.0, -1.0).normalize());
+
+    // the left eye's view is on top
+    let mut over_under = camera(Projection::Orthographic { height: 2.0 }, 0.5);
+    over_under.set_stereo(Some(Stereo { layout: StereoLayout::OverUnder, interocular: 1.0 }));
+    assert_near(over_under.ray_through(0.5, 0.75).unwrap().position(), Vector3::new(-0.5, 0.0, 5.0));
+    assert_near(over_under.ray_through(1.0, 0.0).unwrap().position(), Vector3::new(1.5, -1.0, 5.0));
+
+    // panoramic eyes turn with the view, staying either side of it
+    let mut panorama = camera(Projection::Equirectangular, 1.0);
+    panorama.set_stereo(Some(Stereo { layout: StereoLayout::OverUnder, interocular: 0.5 }));
+    let behind = panorama.ray_through(0.0, 0.75).unwrap();
+    assert_near(behind.direction(), Vector3::new(0.0, 0.0, 1.0));
+    assert_near(behind.position(), Vector3::new(0.25, 0.0, 5.0));
+    assert_near(panorama.ray_through(0.75, 0.25).unwrap().position(), Vector3::new(0.0, 0.0, 5.25));
+}
@@ -0,0 +1,216 @@
+//! Counts the work that goes into a render, to see why a scene is slow. Each
+//! thread counts into counters of its own, cheaply, and hands them over with
+//! `take` when it's done, so the totals don't depend on threads sharing
+//! anything while they trace.
+
+#[cfg(test)]
+mod tests;
+
+use serde_json::{self, Value};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::time::Duration;
+
+use super::render::RenderReport;
+use super::scene::objects::RayType;
+
+/// Every kind of ray, in the order they're counted
+pub const RAY_TYPES: [RayType; 4] = [RayType::Visibility, RayType::Reflection, RayType::Refraction, RayType::Shadow];
+
+thread_local! {
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+
+/// What a thread counted
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counters {
+    /// Rays traced through the scene, by `RAY_TYPES`
+    pub rays: [u64; 4],
+    /// Objects and triangles rays were tested against
+    pub intersection_tests: u64,
+    /// Tests where the ray hit
+    pub intersection_hits: u64,
+    /// Nodes of bounding volume hierarchies rays were tested against
+    pub bvh_nodes: u64,
+    /// Camera samples, and the deepest bounce of each added up
+    pub samples: u64,
+    pub depth_sum: u64,
+    /// Time spent building bounding volume hierarchies
+    pub bvh_build: Duration,
+    /// Deepest bounce of the sample being taken
+    deepest: u32,
+}
+
+impl Counters {
+    pub fn add(&mut self, other: &Counters) {
+        for (rays, &other) in self.rays.iter_mut().zip(&other.rays) {
+            *rays += other;
+        }
+        self.intersection_tests += other.intersection_tests;
+        self.intersection_hits += other.intersection_hits;
+        self.bvh_nodes += other.bvh_nodes;
+        self.samples += other.samples;
+        self.depth_sum += other.depth_sum;
+        self.bvh_build += other.bvh_build;
+    }
+
+    /// Rays of one kind
+    pub fn rays(&self, ray_type: RayType) -> u64 {
+        self.rays[ray_type as usize]
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.rays.iter().sum()
+    }
+
+    /// Average of the deepest bounce of every camera sample, 0 for the
+    /// camera ray itself
+    pub fn average_depth(&self) -> f64 {
+        if self.samples == 0 { 0.0 } else { self.depth_sum as f64 / self.samples as f64 }
+    }
+}
+
+fn count<F: FnOnce(&mut Counters)>(update: F) {
+    COUNTERS.with(|counters| update(&mut counters.borrow_mut()));
+}
+
+/// Counts a ray traced through the scene
+pub fn count_ray(ray_type: RayType) {
+    count(|counters| counters.rays[ray_type as usize] += 1);
+}
+
+/// Counts the tests of a ray against objects or triangles, and how many hit
+pub fn count_intersections(tests: u64, hits: u64) {
+    count(|counters| {
+        counters.intersection_tests += tests;
+        counters.intersection_hits += hits;
+    });
+}
+
+pub fn count_bvh_nodes(nodes: u64) {
+    count(|counters| counters.bvh_nodes += nodes);
+}
+
+pub fn count_bvh_build(time: Duration) {
+    count(|counters| counters.bvh_build += time);
+}
+
+/// Notes that the sample being taken has bounced `depth` times
+pub fn count_depth(depth: u32) {
+    count(|counters| counters.deepest = counters.deepest.max(depth));
+}
+
+/// Counts a camera sample, with the deepest bounce noted since the last one
+pub fn count_sample() {
+    count(|counters| {
+        counters.samples += 1;
+        counters.depth_sum += u64::from(counters.deepest);
+        counters.deepest = 0;
+    });
+}
+
+/// What the current thread counted since it last called `take`
+pub fn take() -> Counters {
+    COUNTERS.with(|counters| counters.replace(Counters::default()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+impl StatsFormat {
+    pub fn from_name(name: &str) -> Option<StatsFormat> {
+        match name {
+            "text" => Some(StatsFormat::Text),
+            "json" => Some(StatsFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Everything known about a finished render
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub counters: Counters,
+    /// Reading the scene, apart from building its hierarchies
+    pub parse_time: Duration,
+    pub bvh_time: Duration,
+    /// Tracing the image and its passes
+    pub render_time: Duration,
+    /// Memory taken by the scene's objects, meshes and hierarchies. They're
+    /// kept from when they're built to the end of the render, so this is
+    /// also the most they take.
+    pub geometry_bytes: usize,
+    pub report: RenderReport,
+}
+
+impl Stats {
+    pub fn to_json(&self) -> Value {
+        let counters = &self.counters;
+        let mut rays = serde_json::Map::new();
+        for &ray_type in &RAY_TYPES {
+            rays.insert(ray_type_name(ray_type).to_string(), Value::from(counters.rays(ray_type)));
+        }
+        rays.insert("total".to_string(), Value::from(counters.total_rays()));
+
+        let seconds = |time: Duration| Value::from(time.as_secs_f64());
+        let mut json = serde_json::Map::new();
+        json.insert("rays".to_string(), Value::Object(rays));
+        json.insert("intersection_tests".to_string(), Value::from(counters.intersection_tests));
+        json.insert("intersection_hits".to_string(), Value::from(counters.intersection_hits));
+        json.insert("bvh_nodes_visited".to_string(), Value::from(counters.bvh_nodes));
+        json.insert("camera_samples".to_string(), Value::from(counters.samples));
+        json.insert("average_depth".to_string(), Value::from(counters.average_depth()));
+        json.insert("samples_per_pixel".to_string(), Value::from(self.report.samples));
+        json.insert("stopped".to_string(), Value::from(self.report.stopped.name()));
+        json.insert("parse_seconds".to_string(), seconds(self.parse_time));
+        json.insert("bvh_build_seconds".to_string(), seconds(self.bvh_time));
+        json.insert("render_seconds".to_string(), seconds(self.render_time));
+        json.insert("geometry_bytes".to_string(), Value::from(self.geometry_bytes as u64));
+        Value::Object(json)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counters = &self.counters;
+        let percent = |part: u64, whole: u64| if whole == 0 { 0.0 } else { part as f64 * 100.0 / whole as f64 };
+
+        writeln!(f, "Rays")?;
+        for &ray_type in &RAY_TYPES {
+            writeln!(f, "  {:<20}{:>16}", ray_type_name(ray_type), counters.rays(ray_type))?;
+        }
+        writeln!(f, "  {:<20}{:>16}", "total", counters.total_rays())?;
+        writeln!(f, "Intersections")?;
+        writeln!(f, "  {:<20}{:>16}", "tests", counters.intersection_tests)?;
+        writeln!(f, "  {:<20}{:>16}  ({:.1}%)", "hits", counters.intersection_hits,
+            percent(counters.intersection_hits, counters.intersection_tests))?;
+        writeln!(f, "  {:<20}{:>16}", "bvh nodes visited", counters.bvh_nodes)?;
+        writeln!(f, "Samples")?;
+        writeln!(f, "  {:<20}{:>16}", "camera samples", counters.samples)?;
+        writeln!(f, "  {:<20}{:>16}", "per pixel", self.report.samples)?;
+        writeln!(f, "  {:<20}{:>16.2}", "average depth", counters.average_depth())?;
+        writeln!(f, "Time")?;
+        writeln!(f, "  {:<20}{:>15.3}s", "parse", self.parse_time.as_secs_f64())?;
+        writeln!(f, "  {:<20}{:>15.3}s", "bvh build", self.bvh_time.as_secs_f64())?;
+        writeln!(f, "  {:<20}{:>15.3}s", "render", self.render_time.as_secs_f64())?;
+        writeln!(f, "Memory")?;
+        let (size, unit) = match self.geometry_bytes as f64 {
+            bytes if bytes < 1024.0 * 1024.0 => (bytes / 1024.0, "KB"),
+            bytes => (bytes / (1024.0 * 1024.0), "MB"),
+        };
+        write!(f, "  {:<20}{:>13.1} {}", "geometry", size, unit)
+    }
+}
+
+fn ray_type_name(ray_type: RayType) -> &'static str {
+    match ray_type {
+        RayType::Visibility => "visibility",
+        RayType::Reflection => "reflection",
+        RayType::Refraction => "refraction",
+        RayType::Shadow => "shadow",
+    }
+}
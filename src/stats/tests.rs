@@ -0,0 +1,133 @@
+use cgmath::{Vector2, Vector3, Zero};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use render::{IntegratorType, RenderReport, RenderSettings, Renderer, StopReason};
+use scene::{Material, MaterialParameter};
+use scene::builder::{Element, SceneBuilder};
+use scene::objects::{Intersect, Light, LightType, Mesh, Ray, TrimeshFace};
+
+use super::*;
+
+#[test]
+fn counters_test() {
+    let scene = SceneBuilder::new()
+        .add(Element::sphere().translate(Vector3::new(0.0, 0.0, -5.0)))
+        .add(Element::sphere().translate(Vector3::new(3.0, 0.0, -5.0)))
+        .build();
+    take();
+
+    let hit = Ray::new(Vector3::zero(), -Vector3::unit_z(), RayType::Visibility);
+    assert!(scene.intersect(&hit, &mut Intersect::new()));
+    let miss = Ray::new(Vector3::zero(), Vector3::unit_z(), RayType::Shadow);
+    assert!(!scene.intersect(&miss, &mut Intersect::new()));
+
+    let counters = take();
+    assert_eq!((counters.rays(RayType::Visibility), counters.rays(RayType::Shadow), counters.total_rays()), (1, 1, 2));
+    assert_eq!(counters.intersection_hits, 1);
+    assert!(counters.intersection_tests >= 1 && counters.bvh_nodes >= 1, "{:?}", counters);
+
+    // taking them starts the counts again
+    assert_eq!(take(), Counters::default());
+}
+
+#[test]
+fn render_counters_test() {
+    let mut mirror = Material::new();
+    mirror.reflective = MaterialParameter::new(Vector3::new(0.8, 0.8, 0.8));
+    let scene = SceneBuilder::new()
+        .add_light(Light::new(LightType::DirectionalLight { orientation: -Vector3::unit_z() }, Vector3::new(1.0, 1.0, 1.0)))
+        .add(Element::square().material(mirror).scale(Vector3::new(10.0, 10.0, 1.0)).translate(Vector3::new(0.0, 0.0, -5.0)))
+        .build();
+    let settings = RenderSettings {
+        width: 8,
+        height: 6,
+        samples: 2,
+        max_depth: 3,
+        threads: 1,
+        seed: 0,
+        integrator: IntegratorType::Whitted,
+        background: Vector3::zero(),
+        crop: None,
+    };
+
+    let renderer = Renderer::new(&scene, settings.clone());
+    renderer.render();
+    let counters = renderer.counters();
+    assert_eq!((counters.samples, counters.rays(RayType::Visibility)), (96, 96));
+    // the mirror fills the view, and reflects every camera ray once into nothing
+    assert_eq!(counters.rays(RayType::Reflection), 96);
+    assert_eq!(counters.rays(RayType::Shadow), 96);
+    assert_eq!(counters.average_depth(), 1.0);
+
+    // the counts don't depend on the threads
+    let threaded = Renderer::new(&scene, RenderSettings { threads: 3, ..settings });
+    threaded.render();
+    assert_eq!(threaded.counters(), counters);
+}
+
+#[test]
+fn geometry_memory_test() {
+    // a grid of triangles, shared by two trimeshes
+    let size = 32;
+    let vertices = (0..=size).flat_map(|y| (0..=size).map(move |x| Vector3::new(f64::from(x), f64::from(y), 0.0))).collect();
+    let faces = (0..size).flat_map(|y| (0..size).flat_map(move |x| {
+        let corner = (y * (size + 1) + x) as usize;
+        let row = (size + 1) as usize;
+        vec![TrimeshFace::new(corner, corner + 1, corner + row), TrimeshFace::new(corner + 1, corner + row + 1, corner + row)]
+    })).collect();
+    let mesh = Arc::new(Mesh::new(vertices, faces, Vec::new(), Vec::<Vector2<f64>>::new(), Vec::new()));
+
+    let once = SceneBuilder::new().add(Element::trimesh(mesh.clone())).build().geometry_memory();
+    let twice = SceneBuilder::new()
+        .add(Element::trimesh(mesh.clone()))
+        .add(Element::trimesh(mesh.clone()).translate(Vector3::unit_z()))
+        .build()
+        .geometry_memory();
+    assert!(once > mesh.memory());
+    assert!(twice > once && twice - once < mesh.memory() / 10, "{} then {}", once, twice);
+}
+
+#[test]
+fn report_test() {
+    let counters = Counters {
+        rays: [10, 4, 2, 20],
+        intersection_tests: 100,
+        intersection_hits: 25,
+        bvh_nodes: 300,
+        samples: 10,
+        depth_sum: 5,
+        ..Counters::default()
+    };
+    let stats = Stats {
+        counters,
+        parse_time: Duration::from_millis(250),
+        bvh_time: Duration::from_millis(50),
+        render_time: Duration::from_secs(2),
+        geometry_bytes: 3 * 1024 * 1024,
+        report: RenderReport {
+            samples: 10,
+            pixels: 1,
+            passes: 10,
+            time: Duration::from_secs(2),
+            noise: 0.1,
+            stopped: StopReason::Samples,
+        },
+    };
+
+    let json = stats.to_json();
+    assert_eq!(json["rays"]["shadow"], 20);
+    assert_eq!(json["rays"]["total"], 36);
+    assert_eq!((json["intersection_tests"].clone(), json["intersection_hits"].clone()), (100.into(), 25.into()));
+    assert_eq!(json["bvh_nodes_visited"], 300);
+    assert_eq!(json["average_depth"], 0.5);
+    assert_eq!(json["parse_seconds"], 0.25);
+    assert_eq!(json["geometry_bytes"], 3 * 1024 * 1024);
+    assert_eq!(json["stopped"], "samples");
+
+    let text = stats.to_string();
+    for line in &["visibility", "hits                              25  (25.0%)", "average depth", "bvh build", "3.0 MB"] {
+        assert!(text.contains(line), "no '{}' in\n{}", line, text);
+    }
+}
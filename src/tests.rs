@@ -0,0 +1,60 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use config::Config;
+
+use super::*;
+
+static SCENE: &str = "SBT-raytracer 1.0
+camera { position = (0, 0, 5); viewdir = (0, 0, -1); }
+point_light { position = (2, 2, 2); color = (1, 1, 1); }
+sphere { material = { diffuse = (0.8, 0.2, 0.2); }; }
+";
+
+/// A directory of its own for a test's files, removed along with them
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str) -> TestDir {
+        let dir = env::temp_dir().join(format!("ray_rs_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn render(args: &[&str]) {
+    let args: Vec<String> = ["ray_rs", "--quiet", "--width", "16", "--samples", "8", "--integrator", "path"].iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+        .collect();
+    run(Config::new(&args).unwrap()).unwrap();
+}
+
+#[test]
+fn time_limited_resume_test() {
+    let dir = TestDir::new("resume_test");
+    let (scene, checkpoint) = (dir.path("scene.ray"), dir.path("render.checkpoint"));
+    fs::write(&scene, SCENE).unwrap();
+    render(&[&scene, &dir.path("straight.png")]);
+
+    // the time limit stops the render after its first pass, which is saved
+    render(&[&scene, &dir.path("resumed.png"), "--checkpoint", &checkpoint, "--time-limit", "0"]);
+    assert!(Path::new(&checkpoint).exists());
+
+    // and carried on to the same image as the render taking every sample
+    render(&[&scene, &dir.path("resumed.png"), "--checkpoint", &checkpoint, "--resume"]);
+    assert!(!Path::new(&checkpoint).exists());
+    assert_eq!(fs::read(dir.path("resumed.png")).unwrap(), fs::read(dir.path("straight.png")).unwrap());
+}